@@ -1,9 +1,40 @@
-use std::{fs, io};
+//! Download the CPRD@Cambridge code lists.
+//!
+//! Each list's zip is kept in a cache directory (named from its URL), and the SHA-256 of every zip
+//! we have extracted is recorded in the cache's `checksums.csv`. A cached zip is only used if it
+//! still matches its checksum, and a list is only extracted again if its zip has changed, so
+//! refreshing one list (`--list ALC138 --refresh`) leaves the rest alone. With `--offline` nothing
+//! is downloaded.
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
 
 use eadapt_needs_analysis::CodeList;
 use qu::ick_use::*;
+use sha2::{Digest, Sha256};
+use structopt::StructOpt;
 
 const LIST_INDEX: &str = include_str!("../camb_code_lists.csv");
+/// The file in the cache directory recording the checksum of each zip, by URL.
+const CHECKSUMS: &str = "checksums.csv";
+
+#[derive(StructOpt)]
+struct Opt {
+    /// Only use cached zips, and fail for any list that isn't cached.
+    #[structopt(long)]
+    offline: bool,
+    /// Download the lists again even if they are cached.
+    #[structopt(long)]
+    refresh: bool,
+    /// Only fetch these lists (e.g. `--list ALC138`). All lists are fetched by default.
+    #[structopt(long = "list")]
+    lists: Vec<String>,
+    /// Where to keep the downloaded zips.
+    #[structopt(long, default_value = "cache", parse(from_os_str))]
+    cache_dir: PathBuf,
+}
 
 fn code_lists() -> Result<Vec<CodeList>> {
     csv::Reader::from_reader(io::Cursor::new(LIST_INDEX))
@@ -14,30 +45,136 @@ fn code_lists() -> Result<Vec<CodeList>> {
 
 #[qu::ick]
 fn main() -> Result {
+    let opt = Opt::from_args();
+    fs::create_dir_all(&opt.cache_dir)?;
+    let checksums_path = opt.cache_dir.join(CHECKSUMS);
+    let mut checksums = load_checksums(&checksums_path)?;
+
     let code_lists = code_lists()?;
     for code_list in &code_lists {
-        let raw = reqwest::blocking::get(&code_list.url())?.bytes()?;
-        let mut ar = zip::ZipArchive::new(io::Cursor::new(raw))?;
-
-        for i in 0..ar.len() {
-            let mut file = ar.by_index(i)?;
-            let out_path = if file.name().contains("DESCRIPTION") {
-                format!(
-                    "../{}_{}.description.csv",
-                    code_list.name.to_lowercase(),
-                    code_list.ty.to_string().to_lowercase()
-                )
-            } else {
-                format!(
-                    "../{}_{}.csv",
-                    code_list.name.to_lowercase(),
-                    code_list.ty.to_string().to_lowercase()
-                )
-            };
-            log::info!("Writing {} to {}", file.name(), out_path);
-            let mut out_file = fs::File::create(&out_path)?;
-            io::copy(&mut file, &mut out_file)?;
+        if !opt.lists.is_empty()
+            && !opt
+                .lists
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(&code_list.name))
+        {
+            continue;
         }
+        let url = code_list.url();
+        let recorded = checksums.get(&url).cloned();
+        let zip_path = opt
+            .cache_dir
+            .join(format!("{}.zip", &sha256(url.as_bytes())[..16]));
+
+        let cached = read_cached(&zip_path)?.filter(|raw| {
+            let valid = recorded.as_deref() == Some(sha256(raw).as_str());
+            if !valid {
+                log::warn!(
+                    "cached zip for {} doesn't match its checksum",
+                    code_list.name
+                );
+            }
+            valid
+        });
+        let raw = match cached {
+            Some(raw) if !opt.refresh => raw,
+            _ if opt.offline => bail!(
+                "{} isn't in the cache (\"{}\") and we are offline",
+                code_list.name,
+                zip_path.display()
+            ),
+            _ => {
+                log::info!("Downloading {} from {}", code_list.name, url);
+                reqwest::blocking::get(&url)?
+                    .error_for_status()?
+                    .bytes()?
+                    .to_vec()
+            }
+        };
+
+        let hash = sha256(&raw);
+        let out_paths = output_paths(code_list);
+        if recorded.as_deref() == Some(hash.as_str()) && out_paths.iter().all(|path| path.exists())
+        {
+            log::info!("{} is unchanged", code_list.name);
+            continue;
+        }
+        extract(&raw, &out_paths)
+            .with_context(|| format!("extracting {} from {}", code_list.name, url))?;
+
+        // Only cache the zip once we know it is good.
+        fs::write(&zip_path, &raw)?;
+        checksums.insert(url, hash);
+        save_checksums(&checksums_path, &checksums)?;
+    }
+    Ok(())
+}
+
+/// Where the codes and the description of a list are written.
+fn output_paths(code_list: &CodeList) -> [PathBuf; 2] {
+    let stem = format!(
+        "../{}_{}",
+        code_list.name.to_lowercase(),
+        code_list.ty.to_string().to_lowercase()
+    );
+    [
+        PathBuf::from(format!("{}.csv", stem)),
+        PathBuf::from(format!("{}.description.csv", stem)),
+    ]
+}
+
+fn extract(raw: &[u8], [codes_path, description_path]: &[PathBuf; 2]) -> Result {
+    let mut ar = zip::ZipArchive::new(io::Cursor::new(raw))?;
+    for i in 0..ar.len() {
+        let mut file = ar.by_index(i)?;
+        let out_path = if file.name().contains("DESCRIPTION") {
+            description_path
+        } else {
+            codes_path
+        };
+        log::info!("Writing {} to {}", file.name(), out_path.display());
+        let mut out_file = fs::File::create(out_path)?;
+        io::copy(&mut file, &mut out_file)?;
+    }
+    Ok(())
+}
+
+/// The contents of a cached zip, if there is one.
+fn read_cached(path: &Path) -> Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(raw) => Ok(Some(raw)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("reading \"{}\"", path.display())),
+    }
+}
+
+fn sha256(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+fn load_checksums(path: &Path) -> Result<BTreeMap<String, String>> {
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let mut checksums = BTreeMap::new();
+    for row in csv::Reader::from_path(path)?.into_records() {
+        let row = row?;
+        match (row.get(0), row.get(1)) {
+            (Some(url), Some(hash)) => {
+                checksums.insert(url.to_owned(), hash.to_owned());
+            }
+            _ => bail!("expected url and sha256 columns in \"{}\"", path.display()),
+        }
+    }
+    Ok(checksums)
+}
+
+fn save_checksums(path: &Path, checksums: &BTreeMap<String, String>) -> Result {
+    let mut out = csv::Writer::from_path(path)?;
+    out.write_record(["url", "sha256"])?;
+    for (url, hash) in checksums {
+        out.write_record([url, hash])?;
     }
+    out.flush()?;
     Ok(())
 }
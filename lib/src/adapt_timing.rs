@@ -0,0 +1,198 @@
+//! How long each step of the ADAPT process takes.
+//!
+//! Each ADAPT record has the date the form was sent to the patient, the date it came back, and the
+//! date it was last reviewed, which should happen in that order. We summarise the time between
+//! each pair, and list records where the dates are in an impossible order.
+use crate::{render::TextTable, Adapt, Adapts, DisclosureControl, PatientId};
+use chrono::NaiveDate;
+use serde::Serialize;
+use std::fmt;
+use term_data_table as tdt;
+
+/// A step in the ADAPT process, from one date to another.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub enum Step {
+    /// From the form being sent to it being completed.
+    SentToCompleted,
+    /// From the form being completed to the last review.
+    CompletedToReview,
+    /// The whole process, from the form being sent to the last review.
+    SentToReview,
+}
+
+impl Step {
+    pub const ALL: [Step; 3] = [
+        Step::SentToCompleted,
+        Step::CompletedToReview,
+        Step::SentToReview,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Step::SentToCompleted => "Form sent to completed",
+            Step::CompletedToReview => "Form completed to last review",
+            Step::SentToReview => "Form sent to last review",
+        }
+    }
+
+    fn dates(self, adapt: &Adapt) -> (NaiveDate, NaiveDate) {
+        match self {
+            Step::SentToCompleted => (adapt.adapt_form_sent_date, adapt.adapt_form_completed_date),
+            Step::CompletedToReview => (adapt.adapt_form_completed_date, adapt.last_review_date),
+            Step::SentToReview => (adapt.adapt_form_sent_date, adapt.last_review_date),
+        }
+    }
+
+    /// The length of this step in days. Negative if the dates are the wrong way round.
+    pub fn days(self, adapt: &Adapt) -> i64 {
+        let (start, end) = self.dates(adapt);
+        (end - start).num_days()
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// The distribution of the length of a step, in days.
+///
+/// Records where the step has a negative length are left out of the distribution (they are in
+/// [`ProcessTiming::misordered`]).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StepSummary {
+    pub step: Step,
+    /// Records with the dates in the right order.
+    pub n: usize,
+    /// Records with the dates the wrong way round.
+    pub negative: usize,
+    pub mean: Option<f64>,
+    pub min: Option<i64>,
+    pub lower_quartile: Option<i64>,
+    pub median: Option<i64>,
+    pub upper_quartile: Option<i64>,
+    pub max: Option<i64>,
+}
+
+impl StepSummary {
+    fn new(step: Step, adapts: &Adapts) -> Self {
+        let mut days: Vec<i64> = adapts.iter_ref().map(|adapt| step.days(adapt)).collect();
+        let total = days.len();
+        days.retain(|days| *days >= 0);
+        days.sort_unstable();
+        let n = days.len();
+        StepSummary {
+            step,
+            n,
+            negative: total - n,
+            mean: (n > 0).then(|| days.iter().sum::<i64>() as f64 / n as f64),
+            min: days.first().copied(),
+            lower_quartile: nearest_rank(&days, 0.25),
+            median: nearest_rank(&days, 0.5),
+            upper_quartile: nearest_rank(&days, 0.75),
+            max: days.last().copied(),
+        }
+    }
+}
+
+/// A record whose dates are in an impossible order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Misordered {
+    pub patient_id: PatientId,
+    pub step: Step,
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessTiming {
+    pub steps: Vec<StepSummary>,
+    /// Records with a step that ends before it starts. The whole process isn't included, as it
+    /// can only be wrong if one of the other steps is.
+    pub misordered: Vec<Misordered>,
+}
+
+impl ProcessTiming {
+    pub fn new(adapts: &Adapts) -> Self {
+        let steps = Step::ALL
+            .iter()
+            .map(|step| StepSummary::new(*step, adapts))
+            .collect();
+        let mut misordered = vec![];
+        for adapt in adapts.iter_ref() {
+            for step in [Step::SentToCompleted, Step::CompletedToReview] {
+                if step.days(adapt) < 0 {
+                    let (start, end) = step.dates(adapt);
+                    misordered.push(Misordered {
+                        patient_id: adapt.id,
+                        step,
+                        start,
+                        end,
+                    });
+                }
+            }
+        }
+        ProcessTiming { steps, misordered }
+    }
+
+    pub fn term_table(&self, dc: &DisclosureControl) -> tdt::Table<'_> {
+        self.text_table(dc).into_term_table()
+    }
+
+    pub fn text_table(&self, dc: &DisclosureControl) -> TextTable {
+        let days = |v: Option<i64>| match v {
+            Some(v) => v.to_string(),
+            None => "-".into(),
+        };
+        let mut table = TextTable::new([
+            "Step (days)",
+            "N",
+            "Wrong order",
+            "Mean",
+            "Min",
+            "25%",
+            "Median",
+            "75%",
+            "Max",
+        ]);
+        for summary in self.steps.iter() {
+            table.push_row([
+                summary.step.label().to_string(),
+                dc.count(summary.n).to_string(),
+                dc.count(summary.negative).to_string(),
+                match summary.mean {
+                    Some(v) => format!("{v:.1}"),
+                    None => "-".into(),
+                },
+                days(summary.min),
+                days(summary.lower_quartile),
+                days(summary.median),
+                days(summary.upper_quartile),
+                days(summary.max),
+            ]);
+        }
+        table
+    }
+}
+
+/// The `p` quantile of `sorted`, using the nearest-rank method.
+fn nearest_rank(sorted: &[i64], p: f64) -> Option<i64> {
+    let rank = (p * sorted.len() as f64).ceil().max(1.) as usize;
+    sorted.get(rank - 1).copied()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn quantiles() {
+        let days = [1, 3, 7, 8, 20];
+        assert_eq!(nearest_rank(&days, 0.25), Some(3));
+        assert_eq!(nearest_rank(&days, 0.5), Some(7));
+        assert_eq!(nearest_rank(&days, 0.75), Some(8));
+        assert_eq!(nearest_rank(&days, 0.), Some(1));
+        assert_eq!(nearest_rank(&[], 0.5), None);
+    }
+}
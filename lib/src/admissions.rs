@@ -0,0 +1,282 @@
+//! Hospital admissions, from the linked Hospital Episode Statistics (HES) admitted patient care
+//! data.
+//!
+//! Each row is one admission (spell), with all its ICD-10 diagnoses.
+use crate::{
+    dataset::{Dataset, Record},
+    envelope::Schema,
+    load_orig,
+    util::optional_string,
+    ArcStr, PatientId, Patients, Registrations, Result,
+};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, ops::Deref, path::Path};
+
+#[derive(Debug, Deserialize)]
+struct AdmissionRaw {
+    #[serde(rename = "PatID")]
+    patient_id: PatientId,
+    #[serde(rename = "AdmiDate")]
+    admission_date: NaiveDate,
+    #[serde(rename = "DisDate")]
+    discharge_date: Option<NaiveDate>,
+    /// ICD-10 codes separated by `;` or `|`, primary diagnosis first.
+    #[serde(rename = "Diagnoses", deserialize_with = "optional_string")]
+    diagnoses: Option<ArcStr>,
+    #[serde(rename = "AdmiMeth", deserialize_with = "optional_string")]
+    admission_method: Option<ArcStr>,
+}
+
+/// How the patient came to be admitted, grouped from the HES `ADMIMETH` field.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum AdmissionMethod {
+    /// Waiting list, booked or planned (`11`-`13`).
+    Elective,
+    /// Via A&E, GP, bed bureau, clinic, etc. (`21`-`28`, `2A`-`2D`).
+    Emergency,
+    /// Before or after giving birth (`31`, `32`).
+    Maternity,
+    /// Transfers, babies born in hospital, etc. (`81`-`84`, `89`).
+    Other,
+    /// Missing or not a valid code.
+    Unknown,
+}
+
+impl AdmissionMethod {
+    pub fn from_code(code: &str) -> Self {
+        let code = code.trim().to_ascii_uppercase();
+        match code.as_str() {
+            "11" | "12" | "13" => AdmissionMethod::Elective,
+            "21" | "22" | "23" | "24" | "25" | "26" | "27" | "28" | "2A" | "2B" | "2C" | "2D" => {
+                AdmissionMethod::Emergency
+            }
+            "31" | "32" => AdmissionMethod::Maternity,
+            "81" | "82" | "83" | "84" | "89" => AdmissionMethod::Other,
+            _ => AdmissionMethod::Unknown,
+        }
+    }
+}
+
+/// A row in the admissions dataset.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Admission {
+    pub patient_id: PatientId,
+    pub admission_date: NaiveDate,
+    /// `None` if the patient hadn't been discharged when the data were extracted.
+    pub discharge_date: Option<NaiveDate>,
+    /// ICD-10 codes without the dot (e.g. `C819`), primary diagnosis first.
+    pub diagnoses: Vec<ArcStr>,
+    pub admission_method: AdmissionMethod,
+}
+
+impl Schema for Admission {
+    const SCHEMA: &'static str = "Admission { patient_id: u64, admission_date: NaiveDate, \
+        discharge_date: Option<NaiveDate>, diagnoses: Vec<str>, \
+        admission_method: AdmissionMethod }";
+}
+
+impl From<AdmissionRaw> for Admission {
+    fn from(raw: AdmissionRaw) -> Self {
+        let diagnoses = match &raw.diagnoses {
+            Some(diagnoses) => diagnoses
+                .split(|ch| ch == ';' || ch == '|')
+                .map(normalize_icd10)
+                .filter(|code| !code.is_empty())
+                .map(ArcStr::from)
+                .collect(),
+            None => vec![],
+        };
+        Admission {
+            patient_id: raw.patient_id,
+            admission_date: raw.admission_date,
+            discharge_date: raw.discharge_date,
+            diagnoses,
+            admission_method: raw
+                .admission_method
+                .as_deref()
+                .map(AdmissionMethod::from_code)
+                .unwrap_or(AdmissionMethod::Unknown),
+        }
+    }
+}
+
+impl Admission {
+    pub fn is_emergency(&self) -> bool {
+        self.admission_method == AdmissionMethod::Emergency
+    }
+
+    /// Whether any diagnosis starts with `prefix` (e.g. `"I2"` for ischaemic heart disease).
+    pub fn has_diagnosis(&self, prefix: &str) -> bool {
+        let prefix = normalize_icd10(prefix);
+        self.diagnoses
+            .iter()
+            .any(|code| code.starts_with(prefix.as_str()))
+    }
+
+    pub fn primary_diagnosis(&self) -> Option<&str> {
+        self.diagnoses.first().map(|code| &**code)
+    }
+
+    /// Length of stay in days, if the patient has been discharged.
+    pub fn length_of_stay(&self) -> Option<i64> {
+        Some((self.discharge_date? - self.admission_date).num_days())
+    }
+}
+
+impl Record for Admission {
+    type Key = PatientId;
+    const DATASET: &'static str = "admissions";
+    fn key(&self) -> PatientId {
+        self.patient_id
+    }
+    /// Each patient's admissions are indexed in date order.
+    fn cmp_within_key(&self, other: &Self) -> std::cmp::Ordering {
+        self.admission_date.cmp(&other.admission_date)
+    }
+}
+
+/// The parsed list of admissions, with a pre-built index for the `id` field.
+pub struct Admissions(Dataset<Admission>);
+
+impl Admissions {
+    pub fn load_orig(path: impl AsRef<Path>) -> Result<Self> {
+        let els: Vec<AdmissionRaw> = load_orig(path)?;
+        Ok(Self::new(els.into_iter().map(Into::into).collect()))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Admissions(Dataset::load(path)?))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result {
+        self.0.save(path)
+    }
+
+    /// A patient's admissions, in date order.
+    pub fn admissions_for_patient(
+        &self,
+        patient_id: PatientId,
+    ) -> impl Iterator<Item = &Admission> + Clone + '_ {
+        self.0.get(patient_id)
+    }
+
+    /// Get an `Admissions` object containing only admissions that match the filter.
+    pub fn filter(&self, f: impl Fn(&Admission) -> bool) -> Self {
+        Admissions(self.0.filter(f))
+    }
+
+    /// Like [`Admissions::filter`], but records the filter in the [`audit`](crate::audit) log.
+    pub fn filter_described(&self, description: &str, f: impl Fn(&Admission) -> bool) -> Self {
+        Admissions(self.0.filter_described(description, f))
+    }
+
+    /// Emergency admissions after each patient's lymphoma diagnosis and before the end of their
+    /// follow-up, per year of follow-up.
+    ///
+    /// Patients without a diagnosis date, or diagnosed after follow-up ended, are left out.
+    pub fn emergency_rate(
+        &self,
+        patients: &Patients,
+        registrations: &Registrations,
+    ) -> AdmissionRate {
+        let mut rate = AdmissionRate::default();
+        for pat in patients.iter_ref() {
+            let Some(start) = pat.lymphoma_diagnosis_date else {
+                continue;
+            };
+            let end = registrations.follow_up_end(pat.patient_id);
+            if start >= end {
+                continue;
+            }
+            let admissions = self
+                .admissions_for_patient(pat.patient_id)
+                .filter(|adm| adm.is_emergency())
+                .filter(|adm| adm.admission_date > start && adm.admission_date <= end)
+                .count();
+            let years = (end - start).num_days() as f64 / 365.25;
+            rate.per_patient.insert(pat.patient_id, (admissions, years));
+        }
+        rate
+    }
+
+    fn new(els: Vec<Admission>) -> Self {
+        Admissions(Dataset::new(els))
+    }
+}
+
+impl Deref for Admissions {
+    type Target = Dataset<Admission>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<'a> IntoIterator for &'a Admissions {
+    type IntoIter = <&'a [Admission] as IntoIterator>::IntoIter;
+    type Item = &'a Admission;
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.els.iter()
+    }
+}
+
+impl FromIterator<Admission> for Admissions {
+    fn from_iter<T>(iter: T) -> Self
+    where
+        T: IntoIterator<Item = Admission>,
+    {
+        Self::new(iter.into_iter().collect())
+    }
+}
+
+/// Admissions counted against follow-up time.
+#[derive(Debug, Default, Clone)]
+pub struct AdmissionRate {
+    /// For each patient, the number of admissions and years of follow-up.
+    pub per_patient: BTreeMap<PatientId, (usize, f64)>,
+}
+
+impl AdmissionRate {
+    pub fn admissions(&self) -> usize {
+        self.per_patient.values().map(|(count, _)| count).sum()
+    }
+
+    pub fn person_years(&self) -> f64 {
+        self.per_patient.values().map(|(_, years)| years).sum()
+    }
+
+    /// Admissions per patient-year across everyone, or `None` if there is no follow-up time.
+    pub fn rate(&self) -> Option<f64> {
+        let years = self.person_years();
+        (years > 0.).then(|| self.admissions() as f64 / years)
+    }
+}
+
+/// Uppercase and remove dots and whitespace, so `c81.9 ` becomes `C819`.
+fn normalize_icd10(code: &str) -> String {
+    code.chars()
+        .filter(|ch| ch.is_ascii_alphanumeric())
+        .map(|ch| ch.to_ascii_uppercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn from_raw() {
+        let adm = Admission::from(AdmissionRaw {
+            patient_id: PatientId::new(1),
+            admission_date: NaiveDate::from_ymd_opt(2020, 1, 1).unwrap(),
+            discharge_date: NaiveDate::from_ymd_opt(2020, 1, 4),
+            diagnoses: Some("c81.9; I21.0|".into()),
+            admission_method: Some("2a".into()),
+        });
+        assert_eq!(adm.primary_diagnosis(), Some("C819"));
+        assert!(adm.has_diagnosis("I21"));
+        assert!(!adm.has_diagnosis("I22"));
+        assert!(adm.is_emergency());
+        assert_eq!(adm.length_of_stay(), Some(3));
+    }
+}
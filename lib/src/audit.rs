@@ -0,0 +1,105 @@
+//! An append-only log of what was done to the data.
+//!
+//! The study sponsor needs to see exactly which exclusions were applied to the data, and when.
+//! Once [`enable`] has been called, dataset loads and saves, and filters that were given a
+//! description (e.g. [`Events::filter_described`](crate::Events::filter_described)), are appended
+//! to the log as one JSON object per line. Nothing is recorded until the log is enabled.
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use qu::ick_use::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+static LOG: Lazy<Mutex<Option<io::LineWriter<fs::File>>>> = Lazy::new(|| Mutex::new(None));
+
+/// Start appending entries to the log at `path`, creating it if necessary.
+pub fn enable(path: impl AsRef<Path>) -> Result {
+    let path = path.as_ref();
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("opening audit log \"{}\"", path.display()))?;
+    *LOG.lock() = Some(io::LineWriter::new(file));
+    Ok(())
+}
+
+/// Stop recording entries.
+pub fn disable() {
+    *LOG.lock() = None;
+}
+
+/// Whether entries are currently being recorded.
+pub fn is_enabled() -> bool {
+    LOG.lock().is_some()
+}
+
+/// A line in the audit log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub time: DateTime<Utc>,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+/// Something that was done to the data.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    Load {
+        path: PathBuf,
+        rows: usize,
+    },
+    Save {
+        path: PathBuf,
+        rows: usize,
+    },
+    Filter {
+        /// What was filtered, e.g. "events".
+        dataset: String,
+        /// A human-readable description of the predicate.
+        description: String,
+        before: usize,
+        after: usize,
+    },
+}
+
+/// Append an entry to the log, if it is enabled.
+///
+/// Failures are logged rather than returned, so that auditing can't stop an analysis running.
+pub fn record(action: Action) {
+    let mut log = LOG.lock();
+    let Some(out) = log.as_mut() else {
+        return;
+    };
+    let entry = Entry {
+        time: Utc::now(),
+        action,
+    };
+    let res = serde_json::to_writer(&mut *out, &entry)
+        .map_err(Error::from)
+        .and_then(|()| Ok(writeln!(out)?));
+    if let Err(e) = res {
+        event!(Level::WARN, "could not write to audit log: {}", e);
+    }
+}
+
+/// Read all the entries from an audit log.
+pub fn read(path: impl AsRef<Path>) -> Result<Vec<Entry>> {
+    let path = path.as_ref();
+    let text =
+        fs::read_to_string(path).with_context(|| format!("reading \"{}\"", path.display()))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(idx, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("parsing line {} of \"{}\"", idx + 1, path.display()))
+        })
+        .collect()
+}
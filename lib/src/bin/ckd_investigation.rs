@@ -1,6 +1,8 @@
 #![allow(unused)]
 use chrono::NaiveDate;
-use eadapt_needs_analysis::{ltcs, read2, Event, Events, Patients};
+use eadapt_needs_analysis::{
+    date_of_extract, ltcs, measurements::kidney, read2, Event, Events, Patients,
+};
 use noisy_float::prelude::*;
 use qu::ick_use::*;
 use std::{
@@ -13,7 +15,7 @@ pub fn main() -> Result {
     let patients = Patients::load("patients_clean.bin")?;
     let events = Events::load("events_clean.bin")?;
     let conditions = ltcs::Conditions::load()?;
-    let thesaurus = read2::Thesaurus::load()?;
+    let thesaurus = read2::Thesaurus::shared()?;
     let lymphoma_codeset = read2::TermCodeSet::load("lymphoma_clean", thesaurus.clone())?;
 
     let diagnosis_dates = lymphoma_codeset
@@ -44,11 +46,18 @@ pub fn main() -> Result {
     );
 
     println!("different values seen: {:#?}", different_values);
+
+    // CKD stage at the date of extract, for everyone with eGFR results.
+    let mut stages = BTreeMap::new();
+    for (_, readings) in events.measurements(&conditions.ckd147) {
+        if let Some(stage) = kidney::ckd_stage(&readings, date_of_extract()) {
+            *stages.entry(stage).or_insert(0) += 1;
+        }
+    }
+    println!("CKD stages at extract: {:#?}", stages);
     Ok(())
 }
 
 fn get_value(evt: &Event) -> Option<R64> {
-    let val = evt.code_value.as_ref()?;
-    let val = val.parse::<f64>().ok()?;
-    R64::try_new(val)
+    R64::try_new(evt.numeric_value()?.value)
 }
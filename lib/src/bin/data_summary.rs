@@ -11,7 +11,7 @@ pub fn main() -> Result {
     let patients = Patients::load("patients_clean.bin")?;
     let events = Events::load("events_clean.bin")?;
     let adapt = Adapts::load("adapt.bin")?;
-    let thesaurus = Thesaurus::load()?;
+    let thesaurus = Thesaurus::shared()?;
     let codes_subtypes_map = CodeSubtypeMap::load("code_subtype_map.bin")?;
 
     println!("{}", Table::from_serde(patients.iter_ref().take(10))?);
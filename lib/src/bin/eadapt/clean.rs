@@ -1,25 +1,35 @@
-use clap::Parser;
+use crate::Global;
 use eadapt_needs_analysis::{
+    header,
     read2::{ReadCode, TermCodeSet, Thesaurus},
-    Adapts, CodeRubricCounts, Events, Patients,
+    Adapts, CodeRubricCounts, Counts, DisclosureControl, Events, Patients,
 };
 use qu::ick_use::*;
 use std::collections::HashSet;
 
-#[derive(Parser)]
-struct Opt {
-    #[clap(long, short)]
-    overwrite: bool,
-}
+pub fn run(global: &Global) -> Result {
+    for path in ["patients_clean.bin", "events_clean.bin"] {
+        global.check_output(path)?;
+    }
 
-#[qu::ick]
-pub fn main(opt: Opt) -> Result {
     let mut patients = Patients::load("patients.bin")?;
     let mut events = Events::load("events.bin")?;
     let adapt = Adapts::load("adapt.bin")?;
-    let thesaurus = Thesaurus::load()?;
+    let thesaurus = Thesaurus::shared()?;
     let mut lymphoma_termset = TermCodeSet::load("lymphoma", thesaurus.clone())?;
 
+    // Different spellings of the same units would otherwise look like different units.
+    let changed = events.canonicalise_units();
+    println!("canonicalised the units of {changed} events");
+    let unmapped = events.unmapped_units();
+    if !unmapped.is_empty() {
+        header("Unrecognised units");
+        println!(
+            "{}",
+            unmapped.text_table("Units", events.len(), &DisclosureControl::NONE)
+        );
+    }
+
     // Build a map from code/rubric pairs to patient IDs.
     let code_rubrics = CodeRubricCounts::from_events(&events, &thesaurus);
 
@@ -54,8 +64,9 @@ pub fn main(opt: Opt) -> Result {
             }
         })
         .collect::<HashSet<_>>();
-    patients.retain(|pat| kept_patids.contains(&pat.patient_id));
-    events.retain(|evt| kept_patids.contains(&evt.patient_id));
+    let description = "has a code in the lymphoma termset, excluding lymphomatoid papulosis";
+    patients.retain_described(description, |pat| kept_patids.contains(&pat.patient_id));
+    events.retain_described(description, |evt| kept_patids.contains(&evt.patient_id));
 
     header("After removing M1628 (lymphomatoid papulosis)");
     // check which codes we removed by adding the description of our removed codes to the excludes
@@ -74,15 +85,25 @@ pub fn main(opt: Opt) -> Result {
     // Collect all patients matching the new reduced code rubric.
     let retained_patient_ids = lymphoma_coderubrics.all_patient_ids();
     // Rebuild tables without excluded participants.
-    let patients = patients.filter(|pat| retained_patient_ids.contains(&pat.patient_id));
-    let events = events.filter(|ev| retained_patient_ids.contains(&ev.patient_id));
+    let description = "has a lymphoma code other than M1628";
+    let patients = patients.filter_described(description, |pat| {
+        retained_patient_ids.contains(&pat.patient_id)
+    });
+    let events = events.filter_described(description, |ev| {
+        retained_patient_ids.contains(&ev.patient_id)
+    });
 
     let lymphoma_coderubrics =
         code_rubrics.filter(|cr| !descriptions_to_remove.contains(&*cr.code_rubric.rubric));
     let retained_patient_ids = lymphoma_coderubrics.all_patient_ids();
     // Rebuild tables without excluded participants.
-    let patients = patients.filter(|pat| retained_patient_ids.contains(&pat.patient_id));
-    let events = events.filter(|ev| retained_patient_ids.contains(&ev.patient_id));
+    let description = "has a lymphoma code/rubric other than the excluded descriptions";
+    let patients = patients.filter_described(description, |pat| {
+        retained_patient_ids.contains(&pat.patient_id)
+    });
+    let events = events.filter_described(description, |ev| {
+        retained_patient_ids.contains(&ev.patient_id)
+    });
 
     header("Final dataset for analysis");
     println!("total patients: {}", patients.len());
@@ -105,15 +126,6 @@ pub fn main(opt: Opt) -> Result {
     // write out clean data
     patients.save("patients_clean.bin")?;
     events.save("events_clean.bin")?;
-    lymphoma_termset.save("lymphoma_clean", opt.overwrite)?;
+    lymphoma_termset.save("lymphoma_clean", global.overwrite)?;
     Ok(())
 }
-
-fn header(header: &str) {
-    let len = header.len();
-    print!("\n{}\n", header);
-    for _ in 0..len {
-        print!("=");
-    }
-    println!("\n")
-}
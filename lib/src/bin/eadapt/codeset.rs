@@ -0,0 +1,178 @@
+use crate::Global;
+use clap::{Args, Subcommand};
+use eadapt_needs_analysis::{
+    read2::{CodesetRegistry, Thesaurus},
+    DisclosureControl, Events,
+};
+use qu::ick_use::*;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+};
+use term_data_table::{Row, Table};
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Little helper to get the first word of a cambridge csv.
+    FirstWords(FirstWords),
+    /// List the registered codesets.
+    List(Manifest),
+    /// Check that every registered codeset loads.
+    Validate(Manifest),
+    /// Show how many events and patients there are for each code in a registered codeset.
+    Usage(Usage),
+}
+
+pub fn run(cmd: Command, global: &Global) -> Result {
+    match cmd {
+        Command::FirstWords(opt) => first_words(opt),
+        Command::List(opt) => list(opt),
+        Command::Validate(opt) => validate(opt, global),
+        Command::Usage(opt) => usage(opt, global),
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct Manifest {
+    /// Use this codeset manifest instead of the built-in one.
+    #[clap(long)]
+    manifest: Option<PathBuf>,
+}
+
+impl Manifest {
+    fn load(&self) -> Result<CodesetRegistry> {
+        Ok(match &self.manifest {
+            Some(path) => CodesetRegistry::load(path)?,
+            None => CodesetRegistry::builtin().clone(),
+        })
+    }
+}
+
+fn list(opt: Manifest) -> Result {
+    let registry = opt.load()?;
+    let mut table = Table::new().with_row(
+        Row::new()
+            .with_cell("Name")
+            .with_cell("Format")
+            .with_cell("Terminology")
+            .with_cell("Source")
+            .with_cell("Version")
+            .with_cell("Description"),
+    );
+    for entry in registry.iter() {
+        table.add_row(
+            Row::new()
+                .with_cell(&entry.name)
+                .with_cell(entry.format.label())
+                .with_cell(entry.terminology.label())
+                .with_cell(&entry.source)
+                .with_cell(entry.version.as_deref().unwrap_or("-"))
+                .with_cell(entry.description.as_deref().unwrap_or("")),
+        );
+    }
+    println!("{}", table.for_terminal());
+    Ok(())
+}
+
+fn validate(opt: Manifest, global: &Global) -> Result {
+    let registry = opt.load()?;
+    let mut table = Table::new().with_row(
+        Row::new()
+            .with_cell("Name")
+            .with_cell("Path")
+            .with_cell("Codes"),
+    );
+    let mut failed = 0;
+    for entry in registry.iter() {
+        let codes = match entry.check(&global.paths) {
+            Ok(codes) => codes.to_string(),
+            Err(e) => {
+                failed += 1;
+                format!("error: {:#}", e)
+            }
+        };
+        table.add_row(
+            Row::new()
+                .with_cell(&entry.name)
+                .with_cell(entry.file_path(&global.paths).display().to_string())
+                .with_cell(codes),
+        );
+    }
+    println!("{}", table.for_terminal());
+    ensure!(failed == 0, "{} codesets failed to load", failed);
+    Ok(())
+}
+
+#[derive(Debug, Args)]
+pub struct Usage {
+    /// The name of the codeset in the registry.
+    name: String,
+    /// Also write the counts to this CSV file.
+    #[clap(long)]
+    csv: Option<PathBuf>,
+    /// Suppress small counts and round the rest, as required for outputs we release.
+    #[clap(long)]
+    release: bool,
+    #[clap(flatten)]
+    manifest: Manifest,
+}
+
+fn usage(opt: Usage, global: &Global) -> Result {
+    let registry = opt.manifest.load()?;
+    let codeset = registry.load_codeset(&opt.name, &global.paths)?;
+    let th = Thesaurus::shared()?;
+    let events = Events::load("events_clean.bin")?;
+    let dc = DisclosureControl::new(opt.release);
+
+    let usage = codeset.usage(&events);
+    println!("{}", usage.term_table(&th, &dc).for_terminal());
+    let unused = usage.unused().count();
+    if unused > 0 {
+        event!(
+            Level::WARN,
+            "{} of {} codes in \"{}\" aren't used in our data",
+            unused,
+            codeset.len(),
+            opt.name
+        );
+    }
+    if let Some(path) = &opt.csv {
+        usage.save_csv(path, &th, &dc)?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Args)]
+pub struct FirstWords {
+    path: PathBuf,
+    /// Output as a list of quoted strings, for pasting into a termset.
+    #[clap(long, short)]
+    for_meta: bool,
+}
+
+fn first_words(opt: FirstWords) -> Result {
+    let mut map: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for record in csv::Reader::from_path(&opt.path)?.into_records() {
+        let record = record?;
+        let mut record = record.get(2).unwrap().splitn(2, ' ');
+        let word1 = record.next().unwrap();
+        let entry = map.entry(word1.to_lowercase()).or_insert(BTreeSet::new());
+        if let Some(word) = record.next() {
+            entry.insert(word.to_lowercase());
+        }
+    }
+
+    if opt.for_meta {
+        for word in map.keys() {
+            println!("{:?},", word);
+        }
+    } else {
+        for (word, rest) in map {
+            println!("{}", word);
+            for word in rest {
+                println!("    {}", word);
+            }
+        }
+    }
+    Ok(())
+}
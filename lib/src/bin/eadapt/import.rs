@@ -0,0 +1,91 @@
+use crate::Global;
+use eadapt_needs_analysis::{
+    deprivation::ImdLookup, file_exists, needs::Needs, orig_path, progress,
+    subtypes::CodeSubtypeMap, Adapts, Admissions, Events, GroupCounts, Patients, Prescriptions,
+    Registrations,
+};
+use qu::ick_use::*;
+use std::path::Path;
+
+const PRESCRIPTIONS_ORIG: &str = "full.therapy.csv";
+const ADMISSIONS_ORIG: &str = "full.hes.csv";
+pub const REGISTRATIONS_ORIG: &str = "full.registrations.csv";
+const NEEDS_ORIG: &str = "full.needs.csv";
+/// The official IMD lookup ("File 1"), if we have downloaded it. Either the 2015 or the 2019
+/// release will do; we tell which from the headers.
+pub const IMD_LOOKUP_ORIG: &str = "imd_lsoa.csv";
+
+/// The parts of the extract we only have for some runs, and the files we save them as.
+pub const OPTIONAL_FILES: [(&str, &str); 4] = [
+    (REGISTRATIONS_ORIG, "registrations.bin"),
+    (PRESCRIPTIONS_ORIG, "prescriptions.bin"),
+    (ADMISSIONS_ORIG, "admissions.bin"),
+    (NEEDS_ORIG, "needs.bin"),
+];
+
+/// Import the original data extract (requires the subtypes map to have been imported).
+pub fn run(global: &Global) -> Result {
+    for path in ["events.bin", "patients.bin", "adapt.bin"] {
+        global.check_output(path)?;
+    }
+
+    #[cfg(feature = "indicatif")]
+    progress::set_handler(progress::indicatif_handler());
+    #[cfg(not(feature = "indicatif"))]
+    progress::set_handler(progress::log_handler());
+
+    let events = Events::load_orig("full.records.csv")?;
+    events.save("events.bin")?;
+
+    let code_subtype_map = CodeSubtypeMap::load("code_subtype_map.bin")?;
+    let (mut patients, warnings) =
+        Patients::load_orig("full.patients.txt", &events, &code_subtype_map)?;
+    for (rule, count) in GroupCounts::from_keys(warnings.iter().map(|v| v.rule)).iter() {
+        println!("{count} patients broke the \"{rule}\" rule (see `validate` for details)");
+    }
+    let unknown_sex = patients
+        .iter_ref()
+        .filter(|pat| !pat.sex.is_known())
+        .count();
+    if unknown_sex > 0 {
+        println!("{unknown_sex} patients have a sex other than M or F");
+    }
+    if let Some(lookup) = ImdLookup::load_if_present(IMD_LOOKUP_ORIG)? {
+        patients.recompute_imd(&lookup);
+    }
+
+    // Only the follow-on extract has registrations and deaths, which improve the diagnosis date
+    // confidence scores.
+    if file_exists(&orig_path(Path::new(REGISTRATIONS_ORIG)))? {
+        global.check_output("registrations.bin")?;
+        let registrations = Registrations::load_orig(REGISTRATIONS_ORIG)?;
+        patients.score_diagnosis_dates(&events, &code_subtype_map, &registrations);
+        registrations.save("registrations.bin")?;
+    }
+    patients.save("patients.bin")?;
+
+    let adapts = Adapts::load_orig("full.adapt.csv")?;
+    adapts.save("adapt.bin")?;
+
+    // ...and prescriptions.
+    if file_exists(&orig_path(Path::new(PRESCRIPTIONS_ORIG)))? {
+        global.check_output("prescriptions.bin")?;
+        let prescriptions = Prescriptions::load_orig(PRESCRIPTIONS_ORIG)?;
+        prescriptions.save("prescriptions.bin")?;
+    }
+
+    // ...and linked hospital admissions.
+    if file_exists(&orig_path(Path::new(ADMISSIONS_ORIG)))? {
+        global.check_output("admissions.bin")?;
+        let admissions = Admissions::load_orig(ADMISSIONS_ORIG)?;
+        admissions.save("admissions.bin")?;
+    }
+
+    // ...and the needs assessment questionnaires.
+    if file_exists(&orig_path(Path::new(NEEDS_ORIG)))? {
+        global.check_output("needs.bin")?;
+        let needs = Needs::load_orig(NEEDS_ORIG)?;
+        needs.save("needs.bin")?;
+    }
+    Ok(())
+}
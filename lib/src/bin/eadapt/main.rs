@@ -0,0 +1,115 @@
+//! Command line interface for the analysis.
+//!
+//! The usual order of running is
+//!
+//! ```text
+//! eadapt thesaurus import
+//! eadapt subtypes import
+//! eadapt import
+//! eadapt clean
+//! eadapt report ...
+//! ```
+//!
+//! or `eadapt pipeline run` to run everything that is out of date.
+use clap::{Parser, Subcommand};
+use eadapt_needs_analysis::{audit, file_exists, DataPaths};
+use qu::ick_use::*;
+use std::path::{Path, PathBuf};
+
+mod clean;
+mod codeset;
+mod import;
+mod pipeline;
+mod report;
+mod subtypes;
+mod termset;
+mod thesaurus;
+mod validate;
+
+#[derive(Parser)]
+struct Opt {
+    /// The root of the data directory.
+    ///
+    /// Defaults to `$EADAPT_DATA_DIR`, then `data_dir` from the config file, then `../data`.
+    #[clap(long, global = true)]
+    data_dir: Option<PathBuf>,
+    /// If set, allow overwriting existing output files.
+    #[clap(long, short, global = true)]
+    overwrite: bool,
+    /// Append a record of loads, saves and exclusions to this file (JSON lines).
+    #[clap(long, global = true)]
+    audit_log: Option<PathBuf>,
+    #[clap(subcommand)]
+    cmd: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Import the original data extract.
+    Import,
+    /// Remove patients we don't want to include from the imported data.
+    Clean,
+    /// Check the imported data for inconsistencies.
+    Validate(validate::Opt),
+    /// Print reports on the cleaned data.
+    Report(report::Opt),
+    /// Search and import the Read v2 thesaurus.
+    #[clap(subcommand)]
+    Thesaurus(thesaurus::Command),
+    /// Manage the mapping from lymphoma codes to subtypes.
+    #[clap(subcommand)]
+    Subtypes(subtypes::Command),
+    /// Manage termsets.
+    #[clap(subcommand)]
+    Termset(termset::Command),
+    /// Helpers for working with code sets.
+    #[clap(subcommand)]
+    Codeset(codeset::Command),
+    /// Run all the above steps in order, skipping any that are up to date.
+    #[clap(subcommand)]
+    Pipeline(pipeline::Command),
+}
+
+/// Options shared by all subcommands.
+pub struct Global {
+    pub overwrite: bool,
+    pub paths: DataPaths,
+}
+
+impl Global {
+    /// Fail if a file in the output directory exists and we aren't allowed to overwrite it.
+    pub fn check_output(&self, path: impl AsRef<Path>) -> Result {
+        let path = self.paths.output_path(path);
+        ensure!(
+            self.overwrite || !file_exists(&path)?,
+            "\"{}\" already exists (use --overwrite to replace it)",
+            path.display()
+        );
+        Ok(())
+    }
+}
+
+#[qu::ick]
+fn main(opt: Opt) -> Result {
+    let paths = DataPaths::resolve(opt.data_dir.as_deref())?;
+    paths.clone().install();
+    if let Some(path) = &opt.audit_log {
+        audit::enable(path)?;
+    }
+    let global = Global {
+        overwrite: opt.overwrite,
+        paths,
+    };
+
+    match opt.cmd {
+        Command::Import => import::run(&global),
+        Command::Clean => clean::run(&global),
+        Command::Validate(opt) => validate::run(opt, &global),
+        Command::Report(opt) => report::run(opt, &global),
+        Command::Thesaurus(cmd) => thesaurus::run(cmd, &global),
+        Command::Subtypes(cmd) => subtypes::run(cmd, &global),
+        Command::Termset(cmd) => termset::run(cmd, &global),
+        Command::Codeset(cmd) => codeset::run(cmd, &global),
+        Command::Pipeline(cmd) => pipeline::run(cmd, &global),
+    }
+}
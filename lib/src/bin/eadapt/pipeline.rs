@@ -0,0 +1,529 @@
+//! Run the whole analysis, skipping stages whose inputs haven't changed.
+//!
+//! Each stage lists the files it reads and writes. After a stage runs we record the SHA-256 of
+//! each of these files in `pipeline.json` in the output directory. On the next run a stage is
+//! skipped if all its inputs hash the same as last time and its outputs are still there and
+//! unchanged. Since a stage's outputs are the next stage's inputs, changing (say) a termset
+//! reruns only the stages downstream of it. Saved data files are hashed without their header
+//! (which records when they were written), so rerunning a stage on the same inputs gives outputs
+//! with the same hashes.
+//!
+//! The report stages print their tables, and save them as markdown under `reports/` in the output
+//! directory. Those files are their outputs, so an up to date report isn't rerun just to see it.
+//!
+//! Hashing the raw extract is slow, so if a file's size and modification time match the manifest
+//! we reuse the recorded hash.
+use crate::{import, report, termset, Global};
+use clap::{Args, Subcommand};
+use eadapt_needs_analysis::{
+    content_sha256, file_exists, read2::Thesaurus, DataPaths, DisclosureControl,
+};
+use qu::ick_use::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+const MANIFEST: &str = "pipeline.json";
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run all stages that are out of date.
+    Run(RunOpt),
+    /// Show which stages are out of date, without running anything.
+    Status(ReportOpt),
+}
+
+#[derive(Args)]
+pub struct RunOpt {
+    /// Rerun every stage, even if it is up to date.
+    #[clap(long)]
+    force: bool,
+    /// Only run this stage (and any out of date stages it depends on).
+    #[clap(long)]
+    stage: Option<String>,
+    #[clap(flatten)]
+    reports: ReportOpt,
+}
+
+#[derive(Args)]
+pub struct ReportOpt {
+    /// Suppress small counts and round the rest in the reports, as required for outputs we
+    /// release. Released reports are saved separately, so switching doesn't overwrite the others.
+    #[clap(long)]
+    release: bool,
+}
+
+pub fn run(cmd: Command, global: &Global) -> Result {
+    match cmd {
+        Command::Run(opt) => run_pipeline(opt, global),
+        Command::Status(opt) => status(opt, global),
+    }
+}
+
+fn run_pipeline(opt: RunOpt, global: &Global) -> Result {
+    let stages = stages(&global.paths, DisclosureControl::new(opt.reports.release))?;
+    let wanted = match &opt.stage {
+        Some(name) => with_dependencies(&stages, name)?,
+        None => stages.iter().map(|stage| stage.name).collect(),
+    };
+    // stages always overwrite their own outputs.
+    let stage_global = Global {
+        overwrite: true,
+        paths: global.paths.clone(),
+    };
+
+    let mut manifest = Manifest::load(&global.paths)?;
+    for stage in stages.iter().filter(|stage| wanted.contains(stage.name)) {
+        let reason = if opt.force {
+            Some("forced".to_string())
+        } else {
+            stage.stale_reason(&manifest)?
+        };
+        let Some(reason) = reason else {
+            event!(Level::INFO, "stage `{}` is up to date", stage.name);
+            continue
+        };
+        event!(Level::INFO, "running stage `{}` ({})", stage.name, reason);
+        (stage.run)(&stage_global).with_context(|| format!("in stage `{}`", stage.name))?;
+        manifest.record(stage)?;
+        // save after every stage so a failure later on doesn't lose our progress.
+        manifest.save(&global.paths)?;
+    }
+    Ok(())
+}
+
+fn status(opt: ReportOpt, global: &Global) -> Result {
+    let stages = stages(&global.paths, DisclosureControl::new(opt.release))?;
+    let manifest = Manifest::load(&global.paths)?;
+    for stage in stages.iter() {
+        match stage.stale_reason(&manifest)? {
+            Some(reason) => println!("{:<16} out of date ({})", stage.name, reason),
+            None => println!("{:<16} up to date", stage.name),
+        }
+    }
+    Ok(())
+}
+
+/// A step in the pipeline.
+struct Stage {
+    name: &'static str,
+    /// Stages that must run before this one.
+    deps: &'static [&'static str],
+    inputs: Vec<PathBuf>,
+    outputs: Vec<PathBuf>,
+    run: Box<dyn Fn(&Global) -> Result>,
+}
+
+impl Stage {
+    /// Returns `None` if the stage is up to date, otherwise a description of why not.
+    fn stale_reason(&self, manifest: &Manifest) -> Result<Option<String>> {
+        let Some(record) = manifest.stages.get(self.name) else {
+            return Ok(Some("never run".into()))
+        };
+        for input in self.inputs.iter() {
+            let Some(current) = manifest.hash(input)? else {
+                return Ok(Some(format!("\"{}\" missing", input.display())))
+            };
+            if record.inputs.get(input) != Some(&current) {
+                return Ok(Some(format!("\"{}\" changed", input.display())));
+            }
+        }
+        for output in self.outputs.iter() {
+            match manifest.hash(output)? {
+                None => return Ok(Some(format!("\"{}\" missing", output.display()))),
+                Some(current) if record.outputs.get(output) != Some(&current) => {
+                    return Ok(Some(format!(
+                        "\"{}\" modified outside the pipeline",
+                        output.display()
+                    )))
+                }
+                _ => (),
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The stages of the analysis, in the order they should run.
+///
+/// The report stages use `dc` for their tables.
+fn stages(paths: &DataPaths, dc: DisclosureControl) -> Result<Vec<Stage>> {
+    let termsets = paths.termset_path("");
+    let meds_termsets = fs::read_dir(&termsets)
+        .with_context(|| format!("reading \"{}\"", termsets.display()))?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<BTreeSet<_>>>()?
+        .into_iter()
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .map(|name| name.ends_with("meds"))
+                .unwrap_or(false)
+        })
+        .collect::<Vec<_>>();
+    let thesaurus = Thesaurus::path(paths);
+    let lymphoma = paths.termset_path("lymphoma");
+    let lymphoma_clean = paths.termset_path("lymphoma_clean");
+    let out = |name: &str| paths.output_path(name);
+    let clean_outputs = vec![
+        out("patients_clean.bin"),
+        out("events_clean.bin"),
+        lymphoma_clean.join("meta.json"),
+        lymphoma_clean.join("codes.txt"),
+    ];
+    // The optional parts of the extract we have, with the files import saves them as.
+    let optional: Vec<(PathBuf, PathBuf)> = import::OPTIONAL_FILES
+        .iter()
+        .map(|(orig, saved)| (paths.orig_path(orig), out(saved)))
+        .filter(|(orig, _)| orig.exists())
+        .collect();
+    // Reports use registrations if we have them.
+    let registrations = paths
+        .orig_path(import::REGISTRATIONS_ORIG)
+        .exists()
+        .then(|| out("registrations.bin"));
+    let imd_lookup = Some(paths.orig_path(import::IMD_LOOKUP_ORIG)).filter(|path| path.exists());
+    let report_inputs = {
+        let mut inputs = clean_outputs.clone();
+        inputs.extend([
+            out("adapt.bin"),
+            out("code_subtype_map.bin"),
+            thesaurus.clone(),
+        ]);
+        inputs
+    };
+
+    let stages = vec![
+        Stage {
+            name: "thesaurus",
+            deps: &[],
+            inputs: vec![
+                paths.read_db_path("drugs.txt"),
+                paths.read_db_path("nondrugs.txt"),
+            ],
+            outputs: vec![thesaurus.clone()],
+            run: Box::new(|global| {
+                crate::thesaurus::run(
+                    crate::thesaurus::Command::Import(Default::default()),
+                    global,
+                )
+            }),
+        },
+        Stage {
+            name: "termsets",
+            deps: &["thesaurus"],
+            inputs: meds_termsets
+                .iter()
+                .map(|path| path.join("meta.json"))
+                .chain([thesaurus.clone()])
+                .collect(),
+            outputs: meds_termsets
+                .iter()
+                .map(|path| path.join("codes.txt"))
+                .collect(),
+            run: Box::new(|global| {
+                termset::run(
+                    termset::Command::Regenerate {
+                        path: None,
+                        fix: false,
+                    },
+                    global,
+                )
+            }),
+        },
+        Stage {
+            name: "subtypes",
+            deps: &[],
+            inputs: {
+                let mut inputs = vec![paths.root.join("code_subtype_mapping.xlsx")];
+                // Review decisions are optional.
+                let review = paths.root.join(crate::subtypes::REVIEW_FILE);
+                if review.exists() {
+                    inputs.push(review);
+                }
+                inputs
+            },
+            outputs: vec![out("code_subtype_map.bin")],
+            run: Box::new(|global| {
+                crate::subtypes::run(crate::subtypes::Command::Import, global)
+            }),
+        },
+        Stage {
+            name: "import",
+            deps: &["subtypes"],
+            inputs: [
+                paths.orig_path("full.records.csv"),
+                paths.orig_path("full.patients.txt"),
+                paths.orig_path("full.adapt.csv"),
+                out("code_subtype_map.bin"),
+            ]
+            .into_iter()
+            .chain(optional.iter().map(|(orig, _)| orig.clone()))
+            .chain(imd_lookup)
+            .collect(),
+            outputs: [out("events.bin"), out("patients.bin"), out("adapt.bin")]
+                .into_iter()
+                .chain(optional.iter().map(|(_, saved)| saved.clone()))
+                .collect(),
+            run: Box::new(import::run),
+        },
+        Stage {
+            name: "clean",
+            deps: &["thesaurus", "import"],
+            inputs: vec![
+                out("events.bin"),
+                out("patients.bin"),
+                out("adapt.bin"),
+                thesaurus.clone(),
+                lymphoma.join("meta.json"),
+                lymphoma.join("codes.txt"),
+            ],
+            outputs: clean_outputs,
+            run: Box::new(crate::clean::run),
+        },
+        report_stage(
+            paths,
+            "demographics",
+            &["clean"],
+            report_inputs.clone(),
+            dc,
+            || report::Command::Demographics,
+        ),
+        report_stage(
+            paths,
+            "data-quality",
+            &["clean"],
+            vec![
+                out("patients_clean.bin"),
+                out("events_clean.bin"),
+                out("adapt.bin"),
+                thesaurus.clone(),
+            ],
+            dc,
+            || report::Command::DataQuality,
+        ),
+        report_stage(
+            paths,
+            "ltc",
+            &["termsets", "clean"],
+            report_inputs
+                .iter()
+                .cloned()
+                .chain(dir_files(&paths.camb_codesets)?)
+                .chain(dir_files(&termsets)?)
+                .collect(),
+            dc,
+            || report::Command::Ltc { cancer_sites: None },
+        ),
+        report_stage(
+            paths,
+            "adherence",
+            &["termsets", "clean"],
+            report_inputs
+                .iter()
+                .cloned()
+                .chain(dir_files(&termsets)?)
+                .chain(registrations.clone())
+                .collect(),
+            dc,
+            || report::Command::Adherence {
+                rules: None,
+                before_after_years: 3.,
+                dedup: None,
+            },
+        ),
+        report_stage(
+            paths,
+            "late-effects",
+            &["termsets", "clean"],
+            report_inputs
+                .iter()
+                .cloned()
+                .chain(dir_files(&paths.camb_codesets)?)
+                .chain(dir_files(&termsets)?)
+                .chain(registrations.clone())
+                .collect(),
+            dc,
+            || report::Command::LateEffects,
+        ),
+        report_stage(
+            paths,
+            "consultations",
+            &["clean"],
+            report_inputs.iter().cloned().chain(registrations).collect(),
+            dc,
+            || report::Command::Consultations {
+                years: 2.,
+                dedup: None,
+            },
+        ),
+    ];
+
+    // check the stage list is in dependency order.
+    for (idx, stage) in stages.iter().enumerate() {
+        for dep in stage.deps {
+            ensure!(
+                stages[..idx].iter().any(|s| s.name == *dep),
+                "stage `{}` depends on `{}`, which doesn't come before it",
+                stage.name,
+                dep
+            );
+        }
+    }
+    Ok(stages)
+}
+
+/// A stage that runs a report.
+///
+/// As well as printing its tables, the report saves them to `reports/<name>.md` in the output
+/// directory (`reports/<name>_release.md` with disclosure control), so they can still be read
+/// when the stage is up to date and isn't rerun.
+fn report_stage(
+    paths: &DataPaths,
+    name: &'static str,
+    deps: &'static [&'static str],
+    inputs: Vec<PathBuf>,
+    dc: DisclosureControl,
+    cmd: fn() -> report::Command,
+) -> Stage {
+    let suffix = if dc == DisclosureControl::NONE {
+        ""
+    } else {
+        "_release"
+    };
+    let path = paths.output_path(format!("reports/{name}{suffix}.md"));
+    Stage {
+        name,
+        deps,
+        inputs,
+        outputs: vec![path.clone()],
+        run: Box::new(move |_| report::run_command_saving(cmd(), &dc, &path)),
+    }
+}
+
+/// The named stage and everything upstream of it.
+fn with_dependencies(stages: &[Stage], name: &str) -> Result<BTreeSet<&'static str>> {
+    let stage = stages
+        .iter()
+        .find(|stage| stage.name == name)
+        .with_context(|| {
+            format!(
+                "no stage `{}` (stages are {})",
+                name,
+                stages
+                    .iter()
+                    .map(|stage| stage.name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })?;
+    let mut out = BTreeSet::from([stage.name]);
+    for dep in stage.deps {
+        out.extend(with_dependencies(stages, dep)?);
+    }
+    Ok(out)
+}
+
+/// All files under `dir`, recursively.
+fn dir_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut out = vec![];
+    for entry in fs::read_dir(dir).with_context(|| format!("reading \"{}\"", dir.display()))? {
+        let path = entry?.path();
+        if path.is_dir() {
+            out.extend(dir_files(&path)?);
+        } else {
+            out.push(path);
+        }
+    }
+    out.sort();
+    Ok(out)
+}
+
+/// What we recorded the last time each stage ran.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    stages: BTreeMap<String, StageRecord>,
+    /// Cache of file hashes, so we don't rehash unchanged files.
+    files: BTreeMap<PathBuf, FileRecord>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StageRecord {
+    inputs: BTreeMap<PathBuf, String>,
+    outputs: BTreeMap<PathBuf, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FileRecord {
+    len: u64,
+    modified: u128,
+    sha256: String,
+}
+
+impl Manifest {
+    fn load(paths: &DataPaths) -> Result<Self> {
+        let path = paths.output_path(MANIFEST);
+        if !file_exists(&path)? {
+            return Ok(Self::default());
+        }
+        let text = fs::read_to_string(&path)?;
+        serde_json::from_str(&text).with_context(|| format!("reading \"{}\"", path.display()))
+    }
+
+    fn save(&self, paths: &DataPaths) -> Result {
+        let path = paths.output_path(MANIFEST);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("writing \"{}\"", path.display()))
+    }
+
+    /// Record the current state of a stage's inputs and outputs after running it.
+    fn record(&mut self, stage: &Stage) -> Result {
+        let mut record = StageRecord::default();
+        for (paths, hashes) in [
+            (&stage.inputs, &mut record.inputs),
+            (&stage.outputs, &mut record.outputs),
+        ] {
+            for path in paths.iter() {
+                let file = self
+                    .hash_file(path)?
+                    .with_context(|| format!("\"{}\" missing after stage ran", path.display()))?;
+                hashes.insert(path.clone(), file.sha256.clone());
+                self.files.insert(path.clone(), file);
+            }
+        }
+        self.stages.insert(stage.name.to_string(), record);
+        Ok(())
+    }
+
+    /// The hash of a file, or `None` if it doesn't exist.
+    fn hash(&self, path: &Path) -> Result<Option<String>> {
+        Ok(self.hash_file(path)?.map(|file| file.sha256))
+    }
+
+    fn hash_file(&self, path: &Path) -> Result<Option<FileRecord>> {
+        let meta = match fs::metadata(path) {
+            Ok(meta) => meta,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let len = meta.len();
+        let modified = meta.modified()?.duration_since(UNIX_EPOCH)?.as_nanos();
+        if let Some(file) = self.files.get(path) {
+            if file.len == len && file.modified == modified {
+                return Ok(Some(file.clone()));
+            }
+        }
+        Ok(Some(FileRecord {
+            len,
+            modified,
+            sha256: content_sha256(path)?,
+        }))
+    }
+}
@@ -0,0 +1,176 @@
+use crate::Global;
+use clap::{Args, Subcommand};
+#[cfg(feature = "plot")]
+use eadapt_needs_analysis::plot::BarChart;
+use eadapt_needs_analysis::{
+    export, output_path,
+    render::{TableFormat, TextTable},
+    DedupPolicy, DisclosureControl,
+};
+use qu::ick_use::*;
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+mod adherence;
+mod consultations;
+mod data_quality;
+mod demographics;
+mod late_effects;
+mod ltc;
+
+#[derive(Args)]
+pub struct Opt {
+    /// Suppress small counts and round the rest, as required for outputs we release.
+    #[clap(long)]
+    pub release: bool,
+    /// How to write the report tables: `term`, `markdown` or `latex`.
+    #[clap(long, default_value_t)]
+    pub format: TableFormat,
+    /// Also save each table as CSV in the output directory, for supplementary files.
+    #[clap(long)]
+    pub csv: bool,
+    /// Save charts of the main distributions as SVG in the output directory.
+    #[cfg(feature = "plot")]
+    #[clap(long)]
+    pub plots: bool,
+    #[clap(subcommand)]
+    pub cmd: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Demographics of the cleaned dataset.
+    Demographics,
+    /// Prevalence of long-term conditions compared with the general population.
+    Ltc {
+        /// A CSV file of Read code prefixes and cancer sites, to use as well as the built-in ones
+        /// when grouping other cancers by site.
+        #[clap(long)]
+        cancer_sites: Option<PathBuf>,
+    },
+    /// Adherence to the late effects monitoring plan (LEMP).
+    Adherence {
+        /// A TOML file of surveillance rules to use instead of the built-in ones.
+        #[clap(long)]
+        rules: Option<PathBuf>,
+        /// How many years before and after ADAPT to compare test rates over.
+        #[clap(long, default_value_t = 3.)]
+        before_after_years: f64,
+        /// Remove duplicate events first: `exact`, `same-day-value` or `same-day-code`.
+        #[clap(long)]
+        dedup: Option<DedupPolicy>,
+    },
+    /// Profile of every field and of event dates, to spot bad data.
+    DataQuality,
+    /// Incidence of late effects after treatment, by treatment received.
+    LateEffects,
+    /// GP consultation rates before and after lymphoma diagnosis.
+    Consultations {
+        /// How many years before and after diagnosis to compare consultation rates over.
+        #[clap(long, default_value_t = 2.)]
+        years: f64,
+        /// Remove duplicate events first: `exact`, `same-day-value` or `same-day-code`.
+        #[clap(long)]
+        dedup: Option<DedupPolicy>,
+    },
+    /// The summary, demographics, LTC and adherence tables as sheets of one Excel workbook.
+    Xlsx {
+        /// Where to save the workbook (default `eadapt_results.xlsx` in the output directory).
+        path: Option<PathBuf>,
+    },
+}
+
+/// How to show the report tables.
+#[derive(Debug, Default)]
+pub struct TableOutput {
+    pub format: TableFormat,
+    /// Save each table as CSV in the output directory.
+    pub csv: bool,
+    /// Save charts as SVG in the output directory.
+    #[cfg(feature = "plot")]
+    pub plots: bool,
+    /// Also add each table, as markdown, to the end of this file.
+    pub save_to: Option<PathBuf>,
+}
+
+impl TableOutput {
+    /// Print `table`, and save it as `<name>.csv` if we are saving CSVs.
+    pub fn show(&self, name: &str, table: TextTable) -> Result {
+        if self.csv {
+            let path = output_path(Path::new(&format!("{}.csv", name)));
+            table.write_csv(&path)?;
+            println!("(saved as \"{}\")", path.display());
+        }
+        if let Some(path) = &self.save_to {
+            let mut file = fs::OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(path)
+                .with_context(|| format!("opening \"{}\"", path.display()))?;
+            writeln!(file, "## {}\n\n{}", name, table.to_markdown())
+                .with_context(|| format!("writing \"{}\"", path.display()))?;
+        }
+        println!("{}", table.render(self.format));
+        Ok(())
+    }
+
+    /// Save the chart as `<name>.svg` if we are saving charts.
+    #[cfg(feature = "plot")]
+    pub fn chart(&self, name: &str, chart: impl FnOnce() -> BarChart) -> Result {
+        if self.plots {
+            let path = output_path(Path::new(&format!("{}.svg", name)));
+            chart().save(&path)?;
+            println!("(chart saved as \"{}\")", path.display());
+        }
+        Ok(())
+    }
+}
+
+pub fn run(opt: Opt, _global: &Global) -> Result {
+    let out = TableOutput {
+        format: opt.format,
+        csv: opt.csv,
+        #[cfg(feature = "plot")]
+        plots: opt.plots,
+        save_to: None,
+    };
+    run_command_as(opt.cmd, &DisclosureControl::new(opt.release), &out)
+}
+
+/// Run a report, also saving its tables to `path` as markdown (replacing what was there).
+pub fn run_command_saving(cmd: Command, dc: &DisclosureControl, path: &Path) -> Result {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("creating \"{}\"", parent.display()))?;
+    }
+    fs::write(path, "").with_context(|| format!("creating \"{}\"", path.display()))?;
+    let out = TableOutput {
+        save_to: Some(path.to_owned()),
+        ..TableOutput::default()
+    };
+    run_command_as(cmd, dc, &out)
+}
+
+/// Run a report, showing its tables as `out` says.
+pub fn run_command_as(cmd: Command, dc: &DisclosureControl, out: &TableOutput) -> Result {
+    match cmd {
+        Command::Demographics => demographics::run(dc, out),
+        Command::Ltc { cancer_sites } => ltc::run(cancer_sites.as_deref(), dc, out),
+        Command::Adherence {
+            rules,
+            before_after_years,
+            dedup,
+        } => adherence::run(rules.as_deref(), before_after_years, dedup, dc, out),
+        Command::DataQuality => data_quality::run(dc, out),
+        Command::LateEffects => late_effects::run(dc, out),
+        Command::Consultations { years, dedup } => consultations::run(years, dedup, dc, out),
+        Command::Xlsx { path } => {
+            let path = path.unwrap_or_else(|| output_path(Path::new("eadapt_results.xlsx")));
+            export::to_xlsx(&path, dc)?;
+            println!("Workbook written to \"{}\"", path.display());
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use super::TableOutput;
+use eadapt_needs_analysis::{
+    lemp::{self, AdherenceOutcome, LempData, SurveillanceRules},
+    measurements::BpThreshold,
+    output_path, Adapts, DedupPolicy, DisclosureControl, Events, Patients, Registrations,
+};
+use qu::ick_use::*;
+use std::path::Path;
+use term_data_table::Table;
+
+// Tests that we can check using Read code EHR. Start looking when person was 'ADAPTed'.
+// Report mean/sd of frequency (measurements per year) and mean/sd of longest gap (years).
+//
+// The tests, and who should have them, are in the LEMP rules (see `lemp/rules.toml` in the
+// library).
+
+pub fn run(
+    rules: Option<&Path>,
+    before_after_years: f64,
+    dedup: Option<DedupPolicy>,
+    dc: &DisclosureControl,
+    out: &TableOutput,
+) -> Result {
+    let rules = match rules {
+        Some(path) => SurveillanceRules::load(path)?,
+        None => SurveillanceRules::builtin(),
+    };
+    let patients = Patients::load("patients_clean.bin")?;
+    let mut events = Events::load("events_clean.bin")?;
+    if let Some(policy) = dedup {
+        let removed = events.dedup(policy);
+        println!("Removed {removed} duplicate events ({policy})");
+    }
+    let adapt = Adapts::load("adapt.bin")?;
+
+    // Patient-level rows can never be released.
+    if *dc == DisclosureControl::NONE {
+        println!("{}", Table::from_serde(patients.iter_ref().take(10))?);
+    }
+
+    let registrations = Registrations::load_if_present("registrations.bin")?;
+    let lemp_data = LempData::new(patients, adapt, events, registrations);
+
+    let mut tidy_stats = vec![];
+    for rule in rules.iter() {
+        let stats = lemp_data.rule_stats(rule)?;
+        tidy_stats.extend(stats.to_tidy(&rule.name, dc));
+        println!("\n{} Stats", rule.name);
+        out.show(
+            &format!("adherence_{}_stats", rule.name.to_lowercase()),
+            stats.text_table(dc),
+        )?;
+    }
+    let path = output_path(Path::new("lemp_stats_tidy.csv"));
+    lemp::save_tidy_stats(&tidy_stats, &path)?;
+    println!("\nStats in long format written to \"{}\"", path.display());
+
+    for rule in rules.iter() {
+        let before_after = lemp_data.before_after(rule, before_after_years)?;
+        println!(
+            "\n{} before and after ADAPT ({} years either side)",
+            rule.name, before_after_years
+        );
+        out.show(
+            &format!("adherence_{}_before_after", rule.name.to_lowercase()),
+            before_after.text_table(dc),
+        )?;
+    }
+
+    // Adjusted estimates for the write-up. A rule with too few eligible patients can't be
+    // modelled, which shouldn't stop the rest of the report.
+    for rule in rules.iter() {
+        for outcome in AdherenceOutcome::ALL {
+            println!("\n{} {}, adjusted", rule.name, outcome);
+            match lemp_data.adherence_model(rule, outcome) {
+                Ok(model) => out.show(
+                    &format!(
+                        "adherence_{}_{}_model",
+                        rule.name.to_lowercase(),
+                        outcome.label().replace(' ', "_")
+                    ),
+                    model.text_table(),
+                )?,
+                Err(e) => println!("could not fit model: {:#}", e),
+            }
+        }
+    }
+
+    for rule in rules.iter() {
+        println!("\n{} adherence by IMD decile", rule.name);
+        out.show(
+            &format!("adherence_{}_imd", rule.name.to_lowercase()),
+            lemp_data.adherence_by_imd(rule)?.text_table(dc),
+        )?;
+    }
+
+    if let Some(bp_rule) = rules.get("BP") {
+        let bp_control = lemp_data.bp_control(bp_rule, BpThreshold::CLINIC)?;
+        println!("\nBP control (latest reading at least 140/90)");
+        out.show("adherence_bp_control", bp_control.text_table(dc))?;
+    }
+
+    // So the clinical team can see who is overdue for which tests. Patient-level, so never
+    // released.
+    if *dc == DisclosureControl::NONE {
+        let path = output_path(Path::new("lemp_patient_adherence.csv"));
+        lemp::save_patient_adherence(&lemp_data.patient_adherence(&rules)?, &path)?;
+        println!("\nPer-patient adherence written to \"{}\"", path.display());
+    }
+
+    Ok(())
+}
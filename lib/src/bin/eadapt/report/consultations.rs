@@ -0,0 +1,29 @@
+use super::TableOutput;
+use eadapt_needs_analysis::{
+    consultations::ConsultationRates, DedupPolicy, DisclosureControl, Events, Patients,
+    Registrations,
+};
+use qu::ick_use::*;
+
+// GP consultations per year before and after lymphoma diagnosis, as a measure of health service
+// use. See the library's `consultations` module for what counts as a consultation.
+
+pub fn run(
+    years: f64,
+    dedup: Option<DedupPolicy>,
+    dc: &DisclosureControl,
+    out: &TableOutput,
+) -> Result {
+    let patients = Patients::load("patients_clean.bin")?;
+    let mut events = Events::load("events_clean.bin")?;
+    if let Some(policy) = dedup {
+        let removed = events.dedup(policy);
+        println!("Removed {removed} duplicate events ({policy})");
+    }
+    let registrations = Registrations::load_if_present("registrations.bin")?;
+
+    let rates = ConsultationRates::new(&patients, &events, &registrations, years);
+    println!("\nGP consultations before and after lymphoma diagnosis ({years} years either side)");
+    out.show("consultations", rates.text_table(dc))?;
+    Ok(())
+}
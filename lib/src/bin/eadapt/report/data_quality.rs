@@ -0,0 +1,89 @@
+use super::TableOutput;
+use chrono::Datelike;
+use eadapt_needs_analysis::{
+    adapt_timing::ProcessTiming, header, quality::QualityReport, read2::Thesaurus,
+    render::TextTable, Adapts, Counts, DisclosureControl, Events, Patients, RangeSet,
+};
+
+use qu::ick_use::*;
+
+pub fn run(dc: &DisclosureControl, out: &TableOutput) -> Result {
+    let patients = Patients::load("patients_clean.bin")?;
+    let events = Events::load("events_clean.bin")?;
+    let events_len = events.len();
+    let adapt = Adapts::load("adapt.bin")?;
+    //let thesaurus = Thesaurus::load("../../readbrowser")?;
+    //let codes_subtypes_map = CodeSubtypeMap::load("code_subtype_map.bin")?;
+    //let lymphoma_codeset = CodeSet::load("lymphoma_codes_clean.toml")?;
+
+    header("Fields");
+    QualityReport::new(&patients, &events, &adapt)?.display();
+
+    header("Event dates");
+    let mut table = TextTable::new(["Years", "Count", "Percentage"]);
+    // Decades from 1900, then 2020 on.
+    let year_buckets = RangeSet::equal_width(1900, 2030, 13);
+    // missing dates are counted separately.
+    let years = events
+        .iter()
+        .map(|evt| evt.date_known().map(|date| date.year()));
+    let bucketed = year_buckets.bucket_values_with_missing(years);
+    for (label, count) in bucketed.for_display() {
+        table.push_row([
+            label.to_string(),
+            dc.count(count).to_string(),
+            percentage(dc, count, events_len),
+        ]);
+    }
+    out.show("data_quality_event_dates", table)?;
+
+    // Shows how much of the record came from outside primary care.
+    header("Event sources");
+    let sources = events.source_counts();
+    out.show(
+        "data_quality_event_sources",
+        sources.text_table("Source", events_len, dc),
+    )?;
+
+    // Shows how much of the record is administrative rather than clinical.
+    header("Read chapters");
+    let chapters = events.chapter_summary(&Thesaurus::shared()?);
+    out.show("data_quality_chapter_groups", chapters.group_text_table(dc))?;
+    out.show("data_quality_chapters", chapters.text_table(dc))?;
+
+    header("Lymphoma diagnosis date confidence");
+    let mut table = TextTable::new(["Confidence", "Patients", "Percentage"]);
+    for (confidence, count) in patients.count_diagnosis_confidence().into_iter().rev() {
+        table.push_row([
+            match confidence {
+                Some(confidence) => confidence.label(),
+                None => "No diagnosis date",
+            }
+            .to_string(),
+            dc.count(count).to_string(),
+            percentage(dc, count, patients.len()),
+        ]);
+    }
+    out.show("data_quality_diagnosis_confidence", table)?;
+
+    header("ADAPT form timing");
+    let timing = ProcessTiming::new(&adapt);
+    out.show("data_quality_adapt_timing", timing.text_table(dc))?;
+    // Patient-level, so never released.
+    if *dc == DisclosureControl::NONE {
+        for record in timing.misordered.iter() {
+            println!(
+                "patient {}: {} runs from {} to {}",
+                record.patient_id, record.step, record.start, record.end
+            );
+        }
+    }
+    Ok(())
+}
+
+fn percentage(dc: &DisclosureControl, count: usize, total: usize) -> String {
+    match dc.percentage(count, total) {
+        Some(pc) => format!("{pc:.1}%"),
+        None => "-".into(),
+    }
+}
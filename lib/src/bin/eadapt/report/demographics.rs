@@ -0,0 +1,212 @@
+use super::TableOutput;
+use chrono::{Datelike, NaiveDate};
+use eadapt_needs_analysis::{
+    date_of_extract,
+    ethnicity::EthnicGroup,
+    header,
+    lifestyle::SmokingAgreement,
+    measurements::body::{self, BmiCategory},
+    read2::{TermCodeSet, Thesaurus},
+    render::TextTable,
+    subtypes::{CodeSubtypeMap, SubtypesByPeriod},
+    Adapts, CodeRubricCounts, Counts, DisclosureControl, Events, GroupCounts, Imd, Patient,
+    Patients, Range, RangeSet, ReferenceDate,
+};
+use qu::ick_use::*;
+use std::collections::BTreeSet;
+
+pub fn run(dc: &DisclosureControl, out: &TableOutput) -> Result {
+    let patients = Patients::load("patients_clean.bin")?;
+    let events = Events::load("events_clean.bin")?;
+    let adapt = Adapts::load("adapt.bin")?;
+    let thesaurus = Thesaurus::shared()?;
+    let codes_subtypes_map = CodeSubtypeMap::load("code_subtype_map.bin")?;
+    let lymphoma_codeset = TermCodeSet::load("lymphoma_clean", thesaurus.clone())?;
+
+    // Build a map from code/rubric pairs to patient IDs.
+    let _code_rubrics = CodeRubricCounts::from_events(&events, &thesaurus);
+
+    header("Data stats");
+    let patients_len = patients.len();
+    println!("total patients: {}", dc.count(patients_len));
+    println!("total events: {}", dc.count(events.len()));
+    println!("total patient adapt info: {}", dc.count(adapt.len()));
+    if let Some(date) = events.iter().map(|evt| evt.date).max() {
+        println!("latest event date: {}", date);
+    }
+    if let Some(date) = events.iter().filter_map(|evt| evt.date_known()).min() {
+        println!("earliest event date: {}", date);
+    }
+
+    header("Sexes");
+    out.show(
+        "demographics_sexes",
+        TextTable::counts("Sex", patients.count_sexes(), patients_len, dc),
+    )?;
+
+    header("Ages");
+    let age_buckets = RangeSet::new(vec![
+        Range::new(0, Some(18)),
+        Range::new(18, Some(35)),
+        Range::new(35, Some(50)),
+        Range::new(50, Some(65)),
+        Range::new(65, Some(80)),
+        Range::new(80, None),
+    ]);
+    let ages = patients.bucket_ages(&age_buckets, ReferenceDate::default());
+    out.show(
+        "demographics_ages",
+        ages.text_table("Age range", patients_len, dc),
+    )?;
+    #[cfg(feature = "plot")]
+    out.chart("demographics_ages", || ages.bar_chart("Ages", dc))?;
+
+    header("Ethnicity");
+    let ethnic_groups = GroupCounts::from(patients.count_ethnic_groups(&events));
+    out.show(
+        "demographics_ethnicity",
+        ethnic_groups.text_table_by("Ethnic group", patients_len, dc, |group| {
+            group
+                .map(EthnicGroup::label)
+                .unwrap_or("missing data")
+                .into()
+        }),
+    )?;
+
+    header("BMI");
+    println!("Latest BMI at the date of extract\n");
+    let bmis = body::bmi_at(&events, date_of_extract());
+    let mut table = TextTable::new(["BMI", "Count", "Percentage"]);
+    let bmi_counts = patients.group_count(|pat| {
+        bmis.get(&pat.patient_id)
+            .map(|bmi| BmiCategory::from_bmi(*bmi))
+    });
+    for category in BmiCategory::ALL {
+        let count = bmi_counts.get(&Some(category));
+        table.push_row(count_row(category.label(), count, patients_len, dc));
+    }
+    let missing = bmi_counts.get(&None);
+    table.push_row(count_row("missing data", missing, patients_len, dc));
+    out.show("demographics_bmi", table)?;
+
+    header("Smoking");
+    println!("GP record smoking status at last ADAPT review, compared with the ADAPT form\n");
+    let agreement = SmokingAgreement::new(&events, &adapt);
+    out.show("demographics_smoking", agreement.text_table(dc))?;
+    let (agreed, known) = agreement.agreed_of_known();
+    println!(
+        "agreement where the GP record has a status: {}",
+        dc.count_with_percentage(agreed, known)
+    );
+
+    header("Age at diagnosis");
+    let lymphoma_events = events.filter_by_codeset(&lymphoma_codeset.code_set);
+    let ages_at_diagnosis = patients.iter().map(|pat| {
+        lymphoma_events
+            .earliest_event_for_patient(pat.patient_id)
+            .and_then(|d| pat.plausible_age_at(d))
+    });
+    out.show(
+        "demographics_age_at_diagnosis",
+        age_buckets
+            .bucket_values_with_missing(ages_at_diagnosis)
+            .text_table("Age range", patients_len, dc),
+    )?;
+
+    header("Date of diagnosis");
+    // Decades from 1900, then 2020 on.
+    let year_buckets = RangeSet::equal_width(1900, 2030, 13);
+    let diagnosis_years = patients.iter().map(|pat| {
+        lymphoma_events
+            .earliest_event_for_patient(pat.patient_id)
+            .map(|date| date.year())
+    });
+    out.show(
+        "demographics_date_of_diagnosis",
+        year_buckets
+            .clone()
+            .bucket_values_with_missing(diagnosis_years)
+            .text_table("Years", patients_len, dc),
+    )?;
+
+    header("IMD");
+    let mut table = TextTable::new(["IMD range", "Count", "Percentage"]);
+    let quintile_counts = patients.count_imd_quintiles();
+    // Missing last.
+    for quintile in (1..=5).map(Some).chain([None]) {
+        table.push_row(count_row(
+            Imd::quintile_label(quintile),
+            quintile_counts[&quintile],
+            patients_len,
+            dc,
+        ));
+    }
+    out.show("demographics_imd", table)?;
+    #[cfg(feature = "plot")]
+    out.chart("demographics_imd", || patients.imd_chart(dc))?;
+
+    header("Lymphoma subtypes");
+    let subtype_counts = GroupCounts::from_keys(
+        patients
+            .iter_ref()
+            .filter_map(Patient::lymphoma_diagnosis_subtype),
+    );
+    out.show(
+        "demographics_subtypes",
+        subtype_counts.text_table_by("Subtype", patients_len, dc, |subtype| {
+            subtype.label().to_string()
+        }),
+    )?;
+
+    header("Lymphoma subtypes by date of diagnosis");
+    println!("Percentages are of the patients diagnosed in each period\n");
+    let date_buckets =
+        year_buckets.map(|year| NaiveDate::from_ymd_opt(year, 1, 1).expect("valid year"));
+    let subtypes_by_period = SubtypesByPeriod::new(&patients, &date_buckets);
+    out.show(
+        "demographics_subtypes_by_period",
+        subtypes_by_period.text_table(dc),
+    )?;
+
+    header("Multiple subtypes");
+    println!("Displays patients who have codes for more than 1 different lymphoma subtype\n");
+    let subtype_ids = codes_subtypes_map.classify(&events);
+    let multiple_subtype_ids = codes_subtypes_map.find_multiple(&subtype_ids);
+    println!(
+        "total number of patients with multiple subtype diagnoses: {}",
+        dc.count(
+            multiple_subtype_ids
+                .values()
+                .flat_map(|ids| ids.iter())
+                .collect::<BTreeSet<_>>()
+                .len()
+        )
+    );
+    let mut table = TextTable::new(["Subtype 1", "Subtype 2", "Count"]);
+    let multiple_subtype_ids = codes_subtypes_map.find_multiple(&subtype_ids);
+    for ((subtype1, subtype2), set) in multiple_subtype_ids.iter() {
+        let len = set.len();
+        table.push_row([
+            subtype1.label().to_string(),
+            subtype2.label().to_string(),
+            dc.count(len).to_string(),
+        ]);
+    }
+    out.show("demographics_multiple_subtypes", table)?;
+
+    Ok(())
+}
+
+/// A row of a table with a label, count, and percentage, with `dc` applied.
+fn count_row(
+    label: impl Into<String>,
+    count: usize,
+    total: usize,
+    dc: &DisclosureControl,
+) -> [String; 3] {
+    let percentage = dc
+        .percentage(count, total)
+        .map(|pc| format!("{:.1}%", pc))
+        .unwrap_or_default();
+    [label.into(), dc.count(count).to_string(), percentage]
+}
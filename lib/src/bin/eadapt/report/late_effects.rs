@@ -0,0 +1,63 @@
+use super::TableOutput;
+use eadapt_needs_analysis::{
+    late_effects::{
+        cardiotoxicity::Cardiotoxicity, secondary_malignancy::SecondaryMalignancies,
+        IncidenceReport, LateEffect, LateEffectCodes,
+    },
+    output_path, read2, Adapts, DisclosureControl, Events, Patients, Registrations,
+};
+use qu::ick_use::*;
+use std::path::Path;
+
+// Incidence of each late effect after the end of treatment, comparing patients who had the
+// treatments that put them at risk with those who didn't.
+
+pub fn run(dc: &DisclosureControl, out: &TableOutput) -> Result {
+    let patients = Patients::load("patients_clean.bin")?;
+    let events = Events::load("events_clean.bin")?;
+    let adapt = Adapts::load("adapt.bin")?;
+    let registrations = Registrations::load_if_present("registrations.bin")?;
+    let codes = LateEffectCodes::load()?;
+
+    let report = IncidenceReport::new(&codes, &patients, &adapt, &events, &registrations);
+    println!("\nLate effects after the end of treatment (95% CI)");
+    out.show("late_effects_incidence", report.text_table(dc))?;
+
+    let cardiotoxicity = Cardiotoxicity::new(
+        codes.get(LateEffect::HeartFailure),
+        &patients,
+        &adapt,
+        &events,
+        &registrations,
+    );
+    println!("\nHeart failure / cardiomyopathy after anthracyclines or heart radiotherapy");
+    out.show("late_effects_cardiotoxicity", cardiotoxicity.text_table(dc))?;
+
+    // New cancers are counted from lymphoma diagnosis rather than the end of treatment, so we
+    // include patients who weren't ADAPTed.
+    let thesaurus = read2::Thesaurus::shared()?;
+    let diagnosis_dates = read2::TermCodeSet::load("lymphoma_clean", thesaurus)?
+        .code_set
+        .into_matcher()
+        .earliest_code(&events);
+    let secondary = SecondaryMalignancies::new(
+        &patients,
+        &events,
+        codes.get(LateEffect::SecondaryMalignancy),
+        &diagnosis_dates,
+        &registrations,
+    );
+    println!("\nNew cancers after lymphoma diagnosis, by site");
+    out.show(
+        "late_effects_secondary_malignancy",
+        secondary.text_table(dc),
+    )?;
+
+    // Patient-level, so never released.
+    if *dc == DisclosureControl::NONE {
+        let path = output_path(Path::new("secondary_malignancies.csv"));
+        secondary.save_cancers(&path)?;
+        println!("\nNew cancers written to \"{}\"", path.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use super::TableOutput;
+use eadapt_needs_analysis::{
+    late_effects::secondary_malignancy::SiteMapping, ltcs, output_path, read2, DisclosureControl,
+    Events, Patients, Range, RangeSet,
+};
+use qu::ick_use::*;
+use std::path::Path;
+
+pub fn run(cancer_sites: Option<&Path>, dc: &DisclosureControl, out: &TableOutput) -> Result {
+    let patients = Patients::load("patients_clean.bin")?;
+    let events = Events::load("events_clean.bin")?;
+    let conditions = ltcs::Conditions::load()?;
+    let thesaurus = read2::Thesaurus::shared()?;
+    let lymphoma_codeset = read2::TermCodeSet::load("lymphoma_clean", thesaurus.clone())?;
+
+    let diagnosis_dates = lymphoma_codeset
+        .code_set
+        .into_matcher()
+        .earliest_code(&events);
+
+    let report = conditions.report(&patients, &events, &diagnosis_dates);
+    out.show("ltc_prevalence", report.text_table(dc))?;
+    #[cfg(feature = "plot")]
+    out.chart("ltc_prevalence", || report.prevalence_chart(dc))?;
+    let path = output_path(Path::new("ltc_prevalence_tidy.csv"));
+    report.save_tidy_csv(&path, dc)?;
+    println!(
+        "Prevalence in long format written to \"{}\"",
+        path.display()
+    );
+
+    // Our cohort is older than the general population, so also compare like with like.
+    let age_bands = RangeSet::new(vec![
+        Range::new(0, Some(40)),
+        Range::new(40, Some(60)),
+        Range::new(60, Some(80)),
+        Range::new(80, None),
+    ]);
+    println!("\nPrevalence by sex and age band at diagnosis");
+    out.show(
+        "ltc_prevalence_stratified",
+        conditions
+            .report_stratified(&patients, &events, &diagnosis_dates, &age_bands)
+            .text_table(dc),
+    )?;
+
+    // TODO just make sure that my quantile function is accurate, then copy table into write-up &
+    // send to Niels, then WRITE WRITE WRITE.
+    out.show(
+        "ltc_significance",
+        report.test_significance(0.05, 10, true).text_table(),
+    )?;
+
+    // Which conditions cluster together at diagnosis. The CSV has every pair, for heat-mapping.
+    let cooccurrence = report.cooccurrence_matrix();
+    println!("\nConditions occurring together at diagnosis");
+    println!("{}", cooccurrence.term_table(dc).for_terminal());
+    let path = output_path(Path::new("ltc_cooccurrence.csv"));
+    cooccurrence.save_csv(&path, dc)?;
+    println!("\nCo-occurrence matrix written to \"{}\"", path.display());
+
+    // Other cancers, by site rather than as a list of codes.
+    let sites = match cancer_sites {
+        Some(path) => SiteMapping::load(path)?,
+        None => SiteMapping::builtin(),
+    };
+    println!("\nOther (non-lymphoma) cancers by site");
+    out.show(
+        "ltc_cancer_sites",
+        conditions
+            .cancer_sites(&patients, &events, &sites)
+            .text_table(dc),
+    )?;
+
+    Ok(())
+}
@@ -0,0 +1,279 @@
+use crate::Global;
+use calamine::{DataType, Reader, Xlsx};
+use clap::Subcommand;
+use eadapt_needs_analysis::{
+    file_exists, output_path, provenance,
+    read2::{CodeRubric, ReadCode, TermCodeSet, Thesaurus},
+    subtypes::{self, CodeSubtypeMap, LymphomaSubtype},
+    CodeRubricCounts, Events, PatientId,
+};
+use qu::ick_use::*;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, BufRead, Write},
+    path::Path,
+};
+
+/// Decisions from `eadapt subtypes review`, in the data directory.
+pub const REVIEW_FILE: &str = "code_subtype_review.csv";
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Import lymphoma subtypes mappings from an excel file.
+    Import,
+    /// Show the events that put patients in their subtypes.
+    ///
+    /// Without a patient ID, the events for every patient are written to
+    /// `subtype_evidence.csv`.
+    Evidence {
+        /// Only show this patient's events.
+        patient_id: Option<PatientId>,
+    },
+    /// Go through lymphoma code/rubric pairs in the data that aren't in the map, and choose a
+    /// subtype for each.
+    ///
+    /// Decisions are saved as they are made to `code_subtype_review.csv` in the data directory,
+    /// which `import` applies on top of the Excel mapping.
+    Review,
+}
+
+pub fn run(cmd: Command, global: &Global) -> Result {
+    match cmd {
+        Command::Import => import(global),
+        Command::Evidence { patient_id } => evidence(patient_id),
+        Command::Review => review(global),
+    }
+}
+
+fn evidence(patient_id: Option<PatientId>) -> Result {
+    let map = CodeSubtypeMap::load("code_subtype_map.bin")?;
+    let events = Events::load("events_clean.bin")?;
+    let evidence = map.classify_with_evidence(&events);
+    match patient_id {
+        Some(patient_id) => {
+            let subtypes = evidence
+                .get(&patient_id)
+                .with_context(|| format!("patient {patient_id} has no lymphoma subtype"))?;
+            println!("{}", subtypes.term_table());
+        }
+        None => {
+            let path = output_path(Path::new("subtype_evidence.csv"));
+            subtypes::save_evidence(&evidence, &path)?;
+            println!("Subtype evidence written to \"{}\"", path.display());
+        }
+    }
+    Ok(())
+}
+
+fn import(global: &Global) -> Result {
+    global.check_output("code_subtype_map.bin")?;
+    let path = global.paths.root.join("code_subtype_mapping.xlsx");
+    let mut workbook: Xlsx<_> = calamine::open_workbook(&path)?;
+    provenance::record_input(&path);
+    let thesaurus = Thesaurus::shared()?;
+
+    // Mappings can be split over several sheets. Sheets without the headers we need are skipped.
+    let mut mappings = BTreeMap::new();
+    let mut errors = vec![];
+    let mut sheets_read = 0;
+    for (name, wksht) in workbook.worksheets() {
+        match read_sheet(&name, &wksht, &mut mappings, &mut errors) {
+            Some(rows) => {
+                println!("Read {rows} mappings from sheet `{name}`");
+                sheets_read += 1;
+            }
+            None => event!(Level::WARN, "skipping sheet `{name}`: no mapping headers"),
+        }
+    }
+    ensure!(
+        sheets_read > 0,
+        "no sheet in \"{}\" has `read code`, `rubric` and `subtype` headers",
+        path.display()
+    );
+    if !errors.is_empty() {
+        for error in errors.iter() {
+            eprintln!("{error}");
+        }
+        bail!(
+            "{} rows of \"{}\" couldn't be read",
+            errors.len(),
+            path.display()
+        );
+    }
+
+    let not_in_thesaurus: BTreeSet<_> = mappings
+        .keys()
+        .map(|cr: &CodeRubric| cr.code)
+        .filter(|code| thesaurus.get(*code).is_none())
+        .collect();
+    for code in not_in_thesaurus.iter() {
+        event!(Level::WARN, "mapped code {code} isn't in the thesaurus");
+    }
+
+    let mut map = CodeSubtypeMap::from(
+        mappings
+            .into_iter()
+            .map(|(cr, (subtype, _))| (cr, subtype))
+            .collect::<BTreeMap<_, _>>(),
+    );
+
+    let review_path = global.paths.root.join(REVIEW_FILE);
+    if file_exists(&review_path)? {
+        provenance::record_input(&review_path);
+        let applied = map.apply_review(&review_path)?;
+        println!(
+            "Applied {applied} decisions from \"{}\"",
+            review_path.display()
+        );
+    }
+
+    println!("{}", map.term_table());
+
+    map.save("code_subtype_map.bin")?;
+    Ok(())
+}
+
+fn review(global: &Global) -> Result {
+    let mut map = CodeSubtypeMap::load("code_subtype_map.bin")?;
+    let events = Events::load("events.bin")?;
+    let thesaurus = Thesaurus::shared()?;
+    let lymphoma = TermCodeSet::load("lymphoma", thesaurus.clone())?;
+    let counts = CodeRubricCounts::from_events(&events, &thesaurus);
+    let unmapped = map.unmapped(&counts.filter_by_codeset(&lymphoma.code_set));
+    let review_path = global.paths.root.join(REVIEW_FILE);
+
+    println!("{} unmapped code/rubric pairs", unmapped.len());
+    println!("Enter a subtype code, nothing to skip, `?` to list subtypes, or `q` to stop.");
+    let mut lines = io::stdin().lock().lines();
+    let mut decided = 0;
+    'pairs: for (idx, cr) in unmapped.iter().enumerate() {
+        println!(
+            "\n[{}/{}] {} \"{}\" ({} patients, {} events)",
+            idx + 1,
+            unmapped.len(),
+            cr.code_rubric.code,
+            cr.code_rubric.rubric,
+            cr.num_patients(),
+            cr.event_count
+        );
+        for description in cr.description.iter() {
+            println!("    {description}");
+        }
+        let subtype = loop {
+            print!("subtype> ");
+            io::stdout().flush()?;
+            let Some(line) = lines.next() else {
+                break 'pairs;
+            };
+            match line?.trim() {
+                "" => continue 'pairs,
+                "q" => break 'pairs,
+                "?" => {
+                    for subtype in LymphomaSubtype::all() {
+                        println!("    {:<20} {}", subtype.code(), subtype.label());
+                    }
+                }
+                input => match input.parse::<LymphomaSubtype>() {
+                    Ok(subtype) => break subtype,
+                    Err(e) => println!("{e}"),
+                },
+            }
+        };
+        subtypes::append_review_decision(&review_path, &cr.code_rubric, subtype)?;
+        map.insert(cr.code_rubric.clone(), subtype);
+        decided += 1;
+    }
+
+    println!(
+        "\n{decided} decisions saved to \"{}\"",
+        review_path.display()
+    );
+    if decided > 0 {
+        map.save("code_subtype_map.bin")?;
+    }
+    Ok(())
+}
+
+/// The columns we need, found from a sheet's headers.
+struct Columns {
+    read_code: usize,
+    rubric: usize,
+    subtype: usize,
+}
+
+impl Columns {
+    /// Find the columns from a header row, ignoring case and surrounding whitespace.
+    fn from_headers(headers: &[DataType]) -> Option<Self> {
+        let find = |names: &[&str]| {
+            headers.iter().position(|cell| {
+                cell.get_string()
+                    .map(|text| names.contains(&text.trim().to_lowercase().as_str()))
+                    .unwrap_or(false)
+            })
+        };
+        Some(Columns {
+            read_code: find(&["read code", "read_code", "code", "readcode"])?,
+            rubric: find(&["rubric", "free text", "text"])?,
+            subtype: find(&["subtype", "lymphoma subtype", "subtype code"])?,
+        })
+    }
+
+    fn read_row(&self, row: &[DataType]) -> Result<(CodeRubric, LymphomaSubtype)> {
+        let cell = |col: usize| -> Result<&str> {
+            let cell = row.get(col).unwrap_or(&DataType::Empty);
+            Ok(cell
+                .get_string()
+                .with_context(|| format!("column {} (`{cell}`) isn't text", col + 1))?
+                .trim())
+        };
+        let code = ReadCode::try_from(cell(self.read_code)?)?;
+        let subtype: LymphomaSubtype = cell(self.subtype)?.parse()?;
+        Ok((CodeRubric::new(code, cell(self.rubric)?), subtype))
+    }
+}
+
+/// Read the mappings from one sheet into `mappings`, along with where they came from.
+///
+/// The first non-empty row must be the headers. Rows that can't be read, or that map a
+/// code/rubric pair that is already mapped to a different subtype, are added to `errors`. Returns
+/// the number of rows read, or `None` if the sheet doesn't have the headers we need.
+fn read_sheet(
+    name: &str,
+    wksht: &calamine::Range<DataType>,
+    mappings: &mut BTreeMap<CodeRubric, (LymphomaSubtype, String)>,
+    errors: &mut Vec<String>,
+) -> Option<usize> {
+    let first_row = wksht.start().map(|(row, _)| row as usize).unwrap_or(0);
+    let mut rows = wksht
+        .rows()
+        .enumerate()
+        .filter(|(_, row)| !row.iter().all(DataType::is_empty));
+    let (_, headers) = rows.next()?;
+    let columns = Columns::from_headers(headers)?;
+    let mut read = 0;
+    for (idx, row) in rows {
+        // Excel rows are 1-based.
+        let location = format!("{name}!{}", first_row + idx + 1);
+        let mapping = columns.read_row(row);
+        let (code_rubric, subtype) = match mapping {
+            Ok(mapping) => mapping,
+            Err(e) => {
+                errors.push(format!("{location}: {e:#}"));
+                continue;
+            }
+        };
+        match mappings.get(&code_rubric) {
+            Some((existing, _)) if *existing == subtype => (),
+            Some((existing, existing_location)) => errors.push(format!(
+                "{location}: {} \"{}\" is mapped to {subtype}, but {existing_location} maps it \
+                    to {existing}",
+                code_rubric.code, code_rubric.rubric
+            )),
+            None => {
+                mappings.insert(code_rubric, (subtype, location));
+            }
+        }
+        read += 1;
+    }
+    Some(read)
+}
@@ -0,0 +1,107 @@
+use crate::Global;
+use clap::Subcommand;
+use eadapt_needs_analysis::{read2, termset_path, Events};
+use qu::ick_use::*;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Regenerate `codes.txt` for termsets from their include/exclude rules.
+    ///
+    /// By default all medication termsets are regenerated.
+    Regenerate {
+        /// Only regenerate the termset at this path.
+        path: Option<PathBuf>,
+        /// Check the codes against the termset and fix any differences, keeping codes that
+        /// aren't in the thesaurus.
+        #[clap(long)]
+        fix: bool,
+    },
+    /// Show how many codes and events each term of a termset matches.
+    ///
+    /// Terms that don't match any codes are usually typos.
+    Usage {
+        /// The name of the termset (its directory in the termsets directory).
+        name: String,
+    },
+}
+
+pub fn run(cmd: Command, _global: &Global) -> Result {
+    match cmd {
+        Command::Regenerate { path, fix } => regenerate(path, fix),
+        Command::Usage { name } => usage(&name),
+    }
+}
+
+fn regenerate(only: Option<PathBuf>, fix: bool) -> Result {
+    let th = read2::Thesaurus::shared()?;
+    for dir in fs::read_dir(termset_path(Path::new("")))? {
+        let dir = dir?;
+        let name = dir
+            .file_name()
+            .into_string()
+            .map_err(|_| format_err!("path not utf8"))?;
+        let dir_path = dir.path();
+        if let Some(path) = only.as_ref() {
+            if *path != dir_path {
+                continue;
+            }
+        } else {
+            if !name.ends_with("meds") {
+                // ignore
+                continue;
+            }
+        }
+        if fix {
+            fix_codes(&dir_path, &name, &th)?;
+        } else {
+            regenerate_codes(&dir_path, &name, &th)?;
+        }
+    }
+    Ok(())
+}
+
+fn regenerate_codes(path: &Path, name: &str, th: &read2::Thesaurus) -> Result {
+    let termset = read2::TermSet::load(path)?;
+    event!(Level::INFO, "Regenerating codes for termset \"{}\"", name);
+    event!(Level::INFO, "  calculating codes");
+    let full_set = termset.match_thesaurus(th.clone());
+    let out_path = path.join("codes.txt");
+    event!(Level::INFO, "  writing codes to \"{}\"", out_path.display());
+    full_set.code_set.save(&out_path, true)?;
+    Ok(())
+}
+
+fn fix_codes(path: &Path, name: &str, th: &read2::Thesaurus) -> Result {
+    event!(Level::INFO, "Fixing codes for termset \"{}\"", name);
+    let mut termset = read2::TermCodeSet::load_direct(path.to_owned(), th.clone())?;
+    let diff = termset.check().apply(&mut termset, path)?;
+    event!(Level::INFO, "  {}", diff);
+    for code in diff.added.iter() {
+        event!(Level::INFO, "  + {} {}", code, th.describe(code));
+    }
+    for code in diff.removed.iter() {
+        event!(Level::INFO, "  - {} {}", code, th.describe(code));
+    }
+    Ok(())
+}
+
+fn usage(name: &str) -> Result {
+    let th = read2::Thesaurus::shared()?;
+    let termset = read2::TermCodeSet::load(name, th)?;
+    let events = Events::load("events_clean.bin")?;
+    let report = termset.term_usage(&events);
+    println!("{}", report.term_table().for_terminal());
+    for usage in report.dead_terms() {
+        event!(
+            Level::WARN,
+            "{} term \"{}\" doesn't match any codes",
+            usage.kind,
+            usage.term
+        );
+    }
+    Ok(())
+}
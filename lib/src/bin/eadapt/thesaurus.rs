@@ -0,0 +1,363 @@
+use crate::Global;
+use clap::{Args, Subcommand};
+use eadapt_needs_analysis::{
+    provenance, read2,
+    read2::{CodeStatus, ReadCode, TermId},
+};
+use qu::ick_use::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+/// The name of the keyword/description file in a TRUD Read v2 release.
+const TRUD_KEYWORD_FILE: &str = "keyv2.all";
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Search the thesaurus for codes, and build termsets.
+    Search(Search),
+    /// Import the Read v2 thesaurus from the readbrowser files.
+    Import(Import),
+}
+
+pub fn run(cmd: Command, global: &Global) -> Result {
+    match cmd {
+        Command::Search(opt) => search(opt, global),
+        Command::Import(opt) => import(opt, global),
+    }
+}
+
+#[derive(Args, Default)]
+pub struct Import {
+    /// Build the thesaurus from Read v2 release zips downloaded from NHS TRUD, instead of the
+    /// extracted readbrowser files. Pass both the main and the drug releases.
+    #[clap(long)]
+    trud: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct Search {
+    /// Include codes where the description matches this regex
+    #[clap(short, long)]
+    include: Vec<String>,
+    /// Exclude codes where the description matches this regex
+    #[clap(short, long)]
+    exclude: Vec<String>,
+    /// A pre-existing term set to use
+    #[clap(short, long)]
+    term_set_path: Option<PathBuf>,
+    /// The Read code to search for.
+    #[clap(short, long)]
+    code: Option<read2::ReadCode>,
+    /// Save the outputted codeset to the given directory.
+    #[clap(short, long)]
+    name: Option<String>,
+    #[clap(short, long)]
+    email: Option<String>,
+    #[clap(long)]
+    save: Option<PathBuf>,
+    /// If set, output first words of descriptions of unmatched descenants
+    ///
+    /// This can be useful for copy/pasting into an include or exclude
+    #[clap(long)]
+    unmatched_first_words: bool,
+    /// If set, outputs the descriptions of descendant codes that didn't match
+    ///
+    /// Descriptions are 1-per-line in lexical order.
+    #[clap(long)]
+    unmatched_descriptions: bool,
+}
+
+enum Mode {
+    IncludeExclude,
+    Code,
+    TermSet,
+}
+
+fn search(opt: Search, global: &Global) -> Result {
+    let mut mode = None;
+    if !opt.include.is_empty() {
+        mode = Some(Mode::IncludeExclude);
+    }
+    if opt.code.is_some() {
+        if mode.is_some() {
+            bail!("please supply exactly one of --include, --code, --term-set");
+        }
+        mode = Some(Mode::Code);
+    }
+    if opt.term_set_path.is_some() {
+        if mode.is_some() {
+            bail!("please supply exactly one of --include, --code, --term-set");
+        }
+        mode = Some(Mode::TermSet);
+    }
+    let mode = if let Some(mode) = mode {
+        mode
+    } else {
+        bail!("please supply exactly one of --include, --code, --term-set");
+    };
+    let rt = read2::Thesaurus::shared()?;
+
+    let user = if let (Some(name), Some(email)) = (opt.name, opt.email) {
+        Some(read2::User {
+            name: name.into(),
+            email: email.into(),
+        })
+    } else {
+        None
+    };
+
+    if matches!(mode, Mode::Code) {
+        let code = opt.code.unwrap();
+        if let Some(descs) = rt.get(code) {
+            println!("Descriptions for code {}", code);
+            for desc in descs.iter() {
+                println!("  {}", desc);
+            }
+            if let Some(preferred) = rt.preferred_term(code) {
+                println!("Preferred term: {}", preferred);
+            }
+            println!("Synonyms");
+            for (term, desc) in rt.synonyms(code) {
+                println!("  {code}{term}: {desc}");
+            }
+            println!("Status: {}", rt.status(code));
+        } else {
+            println!("Code {} not found", code);
+        }
+        return Ok(());
+    }
+
+    let termset = if let Some(path) = opt.term_set_path {
+        read2::TermSet::load(path)?
+    } else {
+        read2::TermSet::new(
+            None,
+            None,
+            opt.include.iter().map(|s| s.clone().into()),
+            opt.exclude.iter().map(|s| s.clone().into()),
+            user,
+        )?
+    }
+    .match_thesaurus(rt.clone());
+
+    println!("Matches\n-------\n");
+    println!("{}\n", termset.term_table().for_terminal());
+    println!("{} codes matched", termset.code_set.len());
+
+    let unmatched_descendants = termset.descendants_not_included_or_excluded();
+
+    println!("Unmatched descendants\n---------------------\n");
+    println!(
+        "{}\n",
+        unmatched_descendants.term_table(Some(&rt)).for_terminal()
+    );
+    println!("{} unmatched descendants", unmatched_descendants.len());
+
+    if opt.unmatched_first_words {
+        // Create an ordered list of the first words in descriptions for unmatched descendants.
+        // (can save time when adding as includes)
+        let mut first_words_unmatched = BTreeSet::new();
+        for code in unmatched_descendants.iter() {
+            let desc = rt.get(code).unwrap();
+            let desc = desc.iter().max_by_key(|v| v.len()).unwrap();
+            if let Some(first) = desc.split(' ').next() {
+                first_words_unmatched.insert(first.trim_matches('*').to_lowercase());
+            }
+        }
+
+        for word in first_words_unmatched {
+            println!("{:?},", word);
+        }
+    }
+
+    if opt.unmatched_descriptions {
+        let mut descriptions = BTreeSet::new();
+        for code in unmatched_descendants.iter() {
+            let desc = rt.get(code).unwrap();
+            for desc in desc.iter() {
+                descriptions.insert(desc.to_string());
+            }
+        }
+
+        for desc in descriptions {
+            println!("{:?},", desc);
+        }
+    }
+
+    if let Some(loc) = &opt.save {
+        termset.save(loc, global.overwrite)?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ReadImport {
+    _term: String,
+    _unknown: u8,
+    description_short: String,
+    description_med: Option<String>,
+    description_long: Option<String>,
+    term_id: TermId,
+    _lang: Language,
+    code: ReadCode,
+    status: String,
+}
+
+impl ReadImport {
+    fn insert(self, th: &mut RawThesaurus) {
+        let longest = self
+            .description_long
+            .as_ref()
+            .or(self.description_med.as_ref())
+            .unwrap_or(&self.description_short);
+        th.terms.insert((self.code, self.term_id), longest.clone());
+
+        // Each of a code's terms has the flag, so keep the least current.
+        let status = CodeStatus::from_flag(&self.status).unwrap_or_else(|| {
+            event!(
+                Level::WARN,
+                "unrecognised status flag \"{}\" for {}",
+                self.status,
+                self.code
+            );
+            CodeStatus::Current
+        });
+        if status != CodeStatus::Current {
+            let entry = th.status.entry(self.code).or_insert(status);
+            *entry = (*entry).max(status);
+        }
+
+        let entry = th.codes.entry(self.code).or_insert_with(HashSet::new);
+        entry.insert(self.description_short);
+        if let Some(med) = self.description_med {
+            entry.insert(med);
+        }
+        if let Some(long) = self.description_long {
+            entry.insert(long);
+        }
+    }
+}
+
+/// Serializes to the same format as `read2::Thesaurus`.
+#[derive(Debug, Serialize, Deserialize)]
+struct RawThesaurus {
+    codes: BTreeMap<ReadCode, HashSet<String>>,
+    terms: BTreeMap<(ReadCode, TermId), String>,
+    status: BTreeMap<ReadCode, CodeStatus>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Language {
+    #[serde(alias = "EN")]
+    En,
+}
+
+fn import(opt: Import, global: &Global) -> Result {
+    let out_path = read2::Thesaurus::path(&global.paths);
+    let read_db = out_path.parent().unwrap();
+    ensure!(
+        global.overwrite || !out_path.exists(),
+        "\"{}\" already exists (use --overwrite to replace it)",
+        out_path.display()
+    );
+
+    let mut th = RawThesaurus {
+        codes: BTreeMap::new(),
+        terms: BTreeMap::new(),
+        status: BTreeMap::new(),
+    };
+
+    if opt.trud.is_empty() {
+        for (name, delimiter) in [("drugs.txt", b'|'), ("nondrugs.txt", b',')] {
+            let path = read_db.join(name);
+            let file =
+                fs::File::open(&path).with_context(|| format!("opening \"{}\"", path.display()))?;
+            provenance::record_input(&path);
+            read_keywords(file, delimiter, &mut th)
+                .with_context(|| format!("reading \"{}\"", path.display()))?;
+        }
+    } else {
+        for path in opt.trud.iter() {
+            provenance::record_input(path);
+            import_trud(path, &mut th)
+                .with_context(|| format!("importing TRUD release \"{}\"", path.display()))?;
+        }
+    }
+    ensure!(!th.codes.is_empty(), "no Read codes were imported");
+
+    let mut out = io::BufWriter::new(fs::File::create(&out_path)?);
+    bincode::serialize_into(&mut out, &th)?;
+    out.flush()?;
+    drop(out);
+    read2::Thesaurus::forget_shared(&global.paths);
+    provenance::write_sidecar(&out_path)
+}
+
+/// Read a file of keyword/description records into the thesaurus, returning the number of
+/// records.
+fn read_keywords(input: impl Read, delimiter: u8, th: &mut RawThesaurus) -> Result<usize> {
+    let reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .delimiter(delimiter)
+        .trim(csv::Trim::All)
+        .from_reader(input);
+    let mut records = 0;
+    for rec in reader.into_deserialize() {
+        let rec: ReadImport = rec?;
+        rec.insert(th);
+        records += 1;
+    }
+    Ok(records)
+}
+
+/// Import the keyword files from a TRUD Read v2 release zip.
+///
+/// Every keyword file in the archive is read, and the number of records must match the number of
+/// lines so we know nothing was skipped.
+fn import_trud(path: &Path, th: &mut RawThesaurus) -> Result {
+    let file = fs::File::open(path)?;
+    let mut archive = zip::ZipArchive::new(io::BufReader::new(file))?;
+    let mut found = 0;
+    for idx in 0..archive.len() {
+        let mut entry = archive.by_index(idx)?;
+        let is_keyword_file = Path::new(entry.name())
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(|name| name.eq_ignore_ascii_case(TRUD_KEYWORD_FILE))
+            .unwrap_or(false);
+        if !entry.is_file() || !is_keyword_file {
+            continue;
+        }
+        let name = entry.name().to_owned();
+        let mut buf = Vec::with_capacity(entry.size() as usize);
+        entry
+            .read_to_end(&mut buf)
+            .with_context(|| format!("extracting \"{}\"", name))?;
+        let lines = buf
+            .split(|ch| *ch == b'\n')
+            .filter(|line| !line.trim_ascii().is_empty())
+            .count();
+        let records =
+            read_keywords(&buf[..], b',', th).with_context(|| format!("reading \"{}\"", name))?;
+        ensure!(
+            records == lines,
+            "\"{}\" has {} lines but only {} records were read",
+            name,
+            lines,
+            records
+        );
+        event!(Level::INFO, "read {} records from \"{}\"", records, name);
+        found += 1;
+    }
+    ensure!(
+        found > 0,
+        "no keyword file ({}) found in the archive",
+        TRUD_KEYWORD_FILE
+    );
+    Ok(())
+}
@@ -0,0 +1,79 @@
+use crate::Global;
+use clap::Args;
+use eadapt_needs_analysis::{
+    header,
+    validate::{
+        self,
+        adapt_flags::{self, FlagCodesets},
+        linkage,
+    },
+    Adapts, DisclosureControl, Events, Patients,
+};
+use qu::ick_use::*;
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub struct Opt {
+    /// Suppress small counts and round the rest in the linkage and ADAPT flag tables, as required
+    /// for outputs we release.
+    #[clap(long)]
+    release: bool,
+    /// Write every offending row to this CSV file (relative to the output directory).
+    #[clap(long)]
+    dump: Option<PathBuf>,
+    /// Compare the ADAPT treatment flags with the codesets listed in this TOML file.
+    #[clap(long)]
+    adapt_flags: Option<PathBuf>,
+    /// Write patients whose ADAPT flags disagree with their GP record to this CSV file (relative
+    /// to the output directory).
+    #[clap(long, requires = "adapt_flags")]
+    dump_discordant: Option<PathBuf>,
+}
+
+/// Check the imported (not cleaned) data against the validation rules.
+pub fn run(opt: Opt, global: &Global) -> Result {
+    let patients = Patients::load("patients.bin")?;
+    let events = Events::load("events.bin")?;
+    let adapts = Adapts::load("adapt.bin")?;
+    let dc = DisclosureControl::new(opt.release);
+
+    let mut report = validate::validate(&patients, &events, &adapts);
+    report.extend(validate::check_raw_imd("full.patients.txt")?);
+
+    header("Validation");
+    println!("{}", report.term_table());
+
+    header("Linkage between tables");
+    let linkage = linkage::linkage_report(&patients, &events, &adapts);
+    println!("{}", linkage.term_table(&dc));
+
+    if let Some(path) = opt.dump {
+        global.check_output(&path)?;
+        let path = global.paths.output_path(path);
+        report.save_violations(&path)?;
+        println!(
+            "wrote {} violations to \"{}\"",
+            report.violations().len(),
+            path.display()
+        );
+    }
+
+    if let Some(path) = opt.adapt_flags {
+        let codesets = FlagCodesets::load(path)?.load_codesets()?;
+        let flag_report = adapt_flags::check_adapt_flags(&adapts, &events, &codesets);
+        header("ADAPT flags against the GP record");
+        println!("{}", flag_report.term_table(&dc));
+
+        if let Some(path) = opt.dump_discordant {
+            global.check_output(&path)?;
+            let path = global.paths.output_path(path);
+            flag_report.save_discordant(&path)?;
+            println!(
+                "wrote {} discordant flags to \"{}\"",
+                flag_report.discordant.len(),
+                path.display()
+            );
+        }
+    }
+    Ok(())
+}
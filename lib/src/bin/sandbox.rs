@@ -13,7 +13,7 @@ pub fn main() -> Result {
     let patients = Patients::load("patients_clean.bin")?;
     let events = Events::load("events_clean.bin")?;
     let conditions = ltcs::Conditions::load()?;
-    let thesaurus = read2::Thesaurus::load()?;
+    let thesaurus = read2::Thesaurus::shared()?;
     let lymphoma_codeset = read2::TermCodeSet::load("lymphoma_clean", thesaurus.clone())?;
 
     let diagnosis_dates = lymphoma_codeset
@@ -48,7 +48,5 @@ pub fn main() -> Result {
 }
 
 fn get_value(evt: &Event) -> Option<R64> {
-    let val = evt.code_value.as_ref()?;
-    let val = val.parse::<f64>().ok()?;
-    R64::try_new(val)
+    R64::try_new(evt.numeric_value()?.value)
 }
@@ -0,0 +1,350 @@
+//! Columnar (parquet) storage for the main datasets.
+//!
+//! The bincode files produced by `save` are compact and quick to load from rust, but they can't
+//! be read from anywhere else. Parquet files can be opened directly from python/R, and the
+//! columnar layout compresses our (very repetitive) events table well.
+use crate::{
+    dates, diagnosis::DateConfidence, intern, output_path, provenance, util, ArcStr, Event, Events,
+    Imd, Patient, PatientId, Patients, ReadCode, Sex,
+};
+use arrow_array::{
+    Array, ArrayRef, Date32Array, Float32Array, RecordBatch, StringArray, UInt16Array, UInt64Array,
+    UInt8Array,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use itertools::Itertools;
+use parquet::{
+    arrow::{arrow_reader::ParquetRecordBatchReaderBuilder, ArrowWriter},
+    basic::Compression,
+    file::properties::WriterProperties,
+};
+use qu::ick_use::*;
+use std::{fs, path::Path, sync::Arc};
+
+/// The number of rows written in each record batch.
+const BATCH_SIZE: usize = 64 * 1024;
+
+impl Events {
+    /// Save the events as a parquet file in the output directory.
+    pub fn save_parquet(&self, path: impl AsRef<Path>) -> Result {
+        save_parquet(&self.els, events_schema(), events_batch, path)
+    }
+
+    /// Load events from a parquet file written by [`Events::save_parquet`].
+    pub fn load_parquet(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::new(load_parquet(path, events_from_batch)?))
+    }
+}
+
+impl Patients {
+    /// Save the patients as a parquet file in the output directory.
+    pub fn save_parquet(&self, path: impl AsRef<Path>) -> Result {
+        save_parquet(&self.els, patients_schema(), patients_batch, path)
+    }
+
+    /// Load patients from a parquet file written by [`Patients::save_parquet`].
+    pub fn load_parquet(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::new(load_parquet(path, patients_from_batch)?))
+    }
+}
+
+// Events
+
+fn events_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("patient_id", DataType::UInt64, false),
+        Field::new("date", DataType::Date32, false),
+        Field::new("read_code", DataType::Utf8, false),
+        Field::new("term_id", DataType::Utf8, true),
+        Field::new("rubric", DataType::Utf8, false),
+        Field::new("code_value", DataType::Utf8, true),
+        Field::new("code_units", DataType::Utf8, true),
+        Field::new("source", DataType::Utf8, false),
+    ]))
+}
+
+fn events_batch(schema: SchemaRef, events: &[Event]) -> Result<RecordBatch> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from_iter_values(
+            events.iter().map(|evt| evt.patient_id.get()),
+        )),
+        Arc::new(Date32Array::from_iter_values(
+            events.iter().map(|evt| dates::to_epoch_days(evt.date)),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            events.iter().map(|evt| evt.read_code.to_string()),
+        )),
+        Arc::new(StringArray::from(
+            events
+                .iter()
+                .map(|evt| evt.term_id.map(|term| term.to_string()))
+                .collect::<Vec<_>>(),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            events.iter().map(|evt| &*evt.rubric),
+        )),
+        Arc::new(StringArray::from(
+            events
+                .iter()
+                .map(|evt| evt.code_value.as_deref())
+                .collect::<Vec<_>>(),
+        )),
+        Arc::new(StringArray::from(
+            events
+                .iter()
+                .map(|evt| evt.code_units.as_deref())
+                .collect::<Vec<_>>(),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            events.iter().map(|evt| &*evt.source),
+        )),
+    ];
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
+
+fn events_from_batch(batch: &RecordBatch, out: &mut Vec<Event>) -> Result {
+    let patient_id = column::<UInt64Array>(batch, "patient_id")?;
+    let date = column::<Date32Array>(batch, "date")?;
+    let read_code = column::<StringArray>(batch, "read_code")?;
+    let term_id = column::<StringArray>(batch, "term_id")?;
+    let rubric = column::<StringArray>(batch, "rubric")?;
+    let code_value = column::<StringArray>(batch, "code_value")?;
+    let code_units = column::<StringArray>(batch, "code_units")?;
+    let source = column::<StringArray>(batch, "source")?;
+
+    for idx in 0..batch.num_rows() {
+        out.push(Event {
+            patient_id: PatientId::new(patient_id.value(idx)),
+            date: dates::from_epoch_days(date.value(idx)),
+            read_code: ReadCode::from_str(read_code.value(idx))?,
+            term_id: opt_str(term_id, idx).map(|term| term.parse()).transpose()?,
+            rubric: intern::RUBRICS.intern(rubric.value(idx)),
+            code_value: opt_str(code_value, idx),
+            code_units: opt_str(code_units, idx),
+            source: intern::SOURCES.intern(source.value(idx)),
+        });
+    }
+    Ok(())
+}
+
+// Patients
+
+fn patients_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("patient_id", DataType::UInt64, false),
+        Field::new("year_of_birth", DataType::UInt16, false),
+        Field::new("month_of_birth", DataType::UInt8, true),
+        Field::new("sex", DataType::Utf8, false),
+        Field::new("ethnicity", DataType::Utf8, true),
+        Field::new("lsoa", DataType::Utf8, true),
+        Field::new("imd_decile", DataType::UInt8, true),
+        Field::new("charlson", DataType::Float32, false),
+        Field::new("lymphoma_diagnosis_date", DataType::Date32, true),
+        Field::new("lymphoma_diagnosis_confidence", DataType::Utf8, true),
+        // Subtype codes separated by `;`.
+        Field::new("lymphoma_subtypes", DataType::Utf8, false),
+    ]))
+}
+
+fn patients_batch(schema: SchemaRef, patients: &[Patient]) -> Result<RecordBatch> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from_iter_values(
+            patients.iter().map(|pat| pat.patient_id.get()),
+        )),
+        Arc::new(UInt16Array::from_iter_values(
+            patients.iter().map(|pat| pat.year_of_birth),
+        )),
+        Arc::new(UInt8Array::from(
+            patients
+                .iter()
+                .map(|pat| pat.month_of_birth)
+                .collect::<Vec<_>>(),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            patients.iter().map(|pat| pat.sex.code()),
+        )),
+        Arc::new(StringArray::from(
+            patients
+                .iter()
+                .map(|pat| pat.ethnicity.as_deref())
+                .collect::<Vec<_>>(),
+        )),
+        Arc::new(StringArray::from(
+            patients
+                .iter()
+                .map(|pat| pat.lsoa.as_deref())
+                .collect::<Vec<_>>(),
+        )),
+        Arc::new(UInt8Array::from(
+            patients
+                .iter()
+                .map(|pat| pat.imd.as_decile())
+                .collect::<Vec<_>>(),
+        )),
+        Arc::new(Float32Array::from_iter_values(
+            patients.iter().map(|pat| pat.charlson),
+        )),
+        Arc::new(Date32Array::from(
+            patients
+                .iter()
+                .map(|pat| pat.lymphoma_diagnosis_date.map(dates::to_epoch_days))
+                .collect::<Vec<_>>(),
+        )),
+        Arc::new(StringArray::from(
+            patients
+                .iter()
+                .map(|pat| pat.lymphoma_diagnosis_confidence.map(DateConfidence::code))
+                .collect::<Vec<_>>(),
+        )),
+        Arc::new(StringArray::from_iter_values(patients.iter().map(|pat| {
+            pat.lymphoma_subtypes
+                .iter()
+                .map(|subtype| subtype.code())
+                .join(";")
+        }))),
+    ];
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
+
+fn patients_from_batch(batch: &RecordBatch, out: &mut Vec<Patient>) -> Result {
+    let patient_id = column::<UInt64Array>(batch, "patient_id")?;
+    let year_of_birth = column::<UInt16Array>(batch, "year_of_birth")?;
+    let month_of_birth = column::<UInt8Array>(batch, "month_of_birth")?;
+    let sex = column::<StringArray>(batch, "sex")?;
+    let ethnicity = column::<StringArray>(batch, "ethnicity")?;
+    let lsoa = column::<StringArray>(batch, "lsoa")?;
+    let imd = column::<UInt8Array>(batch, "imd_decile")?;
+    let charlson = column::<Float32Array>(batch, "charlson")?;
+    let diagnosis_date = column::<Date32Array>(batch, "lymphoma_diagnosis_date")?;
+    let confidence = column::<StringArray>(batch, "lymphoma_diagnosis_confidence")?;
+    let subtypes = column::<StringArray>(batch, "lymphoma_subtypes")?;
+
+    for idx in 0..batch.num_rows() {
+        out.push(Patient {
+            patient_id: PatientId::new(patient_id.value(idx)),
+            year_of_birth: year_of_birth.value(idx),
+            month_of_birth: if month_of_birth.is_null(idx) {
+                None
+            } else {
+                Some(month_of_birth.value(idx))
+            },
+            sex: Sex::from_code(sex.value(idx)),
+            ethnicity: opt_str(ethnicity, idx),
+            lsoa: opt_str(lsoa, idx),
+            imd: if imd.is_null(idx) {
+                Imd::Missing
+            } else {
+                parse_imd(imd.value(idx))?
+            },
+            charlson: charlson.value(idx),
+            lymphoma_diagnosis_date: if diagnosis_date.is_null(idx) {
+                None
+            } else {
+                Some(dates::from_epoch_days(diagnosis_date.value(idx)))
+            },
+            lymphoma_diagnosis_confidence: opt_str(confidence, idx)
+                .map(|code| code.parse())
+                .transpose()?,
+            lymphoma_subtypes: subtypes
+                .value(idx)
+                .split(';')
+                .filter(|code| !code.is_empty())
+                .map(str::parse)
+                .collect::<Result<_>>()?,
+        });
+    }
+    Ok(())
+}
+
+// Helpers
+
+/// Write `contents` to a parquet file in batches.
+fn save_parquet<T>(
+    contents: &[T],
+    schema: SchemaRef,
+    to_batch: fn(SchemaRef, &[T]) -> Result<RecordBatch>,
+    path: impl AsRef<Path>,
+) -> Result {
+    fn inner<T>(
+        contents: &[T],
+        schema: SchemaRef,
+        to_batch: fn(SchemaRef, &[T]) -> Result<RecordBatch>,
+        path: &Path,
+    ) -> Result {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("could not create parent")?;
+        }
+        if util::path_exists(path)? {
+            event!(
+                Level::WARN,
+                "overwriting existing file at \"{}\"",
+                path.display()
+            );
+        }
+        let props = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        let mut writer =
+            ArrowWriter::try_new(fs::File::create(path)?, schema.clone(), Some(props))?;
+        for chunk in contents.chunks(BATCH_SIZE) {
+            writer.write(&to_batch(schema.clone(), chunk)?)?;
+        }
+        writer.close()?;
+        provenance::write_sidecar(path)
+    }
+    let path = output_path(path.as_ref());
+    crate::check_extension(&path, "parquet")?;
+
+    inner(contents, schema, to_batch, &path)
+        .with_context(|| format!("unable to save data to \"{}\"", path.display()))
+}
+
+/// Read a parquet file, converting each record batch with `from_batch`.
+fn load_parquet<T>(
+    path: impl AsRef<Path>,
+    from_batch: fn(&RecordBatch, &mut Vec<T>) -> Result,
+) -> Result<Vec<T>> {
+    fn inner<T>(
+        path: &Path,
+        from_batch: fn(&RecordBatch, &mut Vec<T>) -> Result,
+    ) -> Result<Vec<T>> {
+        let reader = ParquetRecordBatchReaderBuilder::try_new(fs::File::open(path)?)?
+            .with_batch_size(BATCH_SIZE)
+            .build()?;
+        let mut out = vec![];
+        for batch in reader {
+            from_batch(&batch?, &mut out)?;
+        }
+        Ok(out)
+    }
+    let path = output_path(path.as_ref());
+    crate::check_extension(&path, "parquet")?;
+
+    inner(&path, from_batch)
+        .with_context(|| format!("unable to load data from \"{}\"", path.display()))
+}
+
+/// Get a column by name, checking it has the expected type.
+fn column<'a, A: Array + 'static>(batch: &'a RecordBatch, name: &str) -> Result<&'a A> {
+    batch
+        .column_by_name(name)
+        .with_context(|| format!("missing column \"{}\"", name))?
+        .as_any()
+        .downcast_ref::<A>()
+        .with_context(|| format!("column \"{}\" has an unexpected type", name))
+}
+
+fn opt_str(array: &StringArray, idx: usize) -> Option<ArcStr> {
+    if array.is_null(idx) {
+        None
+    } else {
+        Some(array.value(idx).into())
+    }
+}
+
+fn parse_imd(decile: u8) -> Result<Imd> {
+    match Imd::from_decile(decile) {
+        Some(imd) => Ok(imd),
+        None => bail!("IMD decile out of range: {}", decile),
+    }
+}
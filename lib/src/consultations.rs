@@ -0,0 +1,178 @@
+//! GP consultation rates before and after lymphoma diagnosis.
+//!
+//! The extract doesn't say which events were face-to-face consultations, so we count days. A day
+//! counts as a consultation if the practice recorded something clinical on it (a diagnosis,
+//! history, examination or prescription) or an encounter code ("seen in", "seen by"). Days with
+//! only administration, test results or hospital correspondence don't count, and a patient can
+//! have at most one consultation per day.
+use crate::{
+    dates, epi,
+    lemp::{self, BeforeAfter},
+    read2::{ChapterGroup, ReadChapter},
+    render::TextTable,
+    DisclosureControl, Event, Events, PatientId, Patients, Registrations,
+};
+use chrono::{Duration, NaiveDate};
+use term_data_table::Table;
+
+/// Read codes for a patient encounter (`9N1` site of encounter, `9N2` seen by).
+const ENCOUNTER_PREFIXES: &[&str] = &["9N1", "9N2"];
+
+/// Whether `evt` looks like it was recorded during a consultation.
+pub fn is_consultation(evt: &Event) -> bool {
+    if evt.source_kind().is_external() {
+        return false;
+    }
+    let code: &str = evt.read_code.as_ref();
+    if ENCOUNTER_PREFIXES
+        .iter()
+        .any(|prefix| code.starts_with(prefix))
+    {
+        return true;
+    }
+    matches!(
+        ReadChapter::of(evt.read_code).group(),
+        ChapterGroup::Diagnoses | ChapterGroup::HistoryExamination | ChapterGroup::Medication
+    )
+}
+
+/// The number of days from `start` to `end` inclusive with a consultation.
+pub fn consultations_in_window(
+    events: &Events,
+    patient_id: PatientId,
+    start: NaiveDate,
+    end: NaiveDate,
+) -> usize {
+    let mut days = events
+        .for_patient_in_window(patient_id, start, end)
+        .filter(|evt| is_consultation(evt))
+        .map(|evt| evt.date)
+        .collect::<Vec<_>>();
+    // Events are in date order, so days with more than one event are next to each other.
+    days.dedup();
+    days.len()
+}
+
+/// One patient's consultations per year either side of their lymphoma diagnosis.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PatientConsultations {
+    pub patient_id: PatientId,
+    pub before: f64,
+    pub after: f64,
+}
+
+/// Consultation rates before and after lymphoma diagnosis, compared within each patient.
+#[derive(Debug)]
+pub struct ConsultationRates {
+    pub patients: Vec<PatientConsultations>,
+    pub comparison: BeforeAfter,
+}
+
+impl ConsultationRates {
+    /// Compare each patient's consultation rate in the `years` before their lymphoma diagnosis
+    /// with the rate in the `years` after it.
+    ///
+    /// Consultations on the diagnosis date count as after. Both periods are cut short where we
+    /// can't see the patient's record, and patients without a diagnosis date or without any
+    /// follow-up on one side are left out.
+    pub fn new(
+        patients: &Patients,
+        events: &Events,
+        registrations: &Registrations,
+        years: f64,
+    ) -> Self {
+        let months = dates::years_to_months(years);
+        let rate = |patient_id, start, end| {
+            consultations_in_window(events, patient_id, start, end) as f64
+                / epi::years_between(start, end)
+        };
+
+        let mut rates = vec![];
+        for pat in patients.iter_ref() {
+            let Some(diagnosis) = pat.lymphoma_diagnosis_date else {
+                continue;
+            };
+            let before_start = match registrations.follow_up_start(pat.patient_id) {
+                Some(start) => start.max(dates::saturating_add_months(diagnosis, -months)),
+                None => dates::saturating_add_months(diagnosis, -months),
+            };
+            let before_end = diagnosis - Duration::days(1);
+            let after_end = registrations
+                .follow_up_end(pat.patient_id)
+                .min(dates::saturating_add_months(diagnosis, months));
+            if before_end <= before_start || after_end <= diagnosis {
+                continue;
+            }
+            rates.push(PatientConsultations {
+                patient_id: pat.patient_id,
+                before: rate(pat.patient_id, before_start, before_end),
+                after: rate(pat.patient_id, diagnosis, after_end),
+            });
+        }
+        let pairs = rates
+            .iter()
+            .map(|rate| (rate.before, rate.after))
+            .collect::<Vec<_>>();
+        ConsultationRates {
+            comparison: BeforeAfter::from_pairs(years, &pairs, 0.95),
+            patients: rates,
+        }
+    }
+
+    pub fn data_table(&self, dc: &DisclosureControl) -> Table<'_> {
+        self.text_table(dc).into_term_table()
+    }
+
+    /// Per-patient rates are never shown, and the summary is suppressed if there are too few
+    /// patients.
+    pub fn text_table(&self, dc: &DisclosureControl) -> TextTable {
+        let summary = &self.comparison;
+        let num_people = dc.count(summary.num_people);
+        let table = TextTable::default().with_row([
+            "People with follow-up before and after diagnosis".to_string(),
+            num_people.to_string(),
+        ]);
+        if num_people.value().is_none() {
+            return table.with_row(["Consultation rates", "suppressed"]);
+        }
+        let ci = match summary.difference_ci {
+            Some((low, high)) => format!("{low:.2} to {high:.2} per year"),
+            None => "-".into(),
+        };
+        table
+            .with_row([
+                "Mean consultations before diagnosis".to_string(),
+                lemp::per_year(summary.before_mean),
+            ])
+            .with_row([
+                "Mean consultations after diagnosis".to_string(),
+                lemp::per_year(summary.after_mean),
+            ])
+            .with_row([
+                "Mean change".to_string(),
+                lemp::per_year(summary.difference_mean),
+            ])
+            .with_row(["95% CI for change (paired t)".to_string(), ci])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_event;
+
+    #[test]
+    fn consultation() {
+        let date = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
+        let event = |code, source: &str| Event {
+            source: source.into(),
+            ..test_event(1, date, code)
+        };
+        assert!(is_consultation(&event("H33..", "")));
+        assert!(is_consultation(&event("bd3i.", "")));
+        assert!(is_consultation(&event("9N11.", "")));
+        assert!(!is_consultation(&event("9N4..", "")));
+        assert!(!is_consultation(&event("44J3.", "")));
+        assert!(!is_consultation(&event("H33..", "Hospital letter")));
+    }
+}
@@ -0,0 +1,302 @@
+//! The storage shared by our datasets.
+//!
+//! Each dataset is a list of rows, kept in an `Arc` so that clones are cheap, with an index from
+//! each row's key (usually the patient ID) to its position in the list.
+//! [`Patients`](crate::Patients), [`Events`](crate::Events), [`Adapts`](crate::Adapts) and
+//! [`CodeRubricCounts`](crate::CodeRubricCounts) wrap a [`Dataset`] and add their own queries.
+use crate::{audit_filter, envelope::Schema, join::Join, load, save, GroupCounts, Result};
+use rayon::prelude::*;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{cmp::Ordering, collections::BTreeMap, ops::Deref, path::Path, sync::Arc};
+
+/// A row in a [`Dataset`].
+pub trait Record: Clone {
+    /// The field that the dataset is indexed by.
+    type Key: Ord + Copy;
+    /// The name of the dataset in the [`audit`](crate::audit) log.
+    const DATASET: &'static str;
+
+    fn key(&self) -> Self::Key;
+
+    /// The order of rows with the same key in the index. By default, the order they were loaded
+    /// in.
+    fn cmp_within_key(&self, _other: &Self) -> Ordering {
+        Ordering::Equal
+    }
+}
+
+/// A list of rows, with a pre-built index on [`Record::key`].
+#[derive(Clone)]
+pub struct Dataset<T: Record> {
+    pub(crate) els: Arc<Vec<T>>,
+    /// For each key, the indices of the rows with that key.
+    pub(crate) idx: BTreeMap<T::Key, Vec<usize>>,
+}
+
+impl<T: Record> Dataset<T> {
+    pub(crate) fn new(els: Vec<T>) -> Self {
+        let mut this = Dataset {
+            els: Arc::new(els),
+            idx: BTreeMap::new(),
+        };
+        this.rebuild_index();
+        this
+    }
+
+    pub(crate) fn load(path: impl AsRef<Path>) -> Result<Self>
+    where
+        T: Schema + DeserializeOwned,
+    {
+        Ok(Self::new(load(path)?))
+    }
+
+    pub(crate) fn save(&self, path: impl AsRef<Path>) -> Result
+    where
+        T: Schema + Serialize,
+    {
+        save(&self.els, path)
+    }
+
+    /// All rows with the given key.
+    pub fn get(&self, key: T::Key) -> impl Iterator<Item = &T> + Clone + '_ {
+        self.indices(key).iter().map(|idx| &self.els[*idx])
+    }
+
+    /// The first row with the given key.
+    pub fn find(&self, key: T::Key) -> Option<&T> {
+        let idx = self.indices(key).first()?;
+        self.els.get(*idx)
+    }
+
+    /// Like [`Dataset::find`], but the row can be changed.
+    ///
+    /// The key mustn't be changed. This will clone the rows if they are shared, and other clones
+    /// of `self` will not be updated.
+    pub(crate) fn find_mut(&mut self, key: T::Key) -> Option<&mut T> {
+        let idx = *self.idx.get(&key)?.first()?;
+        Arc::make_mut(&mut self.els).get_mut(idx)
+    }
+
+    /// Change every row. As with [`Dataset::find_mut`], keys mustn't be changed.
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> + '_ {
+        Arc::make_mut(&mut self.els).iter_mut()
+    }
+
+    /// The positions of the rows with the given key, in index order.
+    pub(crate) fn indices(&self, key: T::Key) -> &[usize] {
+        match self.idx.get(&key) {
+            Some(idxs) => idxs,
+            None => &[],
+        }
+    }
+
+    /// The distinct keys, in order.
+    pub fn keys(&self) -> impl Iterator<Item = T::Key> + '_ {
+        self.idx.keys().copied()
+    }
+
+    /// Iterate over (clones of) the rows.
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        self.els.iter().cloned()
+    }
+
+    pub fn iter_ref(&self) -> impl Iterator<Item = &T> + '_ {
+        self.els.iter()
+    }
+
+    /// The number of rows for each value of `key`.
+    pub fn group_count<K: Ord>(&self, key: impl FnMut(&T) -> K) -> GroupCounts<K> {
+        GroupCounts::from_keys(self.els.iter().map(key))
+    }
+
+    /// Only the rows that match the filter.
+    pub fn filter(&self, f: impl Fn(&T) -> bool) -> Self {
+        Self::new(self.els.iter().filter(|el| f(el)).cloned().collect())
+    }
+
+    pub fn retain(&mut self, f: impl Fn(&T) -> bool) {
+        Arc::make_mut(&mut self.els).retain(f);
+        self.rebuild_index();
+    }
+
+    /// Like [`Dataset::filter`], but records the filter in the [`audit`](crate::audit) log.
+    pub fn filter_described(&self, description: &str, f: impl Fn(&T) -> bool) -> Self {
+        let out = self.filter(f);
+        audit_filter(T::DATASET, description, self.len(), out.len());
+        out
+    }
+
+    /// Like [`Dataset::retain`], but records the filter in the [`audit`](crate::audit) log.
+    pub fn retain_described(&mut self, description: &str, f: impl Fn(&T) -> bool) {
+        let before = self.len();
+        self.retain(f);
+        audit_filter(T::DATASET, description, before, self.len());
+    }
+
+    pub fn term_table(&self) -> term_data_table::Table<'_>
+    where
+        T: Serialize,
+    {
+        term_data_table::Table::from_serde(self.iter()).unwrap()
+    }
+
+    /// Pair up the rows in `self` and `other` with the same key.
+    ///
+    /// Every combination of matching rows is included, so if each key appears once on both sides
+    /// there is one row per key. Keys that only appear on one side are listed in the result.
+    pub fn join<'a, R>(&'a self, other: &'a Dataset<R>) -> Join<T::Key, (&'a T, &'a R)>
+    where
+        R: Record<Key = T::Key>,
+    {
+        self.join_keys(other, |key, rows| {
+            for left in self.get(key) {
+                rows.extend(other.get(key).map(|right| (left, right)));
+            }
+        })
+    }
+
+    /// Like [`Dataset::join`], but each row in `self` comes with all the matching rows in `other`
+    /// (in index order), rather than one row per pair.
+    pub fn join_grouped<'a, R>(&'a self, other: &'a Dataset<R>) -> Join<T::Key, (&'a T, Vec<&'a R>)>
+    where
+        R: Record<Key = T::Key>,
+    {
+        self.join_keys(other, |key, rows| {
+            for left in self.get(key) {
+                rows.push((left, other.get(key).collect()));
+            }
+        })
+    }
+
+    /// Walk the (sorted) keys of both datasets together, calling `matched` for keys on both sides.
+    fn join_keys<R, Row>(
+        &self,
+        other: &Dataset<R>,
+        mut matched: impl FnMut(T::Key, &mut Vec<Row>),
+    ) -> Join<T::Key, Row>
+    where
+        R: Record<Key = T::Key>,
+    {
+        let mut out = Join {
+            rows: vec![],
+            left_unmatched: vec![],
+            right_unmatched: vec![],
+        };
+        let mut left = self.keys().peekable();
+        let mut right = other.keys().peekable();
+        loop {
+            match (left.peek(), right.peek()) {
+                (Some(l), Some(r)) => match l.cmp(r) {
+                    Ordering::Less => out.left_unmatched.extend(left.next()),
+                    Ordering::Greater => out.right_unmatched.extend(right.next()),
+                    Ordering::Equal => {
+                        matched(*l, &mut out.rows);
+                        left.next();
+                        right.next();
+                    }
+                },
+                (Some(_), None) => out.left_unmatched.extend(left.by_ref()),
+                (None, Some(_)) => out.right_unmatched.extend(right.by_ref()),
+                (None, None) => break,
+            }
+        }
+        out
+    }
+
+    fn rebuild_index(&mut self) {
+        self.idx.clear();
+        for (idx, el) in self.els.iter().enumerate() {
+            self.idx.entry(el.key()).or_default().push(idx);
+        }
+        // Stable, so rows that compare equal stay in the order they were loaded.
+        let els = &self.els;
+        for idxs in self.idx.values_mut() {
+            idxs.sort_by(|a, b| els[*a].cmp_within_key(&els[*b]));
+        }
+    }
+}
+
+impl<T: Record> Deref for Dataset<T> {
+    type Target = [T];
+    fn deref(&self) -> &Self::Target {
+        &self.els
+    }
+}
+
+impl<'a, T: Record> IntoIterator for &'a Dataset<T> {
+    type IntoIter = <&'a [T] as IntoIterator>::IntoIter;
+    type Item = &'a T;
+    fn into_iter(self) -> Self::IntoIter {
+        self.els.iter()
+    }
+}
+
+impl<'a, T: Record + Sync> IntoParallelIterator for &'a Dataset<T> {
+    type Item = &'a T;
+    type Iter = rayon::slice::Iter<'a, T>;
+    fn into_par_iter(self) -> Self::Iter {
+        self.els.par_iter()
+    }
+}
+
+impl<T: Record> FromIterator<T> for Dataset<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self::new(iter.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Row {
+        key: u8,
+        order: u8,
+    }
+
+    impl Record for Row {
+        type Key = u8;
+        const DATASET: &'static str = "test";
+        fn key(&self) -> u8 {
+            self.key
+        }
+        fn cmp_within_key(&self, other: &Self) -> Ordering {
+            self.order.cmp(&other.order)
+        }
+    }
+
+    #[test]
+    fn index() {
+        let row = |key, order| Row { key, order };
+        let mut data: Dataset<Row> = [row(1, 2), row(2, 0), row(1, 1), row(1, 2)]
+            .into_iter()
+            .collect();
+        let orders: Vec<_> = data.get(1).map(|row| row.order).collect();
+        assert_eq!(orders, [1, 2, 2]);
+        assert_eq!(data.find(2), Some(&row(2, 0)));
+        assert_eq!(data.keys().collect::<Vec<_>>(), [1, 2]);
+
+        data.retain(|row| row.order != 1);
+        assert_eq!(data.get(1).count(), 2);
+        assert_eq!(data.find(3), None);
+    }
+
+    #[test]
+    fn join() {
+        let row = |key, order| Row { key, order };
+        let left: Dataset<Row> = [row(1, 0), row(2, 0), row(4, 0)].into_iter().collect();
+        let right: Dataset<Row> = [row(2, 1), row(3, 0), row(2, 0), row(5, 0)]
+            .into_iter()
+            .collect();
+        let joined = left.join(&right);
+        let pairs: Vec<_> = joined.rows.iter().map(|(l, r)| (l.key, r.order)).collect();
+        assert_eq!(pairs, [(2, 0), (2, 1)]);
+        assert_eq!(joined.left_unmatched, [1, 4]);
+        assert_eq!(joined.right_unmatched, [3, 5]);
+
+        let grouped = left.join_grouped(&right);
+        assert_eq!(grouped.rows.len(), 1);
+        assert_eq!(grouped.rows[0].1.len(), 2);
+    }
+}
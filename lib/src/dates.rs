@@ -0,0 +1,82 @@
+//! Calendar arithmetic on dates.
+//!
+//! Adding months or years keeps the day of the month if it can, and otherwise uses the last day of
+//! the month, so 29 February 2020 plus a year is 28 February 2021 (`NaiveDate::with_year` gives
+//! `None` instead). Use these rather than a number of days, so e.g. "5 years before diagnosis"
+//! means the same calendar date however many leap years are in between.
+use chrono::{Duration, Months, NaiveDate};
+
+/// `date` plus `months` (which can be negative), or `None` if that is outside the dates chrono
+/// supports.
+pub fn add_months(date: NaiveDate, months: i32) -> Option<NaiveDate> {
+    let abs = Months::new(months.unsigned_abs());
+    if months < 0 {
+        date.checked_sub_months(abs)
+    } else {
+        date.checked_add_months(abs)
+    }
+}
+
+/// `date` plus `years` (which can be negative), or `None` if that is outside the dates chrono
+/// supports.
+pub fn add_years(date: NaiveDate, years: i32) -> Option<NaiveDate> {
+    add_months(date, years.checked_mul(12)?)
+}
+
+/// `date` plus `months`, stopping at the earliest or latest date chrono supports.
+pub fn saturating_add_months(date: NaiveDate, months: i32) -> NaiveDate {
+    add_months(date, months).unwrap_or(if months < 0 {
+        NaiveDate::MIN
+    } else {
+        NaiveDate::MAX
+    })
+}
+
+/// `date` plus `years`, stopping at the earliest or latest date chrono supports.
+pub fn saturating_add_years(date: NaiveDate, years: i32) -> NaiveDate {
+    saturating_add_months(date, years.saturating_mul(12))
+}
+
+/// A number of years (e.g. from a config file) as a whole number of months.
+pub fn years_to_months(years: f64) -> i32 {
+    (years * 12.).round() as i32
+}
+
+fn epoch() -> NaiveDate {
+    NaiveDate::from_ymd_opt(1970, 1, 1).unwrap()
+}
+
+/// The number of days from the unix epoch to `date`, which is how parquet and polars store dates.
+pub fn to_epoch_days(date: NaiveDate) -> i32 {
+    (date - epoch()).num_days() as i32
+}
+
+/// The date `days` days after the unix epoch (see [`to_epoch_days`]).
+pub fn from_epoch_days(days: i32) -> NaiveDate {
+    epoch() + Duration::days(days.into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn offsets() {
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        assert_eq!(add_years(date(2020, 2, 29), 1), Some(date(2021, 2, 28)));
+        assert_eq!(add_years(date(2020, 2, 29), -4), Some(date(2016, 2, 29)));
+        assert_eq!(add_years(date(2020, 2, 29), -5), Some(date(2015, 2, 28)));
+        assert_eq!(add_months(date(2021, 1, 31), 1), Some(date(2021, 2, 28)));
+        assert_eq!(add_months(date(2021, 3, 31), -1), Some(date(2021, 2, 28)));
+        assert_eq!(add_months(date(2021, 3, 15), -15), Some(date(2019, 12, 15)));
+        assert_eq!(add_years(NaiveDate::MAX, 1), None);
+        assert_eq!(saturating_add_years(NaiveDate::MIN, -1), NaiveDate::MIN);
+        assert_eq!(years_to_months(1.25), 15);
+        assert_eq!(to_epoch_days(date(1970, 1, 2)), 1);
+        assert_eq!(from_epoch_days(-1), date(1969, 12, 31));
+        assert_eq!(
+            from_epoch_days(to_epoch_days(date(2021, 3, 15))),
+            date(2021, 3, 15)
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use crate::{audit_filter, read2::TermId, ArcStr, Event, Events, PatientId, ReadCode};
+use chrono::NaiveDate;
+use std::{collections::HashSet, fmt, str::FromStr};
+
+/// Which events count as duplicates, for [`Events::dedup`].
+///
+/// The extract has many events recorded more than once on the same day, which inflate rates of
+/// tests and consultations.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum DedupPolicy {
+    /// Events that are the same in every field.
+    #[default]
+    Exact,
+    /// Events for the same patient on the same day with the same code and value, e.g. the same
+    /// reading entered twice with different rubrics. Two different readings on one day are kept.
+    SameDayValue,
+    /// Events for the same patient on the same day with the same code, whatever their value.
+    SameDayCode,
+}
+
+impl DedupPolicy {
+    pub const ALL: [DedupPolicy; 3] = [
+        DedupPolicy::Exact,
+        DedupPolicy::SameDayValue,
+        DedupPolicy::SameDayCode,
+    ];
+
+    /// The name used on the command line.
+    pub fn code(self) -> &'static str {
+        match self {
+            DedupPolicy::Exact => "exact",
+            DedupPolicy::SameDayValue => "same-day-value",
+            DedupPolicy::SameDayCode => "same-day-code",
+        }
+    }
+
+    /// The parts of `evt` that must match for it to be a duplicate.
+    fn key(self, evt: &Event) -> DedupKey<'_> {
+        let value = match self {
+            DedupPolicy::SameDayCode => None,
+            _ => Some((&evt.code_value, &evt.code_units)),
+        };
+        let rest = match self {
+            DedupPolicy::Exact => Some((evt.term_id, &evt.rubric, &evt.source)),
+            _ => None,
+        };
+        DedupKey {
+            patient_id: evt.patient_id,
+            date: evt.date,
+            read_code: evt.read_code,
+            value,
+            rest,
+        }
+    }
+}
+
+impl fmt::Display for DedupPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl FromStr for DedupPolicy {
+    type Err = anyhow::Error;
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        DedupPolicy::ALL
+            .into_iter()
+            .find(|policy| policy.code() == input)
+            .ok_or_else(|| anyhow::format_err!("unrecognised dedup policy \"{input}\""))
+    }
+}
+
+#[derive(PartialEq, Eq, Hash)]
+struct DedupKey<'a> {
+    patient_id: PatientId,
+    date: NaiveDate,
+    read_code: ReadCode,
+    value: Option<(&'a Option<ArcStr>, &'a Option<ArcStr>)>,
+    rest: Option<(Option<TermId>, &'a ArcStr, &'a ArcStr)>,
+}
+
+impl Events {
+    /// Remove duplicate events, keeping the first of each, and return how many were removed.
+    ///
+    /// The removal is recorded in the [`audit`](crate::audit) log.
+    pub fn dedup(&mut self, policy: DedupPolicy) -> usize {
+        let before = self.len();
+        let kept = {
+            let mut seen = HashSet::new();
+            self.iter_ref()
+                .filter(|evt| seen.insert(policy.key(evt)))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        let removed = before - kept.len();
+        if removed > 0 {
+            *self = Events::new(kept);
+        }
+        audit_filter(
+            "events",
+            &format!("remove duplicates ({policy})"),
+            before,
+            before - removed,
+        );
+        removed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_event;
+
+    #[test]
+    fn dedup() {
+        let date = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
+        let event = |code, value: Option<&str>, rubric: &str| Event {
+            rubric: rubric.into(),
+            code_value: value.map(Into::into),
+            ..test_event(1, date, code)
+        };
+        let removed = |policy| {
+            Events::new(vec![
+                event("246..", Some("120"), "O/E - BP"),
+                event("246..", Some("120"), "O/E - BP"),
+                event("246..", Some("120"), "Blood pressure"),
+                event("246..", Some("135"), "O/E - BP"),
+                event("H33..", None, "Asthma"),
+            ])
+            .dedup(policy)
+        };
+        assert_eq!(removed(DedupPolicy::Exact), 1);
+        assert_eq!(removed(DedupPolicy::SameDayValue), 2);
+        assert_eq!(removed(DedupPolicy::SameDayCode), 3);
+        assert_eq!(
+            "same-day-code".parse::<DedupPolicy>().unwrap(),
+            DedupPolicy::SameDayCode
+        );
+    }
+}
@@ -0,0 +1,253 @@
+//! Index of Multiple Deprivation (IMD) from the patient's LSOA.
+//!
+//! The extract comes with an IMD decile already worked out, but it is missing for some patients
+//! and we don't get to choose which IMD release it came from. The official lookups map each
+//! 2011 Lower Layer Super Output Area (LSOA) to a decile, so with these we can recompute it.
+use crate::{audit, file_exists, orig_path, provenance, ArcStr, Imd, Patients, Result};
+use qu::ick_use::*;
+use std::{collections::HashMap, fmt, path::Path};
+
+/// A release of the English indices of deprivation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ImdVersion {
+    Imd2015,
+    Imd2019,
+}
+
+impl ImdVersion {
+    /// Work out which release a "File 1" CSV comes from.
+    ///
+    /// Each release uses the local authority districts of its own year (2013 boundaries for
+    /// IMD 2015, 2019 boundaries for IMD 2019), so we go by the district code column header.
+    pub fn from_headers(headers: &csv::StringRecord) -> Result<Self> {
+        let lad = headers
+            .iter()
+            .find(|h| h.starts_with("Local Authority District code"))
+            .context("no \"Local Authority District code\" column")?;
+        if lad.contains("(2013)") {
+            Ok(ImdVersion::Imd2015)
+        } else if lad.contains("(2019)") {
+            Ok(ImdVersion::Imd2019)
+        } else {
+            bail!("can't tell which IMD release has the column {lad:?}")
+        }
+    }
+}
+
+impl fmt::Display for ImdVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImdVersion::Imd2015 => f.write_str("IMD 2015"),
+            ImdVersion::Imd2019 => f.write_str("IMD 2019"),
+        }
+    }
+}
+
+/// Maps LSOA codes (e.g. `E01000001`) to IMD deciles.
+#[derive(Debug, Clone)]
+pub struct ImdLookup {
+    version: ImdVersion,
+    deciles: HashMap<ArcStr, Imd>,
+}
+
+impl ImdLookup {
+    /// Load one of the official "File 1" CSVs (IMD rank and decile by LSOA).
+    ///
+    /// The column headers differ between releases, so we look for the column starting
+    /// "LSOA code" and the IMD decile column rather than using fixed names. The release is
+    /// worked out from the headers too (see [`ImdVersion::from_headers`]).
+    pub fn load_orig(path: impl AsRef<Path>) -> Result<Self> {
+        fn inner(path: &Path) -> Result<ImdLookup> {
+            let mut reader = csv::ReaderBuilder::new()
+                .has_headers(true)
+                .trim(csv::Trim::All)
+                .from_path(path)?;
+            provenance::record_input(path);
+            let headers = reader.headers()?.clone();
+            let version = ImdVersion::from_headers(&headers)?;
+            let lsoa_col = headers
+                .iter()
+                .position(|h| h.starts_with("LSOA code"))
+                .context("no \"LSOA code\" column")?;
+            let decile_col = headers
+                .iter()
+                .position(|h| {
+                    h.starts_with("Index of Multiple Deprivation") && h.contains("Decile")
+                })
+                .context("no IMD decile column")?;
+
+            let mut deciles = HashMap::new();
+            for (row_no, row) in reader.records().enumerate() {
+                let row = row?;
+                let lsoa = row.get(lsoa_col).unwrap_or("");
+                let decile = row.get(decile_col).unwrap_or("");
+                let imd = decile
+                    .parse()
+                    .ok()
+                    .and_then(Imd::from_decile)
+                    .with_context(|| {
+                        format!("invalid IMD decile {decile:?} on row {}", row_no + 2)
+                    })?;
+                deciles.insert(ArcStr::from(lsoa), imd);
+            }
+            audit::record(audit::Action::Load {
+                path: path.to_owned(),
+                rows: deciles.len(),
+            });
+            Ok(ImdLookup { version, deciles })
+        }
+        let path = orig_path(path.as_ref());
+        inner(&path).with_context(|| format!("while loading \"{}\"", path.display()))
+    }
+
+    /// Load the lookup if we have it, otherwise `None`.
+    pub fn load_if_present(path: impl AsRef<Path>) -> Result<Option<Self>> {
+        let path = path.as_ref();
+        if !file_exists(&orig_path(path))? {
+            return Ok(None);
+        }
+        Self::load_orig(path).map(Some)
+    }
+
+    pub fn version(&self) -> ImdVersion {
+        self.version
+    }
+
+    /// The decile for an LSOA, or `None` if the LSOA isn't in the lookup.
+    pub fn decile(&self, lsoa: &str) -> Option<Imd> {
+        self.deciles.get(lsoa.trim()).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.deciles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deciles.is_empty()
+    }
+}
+
+impl FromIterator<(ArcStr, Imd)> for ImdLookup {
+    /// Build a lookup by hand. The version is assumed to be [`ImdVersion::Imd2019`].
+    fn from_iter<T: IntoIterator<Item = (ArcStr, Imd)>>(iter: T) -> Self {
+        ImdLookup {
+            version: ImdVersion::Imd2019,
+            deciles: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// What happened when recomputing IMD deciles.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ImdRecompute {
+    /// Patients whose decile was missing and has been filled in.
+    pub filled: usize,
+    /// Patients whose decile was different in the lookup.
+    pub changed: usize,
+    /// Patients with an LSOA that isn't in the lookup. Their decile is left alone.
+    pub unmatched: usize,
+    /// Patients without an LSOA. Their decile is left alone.
+    pub no_lsoa: usize,
+}
+
+impl Patients {
+    /// Replace each patient's IMD decile with the one in `lookup` for their LSOA.
+    ///
+    /// This fills in missing deciles, and switches everyone we can to the lookup's IMD release.
+    /// Patients whose LSOA we don't have or can't find keep their original decile.
+    pub fn recompute_imd(&mut self, lookup: &ImdLookup) -> ImdRecompute {
+        let mut out = ImdRecompute::default();
+        for pat in self.0.iter_mut() {
+            let Some(lsoa) = &pat.lsoa else {
+                out.no_lsoa += 1;
+                continue;
+            };
+            let Some(imd) = lookup.decile(lsoa) else {
+                out.unmatched += 1;
+                continue;
+            };
+            if pat.imd == Imd::Missing {
+                out.filled += 1;
+            } else if pat.imd != imd {
+                out.changed += 1;
+            }
+            pat.imd = imd;
+        }
+        event!(
+            Level::INFO,
+            "recomputed IMD using {}: {} filled, {} changed, {} LSOAs not found, {} without LSOA",
+            lookup.version(),
+            out.filled,
+            out.changed,
+            out.unmatched,
+            out.no_lsoa
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Patient, PatientId, Sex};
+
+    #[test]
+    fn recompute() {
+        let patient = |patient_id: u64, lsoa: Option<&str>, imd| Patient {
+            patient_id: PatientId::new(patient_id),
+            year_of_birth: 1970,
+            month_of_birth: None,
+            sex: Sex::Female,
+            ethnicity: None,
+            lsoa: lsoa.map(ArcStr::from),
+            imd,
+            charlson: 0.,
+            lymphoma_diagnosis_date: None,
+            lymphoma_diagnosis_confidence: None,
+            lymphoma_subtypes: Default::default(),
+        };
+        let mut patients = Patients::new(vec![
+            patient(1, Some("E01000001"), Imd::Missing),
+            patient(2, Some("E01000002"), Imd::_3),
+            patient(3, Some("E01000003"), Imd::_5),
+            patient(4, None, Imd::_7),
+        ]);
+        let lookup: ImdLookup = [("E01000001", Imd::_9), ("E01000002", Imd::_4)]
+            .into_iter()
+            .map(|(lsoa, imd)| (ArcStr::from(lsoa), imd))
+            .collect();
+        let summary = patients.recompute_imd(&lookup);
+        assert_eq!(
+            summary,
+            ImdRecompute {
+                filled: 1,
+                changed: 1,
+                unmatched: 1,
+                no_lsoa: 1,
+            }
+        );
+        let imds: Vec<_> = patients.iter_ref().map(|pat| pat.imd).collect();
+        assert_eq!(imds, [Imd::_9, Imd::_4, Imd::_5, Imd::_7]);
+    }
+
+    #[test]
+    fn version_from_headers() {
+        let headers = |lad: &str| {
+            csv::StringRecord::from(vec![
+                "LSOA code (2011)",
+                "LSOA name (2011)",
+                lad,
+                "Index of Multiple Deprivation (IMD) Decile",
+            ])
+        };
+        assert_eq!(
+            ImdVersion::from_headers(&headers("Local Authority District code (2013)")).unwrap(),
+            ImdVersion::Imd2015
+        );
+        assert_eq!(
+            ImdVersion::from_headers(&headers("Local Authority District code (2019)")).unwrap(),
+            ImdVersion::Imd2019
+        );
+        assert!(ImdVersion::from_headers(&headers("Local Authority District code")).is_err());
+    }
+}
@@ -0,0 +1,138 @@
+//! How much we trust each patient's lymphoma diagnosis date.
+//!
+//! The diagnosis date is the date of the earliest lymphoma code, but that isn't always when the
+//! patient was diagnosed. When a patient registers with a new practice their past diagnoses are
+//! often added with the registration date (or a made-up date like 1900-01-01), sometimes as a
+//! "history of" code. We score each date so that analyses can be rerun with only the dates we are
+//! sure of.
+use crate::{Event, Registration};
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// Codes recorded this many days after registration may be from the patient's old record.
+const REGISTRATION_DAYS: i64 = 90;
+/// Words in a rubric that mean the event is about a past diagnosis.
+const HISTORICAL_WORDS: &[&str] = &["h/o", "history of", "hx of", "past history", "previous"];
+/// The Read v2 "history of" chapter (`14..`, e.g. `14A..` H/O cardiovascular disease).
+const HISTORY_PREFIX: &str = "14";
+
+/// How sure we are that a diagnosis date is when the patient was diagnosed.
+///
+/// The order is from least to most confident, so `confidence >= DateConfidence::Medium` works.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum DateConfidence {
+    /// A placeholder date, or a code saying the diagnosis was in the past.
+    Low,
+    /// Recorded before or soon after the patient registered with the practice, so it might have
+    /// been copied from their old record.
+    Medium,
+    High,
+}
+
+impl DateConfidence {
+    pub const ALL: [DateConfidence; 3] = [
+        DateConfidence::High,
+        DateConfidence::Medium,
+        DateConfidence::Low,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            DateConfidence::High => "High",
+            DateConfidence::Medium => "Medium (near registration)",
+            DateConfidence::Low => "Low (placeholder date or historical code)",
+        }
+    }
+
+    pub fn code(self) -> &'static str {
+        match self {
+            DateConfidence::High => "high",
+            DateConfidence::Medium => "medium",
+            DateConfidence::Low => "low",
+        }
+    }
+
+    /// Score a diagnosis event, given the patient's registration (if we have it).
+    pub fn score(event: &Event, registration: Option<&Registration>) -> Self {
+        if event.date_known().is_none() || is_historical(event) {
+            return DateConfidence::Low;
+        }
+        match registration {
+            Some(reg) if (event.date - reg.start_date).num_days() <= REGISTRATION_DAYS => {
+                DateConfidence::Medium
+            }
+            _ => DateConfidence::High,
+        }
+    }
+}
+
+impl fmt::Display for DateConfidence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+impl FromStr for DateConfidence {
+    type Err = anyhow::Error;
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        DateConfidence::ALL
+            .into_iter()
+            .find(|confidence| confidence.code() == input)
+            .ok_or_else(|| anyhow::format_err!("unrecognised date confidence \"{input}\""))
+    }
+}
+
+/// Whether `event` records a past diagnosis rather than a new one.
+fn is_historical(event: &Event) -> bool {
+    let code: &str = event.read_code.as_ref();
+    let rubric = event.rubric.to_lowercase();
+    code.starts_with(HISTORY_PREFIX) || HISTORICAL_WORDS.iter().any(|word| rubric.contains(word))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{test_event, PatientId};
+    use chrono::NaiveDate;
+
+    #[test]
+    fn score() {
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        let event = |date, code, rubric: &str| Event {
+            rubric: rubric.into(),
+            ..test_event(1, date, code)
+        };
+        let reg = Registration {
+            patient_id: PatientId::new(1),
+            start_date: date(2010, 1, 1),
+            end_date: None,
+            death_date: None,
+        };
+        let score = |evt: &Event| DateConfidence::score(evt, Some(&reg));
+
+        assert_eq!(
+            score(&event(date(2015, 3, 1), "B620.", "")),
+            DateConfidence::High
+        );
+        assert_eq!(
+            score(&event(date(1900, 1, 1), "B620.", "")),
+            DateConfidence::Low
+        );
+        assert_eq!(
+            score(&event(date(2015, 3, 1), "B620.", "H/O Hodgkin's")),
+            DateConfidence::Low
+        );
+        assert_eq!(
+            score(&event(date(2010, 2, 1), "B620.", "")),
+            DateConfidence::Medium
+        );
+        assert_eq!(
+            score(&event(date(2005, 2, 1), "B620.", "")),
+            DateConfidence::Medium
+        );
+        assert_eq!(
+            DateConfidence::score(&event(date(2010, 2, 1), "B620.", ""), None),
+            DateConfidence::High
+        );
+    }
+}
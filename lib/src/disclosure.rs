@@ -0,0 +1,130 @@
+//! Statistical disclosure control for tables we release.
+//!
+//! Our data provider's rules are that in any released output, counts of 1-4 must be suppressed and
+//! all other counts rounded to the nearest 5. Percentages must be calculated from the rounded
+//! counts, otherwise the original values could be worked back out.
+use std::fmt;
+
+/// How to protect counts before they are shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisclosureControl {
+    /// Non-zero counts below this are suppressed.
+    pub suppress_below: usize,
+    /// Counts that aren't suppressed are rounded to the nearest multiple of this.
+    pub round_to: usize,
+}
+
+impl DisclosureControl {
+    /// Show counts as they are, for internal use only.
+    pub const NONE: Self = Self {
+        suppress_below: 0,
+        round_to: 1,
+    };
+
+    /// The rules for outputs that will leave the secure environment.
+    pub const RELEASE: Self = Self {
+        suppress_below: 5,
+        round_to: 5,
+    };
+
+    /// Use [`Self::RELEASE`] if `release` is true, otherwise [`Self::NONE`].
+    pub fn new(release: bool) -> Self {
+        if release {
+            Self::RELEASE
+        } else {
+            Self::NONE
+        }
+    }
+
+    /// Apply the policy to a count.
+    ///
+    /// Zero is never suppressed, since it can't identify anyone.
+    pub fn count(&self, count: usize) -> Count {
+        if count > 0 && count < self.suppress_below {
+            return Count::Suppressed {
+                below: self.suppress_below,
+            };
+        }
+        let round_to = self.round_to.max(1);
+        // Round half up.
+        Count::Value((count + round_to / 2) / round_to * round_to)
+    }
+
+    /// `count` as a percentage of `total`, where both have had the policy applied.
+    ///
+    /// Returns `None` if either is suppressed or the total is zero.
+    pub fn percentage(&self, count: usize, total: usize) -> Option<f64> {
+        let count = self.count(count).value()?;
+        let total = self.count(total).value()?;
+        if total == 0 {
+            return None;
+        }
+        Some(count as f64 / total as f64 * 100.)
+    }
+
+    /// Format a count and percentage as `"count (pc%)"`, as used in our report tables.
+    pub fn count_with_percentage(&self, count: usize, total: usize) -> String {
+        match self.percentage(count, total) {
+            Some(pc) => format!("{} ({:.1}%)", self.count(count), pc),
+            None => self.count(count).to_string(),
+        }
+    }
+}
+
+impl Default for DisclosureControl {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+/// A count after disclosure control has been applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Count {
+    Value(usize),
+    Suppressed { below: usize },
+}
+
+impl Count {
+    /// The count, if it wasn't suppressed.
+    pub fn value(self) -> Option<usize> {
+        match self {
+            Count::Value(v) => Some(v),
+            Count::Suppressed { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for Count {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Count::Value(v) => write!(f, "{}", v),
+            Count::Suppressed { below } => write!(f, "<{}", below),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn release() {
+        let dc = DisclosureControl::RELEASE;
+        assert_eq!(dc.count(0), Count::Value(0));
+        assert_eq!(dc.count(1), Count::Suppressed { below: 5 });
+        assert_eq!(dc.count(4), Count::Suppressed { below: 5 });
+        assert_eq!(dc.count(5), Count::Value(5));
+        assert_eq!(dc.count(7), Count::Value(5));
+        assert_eq!(dc.count(8), Count::Value(10));
+        assert_eq!(dc.percentage(8, 18), Some(50.));
+        assert_eq!(dc.count_with_percentage(3, 100), "<5");
+    }
+
+    #[test]
+    fn none() {
+        let dc = DisclosureControl::NONE;
+        assert_eq!(dc.count(3), Count::Value(3));
+        assert_eq!(dc.count_with_percentage(1, 4), "1 (25.0%)");
+        assert_eq!(dc.count_with_percentage(0, 0), "0");
+    }
+}
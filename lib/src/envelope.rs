@@ -0,0 +1,240 @@
+//! A small self-describing header for our saved data files.
+//!
+//! Raw bincode has no idea what it's decoding, so if a field is added to e.g. `Event` then old
+//! files either fail with an unhelpful error, or worse, decode into garbage. Files are now written
+//! as
+//!
+//! ```text
+//! MAGIC | format version (u32 LE) | Header (bincode) | Vec<T> (bincode)
+//! ```
+//!
+//! and loading checks the header against the type being loaded. Files without the magic bytes are
+//! assumed to be from before this change and are decoded as raw bincode.
+//!
+//! The header says when the file was written, so saving the same data twice gives different bytes.
+//! Use [`contents_sha256`] to tell whether two files hold the same data.
+use crate::progress::Progress;
+use chrono::{DateTime, Utc};
+use qu::ick_use::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{self, Read, Write};
+
+const MAGIC: &[u8; 8] = b"EADAPT\x00\x01";
+
+/// Bump this if the layout of the header itself changes.
+const FORMAT_VERSION: u32 = 1;
+
+/// A type that can be saved with a header.
+///
+/// `SCHEMA` describes the serialized layout of the type. It is hashed into the file header, so it
+/// must be updated whenever a field is added, removed, reordered or changes type.
+pub trait Schema {
+    const SCHEMA: &'static str;
+
+    fn schema_hash() -> u64 {
+        fnv1a(Self::SCHEMA.as_bytes())
+    }
+}
+
+/// Information stored at the start of every saved file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Header {
+    pub type_name: String,
+    pub schema_hash: u64,
+    pub record_count: u64,
+    pub created: DateTime<Utc>,
+    pub crate_version: String,
+}
+
+/// Write `contents` with a header.
+pub fn write<T: Schema + Serialize>(
+    mut out: impl Write,
+    contents: &[T],
+    progress: Progress,
+) -> Result {
+    let header = Header {
+        type_name: std::any::type_name::<T>().to_string(),
+        schema_hash: T::schema_hash(),
+        record_count: contents.len() as u64,
+        created: Utc::now(),
+        crate_version: env!("CARGO_PKG_VERSION").to_string(),
+    };
+    out.write_all(MAGIC)?;
+    out.write_all(&FORMAT_VERSION.to_le_bytes())?;
+    bincode::serialize_into(&mut out, &header)?;
+    // Write the elements one at a time so we can report progress. This gives the same bytes as
+    // serializing the whole slice.
+    bincode::serialize_into(&mut out, &(contents.len() as u64))?;
+    for el in contents {
+        bincode::serialize_into(&mut out, el)?;
+        progress.inc();
+    }
+    out.flush()?;
+    progress.finish();
+    Ok(())
+}
+
+/// Read data written by [`write`], or a legacy file without a header.
+pub fn read<T: Schema + DeserializeOwned>(mut input: impl Read) -> Result<Vec<T>> {
+    let mut magic = [0; MAGIC.len()];
+    let n = read_up_to(&mut input, &mut magic)?;
+    if n < MAGIC.len() || &magic != MAGIC {
+        event!(
+            Level::WARN,
+            "file has no header (written by an older version?), loading as raw bincode. \
+            Re-save the file to add a header"
+        );
+        let input = io::Cursor::new(&magic[..n]).chain(input);
+        return bincode::deserialize_from(input)
+            .context("decoding legacy file (it may have been written with a different schema)");
+    }
+
+    let header = read_header(&mut input)?;
+    header.check::<T>()?;
+
+    let contents: Vec<T> = bincode::deserialize_from(input).context("decoding file contents")?;
+    ensure!(
+        contents.len() as u64 == header.record_count,
+        "expected {} records, found {}",
+        header.record_count,
+        contents.len()
+    );
+    Ok(contents)
+}
+
+/// The SHA-256 of the data written by [`write`], leaving out the header, in lowercase hex.
+///
+/// Legacy files without a header are hashed whole.
+pub fn contents_sha256(mut input: impl Read) -> Result<String> {
+    let mut hasher = Sha256::new();
+    let mut magic = [0; MAGIC.len()];
+    let n = read_up_to(&mut input, &mut magic)?;
+    if n == MAGIC.len() && &magic == MAGIC {
+        read_header(&mut input)?;
+    } else {
+        hasher.update(&magic[..n]);
+    }
+    io::copy(&mut input, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Read the format version and header that follow the magic bytes.
+fn read_header(mut input: impl Read) -> Result<Header> {
+    let mut version = [0; 4];
+    input.read_exact(&mut version)?;
+    let version = u32::from_le_bytes(version);
+    ensure!(
+        version == FORMAT_VERSION,
+        "unsupported file format version {} (expected {})",
+        version,
+        FORMAT_VERSION
+    );
+    bincode::deserialize_from(input).context("decoding file header")
+}
+
+impl Header {
+    fn check<T: Schema>(&self) -> Result {
+        ensure!(
+            self.schema_hash == T::schema_hash(),
+            "file was written with a different schema: it contains `{}` data written by version \
+            {} at {}, but we are loading `{}` (schema hash {:016x} in file, {:016x} expected). \
+            Regenerate the file from the original data",
+            self.type_name,
+            self.crate_version,
+            self.created,
+            std::any::type_name::<T>(),
+            self.schema_hash,
+            T::schema_hash()
+        );
+        Ok(())
+    }
+}
+
+/// Like `read_exact`, but returns the number of bytes read rather than failing at EOF.
+fn read_up_to(input: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match input.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(m) => n += m,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(n)
+}
+
+/// A hash that is stable across rust versions (unlike `DefaultHasher`).
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct A(u32, String);
+
+    impl Schema for A {
+        const SCHEMA: &'static str = "A(u32, String)";
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct B(u32);
+
+    impl Schema for B {
+        const SCHEMA: &'static str = "B(u32)";
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = vec![A(1, "one".into()), A(2, "two".into())];
+        let mut buf = vec![];
+        write(&mut buf, &data, Progress::new("test", None)).unwrap();
+        assert_eq!(read::<A>(&buf[..]).unwrap(), data);
+    }
+
+    #[test]
+    fn contents_hash() {
+        let save = |data: &[A]| {
+            let mut buf = vec![];
+            write(&mut buf, data, Progress::new("test", None)).unwrap();
+            buf
+        };
+        let data = vec![A(1, "one".into())];
+        let (first, second) = (save(&data), save(&data));
+        // Saving again only changes the header.
+        let hash = contents_sha256(&first[..]).unwrap();
+        assert_eq!(contents_sha256(&second[..]).unwrap(), hash);
+        assert_ne!(
+            contents_sha256(&save(&[A(2, "two".into())])[..]).unwrap(),
+            hash
+        );
+        let legacy = bincode::serialize(&data).unwrap();
+        assert_eq!(
+            contents_sha256(&legacy[..]).unwrap(),
+            format!("{:x}", Sha256::digest(&legacy))
+        );
+    }
+
+    #[test]
+    fn legacy() {
+        let data = vec![A(1, "one".into())];
+        let buf = bincode::serialize(&data).unwrap();
+        assert_eq!(read::<A>(&buf[..]).unwrap(), data);
+    }
+
+    #[test]
+    fn schema_mismatch() {
+        let mut buf = vec![];
+        write(&mut buf, &[A(1, "one".into())], Progress::new("test", None)).unwrap();
+        assert!(read::<B>(&buf[..]).is_err());
+    }
+}
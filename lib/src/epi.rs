@@ -0,0 +1,276 @@
+//! Person-time and incidence rates.
+//!
+//! Each patient is followed from an index date (usually lymphoma diagnosis) until a censoring date
+//! (usually [`Registrations::follow_up_end`](crate::Registrations::follow_up_end)). For incidence,
+//! patients who already have the condition at their index date aren't at risk, and follow-up
+//! stops at the first code for the condition.
+pub mod standardise;
+
+use crate::{read2::CodeSet, Events, PatientId, Patients};
+use chrono::NaiveDate;
+use statrs::{
+    distribution::{ContinuousCDF, Normal},
+    function::gamma::gamma_lr,
+};
+use std::collections::HashMap;
+
+const DAYS_PER_YEAR: f64 = 365.25;
+
+/// The total follow-up of `patients`, in years.
+///
+/// Patients without an index date are left out, as is any time after the censoring date.
+pub fn person_years(
+    patients: &Patients,
+    index_dates: &HashMap<PatientId, NaiveDate>,
+    censor_date: impl Fn(PatientId) -> NaiveDate,
+) -> f64 {
+    patients
+        .iter_ref()
+        .filter_map(|pat| {
+            let start = *index_dates.get(&pat.patient_id)?;
+            Some(years_between(start, censor_date(pat.patient_id)))
+        })
+        .sum()
+}
+
+/// The rate of new cases of the condition defined by `codeset`.
+///
+/// A new case is a patient whose first code in `codeset` is after their index date and on or
+/// before their censoring date. Patients with a code on or before their index date are left out.
+pub fn incidence_rate(
+    patients: &Patients,
+    events: &Events,
+    codeset: &CodeSet,
+    index_dates: &HashMap<PatientId, NaiveDate>,
+    censor_date: impl Fn(PatientId) -> NaiveDate,
+) -> IncidenceRate {
+    IncidenceRate::from_times(&time_to_event(
+        patients,
+        events,
+        codeset,
+        index_dates,
+        censor_date,
+    ))
+}
+
+/// How long a patient was followed, and whether follow-up ended with a new case.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TimeToEvent {
+    pub patient_id: PatientId,
+    /// From the index date to the first code, or to the censoring date if there isn't one.
+    pub years: f64,
+    /// Whether the patient had a code before the censoring date.
+    pub event: bool,
+}
+
+/// Follow-up for each patient at risk of the condition defined by `codeset`, with the same
+/// rules as [`incidence_rate`].
+pub fn time_to_event(
+    patients: &Patients,
+    events: &Events,
+    codeset: &CodeSet,
+    index_dates: &HashMap<PatientId, NaiveDate>,
+    censor_date: impl Fn(PatientId) -> NaiveDate,
+) -> Vec<TimeToEvent> {
+    let mut out = vec![];
+    for pat in patients.iter_ref() {
+        let Some(&start) = index_dates.get(&pat.patient_id) else {
+            continue;
+        };
+        let first = events
+            .events_for_patient(pat.patient_id)
+            .filter(|evt| codeset.contains(evt.read_code))
+            .map(|evt| evt.date)
+            .min();
+        if matches!(first, Some(first) if first <= start) {
+            // prevalent case (including codes with a missing date, which could be from any time)
+            continue;
+        }
+        let censor = censor_date(pat.patient_id);
+        out.push(match first {
+            Some(first) if first <= censor => TimeToEvent {
+                patient_id: pat.patient_id,
+                years: years_between(start, first),
+                event: true,
+            },
+            _ => TimeToEvent {
+                patient_id: pat.patient_id,
+                years: years_between(start, censor),
+                event: false,
+            },
+        });
+    }
+    out
+}
+
+/// The Kaplan-Meier estimate of the proportion of patients who have had the event by `years`.
+///
+/// Returns `None` if there are no patients.
+pub fn cumulative_incidence(times: &[TimeToEvent], years: f64) -> Option<f64> {
+    if times.is_empty() {
+        return None;
+    }
+    let mut times = times.to_vec();
+    times.sort_by(|a, b| a.years.total_cmp(&b.years));
+    let mut at_risk = times.len();
+    let mut survival = 1.;
+    // Events at the same time as a censoring are counted first.
+    for group in times.chunk_by(|a, b| a.years == b.years) {
+        if group[0].years > years {
+            break;
+        }
+        let events = group.iter().filter(|time| time.event).count();
+        survival *= 1. - events as f64 / at_risk as f64;
+        at_risk -= group.len();
+    }
+    Some(1. - survival)
+}
+
+/// A number of cases over some follow-up time.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct IncidenceRate {
+    pub cases: usize,
+    pub person_years: f64,
+}
+
+impl IncidenceRate {
+    /// The rate from a list of follow-up times.
+    pub fn from_times(times: &[TimeToEvent]) -> Self {
+        IncidenceRate {
+            cases: times.iter().filter(|time| time.event).count(),
+            person_years: times.iter().map(|time| time.years).sum(),
+        }
+    }
+
+    /// Cases per 1,000 person-years, or `None` if there is no follow-up.
+    pub fn per_1000(&self) -> Option<f64> {
+        (self.person_years > 0.).then(|| self.cases as f64 / self.person_years * 1000.)
+    }
+
+    /// An exact (Garwood) confidence interval for the rate per 1,000 person-years, e.g. `level`
+    /// 0.95 for a 95% interval.
+    pub fn ci_per_1000(&self, level: f64) -> Option<(f64, f64)> {
+        if self.person_years <= 0. {
+            return None;
+        }
+        let (low, high) = poisson_ci(self.cases, level);
+        let scale = 1000. / self.person_years;
+        Some((low * scale, high * scale))
+    }
+
+    /// This rate divided by `other`, with a confidence interval worked out on the log scale.
+    ///
+    /// Returns `None` if either rate has no cases, as the interval is then unbounded.
+    pub fn rate_ratio(&self, other: &IncidenceRate, level: f64) -> Option<(f64, (f64, f64))> {
+        if self.cases == 0 || other.cases == 0 {
+            return None;
+        }
+        let ratio = self.per_1000()? / other.per_1000()?;
+        let z = Normal::new(0., 1.)
+            .unwrap()
+            .inverse_cdf(1. - (1. - level) / 2.);
+        let se = (1. / self.cases as f64 + 1. / other.cases as f64).sqrt();
+        Some((ratio, (ratio * (-z * se).exp(), ratio * (z * se).exp())))
+    }
+}
+
+/// An exact confidence interval for the mean of a Poisson distribution, given one observation.
+pub fn poisson_ci(count: usize, level: f64) -> (f64, f64) {
+    assert!(
+        0. < level && level < 1.,
+        "confidence level must be between 0 and 1"
+    );
+    let alpha = 1. - level;
+    let k = count as f64;
+    // The bounds are quantiles of gamma distributions with shape k and k + 1 (scale 1).
+    let low = if count == 0 {
+        0.
+    } else {
+        gamma_quantile(k, alpha / 2.)
+    };
+    let high = gamma_quantile(k + 1., 1. - alpha / 2.);
+    (low, high)
+}
+
+/// The `p` quantile of the gamma distribution with shape `shape` and scale 1, by bisection.
+fn gamma_quantile(shape: f64, p: f64) -> f64 {
+    let mut low = 0.;
+    let mut high = shape.max(1.);
+    while gamma_lr(shape, high) < p {
+        high *= 2.;
+    }
+    for _ in 0..100 {
+        let mid = (low + high) / 2.;
+        if gamma_lr(shape, mid) < p {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    (low + high) / 2.
+}
+
+pub(crate) fn years_between(start: NaiveDate, end: NaiveDate) -> f64 {
+    ((end - start).num_days().max(0)) as f64 / DAYS_PER_YEAR
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn poisson() {
+        // Values from R's `poisson.test`.
+        let (low, high) = poisson_ci(0, 0.95);
+        assert_eq!(low, 0.);
+        assert!((high - 3.688879).abs() < 1e-5);
+        let (low, high) = poisson_ci(10, 0.95);
+        assert!((low - 4.795389).abs() < 1e-5);
+        assert!((high - 18.390356).abs() < 1e-5);
+    }
+
+    #[test]
+    fn rate() {
+        let rate = IncidenceRate {
+            cases: 10,
+            person_years: 2000.,
+        };
+        assert_eq!(rate.per_1000(), Some(5.));
+        let (low, high) = rate.ci_per_1000(0.95).unwrap();
+        assert!((low - 2.397695).abs() < 1e-5);
+        assert!((high - 9.195178).abs() < 1e-5);
+        assert_eq!(IncidenceRate::default().per_1000(), None);
+    }
+
+    #[test]
+    fn rate_ratio() {
+        let rate = |cases| IncidenceRate {
+            cases,
+            person_years: 1000.,
+        };
+        let (ratio, (low, high)) = rate(20).rate_ratio(&rate(10), 0.95).unwrap();
+        assert!((ratio - 2.).abs() < 1e-10);
+        assert!((low - 0.9362).abs() < 1e-4);
+        assert!((high - 4.2727).abs() < 1e-4);
+        assert_eq!(rate(20).rate_ratio(&rate(0), 0.95), None);
+    }
+
+    #[test]
+    fn kaplan_meier() {
+        let time = |years, event| TimeToEvent {
+            patient_id: PatientId::new(0),
+            years,
+            event,
+        };
+        let times = [
+            time(3., true),
+            time(1., true),
+            time(4., false),
+            time(2., false),
+        ];
+        assert_eq!(cumulative_incidence(&times, 0.5), Some(0.));
+        assert_eq!(cumulative_incidence(&times, 2.), Some(0.25));
+        assert_eq!(cumulative_incidence(&times, 5.), Some(0.625));
+        assert_eq!(cumulative_incidence(&[], 5.), None);
+    }
+}
@@ -0,0 +1,371 @@
+//! Direct standardisation of prevalence by age and sex.
+//!
+//! Our cohort is much younger than the general population, so crude prevalence can't be compared
+//! directly with reference rates. Instead we weight the prevalence in each age-sex stratum by the
+//! size of that stratum in a standard population.
+use super::poisson_ci;
+use crate::{Patient, Patients, Sex};
+use chrono::NaiveDate;
+use qu::ick_use::*;
+use std::{collections::BTreeMap, fmt};
+
+/// The width of the age bands, in years.
+const BAND_WIDTH: u16 = 5;
+/// The lower bound of the oldest (open) age band.
+const OLDEST_BAND: u16 = 90;
+
+/// The 2013 European Standard Population, for ages 0-4, 5-9, ..., 85-89, 90+.
+const ESP_2013: [f64; 19] = [
+    5000., 5500., 5500., 5500., 6000., 6000., 6500., 7000., 7000., 7000., 7000., 6500., 6000.,
+    5500., 5000., 4000., 2500., 1500., 1000.,
+];
+
+/// A 5-year age band and sex.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AgeSexStratum {
+    /// The lower bound of the age band, a multiple of 5. The last band (90) is open.
+    pub age_band: u16,
+    pub sex: Sex,
+}
+
+impl AgeSexStratum {
+    pub fn new(age: u16, sex: Sex) -> Self {
+        Self {
+            age_band: (age / BAND_WIDTH * BAND_WIDTH).min(OLDEST_BAND),
+            sex,
+        }
+    }
+
+    /// The stratum a patient was in at `date`, or `None` if their age then isn't plausible (see
+    /// [`Patient::plausible_age_at`]).
+    pub fn of_patient(patient: &Patient, date: NaiveDate) -> Option<Self> {
+        Some(Self::new(patient.plausible_age_at(date)?, patient.sex))
+    }
+}
+
+impl fmt::Display for AgeSexStratum {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.age_band >= OLDEST_BAND {
+            write!(f, "{}+ {}", self.age_band, self.sex)
+        } else {
+            write!(
+                f,
+                "{}-{} {}",
+                self.age_band,
+                self.age_band + BAND_WIDTH - 1,
+                self.sex
+            )
+        }
+    }
+}
+
+/// The 2013 European Standard Population, with the same structure for men and women.
+pub fn european_standard_population() -> BTreeMap<AgeSexStratum, f64> {
+    let mut out = BTreeMap::new();
+    for sex in [Sex::Male, Sex::Female] {
+        for (idx, weight) in ESP_2013.iter().enumerate() {
+            let stratum = AgeSexStratum::new(idx as u16 * BAND_WIDTH, sex);
+            out.insert(stratum, *weight);
+        }
+    }
+    out
+}
+
+/// Cases and population in one stratum.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct StratumCount {
+    pub cases: usize,
+    pub population: usize,
+}
+
+/// Count the patients with and without a condition in each stratum at `date`.
+///
+/// The standard population only has men and women, so patients whose sex is recorded as anything
+/// else are left out, as are patients whose age isn't plausible (and how many there were is
+/// logged).
+pub fn prevalence_by_stratum(
+    patients: &Patients,
+    date: NaiveDate,
+    has_condition: impl Fn(&Patient) -> bool,
+) -> BTreeMap<AgeSexStratum, StratumCount> {
+    let mut out: BTreeMap<AgeSexStratum, StratumCount> = BTreeMap::new();
+    let mut unknown_sex = 0;
+    let mut implausible_age = 0;
+    for pat in patients.iter_ref() {
+        if !pat.sex.is_known() {
+            unknown_sex += 1;
+            continue;
+        }
+        let Some(stratum) = AgeSexStratum::of_patient(pat, date) else {
+            implausible_age += 1;
+            continue
+        };
+        let count = out.entry(stratum).or_default();
+        count.population += 1;
+        if has_condition(pat) {
+            count.cases += 1;
+        }
+    }
+    if unknown_sex > 0 {
+        event!(
+            Level::WARN,
+            "left {unknown_sex} patients whose sex isn't male or female out of standardisation"
+        );
+    }
+    if implausible_age > 0 {
+        event!(
+            Level::WARN,
+            "left {implausible_age} patients with an implausible age out of standardisation"
+        );
+    }
+    out
+}
+
+/// The age bands from the youngest to the oldest with anyone in them in our cohort.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AgeBands {
+    /// The lower bound of the youngest band.
+    pub youngest: u16,
+    /// The lower bound of the oldest band.
+    pub oldest: u16,
+}
+
+impl AgeBands {
+    /// The bands covered by the strata with anyone in them in `counts`, or `None` if there
+    /// aren't any.
+    pub fn covered(counts: &BTreeMap<AgeSexStratum, StratumCount>) -> Option<Self> {
+        let mut bands = counts
+            .iter()
+            .filter(|(_, count)| count.population > 0)
+            .map(|(stratum, _)| stratum.age_band);
+        let first = bands.next()?;
+        let (youngest, oldest) = bands.fold((first, first), |(lo, hi), band| {
+            (lo.min(band), hi.max(band))
+        });
+        Some(AgeBands { youngest, oldest })
+    }
+
+    pub fn contains(&self, stratum: &AgeSexStratum) -> bool {
+        (self.youngest..=self.oldest).contains(&stratum.age_band)
+    }
+}
+
+impl fmt::Display for AgeBands {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.oldest >= OLDEST_BAND {
+            write!(f, "ages {}+", self.youngest)
+        } else {
+            write!(f, "ages {}-{}", self.youngest, self.oldest + BAND_WIDTH - 1)
+        }
+    }
+}
+
+/// A directly standardised proportion.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Standardised {
+    /// The proportion before standardisation.
+    pub crude: f64,
+    /// The standardised proportion.
+    pub standardised: f64,
+    /// Confidence interval for the standardised proportion, or `None` if there were no cases.
+    pub ci: Option<(f64, f64)>,
+}
+
+/// Directly standardise the proportions in `counts` to the `standard` population.
+///
+/// The weights cover the whole of `standard`, and strata with nobody in them in our cohort count as
+/// having no cases (how much of the standard population this affected is logged). So restrict the
+/// standard to the ages the cohort covers first, as [`standardise_to_esp`] does. The confidence
+/// interval uses the method of Dobson et al. (1991), which is based on the exact Poisson interval
+/// for the total number of cases.
+pub fn direct_standardise<K: Ord>(
+    counts: &BTreeMap<K, StratumCount>,
+    standard: &BTreeMap<K, f64>,
+    level: f64,
+) -> Standardised {
+    let total_weight: f64 = standard.values().sum();
+    let strata: Vec<(f64, StratumCount)> = counts
+        .iter()
+        .filter(|(_, count)| count.population > 0)
+        .filter_map(|(key, count)| Some((*standard.get(key)?, *count)))
+        .collect();
+    let total_cases: usize = strata.iter().map(|(_, c)| c.cases).sum();
+    let total_population: usize = strata.iter().map(|(_, c)| c.population).sum();
+    let empty_weight = total_weight - strata.iter().map(|(w, _)| w).sum::<f64>();
+    if empty_weight > 0. {
+        event!(
+            Level::WARN,
+            "{:.1}% of the standard population is in strata with nobody in them, so counts as \
+            having no cases",
+            empty_weight / total_weight * 100.
+        );
+    }
+
+    // For each stratum, the weight to multiply the cases by.
+    let mut standardised = 0.;
+    let mut variance = 0.;
+    for (weight, count) in strata.iter() {
+        let w = weight / (total_weight * count.population as f64);
+        standardised += w * count.cases as f64;
+        variance += w * w * count.cases as f64;
+    }
+
+    let crude = if total_population == 0 {
+        f64::NAN
+    } else {
+        total_cases as f64 / total_population as f64
+    };
+    let ci = (total_cases > 0).then(|| {
+        let observed = total_cases as f64;
+        let (low, high) = poisson_ci(total_cases, level);
+        let scale = (variance / observed).sqrt();
+        (
+            standardised + scale * (low - observed),
+            standardised + scale * (high - observed),
+        )
+    });
+    Standardised {
+        crude,
+        standardised: if total_weight > 0. {
+            standardised
+        } else {
+            f64::NAN
+        },
+        ci,
+    }
+}
+
+/// A proportion standardised to the European Standard Population, over the ages our cohort covers.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct EspStandardised {
+    /// The age bands the standard population was restricted to.
+    pub ages: AgeBands,
+    pub proportion: Standardised,
+}
+
+/// Directly standardise `counts` to the 2013 European Standard Population, restricted (and
+/// rescaled) to the age bands our cohort covers. Returns `None` if `counts` has nobody in it.
+///
+/// Our cohort is all adults, so standardising over the whole ESP would count the childhood bands
+/// (about a fifth of the standard) as having no cases, and pull every rate down. The result is
+/// only comparable with rates over the same ages, so it says which ages they were.
+pub fn standardise_to_esp(
+    counts: &BTreeMap<AgeSexStratum, StratumCount>,
+    level: f64,
+) -> Option<EspStandardised> {
+    let ages = AgeBands::covered(counts)?;
+    let mut standard = european_standard_population();
+    standard.retain(|stratum, _| ages.contains(stratum));
+    Some(EspStandardised {
+        ages,
+        proportion: direct_standardise(counts, &standard, level),
+    })
+}
+
+impl fmt::Display for EspStandardised {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.1}% (standardised to the ESP 2013, {})",
+            self.proportion.standardised * 100.,
+            self.ages
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn strata() {
+        assert_eq!(AgeSexStratum::new(37, Sex::Male).age_band, 35);
+        assert_eq!(AgeSexStratum::new(97, Sex::Male).age_band, 90);
+        assert_eq!(
+            AgeSexStratum::new(92, Sex::Female).to_string(),
+            "90+ Female"
+        );
+        let esp = european_standard_population();
+        assert_eq!(esp.len(), 38);
+        assert_eq!(esp.values().sum::<f64>(), 200_000.);
+    }
+
+    #[test]
+    fn standardise() {
+        let count = |cases, population| StratumCount { cases, population };
+        // With the same prevalence in every stratum the standardised and crude rates agree.
+        let counts: BTreeMap<u8, StratumCount> = [(0, count(10, 100)), (1, count(50, 500))]
+            .into_iter()
+            .collect();
+        let standard: BTreeMap<u8, f64> = [(0, 3.), (1, 1.)].into_iter().collect();
+        let s = direct_standardise(&counts, &standard, 0.95);
+        assert!((s.crude - 0.1).abs() < 1e-12);
+        assert!((s.standardised - 0.1).abs() < 1e-12);
+        let (low, high) = s.ci.unwrap();
+        assert!(low < 0.1 && 0.1 < high);
+
+        // Weighting towards the stratum with higher prevalence.
+        let counts: BTreeMap<u8, StratumCount> = [(0, count(50, 100)), (1, count(50, 500))]
+            .into_iter()
+            .collect();
+        let s = direct_standardise(&counts, &standard, 0.95);
+        assert!((s.standardised - (0.75 * 0.5 + 0.25 * 0.1)).abs() < 1e-12);
+
+        // A stratum with nobody in it counts as having no cases, rather than dropping its weight.
+        let counts: BTreeMap<u8, StratumCount> = [(0, count(10, 100)), (1, count(0, 0))]
+            .into_iter()
+            .collect();
+        let s = direct_standardise(&counts, &standard, 0.95);
+        assert!((s.standardised - 0.75 * 0.1).abs() < 1e-12);
+        let s = direct_standardise(&counts.into_iter().take(1).collect(), &standard, 0.95);
+        assert!((s.standardised - 0.75 * 0.1).abs() < 1e-12);
+    }
+
+    #[test]
+    fn esp_over_cohort_ages() {
+        let count = |cases, population| StratumCount { cases, population };
+        // Everyone is aged 20-34 or 50-54, and 10% have the condition.
+        let counts: BTreeMap<AgeSexStratum, StratumCount> = [20, 25, 30, 50]
+            .into_iter()
+            .flat_map(|age| {
+                [Sex::Male, Sex::Female].map(|sex| (AgeSexStratum::new(age, sex), count(1, 10)))
+            })
+            .collect();
+        let s = standardise_to_esp(&counts, 0.95).unwrap();
+        assert_eq!(
+            s.ages,
+            AgeBands {
+                youngest: 20,
+                oldest: 50
+            }
+        );
+        // The empty 35-49 bands count as no cases, but the childhood and older bands are left out.
+        let weight = |bands: &[usize]| bands.iter().map(|band| ESP_2013[*band]).sum::<f64>();
+        let expected = 0.1 * weight(&[4, 5, 6, 10]) / weight(&[4, 5, 6, 7, 8, 9, 10]);
+        assert!((s.proportion.standardised - expected).abs() < 1e-12);
+        assert_eq!(
+            s.to_string(),
+            format!(
+                "{:.1}% (standardised to the ESP 2013, ages 20-54)",
+                expected * 100.
+            )
+        );
+        assert_eq!(standardise_to_esp(&BTreeMap::new(), 0.95), None);
+    }
+
+    #[test]
+    fn implausible_ages() {
+        let date = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
+        let patient = |year_of_birth| Patient {
+            year_of_birth,
+            sex: Sex::Male,
+            ..crate::test_patient(1)
+        };
+        assert_eq!(
+            AgeSexStratum::of_patient(&patient(1950), date),
+            Some(AgeSexStratum::new(70, Sex::Male))
+        );
+        assert_eq!(AgeSexStratum::of_patient(&patient(1880), date), None);
+        assert_eq!(AgeSexStratum::of_patient(&patient(2030), date), None);
+    }
+}
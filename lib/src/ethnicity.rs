@@ -0,0 +1,327 @@
+//! Ethnicity, using the categories from the 2011 census in England and Wales.
+//!
+//! The extract's `Ethnicity` column is free text, usually an NHS ethnic category letter code. Many
+//! patients only have their ethnicity recorded as a Read code in their events, so we look there
+//! too.
+use crate::{Event, Events, GroupCounts, Patient, PatientId, Patients, ReadCode};
+use once_cell::sync::Lazy;
+use std::{collections::BTreeMap, fmt};
+
+/// Read codes for ethnicity, more specific codes first. A code maps to the first entry it equals or
+/// is a child of.
+///
+/// "Not stated" and "not recorded" codes aren't included, since they don't tell us anything.
+const READ_CODES: &[(&str, Ethnicity)] = &[
+    // 2001 census categories
+    ("9i20.", Ethnicity::WhiteBritish),
+    ("9i21.", Ethnicity::WhiteBritish),
+    ("9i22.", Ethnicity::WhiteBritish),
+    ("9i23.", Ethnicity::WhiteBritish),
+    ("9i24.", Ethnicity::WhiteBritish),
+    ("9i25.", Ethnicity::WhiteBritish),
+    ("9i2C.", Ethnicity::GypsyOrIrishTraveller),
+    ("9i2D.", Ethnicity::GypsyOrIrishTraveller),
+    ("9i2E.", Ethnicity::GypsyOrIrishTraveller),
+    ("9i0..", Ethnicity::WhiteBritish),
+    ("9i1..", Ethnicity::WhiteIrish),
+    ("9i2..", Ethnicity::OtherWhite),
+    ("9i3..", Ethnicity::WhiteAndBlackCaribbean),
+    ("9i4..", Ethnicity::WhiteAndBlackAfrican),
+    ("9i5..", Ethnicity::WhiteAndAsian),
+    ("9i6..", Ethnicity::OtherMixed),
+    ("9i7..", Ethnicity::Indian),
+    ("9i8..", Ethnicity::Pakistani),
+    ("9i9..", Ethnicity::Bangladeshi),
+    ("9iA..", Ethnicity::OtherAsian),
+    ("9iB..", Ethnicity::Caribbean),
+    ("9iC..", Ethnicity::African),
+    ("9iD..", Ethnicity::OtherBlack),
+    ("9iE..", Ethnicity::Chinese),
+    ("9iF9.", Ethnicity::Arab),
+    ("9iF..", Ethnicity::Other),
+    // 1991 census categories
+    ("9S10.", Ethnicity::WhiteBritish),
+    ("9S11.", Ethnicity::WhiteIrish),
+    ("9S13.", Ethnicity::WhiteBritish),
+    ("9S14.", Ethnicity::WhiteBritish),
+    ("9S1..", Ethnicity::OtherWhite),
+    ("9S2..", Ethnicity::Caribbean),
+    ("9S3..", Ethnicity::African),
+    ("9S42.", Ethnicity::Caribbean),
+    ("9S43.", Ethnicity::African),
+    ("9S44.", Ethnicity::African),
+    ("9S4..", Ethnicity::OtherBlack),
+    ("9S5..", Ethnicity::OtherMixed),
+    ("9S6..", Ethnicity::Indian),
+    ("9S7..", Ethnicity::Pakistani),
+    ("9S8..", Ethnicity::Bangladeshi),
+    ("9S9..", Ethnicity::Chinese),
+    ("9SA3.", Ethnicity::Caribbean),
+    ("9SA4.", Ethnicity::Arab),
+    ("9SA5.", Ethnicity::African),
+    ("9SA6.", Ethnicity::OtherAsian),
+    ("9SA7.", Ethnicity::OtherAsian),
+    ("9SA8.", Ethnicity::OtherAsian),
+    ("9SA9.", Ethnicity::WhiteIrish),
+    ("9SAA.", Ethnicity::OtherWhite),
+    ("9SAB.", Ethnicity::OtherWhite),
+    ("9SAC.", Ethnicity::OtherWhite),
+    ("9SA..", Ethnicity::Other),
+    ("9SB2.", Ethnicity::WhiteAndAsian),
+    ("9SB5.", Ethnicity::WhiteAndBlackCaribbean),
+    ("9SB6.", Ethnicity::WhiteAndBlackAfrican),
+    ("9SB..", Ethnicity::OtherMixed),
+    ("9SC..", Ethnicity::OtherAsian),
+    ("9SG..", Ethnicity::OtherBlack),
+    ("9SH..", Ethnicity::OtherAsian),
+    ("9SI..", Ethnicity::GypsyOrIrishTraveller),
+    ("9SJ..", Ethnicity::Other),
+];
+
+/// [`READ_CODES`], parsed once.
+static PARSED_READ_CODES: Lazy<Vec<(ReadCode, Ethnicity)>> = Lazy::new(|| {
+    READ_CODES
+        .iter()
+        .map(|(code, ethnicity)| (ReadCode::from_str(code).unwrap(), *ethnicity))
+        .collect()
+});
+
+/// The 18 ethnic groups from the 2011 census.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Ethnicity {
+    /// English, Welsh, Scottish, Northern Irish or British.
+    WhiteBritish,
+    WhiteIrish,
+    GypsyOrIrishTraveller,
+    OtherWhite,
+    WhiteAndBlackCaribbean,
+    WhiteAndBlackAfrican,
+    WhiteAndAsian,
+    OtherMixed,
+    Indian,
+    Pakistani,
+    Bangladeshi,
+    Chinese,
+    OtherAsian,
+    African,
+    Caribbean,
+    OtherBlack,
+    Arab,
+    Other,
+}
+
+/// The 5 high-level groups from the 2011 census, which are what we report because the detailed
+/// groups are too small.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum EthnicGroup {
+    White,
+    Mixed,
+    Asian,
+    Black,
+    Other,
+}
+
+impl Ethnicity {
+    /// Parse the extract's `Ethnicity` column.
+    ///
+    /// This accepts NHS ethnic category codes (`"A"` to `"S"`) and the census names (e.g.
+    /// `"White British"`), ignoring case and punctuation. Returns `None` for "not stated" and
+    /// anything we don't recognise.
+    pub fn from_raw(raw: &str) -> Option<Self> {
+        let norm: String = raw
+            .chars()
+            .filter(|ch| ch.is_ascii_alphanumeric())
+            .map(|ch| ch.to_ascii_lowercase())
+            .collect();
+        Some(match norm.as_str() {
+            "a" | "whitebritish" | "british" => Ethnicity::WhiteBritish,
+            "b" | "whiteirish" | "irish" => Ethnicity::WhiteIrish,
+            "gypsyoririshtraveller" | "gypsy" | "irishtraveller" => {
+                Ethnicity::GypsyOrIrishTraveller
+            }
+            "c" | "otherwhite" | "anyotherwhitebackground" => Ethnicity::OtherWhite,
+            "d" | "whiteandblackcaribbean" => Ethnicity::WhiteAndBlackCaribbean,
+            "e" | "whiteandblackafrican" => Ethnicity::WhiteAndBlackAfrican,
+            "f" | "whiteandasian" => Ethnicity::WhiteAndAsian,
+            "g" | "othermixed" | "anyothermixedbackground" => Ethnicity::OtherMixed,
+            "h" | "indian" => Ethnicity::Indian,
+            "j" | "pakistani" => Ethnicity::Pakistani,
+            "k" | "bangladeshi" => Ethnicity::Bangladeshi,
+            // NHS codes put Chinese under "other", the 2011 census puts it under Asian.
+            "r" | "chinese" => Ethnicity::Chinese,
+            "l" | "otherasian" | "anyotherasianbackground" => Ethnicity::OtherAsian,
+            "n" | "african" | "blackafrican" => Ethnicity::African,
+            "m" | "caribbean" | "blackcaribbean" => Ethnicity::Caribbean,
+            "p" | "otherblack" | "anyotherblackbackground" => Ethnicity::OtherBlack,
+            "arab" => Ethnicity::Arab,
+            "s" | "other" | "anyotherethnicgroup" => Ethnicity::Other,
+            _ => return None,
+        })
+    }
+
+    /// The ethnicity a Read code records, if it is an ethnicity code.
+    pub fn from_read_code(code: ReadCode) -> Option<Self> {
+        PARSED_READ_CODES
+            .iter()
+            .find_map(|(c, ethnicity)| (code == *c || code.is_child_of(*c)).then_some(*ethnicity))
+    }
+
+    pub fn group(self) -> EthnicGroup {
+        match self {
+            Ethnicity::WhiteBritish
+            | Ethnicity::WhiteIrish
+            | Ethnicity::GypsyOrIrishTraveller
+            | Ethnicity::OtherWhite => EthnicGroup::White,
+            Ethnicity::WhiteAndBlackCaribbean
+            | Ethnicity::WhiteAndBlackAfrican
+            | Ethnicity::WhiteAndAsian
+            | Ethnicity::OtherMixed => EthnicGroup::Mixed,
+            Ethnicity::Indian
+            | Ethnicity::Pakistani
+            | Ethnicity::Bangladeshi
+            | Ethnicity::Chinese
+            | Ethnicity::OtherAsian => EthnicGroup::Asian,
+            Ethnicity::African | Ethnicity::Caribbean | Ethnicity::OtherBlack => EthnicGroup::Black,
+            Ethnicity::Arab | Ethnicity::Other => EthnicGroup::Other,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Ethnicity::WhiteBritish => "White: British",
+            Ethnicity::WhiteIrish => "White: Irish",
+            Ethnicity::GypsyOrIrishTraveller => "White: Gypsy or Irish Traveller",
+            Ethnicity::OtherWhite => "White: other",
+            Ethnicity::WhiteAndBlackCaribbean => "Mixed: White and Black Caribbean",
+            Ethnicity::WhiteAndBlackAfrican => "Mixed: White and Black African",
+            Ethnicity::WhiteAndAsian => "Mixed: White and Asian",
+            Ethnicity::OtherMixed => "Mixed: other",
+            Ethnicity::Indian => "Asian: Indian",
+            Ethnicity::Pakistani => "Asian: Pakistani",
+            Ethnicity::Bangladeshi => "Asian: Bangladeshi",
+            Ethnicity::Chinese => "Asian: Chinese",
+            Ethnicity::OtherAsian => "Asian: other",
+            Ethnicity::African => "Black: African",
+            Ethnicity::Caribbean => "Black: Caribbean",
+            Ethnicity::OtherBlack => "Black: other",
+            Ethnicity::Arab => "Other: Arab",
+            Ethnicity::Other => "Other: any other",
+        }
+    }
+}
+
+impl fmt::Display for Ethnicity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+impl EthnicGroup {
+    pub const ALL: [EthnicGroup; 5] = [
+        EthnicGroup::White,
+        EthnicGroup::Mixed,
+        EthnicGroup::Asian,
+        EthnicGroup::Black,
+        EthnicGroup::Other,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            EthnicGroup::White => "White",
+            EthnicGroup::Mixed => "Mixed/multiple",
+            EthnicGroup::Asian => "Asian/Asian British",
+            EthnicGroup::Black => "Black/African/Caribbean/Black British",
+            EthnicGroup::Other => "Other",
+        }
+    }
+}
+
+impl fmt::Display for EthnicGroup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// The ethnicity recorded by the most recent ethnicity Read code.
+pub fn ethnicity_from_events<'a>(events: impl IntoIterator<Item = &'a Event>) -> Option<Ethnicity> {
+    events
+        .into_iter()
+        .filter_map(|evt| Some((evt.date, Ethnicity::from_read_code(evt.read_code)?)))
+        .max_by_key(|(date, _)| *date)
+        .map(|(_, ethnicity)| ethnicity)
+}
+
+impl Patient {
+    /// The ethnicity from the extract's `Ethnicity` column, if we can make sense of it.
+    pub fn ethnicity_category(&self) -> Option<Ethnicity> {
+        Ethnicity::from_raw(self.ethnicity.as_deref()?)
+    }
+}
+
+impl Patients {
+    /// Each patient's ethnicity, using the `Ethnicity` column where we can, and the most recent
+    /// ethnicity Read code otherwise.
+    pub fn ethnicity_category(&self, events: &Events) -> BTreeMap<PatientId, Option<Ethnicity>> {
+        self.els
+            .iter()
+            .map(|pat| {
+                let ethnicity = pat
+                    .ethnicity_category()
+                    .or_else(|| ethnicity_from_events(events.events_for_patient(pat.patient_id)));
+                (pat.patient_id, ethnicity)
+            })
+            .collect()
+    }
+
+    /// The number of patients in each high-level ethnic group, with `None` for unknown.
+    pub fn count_ethnic_groups(&self, events: &Events) -> BTreeMap<Option<EthnicGroup>, usize> {
+        let groups = self
+            .ethnicity_category(events)
+            .into_values()
+            .map(|ethnicity| ethnicity.map(Ethnicity::group));
+        // Make sure all categories are included.
+        GroupCounts::from_keys(groups)
+            .with_keys(EthnicGroup::ALL.map(Some))
+            .with_keys([None])
+            .into_map()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn raw() {
+        assert_eq!(Ethnicity::from_raw("A"), Some(Ethnicity::WhiteBritish));
+        assert_eq!(
+            Ethnicity::from_raw(" White and Black Caribbean"),
+            Some(Ethnicity::WhiteAndBlackCaribbean)
+        );
+        assert_eq!(Ethnicity::from_raw("R"), Some(Ethnicity::Chinese));
+        assert_eq!(Ethnicity::from_raw("Z"), None);
+    }
+
+    #[test]
+    fn read_codes() {
+        let code = |c| ReadCode::from_str(c).unwrap();
+        assert_eq!(
+            Ethnicity::from_read_code(code("9i21.")),
+            Some(Ethnicity::WhiteBritish)
+        );
+        assert_eq!(
+            Ethnicity::from_read_code(code("9i2F.")),
+            Some(Ethnicity::OtherWhite)
+        );
+        assert_eq!(
+            Ethnicity::from_read_code(code("9iF9.")),
+            Some(Ethnicity::Arab)
+        );
+        assert_eq!(
+            Ethnicity::from_read_code(code("9SA9.")),
+            Some(Ethnicity::WhiteIrish)
+        );
+        assert_eq!(Ethnicity::from_read_code(code("9iG..")), None);
+        assert_eq!(Ethnicity::from_read_code(code("9S...")), None);
+    }
+}
@@ -0,0 +1,238 @@
+//! All our main results in one Excel workbook, which is how the study team circulates them.
+//!
+//! Each table is a [`TextTable`] (with disclosure control already applied) on its own sheet. Cells
+//! that are plain numbers are written as numbers so they can be used in formulas, everything else
+//! (e.g. "12 (12.0%)", or a suppressed count) is written as text.
+use crate::{
+    lemp::{LempData, SurveillanceRules},
+    ltcs,
+    read2::{TermCodeSet, Thesaurus},
+    render::TextTable,
+    Adapts, Counts, DisclosureControl, Events, Patients, Range, RangeSet, ReferenceDate,
+    Registrations,
+};
+use qu::ick_use::*;
+use rust_xlsxwriter::{Format, Worksheet};
+use std::path::Path;
+
+/// The longest sheet name Excel allows.
+const MAX_SHEET_NAME: usize = 31;
+
+/// A workbook of named tables, one per sheet.
+#[derive(Debug, Clone, Default)]
+pub struct Workbook {
+    sheets: Vec<(String, TextTable)>,
+}
+
+impl Workbook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `table` as a new sheet.
+    ///
+    /// Characters Excel doesn't allow in sheet names are replaced, and long names are shortened.
+    pub fn add_sheet(&mut self, name: &str, table: TextTable) {
+        self.sheets.push((sheet_name(name), table));
+    }
+
+    pub fn with_sheet(mut self, name: &str, table: TextTable) -> Self {
+        self.add_sheet(name, table);
+        self
+    }
+
+    /// The names of the sheets, in order.
+    pub fn sheet_names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.sheets.iter().map(|(name, _)| name.as_str())
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result {
+        fn inner(this: &Workbook, path: &Path) -> Result {
+            let mut workbook = rust_xlsxwriter::Workbook::new();
+            let bold = Format::new().set_bold();
+            for (name, table) in &this.sheets {
+                let sheet = workbook.add_worksheet();
+                sheet.set_name(name)?;
+                write_table(sheet, table, &bold)?;
+            }
+            workbook.save(path)?;
+            Ok(())
+        }
+        let path = path.as_ref();
+        inner(self, path).with_context(|| format!("saving workbook to \"{}\"", path.display()))
+    }
+}
+
+fn write_table(sheet: &mut Worksheet, table: &TextTable, header_format: &Format) -> Result {
+    let mut row_idx = 0;
+    if !table.headers().is_empty() {
+        for (col_idx, header) in table.headers().iter().enumerate() {
+            sheet.write_string_with_format(0, u16::try_from(col_idx)?, header, header_format)?;
+        }
+        row_idx += 1;
+    }
+    for row in table.rows() {
+        for (col_idx, cell) in row.iter().enumerate() {
+            let col_idx = u16::try_from(col_idx)?;
+            match cell.parse::<f64>() {
+                Ok(number) if number.is_finite() => sheet.write_number(row_idx, col_idx, number)?,
+                _ => sheet.write_string(row_idx, col_idx, cell)?,
+            };
+        }
+        row_idx += 1;
+    }
+    sheet.autofit();
+    Ok(())
+}
+
+/// A sheet name Excel will accept.
+fn sheet_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|ch| match ch {
+            '[' | ']' | ':' | '*' | '?' | '/' | '\\' => '_',
+            ch => ch,
+        })
+        .take(MAX_SHEET_NAME)
+        .collect();
+    // Names can't start or end with an apostrophe.
+    let name = name.trim_matches('\'');
+    if name.is_empty() {
+        "Sheet".into()
+    } else {
+        name.into()
+    }
+}
+
+/// Write the patient/ADAPT summary, demographics, LTC report, LTC significance table and
+/// adherence stats for the cleaned data to one workbook.
+pub fn to_xlsx(path: impl AsRef<Path>, dc: &DisclosureControl) -> Result {
+    workbook(dc)?.save(path)
+}
+
+/// The workbook written by [`to_xlsx`].
+pub fn workbook(dc: &DisclosureControl) -> Result<Workbook> {
+    let patients = Patients::load("patients_clean.bin")?;
+    let events = Events::load("events_clean.bin")?;
+    let adapt = Adapts::load("adapt.bin")?;
+    let mut out = Workbook::new();
+
+    out.add_sheet(
+        "Summary",
+        TextTable::default()
+            .with_row(["Patients".to_string(), dc.count(patients.len()).to_string()])
+            .with_row(["Events".to_string(), dc.count(events.len()).to_string()])
+            .with_row([
+                "Patients with ADAPT information".to_string(),
+                dc.count(adapt.len()).to_string(),
+            ]),
+    );
+
+    let total = patients.len();
+    let count_table = |heading: &str, counts: Vec<(String, usize)>| {
+        let mut table = TextTable::new([heading, "Count", "Percentage"]);
+        for (label, count) in counts {
+            table.push_row([
+                label,
+                dc.count(count).to_string(),
+                percentage_cell(dc.percentage(count, total)),
+            ]);
+        }
+        table
+    };
+    out.add_sheet(
+        "Sex",
+        count_table(
+            "Sex",
+            patients
+                .count_sexes()
+                .into_iter()
+                .map(|(sex, count)| (sex.to_string(), count))
+                .collect(),
+        ),
+    );
+    let age_buckets = RangeSet::new(vec![
+        Range::new(0, Some(18)),
+        Range::new(18, Some(35)),
+        Range::new(35, Some(50)),
+        Range::new(50, Some(65)),
+        Range::new(65, Some(80)),
+        Range::new(80, None),
+    ]);
+    out.add_sheet(
+        "Age",
+        count_table(
+            "Age range",
+            patients
+                .bucket_ages(&age_buckets, ReferenceDate::default())
+                .for_display()
+                .map(|(range, count)| (range.to_string(), count))
+                .collect(),
+        ),
+    );
+    out.add_sheet(
+        "IMD",
+        count_table(
+            "IMD decile",
+            patients
+                .count_imd()
+                .into_iter()
+                .map(|(imd, count)| (imd.to_string(), count))
+                .collect(),
+        ),
+    );
+
+    let conditions = ltcs::Conditions::load()?;
+    let lymphoma_codeset = TermCodeSet::load("lymphoma_clean", Thesaurus::shared()?)?;
+    let diagnosis_dates = lymphoma_codeset
+        .code_set
+        .into_matcher()
+        .earliest_code(&events);
+    let report = conditions.report(&patients, &events, &diagnosis_dates);
+    out.add_sheet("LTC prevalence", report.text_table(dc));
+    out.add_sheet(
+        "LTC significance",
+        report.test_significance(0.05, 10, true).text_table(),
+    );
+
+    let registrations = Registrations::load_if_present("registrations.bin")?;
+    let lemp_data = LempData::new(patients, adapt, events, registrations);
+    for rule in SurveillanceRules::builtin().iter() {
+        let stats = lemp_data.rule_stats(rule)?;
+        out.add_sheet(&format!("{} adherence", rule.name), stats.text_table(dc));
+    }
+    Ok(out)
+}
+
+fn percentage_cell(percentage: Option<f64>) -> String {
+    match percentage {
+        Some(percentage) => format!("{:.1}", percentage),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sheet_names() {
+        assert_eq!(sheet_name("LTC prevalence"), "LTC prevalence");
+        assert_eq!(
+            sheet_name("BP [clinic]: 0/5 years"),
+            "BP _clinic__ 0_5 years"
+        );
+        assert_eq!(sheet_name(&"a".repeat(40)).len(), MAX_SHEET_NAME);
+        assert_eq!(sheet_name("''"), "Sheet");
+        let workbook = Workbook::new()
+            .with_sheet(
+                "Summary",
+                TextTable::default().with_row(["Patients", "120"]),
+            )
+            .with_sheet("x?", TextTable::new(["Sex", "Count"]));
+        assert_eq!(
+            workbook.sheet_names().collect::<Vec<_>>(),
+            ["Summary", "x_"]
+        );
+    }
+}
@@ -0,0 +1,158 @@
+//! Conversions between our typed datasets and polars `DataFrame`s.
+//!
+//! Useful for exploratory work in evcxr: load and filter with the typed API (code sets etc.), then
+//! convert to a `DataFrame` for grouping/pivoting, and back again if needed.
+use crate::{dates, intern, ArcStr, Event, Events, PatientId, ReadCode};
+use polars::prelude::*;
+use qu::ick_use::*;
+
+impl Events {
+    /// Convert the events into a `DataFrame`.
+    ///
+    /// Columns are `patient_id` (u64), `date` (date), `read_code`, `term_id`, `rubric`,
+    /// `code_value`, `code_units` and `source` (all strings, `term_id`, `code_value` and
+    /// `code_units` nullable).
+    pub fn to_dataframe(&self) -> Result<DataFrame> {
+        let df = DataFrame::new(vec![
+            Column::new(
+                "patient_id".into(),
+                self.els
+                    .iter()
+                    .map(|evt| evt.patient_id.get())
+                    .collect::<Vec<_>>(),
+            ),
+            Column::new(
+                "date".into(),
+                self.els
+                    .iter()
+                    .map(|evt| dates::to_epoch_days(evt.date))
+                    .collect::<Vec<_>>(),
+            )
+            .cast(&DataType::Date)?,
+            Column::new(
+                "read_code".into(),
+                self.els
+                    .iter()
+                    .map(|evt| evt.read_code.to_string())
+                    .collect::<Vec<_>>(),
+            ),
+            Column::new(
+                "term_id".into(),
+                self.els
+                    .iter()
+                    .map(|evt| evt.term_id.map(|term| term.to_string()))
+                    .collect::<Vec<_>>(),
+            ),
+            Column::new(
+                "rubric".into(),
+                self.els.iter().map(|evt| &*evt.rubric).collect::<Vec<_>>(),
+            ),
+            Column::new(
+                "code_value".into(),
+                self.els
+                    .iter()
+                    .map(|evt| evt.code_value.as_deref())
+                    .collect::<Vec<_>>(),
+            ),
+            Column::new(
+                "code_units".into(),
+                self.els
+                    .iter()
+                    .map(|evt| evt.code_units.as_deref())
+                    .collect::<Vec<_>>(),
+            ),
+            Column::new(
+                "source".into(),
+                self.els.iter().map(|evt| &*evt.source).collect::<Vec<_>>(),
+            ),
+        ])?;
+        Ok(df)
+    }
+
+    /// Build events from a `DataFrame` with the columns produced by [`Events::to_dataframe`].
+    ///
+    /// Extra columns are ignored, so it's fine to add derived columns during analysis. Nulls are
+    /// only allowed where the event field is optional. Rubrics and sources are shared through the
+    /// same [`intern`] pools as the other loaders.
+    pub fn from_dataframe(df: &DataFrame) -> Result<Self> {
+        fn inner(df: &DataFrame) -> Result<Events> {
+            let patient_id = df.column("patient_id")?.u64()?;
+            let date = df.column("date")?.date()?.physical();
+            let read_code = df.column("read_code")?.str()?;
+            let term_id = df.column("term_id")?.str()?;
+            let rubric = df.column("rubric")?.str()?;
+            let code_value = df.column("code_value")?.str()?;
+            let code_units = df.column("code_units")?.str()?;
+            let source = df.column("source")?.str()?;
+
+            let mut els = Vec::with_capacity(df.height());
+            for idx in 0..df.height() {
+                els.push(Event {
+                    patient_id: PatientId::new(
+                        patient_id
+                            .get(idx)
+                            .with_context(|| format!("missing patient_id in row {}", idx))?,
+                    ),
+                    date: dates::from_epoch_days(
+                        date.get(idx)
+                            .with_context(|| format!("missing date in row {}", idx))?,
+                    ),
+                    read_code: ReadCode::from_str(
+                        read_code
+                            .get(idx)
+                            .with_context(|| format!("missing read_code in row {}", idx))?,
+                    )?,
+                    term_id: term_id.get(idx).map(str::parse).transpose()?,
+                    rubric: intern::RUBRICS.intern(
+                        rubric
+                            .get(idx)
+                            .with_context(|| format!("missing rubric in row {}", idx))?,
+                    ),
+                    code_value: code_value.get(idx).map(ArcStr::from),
+                    code_units: code_units.get(idx).map(ArcStr::from),
+                    source: intern::SOURCES.intern(
+                        source
+                            .get(idx)
+                            .with_context(|| format!("missing source in row {}", idx))?,
+                    ),
+                });
+            }
+            Ok(Events::new(els))
+        }
+        inner(df).context("converting dataframe to events")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_event;
+    use std::sync::Arc;
+
+    #[test]
+    fn round_trip() {
+        let date = chrono::NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
+        let events = Events::new(vec![Event {
+            rubric: "Full blood count".into(),
+            source: "GP surgery".into(),
+            ..test_event(1, date, "424..")
+        }]);
+        let mut df = events.to_dataframe().unwrap();
+        let back = Events::from_dataframe(&df).unwrap();
+        let evt = back.iter_ref().next().unwrap();
+        assert_eq!((evt.date, &*evt.rubric), (date, "Full blood count"));
+        // Loaded strings are shared with other events with the same text.
+        assert!(Arc::ptr_eq(
+            &evt.rubric,
+            &intern::RUBRICS.intern("Full blood count")
+        ));
+        assert!(Arc::ptr_eq(
+            &evt.source,
+            &intern::SOURCES.intern("GP surgery")
+        ));
+
+        df.with_column(Column::new("source".into(), [None::<&str>]))
+            .unwrap();
+        assert!(Events::from_dataframe(&df).is_err());
+    }
+}
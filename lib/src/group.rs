@@ -0,0 +1,160 @@
+use crate::{render::TextTable, DisclosureControl};
+use std::{
+    collections::{btree_map, BTreeMap},
+    fmt,
+};
+
+/// A count for each of a list of keys, and the ways we show them.
+///
+/// Implemented by [`GroupCounts`], [`RangeSetCounts`](crate::RangeSetCounts) and
+/// [`RangeSetCountsWithMissing`](crate::RangeSetCountsWithMissing).
+pub trait Counts {
+    /// What is counted, e.g. `&K` for a [`GroupCounts<K>`].
+    type Key<'a>
+    where
+        Self: 'a;
+
+    /// Each key with its count, in order.
+    fn counts(&self) -> impl Iterator<Item = (Self::Key<'_>, usize)>;
+
+    /// Each count with a label to show it by, in the order to show them.
+    fn for_display(&self) -> impl Iterator<Item = (&dyn fmt::Display, usize)>;
+
+    /// Each count as a percentage of `denominator` (NaN if `denominator` is 0).
+    fn percentages(&self, denominator: usize) -> impl Iterator<Item = (Self::Key<'_>, f64)> {
+        self.counts()
+            .map(move |(key, count)| (key, count as f64 / denominator as f64 * 100.))
+    }
+
+    /// A table of the counts with their percentage of `denominator`.
+    fn text_table(&self, heading: &str, denominator: usize, dc: &DisclosureControl) -> TextTable {
+        TextTable::counts(heading, self.for_display(), denominator, dc)
+    }
+}
+
+/// The number of things with each key, in key order.
+///
+/// Built with [`Dataset::group_count`](crate::Dataset::group_count), e.g.
+/// `patients.group_count(|pat| pat.sex)` or `events.group_count(|evt| evt.date.year())`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupCounts<K> {
+    counts: BTreeMap<K, usize>,
+}
+
+impl<K: Ord> GroupCounts<K> {
+    /// Count how many times each key appears.
+    pub fn from_keys(keys: impl IntoIterator<Item = K>) -> Self {
+        let mut counts = BTreeMap::new();
+        for key in keys {
+            *counts.entry(key).or_default() += 1;
+        }
+        GroupCounts { counts }
+    }
+
+    /// Make sure all of `keys` are included, with a count of 0 if nothing had them.
+    pub fn with_keys(mut self, keys: impl IntoIterator<Item = K>) -> Self {
+        for key in keys {
+            self.counts.entry(key).or_default();
+        }
+        self
+    }
+
+    pub fn get(&self, key: &K) -> usize {
+        self.counts.get(key).copied().unwrap_or(0)
+    }
+
+    /// The number of things counted.
+    pub fn total(&self) -> usize {
+        self.counts.values().sum()
+    }
+
+    /// The number of different keys.
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, usize)> {
+        self.counts.iter().map(|(key, count)| (key, *count))
+    }
+
+    pub fn into_map(self) -> BTreeMap<K, usize> {
+        self.counts
+    }
+
+    /// A table of the counts with their percentage of `denominator`, with `label` for the name of
+    /// each key.
+    pub fn text_table_by(
+        &self,
+        heading: &str,
+        denominator: usize,
+        dc: &DisclosureControl,
+        label: impl Fn(&K) -> String,
+    ) -> TextTable {
+        TextTable::counts(
+            heading,
+            self.iter().map(|(key, count)| (label(key), count)),
+            denominator,
+            dc,
+        )
+    }
+}
+
+impl<K: Ord + fmt::Display> Counts for GroupCounts<K> {
+    type Key<'a>
+        = &'a K
+    where
+        K: 'a;
+
+    fn counts(&self) -> impl Iterator<Item = (&K, usize)> {
+        self.iter()
+    }
+
+    fn for_display(&self) -> impl Iterator<Item = (&dyn fmt::Display, usize)> {
+        self.iter()
+            .map(|(key, count)| (key as &dyn fmt::Display, count))
+    }
+}
+
+impl<K> From<BTreeMap<K, usize>> for GroupCounts<K> {
+    fn from(counts: BTreeMap<K, usize>) -> Self {
+        GroupCounts { counts }
+    }
+}
+
+impl<K> IntoIterator for GroupCounts<K> {
+    type Item = (K, usize);
+    type IntoIter = btree_map::IntoIter<K, usize>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.counts.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn group_counts() {
+        let counts = GroupCounts::from_keys([("M", 1), ("F", 2), ("M", 1)]).with_keys([("F", 1)]);
+        assert_eq!(
+            counts.iter().collect::<Vec<_>>(),
+            [(&("F", 1), 0), (&("F", 2), 1), (&("M", 1), 2)]
+        );
+        assert_eq!(counts.total(), 3);
+        assert_eq!(counts.get(&("M", 1)), 2);
+        let sexes = GroupCounts::from_keys(["M", "F", "M", "M"]);
+        assert_eq!(sexes.percentages(4).last().unwrap(), (&"M", 75.));
+        assert_eq!(
+            sexes.text_table("Sex", 4, &DisclosureControl::NONE).rows()[0],
+            ["F", "1", "25.0%"]
+        );
+        let table = counts.text_table_by("Group", 3, &DisclosureControl::NONE, |(sex, n)| {
+            format!("{sex} {n}")
+        });
+        assert_eq!(table.rows()[2], ["M 1", "2", "66.7%"]);
+    }
+}
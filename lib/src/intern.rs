@@ -0,0 +1,102 @@
+//! Sharing one allocation between identical strings.
+//!
+//! The same rubrics (and the handful of event sources) appear on millions of events. Loading them
+//! through a pool means each distinct string is only stored once, however many events use it.
+use crate::ArcStr;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{
+    de::{self, Visitor},
+    Deserializer,
+};
+use std::{collections::HashSet, fmt};
+
+/// Rubrics from the events dataset.
+pub static RUBRICS: Lazy<Interner> = Lazy::new(Interner::default);
+/// Sources from the events dataset.
+pub static SOURCES: Lazy<Interner> = Lazy::new(Interner::default);
+
+/// A pool of strings.
+///
+/// Strings are never removed, so the pool lives as long as the program.
+#[derive(Debug, Default)]
+pub struct Interner {
+    strings: Mutex<HashSet<ArcStr>>,
+}
+
+impl Interner {
+    /// Get the pooled copy of `s`, adding it to the pool if this is the first time we've seen it.
+    pub fn intern(&self, s: &str) -> ArcStr {
+        let mut strings = self.strings.lock();
+        if let Some(existing) = strings.get(s) {
+            return existing.clone();
+        }
+        let new = ArcStr::from(s);
+        strings.insert(new.clone());
+        new
+    }
+
+    /// The number of distinct strings in the pool.
+    pub fn len(&self) -> usize {
+        self.strings.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.lock().is_empty()
+    }
+
+    /// All the distinct strings in the pool, in sorted order.
+    pub fn dictionary(&self) -> Vec<ArcStr> {
+        let mut out: Vec<ArcStr> = self.strings.lock().iter().cloned().collect();
+        out.sort();
+        out
+    }
+}
+
+/// Deserialize a rubric through [`RUBRICS`].
+pub(crate) fn rubric<'de, D>(d: D) -> Result<ArcStr, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    d.deserialize_str(InternVisitor(&RUBRICS))
+}
+
+/// Deserialize an event source through [`SOURCES`].
+pub(crate) fn source<'de, D>(d: D) -> Result<ArcStr, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    d.deserialize_str(InternVisitor(&SOURCES))
+}
+
+/// Interns strings as they are deserialized, so we don't allocate for strings we've seen before.
+struct InternVisitor<'a>(&'a Interner);
+
+impl<'de, 'a> Visitor<'de> for InternVisitor<'a> {
+    type Value = ArcStr;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a string")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        Ok(self.0.intern(v))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn shared() {
+        let pool = Interner::default();
+        let a = pool.intern("Hodgkin's disease");
+        let b = pool.intern(&String::from("Hodgkin's disease"));
+        assert!(Arc::ptr_eq(&a, &b));
+        pool.intern("Asthma");
+        assert_eq!(pool.len(), 2);
+        assert_eq!(&*pool.dictionary()[0], "Asthma");
+    }
+}
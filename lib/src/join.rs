@@ -0,0 +1,80 @@
+//! Linking datasets that share a patient ID.
+//!
+//! The generic joins live on [`Dataset`](crate::Dataset) and give pairs of rows. The functions
+//! here wrap them for the combinations we use, with a named type for each joined row.
+use crate::{Adapt, Adapts, Event, Events, Patient, PatientId, Patients};
+use chrono::NaiveDate;
+use qu::ick_use::*;
+
+/// The result of joining two datasets.
+#[derive(Debug, Clone)]
+pub struct Join<K, Row> {
+    /// The joined rows, in key order.
+    pub rows: Vec<Row>,
+    /// Keys that were only in the left dataset.
+    pub left_unmatched: Vec<K>,
+    /// Keys that were only in the right dataset.
+    pub right_unmatched: Vec<K>,
+}
+
+impl<K, Row> Join<K, Row> {
+    /// Convert each joined row, keeping the unmatched keys.
+    pub fn map<U>(self, f: impl FnMut(Row) -> U) -> Join<K, U> {
+        Join {
+            rows: self.rows.into_iter().map(f).collect(),
+            left_unmatched: self.left_unmatched,
+            right_unmatched: self.right_unmatched,
+        }
+    }
+
+    /// Log how many keys didn't match on each side, with a description of the unmatched keys.
+    pub fn log_unmatched(&self, left: &str, right: &str) {
+        event!(
+            Level::INFO,
+            "joined {} rows ({} {left}, {} {right})",
+            self.rows.len(),
+            self.left_unmatched.len(),
+            self.right_unmatched.len(),
+        );
+    }
+}
+
+/// A patient with their ADAPT record.
+#[derive(Debug, Clone)]
+pub struct PatientAdapt {
+    pub patient: Patient,
+    pub adapt: Adapt,
+}
+
+impl PatientAdapt {
+    /// The date the patient was 'ADAPTed'.
+    pub fn adapt_date(&self) -> NaiveDate {
+        self.adapt.last_review_date
+    }
+}
+
+/// A patient with all their events, in date order.
+#[derive(Debug, Clone)]
+pub struct PatientEvents<'a> {
+    pub patient: &'a Patient,
+    pub events: Vec<&'a Event>,
+}
+
+impl Patients {
+    /// Link each patient to their ADAPT record.
+    pub fn join(&self, adapts: &Adapts) -> Join<PatientId, PatientAdapt> {
+        self.0.join(adapts).map(|(patient, adapt)| PatientAdapt {
+            patient: patient.clone(),
+            adapt: adapt.clone(),
+        })
+    }
+
+    /// Link each patient to their events.
+    ///
+    /// Patients without any events are in `left_unmatched`, not in `rows`.
+    pub fn join_events<'a>(&'a self, events: &'a Events) -> Join<PatientId, PatientEvents<'a>> {
+        self.0
+            .join_grouped(events)
+            .map(|(patient, events)| PatientEvents { patient, events })
+    }
+}
@@ -0,0 +1,312 @@
+//! Late effects of lymphoma treatment.
+//!
+//! Each [`LateEffect`] is a condition that some treatments put survivors at risk of, defined by a
+//! codeset. We look for new cases in the GP record after the end of treatment, and compare the
+//! incidence between patients with and without each treatment (as recorded in the ADAPT flags
+//! from [`LateEffect::exposures`]).
+pub mod cardiotoxicity;
+pub mod secondary_malignancy;
+
+use crate::{
+    epi::{self, IncidenceRate},
+    lemp::AdaptFlag,
+    read2::{CodeSet, CodesetRegistry},
+    render::TextTable,
+    Adapt, Adapts, DataPaths, DisclosureControl, Events, PatientId, Patients, Registrations,
+    Result,
+};
+use chrono::NaiveDate;
+use std::{collections::HashMap, fmt};
+use term_data_table::Table;
+
+/// A late effect of treatment that we can find in the GP record.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LateEffect {
+    /// Heart failure or cardiomyopathy.
+    HeartFailure,
+    PulmonaryFibrosis,
+    Hypothyroidism,
+    /// A new cancer that isn't lymphoma or leukaemia.
+    SecondaryMalignancy,
+    Infertility,
+}
+
+impl LateEffect {
+    pub const ALL: [LateEffect; 5] = [
+        LateEffect::HeartFailure,
+        LateEffect::PulmonaryFibrosis,
+        LateEffect::Hypothyroidism,
+        LateEffect::SecondaryMalignancy,
+        LateEffect::Infertility,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            LateEffect::HeartFailure => "Heart failure / cardiomyopathy",
+            LateEffect::PulmonaryFibrosis => "Pulmonary fibrosis",
+            LateEffect::Hypothyroidism => "Hypothyroidism",
+            LateEffect::SecondaryMalignancy => "Secondary malignancy",
+            LateEffect::Infertility => "Infertility",
+        }
+    }
+
+    /// The ADAPT flags for treatments that put patients at risk of this late effect.
+    pub fn exposures(self) -> &'static [AdaptFlag] {
+        match self {
+            LateEffect::HeartFailure => &[
+                AdaptFlag::ChemoDoxorubicin,
+                AdaptFlag::RadiationHeart,
+                AdaptFlag::ChemoDoxorubicinRadiationHeart,
+            ],
+            LateEffect::PulmonaryFibrosis => {
+                &[AdaptFlag::RadiationLungs, AdaptFlag::ChemoBleomycin]
+            }
+            LateEffect::Hypothyroidism => {
+                &[AdaptFlag::RadiationThyroid, AdaptFlag::RadiationHeadNeck]
+            }
+            LateEffect::SecondaryMalignancy => &[
+                AdaptFlag::AnyRadiotherapy,
+                AdaptFlag::FemaleSub36RadiationChest,
+                AdaptFlag::RadiationGulletStomach,
+                AdaptFlag::RadiationBowels,
+            ],
+            LateEffect::Infertility => &[AdaptFlag::MaleChemo],
+        }
+    }
+
+    /// Load the codes for this late effect.
+    ///
+    /// We use the Cambridge codesets where there is one, and our own termsets otherwise.
+    pub fn load_codeset(self, paths: &DataPaths) -> Result<CodeSet> {
+        let load = |name| CodesetRegistry::builtin().load_codeset(name, paths);
+        Ok(match self {
+            LateEffect::HeartFailure => load("hef158_mc")?
+                .iter()
+                .chain(load("cardiomyopathy")?.iter())
+                .collect(),
+            LateEffect::PulmonaryFibrosis => load("pulmonary_fibrosis")?,
+            LateEffect::Hypothyroidism => load("hypothyroidism")?,
+            // Anything else would be the original lymphoma coming back.
+            LateEffect::SecondaryMalignancy => load("can146_mc")? - load("lymphoma_leukaemia")?,
+            LateEffect::Infertility => load("infertility")?,
+        })
+    }
+}
+
+impl fmt::Display for LateEffect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// The codesets for all the late effects.
+pub struct LateEffectCodes {
+    codesets: Vec<(LateEffect, CodeSet)>,
+}
+
+impl LateEffectCodes {
+    /// Load codesets from the data directories in use.
+    pub fn load() -> Result<Self> {
+        Self::load_from(&DataPaths::current())
+    }
+
+    /// Load codesets from the given data directories.
+    pub fn load_from(paths: &DataPaths) -> Result<Self> {
+        let codesets = LateEffect::ALL
+            .iter()
+            .map(|effect| Ok((*effect, effect.load_codeset(paths)?)))
+            .collect::<Result<_>>()?;
+        Ok(LateEffectCodes { codesets })
+    }
+
+    pub fn get(&self, effect: LateEffect) -> &CodeSet {
+        self.codesets
+            .iter()
+            .find(|(e, _)| *e == effect)
+            .map(|(_, codeset)| codeset)
+            .expect("all late effects are loaded")
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (LateEffect, &CodeSet)> + '_ {
+        self.codesets
+            .iter()
+            .map(|(effect, codeset)| (*effect, codeset))
+    }
+}
+
+/// The incidence of a late effect in patients with and without one treatment.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ExposureIncidence {
+    pub effect: LateEffect,
+    pub exposure: AdaptFlag,
+    pub exposed: IncidenceRate,
+    pub unexposed: IncidenceRate,
+}
+
+impl ExposureIncidence {
+    /// The incidence rate in exposed patients divided by the rate in unexposed patients.
+    ///
+    /// `None` if either group has no follow-up or there are no unexposed cases.
+    pub fn rate_ratio(&self) -> Option<f64> {
+        let exposed = self.exposed.per_1000()?;
+        let unexposed = self.unexposed.per_1000()?;
+        (unexposed > 0.).then(|| exposed / unexposed)
+    }
+}
+
+/// The incidence of `effect` after the end of treatment, split by each of its exposures.
+///
+/// Patients are followed from their treatment end date (from the ADAPT record) to the end of
+/// their registration. Patients without an ADAPT record are left out.
+pub fn incidence(
+    effect: LateEffect,
+    codeset: &CodeSet,
+    patients: &Patients,
+    adapts: &Adapts,
+    events: &Events,
+    registrations: &Registrations,
+) -> Vec<ExposureIncidence> {
+    let censor_date = |id| registrations.follow_up_end(id);
+    effect
+        .exposures()
+        .iter()
+        .map(|exposure| {
+            let (exposed, unexposed) = treatment_end_dates(adapts, |adapt| exposure.is_set(adapt));
+            ExposureIncidence {
+                effect,
+                exposure: *exposure,
+                exposed: epi::incidence_rate(patients, events, codeset, &exposed, censor_date),
+                unexposed: epi::incidence_rate(patients, events, codeset, &unexposed, censor_date),
+            }
+        })
+        .collect()
+}
+
+/// The incidence of each late effect, split by exposure.
+#[derive(Debug, Clone)]
+pub struct IncidenceReport {
+    pub rows: Vec<ExposureIncidence>,
+}
+
+impl IncidenceReport {
+    /// The incidence of every late effect, split by their exposures.
+    pub fn new(
+        codes: &LateEffectCodes,
+        patients: &Patients,
+        adapts: &Adapts,
+        events: &Events,
+        registrations: &Registrations,
+    ) -> Self {
+        let rows = codes
+            .iter()
+            .flat_map(|(effect, codeset)| {
+                incidence(effect, codeset, patients, adapts, events, registrations)
+            })
+            .collect();
+        IncidenceReport { rows }
+    }
+
+    pub fn data_table(&self, dc: &DisclosureControl) -> Table<'_> {
+        self.text_table(dc).into_term_table()
+    }
+
+    /// Cases are rounded or suppressed according to `dc`. Rates are worked out from the rounded
+    /// number of cases, so they can't be used to get back to the exact count.
+    pub fn text_table(&self, dc: &DisclosureControl) -> TextTable {
+        let mut table = TextTable::new([
+            "Late effect",
+            "Treatment",
+            "Cases (treated)",
+            "Rate per 1,000 PY (treated)",
+            "Cases (not treated)",
+            "Rate per 1,000 PY (not treated)",
+            "Rate ratio",
+        ]);
+        for row in self.rows.iter() {
+            let exposed = disclosed(row.exposed, dc);
+            let unexposed = disclosed(row.unexposed, dc);
+            let rate_ratio = match (exposed, unexposed) {
+                (Some(exposed), Some(unexposed)) => ExposureIncidence {
+                    exposed,
+                    unexposed,
+                    ..*row
+                }
+                .rate_ratio(),
+                _ => None,
+            };
+            table.push_row([
+                row.effect.label().to_string(),
+                row.exposure.label().to_string(),
+                dc.count(row.exposed.cases).to_string(),
+                format_rate(exposed),
+                dc.count(row.unexposed.cases).to_string(),
+                format_rate(unexposed),
+                match rate_ratio {
+                    Some(v) => format!("{v:.2}"),
+                    None => "-".into(),
+                },
+            ]);
+        }
+        table
+    }
+}
+
+/// Treatment end dates for patients who were and weren't `exposed`.
+fn treatment_end_dates(
+    adapts: &Adapts,
+    exposed: impl Fn(&Adapt) -> bool,
+) -> (HashMap<PatientId, NaiveDate>, HashMap<PatientId, NaiveDate>) {
+    let mut exposed_dates = HashMap::new();
+    let mut unexposed_dates = HashMap::new();
+    for adapt in adapts.iter_ref() {
+        let dates = if exposed(adapt) {
+            &mut exposed_dates
+        } else {
+            &mut unexposed_dates
+        };
+        dates.insert(adapt.id, adapt.treatment_end_date);
+    }
+    (exposed_dates, unexposed_dates)
+}
+
+/// `rate` with the number of cases rounded according to `dc`, or `None` if it is suppressed.
+fn disclosed(rate: IncidenceRate, dc: &DisclosureControl) -> Option<IncidenceRate> {
+    Some(IncidenceRate {
+        cases: dc.count(rate.cases).value()?,
+        ..rate
+    })
+}
+
+/// The rate per 1,000 person-years with a 95% confidence interval.
+fn format_rate(rate: Option<IncidenceRate>) -> String {
+    match rate.and_then(|rate| Some((rate.per_1000()?, rate.ci_per_1000(0.95)?))) {
+        Some((rate, (low, high))) => format!("{rate:.1} ({low:.1} to {high:.1})"),
+        None => "-".into(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rate_ratio() {
+        let rate = |cases, person_years| IncidenceRate {
+            cases,
+            person_years,
+        };
+        let row = |exposed, unexposed| ExposureIncidence {
+            effect: LateEffect::HeartFailure,
+            exposure: AdaptFlag::ChemoDoxorubicin,
+            exposed,
+            unexposed,
+        };
+        assert_eq!(row(rate(6, 200.), rate(3, 300.)).rate_ratio(), Some(3.));
+        assert_eq!(row(rate(6, 200.), rate(0, 300.)).rate_ratio(), None);
+        assert_eq!(row(rate(0, 0.), rate(3, 300.)).rate_ratio(), None);
+
+        let dc = DisclosureControl::RELEASE;
+        assert_eq!(disclosed(rate(3, 100.), &dc), None);
+        assert_eq!(disclosed(rate(8, 100.), &dc), Some(rate(10, 100.)));
+    }
+}
@@ -0,0 +1,161 @@
+//! Heart failure and cardiomyopathy after anthracyclines or radiotherapy to the heart.
+//!
+//! This is the late effect we expect to see most of, so as well as the rates in
+//! [`IncidenceReport`](super::IncidenceReport) we compare the timing of new cases in exposed and
+//! unexposed patients. Patients are exposed if any of the flags in
+//! [`LateEffect::HeartFailure`]'s exposures are set, and are followed from the end of treatment.
+use super::{treatment_end_dates, LateEffect};
+use crate::{
+    epi::{self, IncidenceRate, TimeToEvent},
+    read2::CodeSet,
+    render::TextTable,
+    Adapts, DisclosureControl, Events, Patients, Registrations,
+};
+use term_data_table::Table;
+
+/// The years after treatment we report the cumulative incidence at.
+const CUMULATIVE_YEARS: [f64; 2] = [5., 10.];
+
+/// New cases in one group of patients.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CardioGroup {
+    /// Patients at risk at the end of treatment.
+    pub num_patients: usize,
+    pub rate: IncidenceRate,
+    /// Median years from the end of treatment to the first code, for patients with a code.
+    pub years_median: Option<f64>,
+    /// The proportion with a code by each of [`CUMULATIVE_YEARS`], from a Kaplan-Meier estimate.
+    pub cumulative_incidence: [Option<f64>; 2],
+}
+
+impl CardioGroup {
+    fn new(times: &[TimeToEvent]) -> Self {
+        let mut years = times
+            .iter()
+            .filter(|time| time.event)
+            .map(|time| time.years)
+            .collect::<Vec<_>>();
+        years.sort_unstable_by(f64::total_cmp);
+        let years_median = match years.len() {
+            0 => None,
+            n if n % 2 == 1 => Some(years[n / 2]),
+            n => Some((years[n / 2 - 1] + years[n / 2]) / 2.),
+        };
+        CardioGroup {
+            num_patients: times.len(),
+            rate: IncidenceRate::from_times(times),
+            years_median,
+            cumulative_incidence: CUMULATIVE_YEARS
+                .map(|years| epi::cumulative_incidence(times, years)),
+        }
+    }
+}
+
+/// Heart failure and cardiomyopathy in patients with and without cardiotoxic treatment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cardiotoxicity {
+    pub exposed: CardioGroup,
+    pub unexposed: CardioGroup,
+    /// The rate in exposed over unexposed patients, with a 95% confidence interval.
+    pub rate_ratio: Option<(f64, (f64, f64))>,
+}
+
+impl Cardiotoxicity {
+    /// `codeset` should be the codes for [`LateEffect::HeartFailure`].
+    pub fn new(
+        codeset: &CodeSet,
+        patients: &Patients,
+        adapts: &Adapts,
+        events: &Events,
+        registrations: &Registrations,
+    ) -> Self {
+        let exposures = LateEffect::HeartFailure.exposures();
+        let (exposed, unexposed) = treatment_end_dates(adapts, |adapt| {
+            exposures.iter().any(|flag| flag.is_set(adapt))
+        });
+        let censor_date = |id| registrations.follow_up_end(id);
+        let exposed = CardioGroup::new(&epi::time_to_event(
+            patients,
+            events,
+            codeset,
+            &exposed,
+            censor_date,
+        ));
+        let unexposed = CardioGroup::new(&epi::time_to_event(
+            patients,
+            events,
+            codeset,
+            &unexposed,
+            censor_date,
+        ));
+        Cardiotoxicity {
+            rate_ratio: exposed.rate.rate_ratio(&unexposed.rate, 0.95),
+            exposed,
+            unexposed,
+        }
+    }
+
+    pub fn data_table(&self, dc: &DisclosureControl) -> Table<'_> {
+        self.text_table(dc).into_term_table()
+    }
+
+    /// Counts are rounded or suppressed according to `dc`, and everything else is hidden for a
+    /// group whose number of cases is suppressed. Rates are worked out from the rounded counts.
+    pub fn text_table(&self, dc: &DisclosureControl) -> TextTable {
+        let groups = [&self.exposed, &self.unexposed];
+        let rounded = |group: &CardioGroup| {
+            Some(IncidenceRate {
+                cases: dc.count(group.rate.cases).value()?,
+                ..group.rate
+            })
+        };
+        let mut table = TextTable::new(["", "Anthracycline or heart radiotherapy", "Neither"]);
+        let mut add_row = |label: String, cell: &dyn Fn(&CardioGroup, IncidenceRate) -> String| {
+            let cells = groups.iter().map(|group| match rounded(group) {
+                Some(rate) => cell(group, rate),
+                None => "suppressed".into(),
+            });
+            table.push_row(std::iter::once(label).chain(cells));
+        };
+        add_row("Patients".into(), &|group, _| {
+            dc.count(group.num_patients).to_string()
+        });
+        add_row("New cases".into(), &|_, rate| rate.cases.to_string());
+        add_row("Rate per 1,000 PY (95% CI)".into(), &|_, rate| match (
+            rate.per_1000(),
+            rate.ci_per_1000(0.95),
+        ) {
+            (Some(rate), Some((low, high))) => format!("{rate:.1} ({low:.1} to {high:.1})"),
+            _ => "-".into(),
+        });
+        add_row("Median years to first code".into(), &|group, _| {
+            format_option(group.years_median, |v| format!("{v:.1}"))
+        });
+        for (idx, years) in CUMULATIVE_YEARS.iter().enumerate() {
+            add_row(
+                format!("Cumulative incidence at {years} years"),
+                &|group, _| {
+                    format_option(group.cumulative_incidence[idx], |v| {
+                        format!("{:.1}%", v * 100.)
+                    })
+                },
+            );
+        }
+        let rate_ratio = match (rounded(&self.exposed), rounded(&self.unexposed)) {
+            (Some(exposed), Some(unexposed)) => format_option(
+                exposed.rate_ratio(&unexposed, 0.95),
+                |(ratio, (low, high))| format!("{ratio:.2} ({low:.2} to {high:.2})"),
+            ),
+            _ => "suppressed".into(),
+        };
+        table.push_row(["Rate ratio (95% CI)".to_string(), rate_ratio, String::new()]);
+        table
+    }
+}
+
+fn format_option<T>(value: Option<T>, f: impl FnOnce(T) -> String) -> String {
+    match value {
+        Some(value) => f(value),
+        None => "-".into(),
+    }
+}
@@ -0,0 +1,473 @@
+//! New cancers after lymphoma.
+//!
+//! A secondary malignancy is a code for [`LateEffect::SecondaryMalignancy`] (the Cambridge cancer
+//! codeset, without lymphoma and leukaemia) first recorded after the patient's lymphoma
+//! diagnosis. We group codes by the site of the cancer, using the Read v2 neoplasm chapter (`B`)
+//! and its `[X]` codes (`Byu`), which follow ICD-10 chapter II.
+//!
+//! [`LateEffect::SecondaryMalignancy`]: super::LateEffect::SecondaryMalignancy
+use crate::{
+    epi::{self, IncidenceRate},
+    provenance,
+    read2::{CodeSet, ReadCode},
+    render::TextTable,
+    DisclosureControl, Events, PatientId, Patients, Registrations, Result,
+};
+use chrono::NaiveDate;
+use qu::ick_use::*;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, path::Path, str::FromStr};
+use term_data_table::Table;
+
+/// Where a cancer is.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub enum CancerSite {
+    HeadAndNeck,
+    OesophagusStomach,
+    Colorectal,
+    OtherDigestive,
+    Lung,
+    OtherThoracic,
+    BoneSoftTissue,
+    Melanoma,
+    /// Other skin cancers, e.g. basal and squamous cell carcinomas.
+    SkinNonMelanoma,
+    Breast,
+    FemaleGenital,
+    Prostate,
+    OtherMaleGenital,
+    Urinary,
+    BrainCns,
+    ThyroidEndocrine,
+    /// Blood cancers that aren't lymphoma or leukaemia, e.g. myeloma.
+    OtherHaematological,
+    /// Secondary (metastatic) cancer, where the primary isn't coded.
+    Metastatic,
+    Unspecified,
+}
+
+/// Read code prefixes for each site. Where more than one prefix matches a code, the longest one
+/// wins.
+const SITE_PREFIXES: &[(&str, CancerSite)] = &[
+    ("B0", CancerSite::HeadAndNeck),
+    ("B20", CancerSite::HeadAndNeck),
+    ("B21", CancerSite::HeadAndNeck),
+    ("Byu0", CancerSite::HeadAndNeck),
+    ("B10", CancerSite::OesophagusStomach),
+    ("B11", CancerSite::OesophagusStomach),
+    ("B13", CancerSite::Colorectal),
+    ("B14", CancerSite::Colorectal),
+    ("B1", CancerSite::OtherDigestive),
+    ("Byu1", CancerSite::OtherDigestive),
+    ("B22", CancerSite::Lung),
+    ("Byu20", CancerSite::Lung),
+    ("B2", CancerSite::OtherThoracic),
+    ("Byu2", CancerSite::OtherThoracic),
+    ("B30", CancerSite::BoneSoftTissue),
+    ("B31", CancerSite::BoneSoftTissue),
+    ("Byu3", CancerSite::BoneSoftTissue),
+    ("Byu5", CancerSite::BoneSoftTissue),
+    ("B32", CancerSite::Melanoma),
+    ("Byu4", CancerSite::Melanoma),
+    ("B33", CancerSite::SkinNonMelanoma),
+    ("B34", CancerSite::Breast),
+    ("B35", CancerSite::Breast),
+    ("Byu6", CancerSite::Breast),
+    ("B40", CancerSite::FemaleGenital),
+    ("B41", CancerSite::FemaleGenital),
+    ("B42", CancerSite::FemaleGenital),
+    ("B43", CancerSite::FemaleGenital),
+    ("B44", CancerSite::FemaleGenital),
+    ("B45", CancerSite::FemaleGenital),
+    ("Byu7", CancerSite::FemaleGenital),
+    ("B46", CancerSite::Prostate),
+    ("B47", CancerSite::OtherMaleGenital),
+    ("B48", CancerSite::OtherMaleGenital),
+    ("Byu8", CancerSite::OtherMaleGenital),
+    ("B49", CancerSite::Urinary),
+    ("B4A", CancerSite::Urinary),
+    ("Byu9", CancerSite::Urinary),
+    ("B50", CancerSite::BrainCns),
+    ("B51", CancerSite::BrainCns),
+    ("B52", CancerSite::BrainCns),
+    ("ByuA", CancerSite::BrainCns),
+    ("B53", CancerSite::ThyroidEndocrine),
+    ("B54", CancerSite::ThyroidEndocrine),
+    ("ByuB", CancerSite::ThyroidEndocrine),
+    ("B6", CancerSite::OtherHaematological),
+    ("ByuD", CancerSite::OtherHaematological),
+    ("B56", CancerSite::Metastatic),
+    ("B57", CancerSite::Metastatic),
+    ("B58", CancerSite::Metastatic),
+    ("ByuC2", CancerSite::Metastatic),
+    ("ByuC3", CancerSite::Metastatic),
+    ("ByuC4", CancerSite::Metastatic),
+    ("ByuC5", CancerSite::Metastatic),
+    ("ByuC6", CancerSite::Metastatic),
+    ("ByuC7", CancerSite::Metastatic),
+];
+
+impl CancerSite {
+    pub const ALL: [CancerSite; 19] = [
+        CancerSite::HeadAndNeck,
+        CancerSite::OesophagusStomach,
+        CancerSite::Colorectal,
+        CancerSite::OtherDigestive,
+        CancerSite::Lung,
+        CancerSite::OtherThoracic,
+        CancerSite::BoneSoftTissue,
+        CancerSite::Melanoma,
+        CancerSite::SkinNonMelanoma,
+        CancerSite::Breast,
+        CancerSite::FemaleGenital,
+        CancerSite::Prostate,
+        CancerSite::OtherMaleGenital,
+        CancerSite::Urinary,
+        CancerSite::BrainCns,
+        CancerSite::ThyroidEndocrine,
+        CancerSite::OtherHaematological,
+        CancerSite::Metastatic,
+        CancerSite::Unspecified,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            CancerSite::HeadAndNeck => "Head and neck",
+            CancerSite::OesophagusStomach => "Oesophagus and stomach",
+            CancerSite::Colorectal => "Colorectal",
+            CancerSite::OtherDigestive => "Other digestive",
+            CancerSite::Lung => "Lung",
+            CancerSite::OtherThoracic => "Other thoracic",
+            CancerSite::BoneSoftTissue => "Bone and soft tissue",
+            CancerSite::Melanoma => "Melanoma",
+            CancerSite::SkinNonMelanoma => "Skin, non-melanoma",
+            CancerSite::Breast => "Breast",
+            CancerSite::FemaleGenital => "Female genital",
+            CancerSite::Prostate => "Prostate",
+            CancerSite::OtherMaleGenital => "Other male genital",
+            CancerSite::Urinary => "Urinary tract",
+            CancerSite::BrainCns => "Eye, brain and CNS",
+            CancerSite::ThyroidEndocrine => "Thyroid and endocrine",
+            CancerSite::OtherHaematological => "Other haematological",
+            CancerSite::Metastatic => "Metastatic, primary not coded",
+            CancerSite::Unspecified => "Unspecified",
+        }
+    }
+
+    /// The site for a cancer code. Codes outside the neoplasm chapter are `Unspecified`.
+    pub fn of(code: ReadCode) -> Self {
+        let code: &str = code.as_ref();
+        SITE_PREFIXES
+            .iter()
+            .filter(|(prefix, _)| code.starts_with(prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, site)| *site)
+            .unwrap_or(CancerSite::Unspecified)
+    }
+}
+
+impl fmt::Display for CancerSite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+impl FromStr for CancerSite {
+    type Err = Error;
+    /// Parse a site from its label (ignoring case).
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let input = input.trim();
+        CancerSite::ALL
+            .into_iter()
+            .find(|site| site.label().eq_ignore_ascii_case(input))
+            .ok_or_else(|| format_err!("unrecognised cancer site \"{input}\""))
+    }
+}
+
+/// Which site each cancer code is for: our own Read code prefixes, plus any from a mapping file.
+///
+/// Prefixes from the file are checked first (the longest matching one wins), so they can
+/// override or extend ours, e.g. for local codes outside the neoplasm chapter.
+#[derive(Debug, Clone, Default)]
+pub struct SiteMapping {
+    prefixes: Vec<(String, CancerSite)>,
+}
+
+impl SiteMapping {
+    /// Just our prefixes (see [`CancerSite::of`]).
+    pub fn builtin() -> Self {
+        Self::default()
+    }
+
+    /// Load extra prefixes from a CSV file with `prefix` and `site` columns, where `site` is a
+    /// [`CancerSite::label`] (e.g. "Breast").
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        #[derive(Deserialize)]
+        struct Row {
+            prefix: String,
+            site: String,
+        }
+        fn inner(path: &Path) -> Result<SiteMapping> {
+            let mut reader = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_path(path)?;
+            provenance::record_input(path);
+            let mut prefixes = vec![];
+            for (row_no, row) in reader.deserialize().enumerate() {
+                let row: Row = row?;
+                let site = row
+                    .site
+                    .parse()
+                    .with_context(|| format!("on row {}", row_no + 2))?;
+                prefixes.push((row.prefix, site));
+            }
+            Ok(SiteMapping { prefixes })
+        }
+        let path = path.as_ref();
+        inner(path).with_context(|| format!("while loading \"{}\"", path.display()))
+    }
+
+    /// The site for a cancer code.
+    pub fn site(&self, code: ReadCode) -> CancerSite {
+        let code_str: &str = code.as_ref();
+        self.prefixes
+            .iter()
+            .filter(|(prefix, _)| code_str.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, site)| *site)
+            .unwrap_or_else(|| CancerSite::of(code))
+    }
+}
+
+/// The first code for a cancer site after a patient's lymphoma diagnosis.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SecondaryCancer {
+    pub patient_id: PatientId,
+    pub site: CancerSite,
+    pub read_code: ReadCode,
+    pub date: NaiveDate,
+    pub years_after_diagnosis: f64,
+}
+
+/// New cancers at one site (or any site), with the time from lymphoma diagnosis to the first
+/// code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SiteSummary {
+    /// `None` for cancers at any site.
+    pub site: Option<CancerSite>,
+    pub rate: IncidenceRate,
+    pub years_lower_quartile: Option<f64>,
+    pub years_median: Option<f64>,
+    pub years_upper_quartile: Option<f64>,
+}
+
+impl SiteSummary {
+    fn new(site: Option<CancerSite>, rate: IncidenceRate, cancers: &[SecondaryCancer]) -> Self {
+        let mut years = cancers
+            .iter()
+            .map(|cancer| cancer.years_after_diagnosis)
+            .collect::<Vec<_>>();
+        years.sort_unstable_by(f64::total_cmp);
+        SiteSummary {
+            site,
+            rate,
+            years_lower_quartile: nearest_rank(&years, 0.25),
+            years_median: nearest_rank(&years, 0.5),
+            years_upper_quartile: nearest_rank(&years, 0.75),
+        }
+    }
+}
+
+/// New cancers after lymphoma diagnosis, by site.
+#[derive(Debug, Clone)]
+pub struct SecondaryMalignancies {
+    /// The first code for each site for each patient, for sites that weren't already coded at
+    /// diagnosis.
+    pub cancers: Vec<SecondaryCancer>,
+    /// New cancers at any site. Patients with a cancer code at or before diagnosis aren't at risk.
+    pub any_site: SiteSummary,
+    /// Only sites with at least one new cancer.
+    pub sites: Vec<SiteSummary>,
+}
+
+impl SecondaryMalignancies {
+    /// Find new cancers in `codeset` between each patient's lymphoma diagnosis and the end of
+    /// their follow-up. Patients without a diagnosis date are left out.
+    pub fn new(
+        patients: &Patients,
+        events: &Events,
+        codeset: &CodeSet,
+        diagnosis_dates: &HashMap<PatientId, NaiveDate>,
+        registrations: &Registrations,
+    ) -> Self {
+        let censor_date = |id| registrations.follow_up_end(id);
+        let first_after = |codeset: &CodeSet| {
+            first_codes_after(patients, events, codeset, diagnosis_dates, censor_date)
+        };
+
+        let any_rate = epi::incidence_rate(patients, events, codeset, diagnosis_dates, censor_date);
+        let any_site = SiteSummary::new(None, any_rate, &first_after(codeset));
+
+        let mut cancers = vec![];
+        let mut sites = vec![];
+        for site in CancerSite::ALL {
+            let site_codes: CodeSet = codeset
+                .iter()
+                .filter(|code| CancerSite::of(*code) == site)
+                .collect();
+            let site_cancers = first_after(&site_codes);
+            if site_cancers.is_empty() {
+                continue;
+            }
+            let rate =
+                epi::incidence_rate(patients, events, &site_codes, diagnosis_dates, censor_date);
+            sites.push(SiteSummary::new(Some(site), rate, &site_cancers));
+            cancers.extend(site_cancers);
+        }
+        cancers.sort_by_key(|cancer| (cancer.patient_id, cancer.date));
+
+        SecondaryMalignancies {
+            cancers,
+            any_site,
+            sites,
+        }
+    }
+
+    pub fn data_table(&self, dc: &DisclosureControl) -> Table<'_> {
+        self.text_table(dc).into_term_table()
+    }
+
+    /// Cases are rounded or suppressed according to `dc`, and time to diagnosis is only shown for
+    /// sites where the number of cases isn't suppressed.
+    pub fn text_table(&self, dc: &DisclosureControl) -> TextTable {
+        let mut table = TextTable::new([
+            "Site",
+            "New cancers",
+            "Rate per 1,000 PY",
+            "Years from lymphoma diagnosis, median (IQR)",
+        ]);
+        for summary in std::iter::once(&self.any_site).chain(self.sites.iter()) {
+            let cases = dc.count(summary.rate.cases);
+            let rate = cases.value().and_then(|cases| {
+                IncidenceRate {
+                    cases,
+                    ..summary.rate
+                }
+                .per_1000()
+            });
+            let years = match (
+                cases.value(),
+                summary.years_lower_quartile,
+                summary.years_median,
+                summary.years_upper_quartile,
+            ) {
+                (Some(_), Some(low), Some(median), Some(high)) => {
+                    format!("{median:.1} ({low:.1} to {high:.1})")
+                }
+                _ => "-".into(),
+            };
+            table.push_row([
+                summary
+                    .site
+                    .map(CancerSite::label)
+                    .unwrap_or("Any site")
+                    .to_string(),
+                cases.to_string(),
+                match rate {
+                    Some(rate) => format!("{rate:.1}"),
+                    None => "-".into(),
+                },
+                years,
+            ]);
+        }
+        table
+    }
+
+    /// Write the new cancers to a CSV file.
+    ///
+    /// This is patient-level data, so it must stay in the secure environment.
+    pub fn save_cancers(&self, path: impl AsRef<Path>) -> Result {
+        let path = path.as_ref();
+        let mut out = csv::Writer::from_path(path)
+            .with_context(|| format!("creating \"{}\"", path.display()))?;
+        for cancer in self.cancers.iter() {
+            out.serialize(cancer)?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// Each patient's first code in `codeset`, if it is after their index date and on or before
+/// their censoring date (the same cases as [`epi::incidence_rate`]).
+fn first_codes_after(
+    patients: &Patients,
+    events: &Events,
+    codeset: &CodeSet,
+    index_dates: &HashMap<PatientId, NaiveDate>,
+    censor_date: impl Fn(PatientId) -> NaiveDate,
+) -> Vec<SecondaryCancer> {
+    patients
+        .iter_ref()
+        .filter_map(|pat| {
+            let start = *index_dates.get(&pat.patient_id)?;
+            let first = events
+                .events_for_patient(pat.patient_id)
+                .filter(|evt| codeset.contains(evt.read_code))
+                .min_by_key(|evt| evt.date)?;
+            // A code with a missing date sorts first, so it counts as a cancer before the index
+            // date.
+            if first.date <= start || first.date > censor_date(pat.patient_id) {
+                return None;
+            }
+            Some(SecondaryCancer {
+                patient_id: pat.patient_id,
+                site: CancerSite::of(first.read_code),
+                read_code: first.read_code,
+                date: first.date,
+                years_after_diagnosis: epi::years_between(start, first.date),
+            })
+        })
+        .collect()
+}
+
+/// The `p` quantile of `sorted`, using the nearest-rank method.
+fn nearest_rank(sorted: &[f64], p: f64) -> Option<f64> {
+    let rank = (p * sorted.len() as f64).ceil().max(1.) as usize;
+    sorted.get(rank - 1).copied()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn site() {
+        let site = |code: &str| CancerSite::of(ReadCode::from_str(code).unwrap());
+        assert_eq!(site("B22.."), CancerSite::Lung);
+        assert_eq!(site("B2210"), CancerSite::Lung);
+        assert_eq!(site("B23.."), CancerSite::OtherThoracic);
+        assert_eq!(site("B21.."), CancerSite::HeadAndNeck);
+        assert_eq!(site("B13.."), CancerSite::Colorectal);
+        assert_eq!(site("B17.."), CancerSite::OtherDigestive);
+        assert_eq!(site("B34.."), CancerSite::Breast);
+        assert_eq!(site("B630."), CancerSite::OtherHaematological);
+        assert_eq!(site("B577."), CancerSite::Metastatic);
+        assert_eq!(site("Byu20"), CancerSite::Lung);
+        assert_eq!(site("Byu21"), CancerSite::OtherThoracic);
+        assert_eq!(site("ByuC8"), CancerSite::Unspecified);
+        assert_eq!(site("B59.."), CancerSite::Unspecified);
+        assert_eq!(site("B33.."), CancerSite::SkinNonMelanoma);
+
+        let mapping = SiteMapping {
+            prefixes: vec![("B33".into(), CancerSite::Melanoma)],
+        };
+        let code = |code: &str| ReadCode::from_str(code).unwrap();
+        assert_eq!(mapping.site(code("B33..")), CancerSite::Melanoma);
+        assert_eq!(mapping.site(code("B22..")), CancerSite::Lung);
+        assert_eq!(
+            "skin, NON-melanoma".parse::<CancerSite>().unwrap(),
+            CancerSite::SkinNonMelanoma
+        );
+    }
+}
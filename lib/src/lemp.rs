@@ -0,0 +1,1344 @@
+//! Adherence to the late effects monitoring plan (LEMP).
+//!
+//! Depending on their treatment, ADAPTed patients should have some tests (e.g. blood pressure)
+//! done regularly. Each test is described by a [`SurveillanceRule`], and we look in the GP record
+//! for codes showing the test was done between the patient's ADAPT date and the end of their
+//! follow-up.
+//!
+//! The rules are read from a TOML file, with a list of `[[rule]]` tables. The rules we use by
+//! default are in `lemp/rules.toml`, and look like
+//!
+//! ```toml
+//! [[rule]]
+//! name = "Flu"
+//! eligibility = ["chemo_bleomycin", "radiation_lungs"]
+//! codeset = "influenza_vaccination/codes.txt"
+//! interval_years = 1
+//! grace_months = 15
+//! period = "flu_season"
+//! ```
+use crate::{
+    dates,
+    join::PatientAdapt,
+    measurements::{self, BpCodes, BpThreshold},
+    read2::CodeSet,
+    render::TextTable,
+    stats::{
+        self,
+        glm::{self, Design, Family, GlmFit},
+        QuantileMethod, Quartiles, TrendGroup, TrendTest,
+    },
+    termset_path, Adapt, Adapts, DisclosureControl, Event, Events, Imd, LymphomaSubtype, Patient,
+    PatientId, Patients, Registrations, Result, Sex,
+};
+use chrono::{Datelike, Duration, NaiveDate};
+use qu::ick_use::*;
+use serde::{Deserialize, Serialize};
+use statrs::distribution::{ContinuousCDF, StudentsT};
+use std::{
+    collections::BTreeMap,
+    fmt, fs, iter,
+    path::{Path, PathBuf},
+};
+use term_data_table::Table;
+
+const DEFAULT_RULES: &str = include_str!("lemp/rules.toml");
+const DAYS_PER_YEAR: f64 = 365.25;
+
+/// A test that some ADAPTed patients should have regularly.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SurveillanceRule {
+    pub name: String,
+    /// Patients should have the test if any of these are set in their ADAPT record.
+    pub eligibility: Vec<AdaptFlag>,
+    /// The codes showing the test was done, relative to the termsets directory.
+    pub codeset: PathBuf,
+    /// How often the test should be done.
+    pub interval_years: f64,
+    /// How long after the start of each interval a test still counts for that interval, e.g. 15
+    /// months for an annual test. Defaults to the interval.
+    #[serde(default)]
+    pub grace_months: Option<f64>,
+    /// What we count tests in, for the proportion of years with a test.
+    #[serde(default)]
+    pub period: Period,
+    /// Where the codeset came from.
+    #[serde(default)]
+    pub provenance: Option<String>,
+}
+
+impl SurveillanceRule {
+    pub fn is_eligible(&self, adapt: &Adapt) -> bool {
+        self.eligibility.iter().any(|flag| flag.is_set(adapt))
+    }
+
+    /// The length of an interval, to the nearest month.
+    pub fn interval_months(&self) -> i32 {
+        dates::years_to_months(self.interval_years)
+    }
+
+    /// How long after the start of an interval a test counts for it, to the nearest month.
+    pub fn grace_period_months(&self) -> i32 {
+        match self.grace_months {
+            Some(months) => months.round() as i32,
+            None => self.interval_months(),
+        }
+    }
+
+    /// The longest time between tests that still counts as adherent, in years. This is the grace
+    /// period, so an annual test with 15 months' grace can be up to 15 months late.
+    pub fn max_gap_years(&self) -> f64 {
+        f64::from(self.grace_period_months()) / 12.
+    }
+
+    pub fn load_codeset(&self) -> Result<CodeSet> {
+        CodeSet::load(termset_path(&self.codeset))
+            .with_context(|| format!("loading the codeset for rule \"{}\"", self.name))
+    }
+}
+
+/// The periods that each should contain a test.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Period {
+    /// Consecutive intervals from the ADAPT date, with the rule's grace period.
+    #[default]
+    Interval,
+    /// Flu seasons (September to March), for vaccinations given in the autumn.
+    FluSeason,
+}
+
+impl Period {
+    pub fn label(self) -> &'static str {
+        match self {
+            Period::Interval => "years",
+            Period::FluSeason => "flu seasons",
+        }
+    }
+}
+
+/// A flu season, running from September to the end of the following March.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FluSeason {
+    /// The year the season starts in.
+    pub start_year: i32,
+}
+
+impl FluSeason {
+    /// The season `date` is in, or `None` for April to August.
+    pub fn of(date: NaiveDate) -> Option<Self> {
+        match date.month() {
+            9..=12 => Some(FluSeason {
+                start_year: date.year(),
+            }),
+            1..=3 => Some(FluSeason {
+                start_year: date.year() - 1,
+            }),
+            _ => None,
+        }
+    }
+
+    /// The first day of the season.
+    pub fn start(self) -> NaiveDate {
+        NaiveDate::from_ymd_opt(self.start_year, 9, 1).unwrap()
+    }
+
+    /// The last day of the season.
+    pub fn end(self) -> NaiveDate {
+        NaiveDate::from_ymd_opt(self.start_year + 1, 3, 31).unwrap()
+    }
+}
+
+impl fmt::Display for FluSeason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{:02}", self.start_year, (self.start_year + 1) % 100)
+    }
+}
+
+/// A list of [`SurveillanceRule`]s.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SurveillanceRules {
+    #[serde(rename = "rule")]
+    pub rules: Vec<SurveillanceRule>,
+}
+
+impl SurveillanceRules {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        fn inner(path: &Path) -> Result<SurveillanceRules> {
+            SurveillanceRules::parse(&fs::read_to_string(path)?)
+        }
+        let path = path.as_ref();
+        inner(path).with_context(|| format!("while loading \"{}\"", path.display()))
+    }
+
+    /// The rules in `lemp/rules.toml`.
+    pub fn builtin() -> Self {
+        Self::parse(DEFAULT_RULES).expect("built-in LEMP rules are invalid")
+    }
+
+    fn parse(input: &str) -> Result<Self> {
+        let rules: Self = toml::from_str(input)?;
+        for rule in rules.iter() {
+            ensure!(
+                rule.interval_months() > 0,
+                "rule \"{}\" must have an interval of at least a month",
+                rule.name
+            );
+            ensure!(
+                rule.grace_period_months() >= 0,
+                "rule \"{}\" has a negative grace period",
+                rule.name
+            );
+        }
+        Ok(rules)
+    }
+
+    /// The rule with the given name.
+    pub fn get(&self, name: &str) -> Option<&SurveillanceRule> {
+        self.rules.iter().find(|rule| rule.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &SurveillanceRule> + '_ {
+        self.rules.iter()
+    }
+}
+
+/// The treatment flags in an ADAPT record, as used in [`SurveillanceRule::eligibility`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdaptFlag {
+    ChemoDoxorubicin,
+    RadiationHeart,
+    #[serde(rename = "female_sub_50_chemo_doxorubicin_radiation_heart")]
+    FemaleSub50ChemoDoxorubicinRadiationHeart,
+    ChemoDoxorubicinRadiationHeart,
+    RadiationLungs,
+    ChemoBleomycin,
+    CurrentOrExSmoker,
+    #[serde(rename = "female_sub_36_radiation_chest")]
+    FemaleSub36RadiationChest,
+    RadiationThyroid,
+    MaleChemo,
+    AnyRadiotherapy,
+    RadiationHeadNeck,
+    RadiationGulletStomach,
+    RadiationBowels,
+    ChemoVincristineVinblastine,
+    ChemoPrednisoneDexamethasone,
+    #[serde(rename = "low_energy_last_12_months")]
+    LowEnergyLast12Months,
+    ChemoCisplatinCarboplatin,
+    RadiationAbdomenKidney,
+    HodgkinLymphomaStemCellTransplant,
+}
+
+impl AdaptFlag {
+    pub const ALL: [AdaptFlag; 20] = [
+        AdaptFlag::ChemoDoxorubicin,
+        AdaptFlag::RadiationHeart,
+        AdaptFlag::FemaleSub50ChemoDoxorubicinRadiationHeart,
+        AdaptFlag::ChemoDoxorubicinRadiationHeart,
+        AdaptFlag::RadiationLungs,
+        AdaptFlag::ChemoBleomycin,
+        AdaptFlag::CurrentOrExSmoker,
+        AdaptFlag::FemaleSub36RadiationChest,
+        AdaptFlag::RadiationThyroid,
+        AdaptFlag::MaleChemo,
+        AdaptFlag::AnyRadiotherapy,
+        AdaptFlag::RadiationHeadNeck,
+        AdaptFlag::RadiationGulletStomach,
+        AdaptFlag::RadiationBowels,
+        AdaptFlag::ChemoVincristineVinblastine,
+        AdaptFlag::ChemoPrednisoneDexamethasone,
+        AdaptFlag::LowEnergyLast12Months,
+        AdaptFlag::ChemoCisplatinCarboplatin,
+        AdaptFlag::RadiationAbdomenKidney,
+        AdaptFlag::HodgkinLymphomaStemCellTransplant,
+    ];
+
+    /// The name of the flag, as used in rule files.
+    pub fn label(self) -> &'static str {
+        match self {
+            AdaptFlag::ChemoDoxorubicin => "chemo_doxorubicin",
+            AdaptFlag::RadiationHeart => "radiation_heart",
+            AdaptFlag::FemaleSub50ChemoDoxorubicinRadiationHeart => {
+                "female_sub_50_chemo_doxorubicin_radiation_heart"
+            }
+            AdaptFlag::ChemoDoxorubicinRadiationHeart => "chemo_doxorubicin_radiation_heart",
+            AdaptFlag::RadiationLungs => "radiation_lungs",
+            AdaptFlag::ChemoBleomycin => "chemo_bleomycin",
+            AdaptFlag::CurrentOrExSmoker => "current_or_ex_smoker",
+            AdaptFlag::FemaleSub36RadiationChest => "female_sub_36_radiation_chest",
+            AdaptFlag::RadiationThyroid => "radiation_thyroid",
+            AdaptFlag::MaleChemo => "male_chemo",
+            AdaptFlag::AnyRadiotherapy => "any_radiotherapy",
+            AdaptFlag::RadiationHeadNeck => "radiation_head_neck",
+            AdaptFlag::RadiationGulletStomach => "radiation_gullet_stomach",
+            AdaptFlag::RadiationBowels => "radiation_bowels",
+            AdaptFlag::ChemoVincristineVinblastine => "chemo_vincristine_vinblastine",
+            AdaptFlag::ChemoPrednisoneDexamethasone => "chemo_prednisone_dexamethasone",
+            AdaptFlag::LowEnergyLast12Months => "low_energy_last_12_months",
+            AdaptFlag::ChemoCisplatinCarboplatin => "chemo_cisplatin_carboplatin",
+            AdaptFlag::RadiationAbdomenKidney => "radiation_abdomen_kidney",
+            AdaptFlag::HodgkinLymphomaStemCellTransplant => "hodgkin_lymphoma_stem_cell_transplant",
+        }
+    }
+
+    pub fn is_set(self, adapt: &Adapt) -> bool {
+        match self {
+            AdaptFlag::ChemoDoxorubicin => adapt.chemo_doxorubicin,
+            AdaptFlag::RadiationHeart => adapt.radiation_heart,
+            AdaptFlag::FemaleSub50ChemoDoxorubicinRadiationHeart => {
+                adapt.female_sub_50_chemo_doxorubicin_radiation_heart
+            }
+            AdaptFlag::ChemoDoxorubicinRadiationHeart => adapt.chemo_doxorubicin_radiation_heart,
+            AdaptFlag::RadiationLungs => adapt.radiation_lungs,
+            AdaptFlag::ChemoBleomycin => adapt.chemo_bleomycin,
+            AdaptFlag::CurrentOrExSmoker => adapt.current_or_ex_smoker,
+            AdaptFlag::FemaleSub36RadiationChest => adapt.female_sub_36_radiation_chest,
+            AdaptFlag::RadiationThyroid => adapt.radiation_thyroid,
+            AdaptFlag::MaleChemo => adapt.male_chemo,
+            AdaptFlag::AnyRadiotherapy => adapt.any_radiotherapy,
+            AdaptFlag::RadiationHeadNeck => adapt.radiation_head_neck,
+            AdaptFlag::RadiationGulletStomach => adapt.radiation_gullet_stomach,
+            AdaptFlag::RadiationBowels => adapt.radiation_bowels,
+            AdaptFlag::ChemoVincristineVinblastine => adapt.chemo_vincristine_vinblastine,
+            AdaptFlag::ChemoPrednisoneDexamethasone => adapt.chemo_prednisone_dexamethasone,
+            AdaptFlag::LowEnergyLast12Months => adapt.low_energy_last_12_months,
+            AdaptFlag::ChemoCisplatinCarboplatin => adapt.chemo_cisplatin_carboplatin,
+            AdaptFlag::RadiationAbdomenKidney => adapt.radiation_abdomen_kidney,
+            AdaptFlag::HodgkinLymphomaStemCellTransplant => {
+                adapt.hodgkin_lymphoma_stem_cell_transplant
+            }
+        }
+    }
+}
+
+impl fmt::Display for AdaptFlag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// The ADAPTed patients, with everything we need to check their tests.
+pub struct LempData {
+    adapt_patients: Vec<PatientAdapt>,
+    events: Events,
+    registrations: Registrations,
+}
+
+impl LempData {
+    pub fn new(
+        patients: Patients,
+        adapts: Adapts,
+        events: Events,
+        registrations: Registrations,
+    ) -> Self {
+        let joined = patients.join(&adapts);
+        joined.log_unmatched(
+            "patients without an ADAPT record",
+            "ADAPT records without a patient",
+        );
+        Self {
+            adapt_patients: joined.rows,
+            events,
+            registrations,
+        }
+    }
+
+    /// The patients who should have the test in `rule`.
+    pub fn eligible<'a>(
+        &'a self,
+        rule: &'a SurveillanceRule,
+    ) -> impl Iterator<Item = &'a PatientAdapt> + 'a {
+        self.adapt_patients
+            .iter()
+            .filter(|ap| rule.is_eligible(&ap.adapt))
+    }
+
+    /// How often the patients eligible for `rule` have had the test.
+    pub fn rule_stats(&self, rule: &SurveillanceRule) -> Result<Stats> {
+        let codeset = rule.load_codeset()?;
+        let rows: Vec<_> = self
+            .eligible(rule)
+            .map(|pa| self.adherence(pa, rule, &codeset))
+            .collect();
+        Ok(Stats::from_patients(&rows, rule.period))
+    }
+
+    /// A model of adherence to `rule` in the eligible patients, adjusted for age at ADAPT (per 10
+    /// years), sex, IMD decile (as a trend) and lymphoma subtype.
+    ///
+    /// Patients without follow-up, with a missing IMD, or whose sex isn't recorded as male or
+    /// female, are left out. Other sexes are too rare to estimate an effect for, so rather than
+    /// fit them as their own level we log how many were left out.
+    pub fn adherence_model(
+        &self,
+        rule: &SurveillanceRule,
+        outcome: AdherenceOutcome,
+    ) -> Result<GlmFit> {
+        let codeset = rule.load_codeset()?;
+        let mut rows = vec![];
+        let mut unknown_sex = 0;
+        for pa in self.eligible(rule) {
+            if !pa.patient.sex.is_known() {
+                unknown_sex += 1;
+                continue;
+            }
+            let adherence = self.adherence(pa, rule, &codeset);
+            let (Some(follow_up_years), Some(imd)) =
+                (adherence.follow_up_years, pa.patient.imd.as_decile())
+            else {
+                continue;
+            };
+            let response = match outcome {
+                AdherenceOutcome::Adherent => match adherence.adherent {
+                    Some(adherent) => f64::from(u8::from(adherent)),
+                    None => continue,
+                },
+                AdherenceOutcome::TestCount => adherence.n_tests as f64,
+            };
+            rows.push((pa, response, follow_up_years, imd));
+        }
+        if unknown_sex > 0 {
+            event!(
+                Level::WARN,
+                "left {unknown_sex} patients whose sex isn't male or female out of the {} model \
+                for {}",
+                outcome,
+                rule.name
+            );
+        }
+
+        let sexes = rows
+            .iter()
+            .map(|(pa, ..)| pa.patient.sex)
+            .collect::<Vec<_>>();
+        let subtypes = rows
+            .iter()
+            .map(|(pa, ..)| subtype_group(&pa.patient))
+            .collect::<Vec<_>>();
+        let design = Design::new(rows.len())
+            .with_numeric(
+                "Age at ADAPT (per 10 years)",
+                rows.iter()
+                    .map(|(pa, ..)| f64::from(pa.patient.age_at(pa.adapt_date())) / 10.),
+            )
+            .with_categorical("Sex", &sexes, &Sex::Female)
+            .with_numeric("IMD decile", rows.iter().map(|(.., imd)| f64::from(*imd)))
+            .with_categorical("Subtype", &subtypes, &"Hodgkin");
+        let response = rows.iter().map(|(_, y, ..)| *y).collect::<Vec<_>>();
+        let fit = match outcome {
+            AdherenceOutcome::Adherent => glm::fit(Family::Binomial, &design, &response, None),
+            AdherenceOutcome::TestCount => {
+                let offset = rows
+                    .iter()
+                    .map(|(_, _, years, _)| years.ln())
+                    .collect::<Vec<_>>();
+                glm::fit(Family::Poisson, &design, &response, Some(&offset))
+            }
+        };
+        fit.with_context(|| format!("fitting {} model for {}", outcome, rule.name))
+    }
+
+    /// Compare each eligible patient's test rate in the `years` before their ADAPT date with the
+    /// rate in the `years` after it.
+    ///
+    /// Both periods are cut short where we can't see the patient's record, and patients without
+    /// any follow-up on one side are left out.
+    pub fn before_after(&self, rule: &SurveillanceRule, years: f64) -> Result<BeforeAfter> {
+        let codeset = rule.load_codeset()?;
+        let months = dates::years_to_months(years);
+        let rate = |patient_id, start: NaiveDate, end: NaiveDate| {
+            let span = (end - start).num_days() as f64 / DAYS_PER_YEAR;
+            let tests = self
+                .events
+                .for_patient_in_window(patient_id, start, end)
+                .filter(|evt| codeset.contains(evt.read_code))
+                .count();
+            tests as f64 / span
+        };
+
+        let mut pairs = vec![];
+        for pa in self.eligible(rule) {
+            let patient_id = pa.patient.patient_id;
+            let adapt_date = pa.adapt_date();
+            let before_start = match self.registrations.follow_up_start(patient_id) {
+                Some(start) => start.max(dates::saturating_add_months(adapt_date, -months)),
+                None => dates::saturating_add_months(adapt_date, -months),
+            };
+            // Tests on the ADAPT date count as after.
+            let before_end = adapt_date - Duration::days(1);
+            let after_end = self
+                .registrations
+                .follow_up_end(patient_id)
+                .min(dates::saturating_add_months(adapt_date, months));
+            if before_end <= before_start || after_end <= adapt_date {
+                continue;
+            }
+            pairs.push((
+                rate(patient_id, before_start, before_end),
+                rate(patient_id, adapt_date, after_end),
+            ));
+        }
+        Ok(BeforeAfter::from_pairs(years, &pairs, 0.95))
+    }
+
+    /// How many of the patients eligible for `rule` (which should be the BP rule) have a raised BP
+    /// at their most recent reading.
+    pub fn bp_control(&self, rule: &SurveillanceRule, threshold: BpThreshold) -> Result<BpControl> {
+        let readings = measurements::blood_pressure(&self.events, &BpCodes::load()?);
+        let mut control = BpControl::default();
+        for pa in self.eligible(rule) {
+            let end_date = self.registrations.follow_up_end(pa.patient.patient_id);
+            control.num_people += 1;
+            let latest = readings
+                .get(&pa.patient.patient_id)
+                .and_then(|readings| measurements::latest_before(readings, end_date));
+            if let Some(latest) = latest {
+                control.with_reading += 1;
+                if latest.is_above(threshold) {
+                    control.above_threshold += 1;
+                }
+            }
+        }
+        Ok(control)
+    }
+
+    /// The proportion of eligible patients adherent to `rule` in each IMD decile, with a test for
+    /// a trend across the deciles.
+    ///
+    /// Patients with a missing IMD, or without follow-up, are left out.
+    pub fn adherence_by_imd(&self, rule: &SurveillanceRule) -> Result<ImdTrend> {
+        let codeset = rule.load_codeset()?;
+        let mut groups = BTreeMap::<Imd, TrendGroup>::new();
+        for pa in self.eligible(rule) {
+            if pa.patient.imd == Imd::Missing {
+                continue;
+            }
+            let Some(adherent) = self.adherence(pa, rule, &codeset).adherent else {
+                continue;
+            };
+            let group = groups.entry(pa.patient.imd).or_default();
+            group.total += 1;
+            if adherent {
+                group.cases += 1;
+            }
+        }
+        let groups = groups.into_iter().collect::<Vec<_>>();
+        let scored = groups
+            .iter()
+            .filter_map(|(imd, group)| Some((f64::from(imd.as_decile()?), *group)))
+            .collect::<Vec<_>>();
+        Ok(ImdTrend {
+            trend: stats::cochran_armitage(&scored),
+            groups,
+        })
+    }
+
+    /// Each ADAPTed patient's tests for each rule, in patient ID order.
+    ///
+    /// Every patient gets a row for every rule, with `eligible` saying whether they should have
+    /// the test.
+    pub fn patient_adherence(&self, rules: &SurveillanceRules) -> Result<Vec<PatientAdherence>> {
+        let mut out = vec![];
+        for rule in rules.iter() {
+            let codeset = rule.load_codeset()?;
+            out.extend(
+                self.adapt_patients
+                    .iter()
+                    .map(|pa| self.adherence(pa, rule, &codeset)),
+            );
+        }
+        // Stable, so each patient's rules stay in the order they are in the rules file.
+        out.sort_by_key(|row| row.patient_id);
+        Ok(out)
+    }
+
+    /// How often one patient has had the test in `rule`.
+    fn adherence(
+        &self,
+        pa: &PatientAdapt,
+        rule: &SurveillanceRule,
+        code_set: &CodeSet,
+    ) -> PatientAdherence {
+        let eligible = rule.is_eligible(&pa.adapt);
+        let mut out = PatientAdherence {
+            patient_id: pa.patient.patient_id,
+            rule: rule.name.clone(),
+            eligible,
+            n_tests: 0,
+            follow_up_years: None,
+            rate: None,
+            longest_gap_years: None,
+            years_with_test: None,
+            adherent: None,
+        };
+
+        let adapt_date = pa.adapt_date();
+        // Follow-up stops if the patient dies or leaves the practice.
+        let end_date = self.registrations.follow_up_end(pa.patient.patient_id);
+        if end_date <= adapt_date {
+            return out;
+        }
+        let events = self
+            .events
+            .for_patient_in_window(pa.patient.patient_id, adapt_date, end_date)
+            .filter(|&evt| code_set.contains(evt.read_code))
+            .collect::<Vec<_>>();
+
+        // The timespan between when this patient was ADAPTed, and the end of their follow-up,
+        // in years.
+        let span = (end_date - adapt_date).num_seconds() as f64 / (60. * 60. * 24. * DAYS_PER_YEAR);
+        // The longest time without a test, in years.
+        let longest = biggest_gap(adapt_date, end_date, events.iter().copied()).num_days() as f64
+            / DAYS_PER_YEAR;
+        assert!(longest >= 0.);
+
+        out.n_tests = events.len();
+        out.follow_up_years = Some(span);
+        // The rate of measurement, in years.
+        out.rate = Some(events.len() as f64 / span);
+        out.longest_gap_years = Some(longest);
+        let dates: Vec<_> = events.iter().map(|evt| evt.date).collect();
+        out.years_with_test = match rule.period {
+            Period::Interval => proportion_covered(
+                adapt_date,
+                end_date,
+                &dates,
+                rule.interval_months(),
+                rule.grace_period_months(),
+            ),
+            Period::FluSeason => proportion_seasons_covered(adapt_date, end_date, &dates),
+        };
+        // Someone followed up for less than the grace period can't miss a test, so we can't say
+        // whether they are adherent.
+        out.adherent =
+            (eligible && span >= rule.max_gap_years()).then_some(longest <= rule.max_gap_years());
+        out
+    }
+}
+
+/// What to model in [`LempData::adherence_model`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AdherenceOutcome {
+    /// Whether the longest gap between tests is within the rule's grace period (logistic).
+    Adherent,
+    /// The number of tests, with follow-up time as the exposure (Poisson).
+    TestCount,
+}
+
+impl AdherenceOutcome {
+    pub const ALL: [AdherenceOutcome; 2] =
+        [AdherenceOutcome::Adherent, AdherenceOutcome::TestCount];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            AdherenceOutcome::Adherent => "adherence",
+            AdherenceOutcome::TestCount => "test rate",
+        }
+    }
+}
+
+impl fmt::Display for AdherenceOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// The broad lymphoma subtype, for adjusting models (most finer subtypes have too few patients).
+fn subtype_group(patient: &Patient) -> &'static str {
+    match patient.lymphoma_diagnosis_subtype() {
+        Some(LymphomaSubtype::Hodgkin) => "Hodgkin",
+        Some(LymphomaSubtype::NonHodgkin(_)) => "Non-Hodgkin",
+        _ => "Unspecified",
+    }
+}
+
+/// One patient's tests for one [`SurveillanceRule`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PatientAdherence {
+    pub patient_id: PatientId,
+    /// The [`SurveillanceRule::name`].
+    pub rule: String,
+    /// Whether the patient should have the test.
+    pub eligible: bool,
+    /// The number of tests between the ADAPT date and the end of follow-up.
+    pub n_tests: usize,
+    /// The time from the ADAPT date to the end of follow-up, in years, or `None` if the patient
+    /// has no follow-up after their ADAPT date.
+    pub follow_up_years: Option<f64>,
+    /// Tests per year, or `None` if the patient has no follow-up after their ADAPT date.
+    pub rate: Option<f64>,
+    /// The longest time without a test, in years, or `None` if the patient has no follow-up.
+    pub longest_gap_years: Option<f64>,
+    /// The proportion of the (complete) intervals since the ADAPT date with a test in them,
+    /// allowing for the grace period, or of flu seasons for [`Period::FluSeason`]. `None` if the
+    /// patient hasn't been followed for a whole interval or season.
+    pub years_with_test: Option<f64>,
+    /// Whether the longest gap is within the rule's grace period (see
+    /// [`SurveillanceRule::max_gap_years`]), or `None` if the patient isn't eligible or their
+    /// follow-up is shorter than the grace period.
+    pub adherent: Option<bool>,
+}
+
+/// Write per-patient adherence to a CSV file.
+///
+/// This is patient-level data, so it must stay in the secure environment.
+pub fn save_patient_adherence(rows: &[PatientAdherence], path: impl AsRef<Path>) -> Result {
+    fn inner(rows: &[PatientAdherence], path: &Path) -> Result {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("could not create parent")?;
+        }
+        let mut out = csv::Writer::from_path(path)?;
+        for row in rows {
+            out.serialize(row)?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+    let path = path.as_ref();
+    inner(rows, path).with_context(|| format!("saving adherence to \"{}\"", path.display()))
+}
+
+/// Gives the biggest gap between events, a start date, and an end date.
+///
+/// `events` must be in date order and between the start and end dates, as returned by
+/// [`Events::for_patient_in_window`].
+fn biggest_gap<'a>(
+    start_date: NaiveDate,
+    end_date: NaiveDate,
+    events: impl Iterator<Item = &'a Event> + 'a,
+) -> Duration {
+    let dates = iter::once(start_date)
+        .chain(events.map(|evt| evt.date))
+        .chain(iter::once(end_date))
+        .collect::<Vec<_>>();
+    // Cannot panic as `dates` has at least 2 elements.
+    dates
+        .array_windows()
+        .map(|[prev, next]| *next - *prev)
+        .max()
+        .unwrap()
+}
+
+/// The proportion of intervals in `start..end` with at least one of `dates` in them.
+///
+/// Intervals of `interval_months` run from `start`, and only whole intervals are counted. A date
+/// counts for an interval if it is within `grace_months` of the interval's start, so with a grace
+/// period longer than the interval a test can count for two intervals. `dates` must be sorted.
+fn proportion_covered(
+    start: NaiveDate,
+    end: NaiveDate,
+    dates: &[NaiveDate],
+    interval_months: i32,
+    grace_months: i32,
+) -> Option<f64> {
+    let mut intervals = 0;
+    let mut covered = 0;
+    // Each interval is counted from `start`, so a start on the 31st doesn't drift to the 28th.
+    let interval_start = |idx: i32| dates::saturating_add_months(start, idx * interval_months);
+    while interval_start(intervals + 1) <= end {
+        let this_start = interval_start(intervals);
+        intervals += 1;
+        let first = dates.partition_point(|date| *date < this_start);
+        let grace_end = dates::saturating_add_months(this_start, grace_months);
+        if matches!(dates.get(first), Some(date) if *date <= grace_end) {
+            covered += 1;
+        }
+    }
+    (intervals > 0).then(|| covered as f64 / intervals as f64)
+}
+
+/// The proportion of the flu seasons in `start..=end` with at least one of `dates` in them.
+///
+/// Only seasons that the patient was followed up for from start to finish are counted. `dates`
+/// must be sorted.
+fn proportion_seasons_covered(
+    start: NaiveDate,
+    end: NaiveDate,
+    dates: &[NaiveDate],
+) -> Option<f64> {
+    let mut seasons = 0;
+    let mut covered = 0;
+    let mut season = FluSeason {
+        start_year: start.year(),
+    };
+    if season.start() < start {
+        season.start_year += 1;
+    }
+    while season.end() <= end {
+        seasons += 1;
+        let first = dates.partition_point(|date| *date < season.start());
+        if matches!(dates.get(first), Some(date) if *date <= season.end()) {
+            covered += 1;
+        }
+        season.start_year += 1;
+    }
+    (seasons > 0).then(|| covered as f64 / seasons as f64)
+}
+
+/// How often the patients eligible for a test had it.
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    /// Total people in the denominator
+    pub num_people: usize,
+    /// The average number of coded events per year
+    pub rate_mean: f64,
+    /// Standard deviation for `rate_mean`
+    pub rate_sd: f64,
+    /// The 25th percentile rate
+    pub rate_25_percentile: f64,
+    /// The 50th percentile rate
+    pub rate_50_percentile: f64,
+    /// The 75th percentile rate
+    pub rate_75_percentile: f64,
+    /// The average longest gap between coded events, in years
+    pub longest_mean: f64,
+    /// The standard deviation for `longest_mean`
+    pub longest_sd: f64,
+    /// The average (median) longest gap between coded events, in years
+    pub longest_median: f64,
+    /// How many people had no events.
+    pub count_no_data: usize,
+    /// The average proportion of intervals (or flu seasons) with a test, for people followed for
+    /// at least one whole interval
+    pub years_with_test_mean: f64,
+    /// What `years_with_test_mean` counts
+    pub period: Period,
+}
+
+impl Stats {
+    /// Summarise the eligible patients in `rows`. Patients without any follow-up are left out.
+    pub fn from_patients(rows: &[PatientAdherence], period: Period) -> Self {
+        let followed: Vec<_> = rows
+            .iter()
+            .filter(|row| row.eligible)
+            .filter_map(|row| Some((row.n_tests, row.rate?, row.longest_gap_years?)))
+            .collect();
+        let n = followed.len();
+        if n == 0 {
+            return Stats {
+                num_people: 0,
+                count_no_data: 0,
+                rate_mean: f64::NAN,
+                rate_sd: f64::NAN,
+                rate_25_percentile: f64::NAN,
+                rate_50_percentile: f64::NAN,
+                rate_75_percentile: f64::NAN,
+                longest_mean: f64::NAN,
+                longest_sd: f64::NAN,
+                longest_median: f64::NAN,
+                years_with_test_mean: f64::NAN,
+                period,
+            };
+        }
+
+        // Keep track of the number of people who never had a test
+        let count_no_data = followed
+            .iter()
+            .filter(|(n_tests, ..)| *n_tests == 0)
+            .count();
+        let mut patient_rates: Vec<f64> = followed.iter().map(|(_, rate, _)| *rate).collect();
+        let mut patient_longest_gaps: Vec<f64> =
+            followed.iter().map(|(.., longest)| *longest).collect();
+
+        let denom = n as f64;
+        let rate_mean = patient_rates.iter().sum::<f64>() / denom;
+        let rate_square_mean = patient_rates.iter().map(|r| r * r).sum::<f64>() / denom;
+        let rate_sd = (rate_square_mean - rate_mean * rate_mean).sqrt();
+
+        patient_rates.sort_by(f64::total_cmp);
+        patient_longest_gaps.sort_by(f64::total_cmp);
+
+        // `n > 0`, so these always exist.
+        let rate_quartiles =
+            Quartiles::new(&patient_rates, QuantileMethod::default()).expect("at least one rate");
+
+        let longest_mean = patient_longest_gaps.iter().sum::<f64>() / denom;
+        let longest_square_mean = patient_longest_gaps.iter().map(|l| l * l).sum::<f64>() / denom;
+        let longest_sd = (longest_square_mean - longest_mean * longest_mean).sqrt();
+        let longest_50_percentile = stats::median(&patient_longest_gaps).unwrap_or(f64::NAN);
+
+        let years_with_test: Vec<f64> = rows
+            .iter()
+            .filter(|row| row.eligible)
+            .filter_map(|row| row.years_with_test)
+            .collect();
+        let years_with_test_mean = if years_with_test.is_empty() {
+            f64::NAN
+        } else {
+            years_with_test.iter().sum::<f64>() / years_with_test.len() as f64
+        };
+
+        Stats {
+            num_people: n,
+            rate_mean,
+            rate_sd,
+            rate_25_percentile: rate_quartiles.lower,
+            rate_50_percentile: rate_quartiles.median,
+            rate_75_percentile: rate_quartiles.upper,
+            longest_mean,
+            longest_sd,
+            longest_median: longest_50_percentile,
+            count_no_data,
+            years_with_test_mean,
+            period,
+        }
+    }
+
+    pub fn data_table(&self, dc: &DisclosureControl) -> Table<'_> {
+        self.text_table(dc).into_term_table()
+    }
+
+    /// The summary as label/value rows, without headers.
+    pub fn text_table(&self, dc: &DisclosureControl) -> TextTable {
+        let with_test = self.num_people - self.count_no_data;
+        let mut table = TextTable::default()
+            .with_row([
+                "Total people with prerequisite treatment".to_string(),
+                dc.count(self.num_people).to_string(),
+            ])
+            .with_row([
+                "Total people with prerequisite treatment who have at least 1 test".to_string(),
+                dc.count(with_test).to_string(),
+            ]);
+        // Summary statistics of very few people can reveal individual values.
+        if dc.count(self.num_people).value().is_none() {
+            return table.with_row(["Test rates and gaps", "suppressed"]);
+        }
+        let per_year = |rate: f64| format!("{:.1} per year", rate);
+        let years = |gap: f64| format!("{:.1} years", gap);
+        for (label, value) in [
+            ("Mean test rate", per_year(self.rate_mean)),
+            ("SD test rate", per_year(self.rate_sd)),
+            (
+                "25th percentile test rate",
+                per_year(self.rate_25_percentile),
+            ),
+            (
+                "50th percentile test rate",
+                per_year(self.rate_50_percentile),
+            ),
+            (
+                "75th percentile test rate",
+                per_year(self.rate_75_percentile),
+            ),
+            ("Mean longest gap between tests", years(self.longest_mean)),
+            ("SD longest gap between tests", years(self.longest_sd)),
+            (
+                "Median longest gap between tests",
+                years(self.longest_median),
+            ),
+        ] {
+            table.push_row([label.to_string(), value]);
+        }
+        table.with_row([
+            format!("Mean proportion of {} with a test", self.period.label()),
+            format!("{:.0}%", self.years_with_test_mean * 100.),
+        ])
+    }
+
+    pub fn to_markdown(&self, dc: &DisclosureControl) -> String {
+        self.text_table(dc).to_markdown()
+    }
+
+    pub fn to_latex(&self, dc: &DisclosureControl) -> String {
+        self.text_table(dc).to_latex()
+    }
+
+    /// The statistics in long format, one row per statistic, for the rule called `rule`.
+    ///
+    /// Counts are protected by `dc`, and the summary statistics are left out (`None`) if the
+    /// number of people is suppressed.
+    pub fn to_tidy(&self, rule: &str, dc: &DisclosureControl) -> Vec<TidyStat> {
+        let released = dc.count(self.num_people).value().is_some();
+        let count = |count: usize| dc.count(count).value().map(|count| count as f64);
+        let summary = |value: f64| (released && value.is_finite()).then_some(value);
+        [
+            ("num_people", count(self.num_people)),
+            ("num_with_test", count(self.num_people - self.count_no_data)),
+            ("rate_mean", summary(self.rate_mean)),
+            ("rate_sd", summary(self.rate_sd)),
+            ("rate_p25", summary(self.rate_25_percentile)),
+            ("rate_p50", summary(self.rate_50_percentile)),
+            ("rate_p75", summary(self.rate_75_percentile)),
+            ("longest_gap_mean", summary(self.longest_mean)),
+            ("longest_gap_sd", summary(self.longest_sd)),
+            ("longest_gap_median", summary(self.longest_median)),
+            ("proportion_with_test", summary(self.years_with_test_mean)),
+        ]
+        .into_iter()
+        .map(|(statistic, value)| TidyStat {
+            rule: rule.to_string(),
+            statistic,
+            value,
+            period: self.period,
+        })
+        .collect()
+    }
+}
+
+/// One adherence statistic for one rule (see [`Stats::to_tidy`]).
+#[derive(Debug, Clone, Serialize)]
+pub struct TidyStat {
+    pub rule: String,
+    /// Rates are per year, and gaps are in years.
+    pub statistic: &'static str,
+    /// `None` if suppressed or undefined.
+    pub value: Option<f64>,
+    /// What `proportion_with_test` counts.
+    pub period: Period,
+}
+
+/// Write adherence statistics in long format to a CSV file.
+pub fn save_tidy_stats(rows: &[TidyStat], path: impl AsRef<Path>) -> Result {
+    let path = path.as_ref();
+    let mut out =
+        csv::Writer::from_path(path).with_context(|| format!("creating \"{}\"", path.display()))?;
+    for row in rows {
+        out.serialize(row)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Test rates before and after ADAPT, compared within each patient.
+#[derive(Debug, Serialize)]
+pub struct BeforeAfter {
+    /// The length of the periods before and after ADAPT, in years
+    pub years: f64,
+    /// People with some follow-up both before and after ADAPT
+    pub num_people: usize,
+    /// The average number of tests per year before ADAPT, if there is anyone to average over
+    pub before_mean: Option<f64>,
+    /// The average number of tests per year after ADAPT, if there is anyone to average over
+    pub after_mean: Option<f64>,
+    /// The average change in tests per year (after - before), if there is anyone to average over
+    pub difference_mean: Option<f64>,
+    /// 95% confidence interval for `difference_mean` (paired t), if there are at least 2 people
+    pub difference_ci: Option<(f64, f64)>,
+}
+
+impl BeforeAfter {
+    /// Summarise pairs of (before, after) rates.
+    pub(crate) fn from_pairs(years: f64, pairs: &[(f64, f64)], level: f64) -> Self {
+        let n = pairs.len();
+        if n == 0 {
+            return BeforeAfter {
+                years,
+                num_people: 0,
+                before_mean: None,
+                after_mean: None,
+                difference_mean: None,
+                difference_ci: None,
+            };
+        }
+        let denom = n as f64;
+        let before_mean = pairs.iter().map(|(before, _)| before).sum::<f64>() / denom;
+        let after_mean = pairs.iter().map(|(_, after)| after).sum::<f64>() / denom;
+        let difference_mean = after_mean - before_mean;
+        let difference_ci = (n >= 2).then(|| {
+            let variance = pairs
+                .iter()
+                .map(|(before, after)| (after - before - difference_mean).powi(2))
+                .sum::<f64>()
+                / (denom - 1.);
+            let t = StudentsT::new(0., 1., denom - 1.)
+                .unwrap()
+                .inverse_cdf(1. - (1. - level) / 2.);
+            let half_width = t * (variance / denom).sqrt();
+            (difference_mean - half_width, difference_mean + half_width)
+        });
+        BeforeAfter {
+            years,
+            num_people: n,
+            before_mean: Some(before_mean),
+            after_mean: Some(after_mean),
+            difference_mean: Some(difference_mean),
+            difference_ci,
+        }
+    }
+
+    pub fn data_table(&self, dc: &DisclosureControl) -> Table<'_> {
+        self.text_table(dc).into_term_table()
+    }
+
+    /// The comparison as label/value rows, without headers.
+    pub fn text_table(&self, dc: &DisclosureControl) -> TextTable {
+        let table = TextTable::default().with_row([
+            "People with follow-up before and after ADAPT".to_string(),
+            dc.count(self.num_people).to_string(),
+        ]);
+        // Summary statistics of very few people can reveal individual values.
+        if dc.count(self.num_people).value().is_none() {
+            return table.with_row(["Test rates", "suppressed"]);
+        }
+        let ci = match self.difference_ci {
+            Some((low, high)) => format!("{low:.2} to {high:.2} per year"),
+            None => "-".into(),
+        };
+        table
+            .with_row([
+                "Mean test rate before ADAPT".to_string(),
+                per_year(self.before_mean),
+            ])
+            .with_row([
+                "Mean test rate after ADAPT".to_string(),
+                per_year(self.after_mean),
+            ])
+            .with_row([
+                "Mean change in test rate".to_string(),
+                per_year(self.difference_mean),
+            ])
+            .with_row(["95% CI for change".to_string(), ci])
+    }
+}
+
+/// Format a mean rate for a table, or "-" if there was nobody to take the mean over.
+pub(crate) fn per_year(mean: Option<f64>) -> String {
+    match mean {
+        Some(mean) => format!("{mean:.2} per year"),
+        None => "-".into(),
+    }
+}
+
+/// Blood pressure control among the patients who should have their BP monitored.
+#[derive(Debug, Default)]
+pub struct BpControl {
+    /// People who should have their BP monitored.
+    pub num_people: usize,
+    /// ...of whom have at least one reading.
+    pub with_reading: usize,
+    /// ...of whom had a raised BP at their latest reading.
+    pub above_threshold: usize,
+}
+
+impl BpControl {
+    pub fn data_table(&self, dc: &DisclosureControl) -> Table<'_> {
+        self.text_table(dc).into_term_table()
+    }
+
+    /// The counts as label/value rows, without headers.
+    pub fn text_table(&self, dc: &DisclosureControl) -> TextTable {
+        TextTable::default()
+            .with_row([
+                "Total people with prerequisite treatment".to_string(),
+                dc.count(self.num_people).to_string(),
+            ])
+            .with_row([
+                "...with at least 1 BP reading".to_string(),
+                dc.count_with_percentage(self.with_reading, self.num_people),
+            ])
+            .with_row([
+                "...whose latest BP is raised".to_string(),
+                dc.count_with_percentage(self.above_threshold, self.with_reading),
+            ])
+    }
+}
+
+/// Adherence by IMD decile, from [`LempData::adherence_by_imd`].
+#[derive(Debug, Clone)]
+pub struct ImdTrend {
+    /// The adherent (cases) and eligible (total) patients in each decile.
+    pub groups: Vec<(Imd, TrendGroup)>,
+    /// `None` if everyone or no-one is adherent, or everyone is in the same decile.
+    pub trend: Option<TrendTest>,
+}
+
+impl ImdTrend {
+    pub fn text_table(&self, dc: &DisclosureControl) -> TextTable {
+        let mut table = TextTable::new(["IMD decile", "Eligible", "Adherent"]);
+        for (imd, group) in &self.groups {
+            table.push_row([
+                imd.to_string(),
+                dc.count(group.total).to_string(),
+                dc.count_with_percentage(group.cases, group.total),
+            ]);
+        }
+        let trend = match self.trend {
+            Some(trend) => format!("z = {:.2}, p = {:.3}", trend.z, trend.p_value),
+            None => "not enough variation to test".into(),
+        };
+        table.push_row(["Trend (Cochran-Armitage)".to_string(), trend]);
+        table
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn builtin_rules() {
+        let rules = SurveillanceRules::builtin();
+        assert_eq!(rules.rules.len(), 6);
+        let flu = rules.get("Flu").unwrap();
+        assert_eq!(
+            flu.eligibility,
+            [AdaptFlag::ChemoBleomycin, AdaptFlag::RadiationLungs]
+        );
+        assert!(SurveillanceRules::parse("[[rule]]\nname = \"x\"").is_err());
+        let mut flu = flu.clone();
+        flu.grace_months = Some(15.);
+        assert_eq!(flu.max_gap_years(), 1.25);
+        flu.grace_months = None;
+        assert_eq!(flu.max_gap_years(), flu.interval_years);
+    }
+
+    #[test]
+    fn flag_labels() {
+        use serde::de::{
+            value::{Error, StrDeserializer},
+            IntoDeserializer,
+        };
+        for flag in AdaptFlag::ALL {
+            let de: StrDeserializer<Error> = flag.label().into_deserializer();
+            assert_eq!(AdaptFlag::deserialize(de).unwrap(), flag);
+        }
+    }
+
+    #[test]
+    fn stats_from_patients() {
+        let row = |eligible, n_tests, rate: Option<f64>| PatientAdherence {
+            patient_id: PatientId::new(1),
+            rule: "BP".into(),
+            eligible,
+            n_tests,
+            follow_up_years: rate.map(|_| 2.),
+            rate,
+            longest_gap_years: rate.map(|_| 1.),
+            years_with_test: None,
+            adherent: None,
+        };
+        let rows = [
+            row(true, 0, Some(0.)),
+            row(true, 4, Some(2.)),
+            row(true, 2, Some(1.)),
+            row(false, 10, Some(5.)),
+            row(true, 0, None),
+        ];
+        let stats = Stats::from_patients(&rows, Period::Interval);
+        assert_eq!(stats.num_people, 3);
+        assert_eq!(stats.count_no_data, 1);
+        assert_eq!(stats.rate_mean, 1.);
+        assert_eq!(stats.rate_50_percentile, 1.);
+        assert_eq!(stats.longest_median, 1.);
+
+        let tidy = stats.to_tidy("BP", &DisclosureControl::NONE);
+        assert_eq!(tidy[0].statistic, "num_people");
+        assert_eq!(tidy[0].value, Some(3.));
+        assert_eq!(tidy[1].value, Some(2.));
+        // No-one was followed for a whole interval.
+        assert_eq!(tidy.last().unwrap().value, None);
+    }
+
+    #[test]
+    fn short_follow_up() {
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        let rule = SurveillanceRules::builtin().get("Flu").unwrap().clone();
+        let data = LempData {
+            adapt_patients: vec![],
+            events: Events::new(vec![]),
+            registrations: Registrations::default(),
+        };
+        let pa = |last_review_date| PatientAdapt {
+            patient: Patient {
+                patient_id: PatientId::new(1),
+                year_of_birth: 1970,
+                month_of_birth: None,
+                sex: Sex::Female,
+                ethnicity: None,
+                lsoa: None,
+                imd: Imd::Missing,
+                charlson: 0.,
+                lymphoma_diagnosis_date: None,
+                lymphoma_diagnosis_confidence: None,
+                lymphoma_subtypes: Default::default(),
+            },
+            adapt: Adapt {
+                radiation_lungs: true,
+                ..crate::test_adapt(1, last_review_date)
+            },
+        };
+        // Followed up for less than the grace period, with no tests.
+        let short = data.adherence(
+            &pa(crate::date_of_extract() - Duration::days(180)),
+            &rule,
+            &CodeSet::default(),
+        );
+        assert_eq!((short.eligible, short.n_tests), (true, 0));
+        assert_eq!(short.adherent, None);
+        // Long enough to have missed a test.
+        let long = data.adherence(&pa(date(2015, 1, 1)), &rule, &CodeSet::default());
+        assert_eq!(long.adherent, Some(false));
+    }
+
+    #[test]
+    fn before_after() {
+        let ba = BeforeAfter::from_pairs(3., &[(1., 2.), (0., 2.), (1., 1.)], 0.95);
+        assert_eq!(ba.num_people, 3);
+        assert!((ba.difference_mean.unwrap() - 1.).abs() < 1e-12);
+        // Differences 1, 2, 0: sd 1, t(2) = 4.302653.
+        let (low, high) = ba.difference_ci.unwrap();
+        let half_width = 4.302653 / 3f64.sqrt();
+        assert!((low - (1. - half_width)).abs() < 1e-5);
+        assert!((high - (1. + half_width)).abs() < 1e-5);
+        assert_eq!(
+            BeforeAfter::from_pairs(3., &[(1., 2.)], 0.95).difference_ci,
+            None
+        );
+        // Nobody to take a mean over.
+        let empty = BeforeAfter::from_pairs(3., &[], 0.95);
+        assert_eq!(empty.before_mean, None);
+        assert_eq!(empty.difference_mean, None);
+        let table = empty.text_table(&DisclosureControl::NONE);
+        assert!(table.rows().iter().all(|row| !row[1].contains("NaN")));
+        assert_eq!(table.rows()[1][1], "-");
+    }
+
+    #[test]
+    fn flu_seasons() {
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        let season = FluSeason::of(date(2020, 2, 1)).unwrap();
+        assert_eq!(season.to_string(), "2019/20");
+        assert_eq!(season.start(), date(2019, 9, 1));
+        assert_eq!(FluSeason::of(date(2020, 6, 1)), None);
+
+        // ADAPTed in October 2015, so the first whole season is 2016/17. Three seasons, with
+        // vaccinations in two of them.
+        let dates = [date(2015, 10, 20), date(2016, 10, 1), date(2018, 1, 10)];
+        assert_eq!(
+            proportion_seasons_covered(date(2015, 10, 1), date(2019, 5, 1), &dates),
+            Some(2. / 3.)
+        );
+        assert_eq!(
+            proportion_seasons_covered(date(2015, 10, 1), date(2016, 5, 1), &dates),
+            None
+        );
+    }
+
+    #[test]
+    fn covered() {
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        let year = 12;
+        let grace = 15;
+        let start = date(2010, 1, 1);
+        let end = date(2013, 6, 1);
+        // Three whole years. With the grace period, the test in February 2012 counts for both the
+        // second and third years.
+        let dates = [date(2010, 6, 1), date(2012, 2, 1)];
+        assert_eq!(
+            proportion_covered(start, end, &dates, year, grace),
+            Some(1.)
+        );
+        assert_eq!(
+            proportion_covered(start, end, &dates, year, year),
+            Some(2. / 3.)
+        );
+        assert_eq!(
+            proportion_covered(start, date(2010, 6, 1), &dates, year, grace),
+            None
+        );
+    }
+}
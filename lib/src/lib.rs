@@ -1,40 +1,193 @@
+pub mod adapt_timing;
+mod admissions;
+pub mod audit;
+#[cfg(feature = "parquet")]
+mod columnar;
+pub mod consultations;
+mod dataset;
+pub mod dates;
+mod dedup;
+pub mod deprivation;
+pub mod diagnosis;
+pub mod disclosure;
+mod envelope;
+pub mod epi;
+pub mod ethnicity;
+pub mod export;
+#[cfg(feature = "polars")]
+mod frame;
+mod group;
+pub mod intern;
+pub mod join;
+pub mod late_effects;
+pub mod lemp;
+pub mod lifestyle;
 pub mod ltcs;
+pub mod measurements;
+pub mod needs;
+mod paths;
+#[cfg(feature = "plot")]
+pub mod plot;
+mod prescriptions;
+pub mod progress;
+pub mod provenance;
+pub mod pseudo;
+pub mod quality;
 mod range;
+mod registrations;
 pub mod read2;
+pub mod render;
+pub mod rubric;
+mod source;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+pub mod stats;
 pub mod subtypes;
 mod util;
+pub mod validate;
 
 pub use anyhow::{Context, Error};
 use chrono::{Datelike, NaiveDate, Utc};
-use itertools::Either;
+use once_cell::sync::OnceCell;
 use qu::ick_use::*;
+use rayon::prelude::*;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{
-    collections::{BTreeMap, BTreeSet},
-    fmt, fs, io, iter,
+    borrow::Cow,
+    cmp,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt, fs,
+    io::{self, BufRead, Write},
     ops::Deref,
     path::{Path, PathBuf},
+    str::FromStr,
     sync::Arc,
 };
 
 pub use crate::{
-    range::{Range, RangeSet, RangeSetCounts, RangeSetCountsWithMissing},
+    admissions::{Admission, AdmissionMethod, AdmissionRate, Admissions},
+    dataset::{Dataset, Record},
+    dedup::DedupPolicy,
+    disclosure::DisclosureControl,
+    paths::DataPaths,
+    prescriptions::{Prescription, Prescriptions},
+    group::{Counts, GroupCounts},
+    range::{
+        Interpolate, Range, RangeProblem, RangeSet, RangeSetCounts, RangeSetCountsWithMissing,
+        Uncovered,
+    },
     read2::ReadCode,
+    registrations::{Registration, Registrations},
+    source::Source,
     util::{header, ResultExt, Table},
 };
 use crate::{
-    read2::{CodeRubric, CodeSet, Thesaurus},
+    diagnosis::DateConfidence,
+    envelope::Schema,
+    progress::Progress,
+    read2::{
+        ChapterSummary, CodeRubric, CodeSet, PackedReadCode, ReadCodeTerm, TermId, TermSet,
+        Thesaurus,
+    },
+    rubric::{edit_distance, ContextTriggers, RubricNormalisation},
     subtypes::{CodeSubtypeMap, LymphomaSubtype},
-    util::{adapt_date, bool_01, imd, maybe_read, opt_adapt_date, optional_string},
+    util::{adapt_date, bool_01, imd, maybe_read_term, opt_adapt_date, optional_string},
 };
 
 pub fn date_of_extract() -> NaiveDate {
     NaiveDate::from_ymd_opt(2021, 11, 17).unwrap()
 }
 
+/// The oldest age we believe. Older ages (and negative ones) come from errors in the year of
+/// birth.
+pub const MAX_PLAUSIBLE_AGE: u16 = 110;
+
+/// The date to calculate ages at.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ReferenceDate {
+    /// The date of the extract (see [`date_of_extract`]), so results don't change with the day
+    /// they are run.
+    #[default]
+    Extract,
+    Today,
+    Date(NaiveDate),
+}
+
+impl ReferenceDate {
+    pub fn date(self) -> NaiveDate {
+        match self {
+            ReferenceDate::Extract => date_of_extract(),
+            ReferenceDate::Today => Utc::now().date_naive(),
+            ReferenceDate::Date(date) => date,
+        }
+    }
+}
+
+impl From<NaiveDate> for ReferenceDate {
+    fn from(date: NaiveDate) -> Self {
+        ReferenceDate::Date(date)
+    }
+}
+
+/// The date the extract uses for events whose date wasn't recorded.
+///
+/// Any date on or before this is normalised to it when events are loaded, so missing dates sort
+/// before every real date. Use [`Event::date_known`] rather than comparing with this directly.
+pub fn missing_date() -> NaiveDate {
+    NaiveDate::from_ymd_opt(1900, 1, 1).unwrap()
+}
+
+/// Whether `date` is a placeholder for a date that wasn't recorded.
+pub fn is_missing_date(date: NaiveDate) -> bool {
+    date <= missing_date()
+}
+
 pub type ArcStr = Arc<str>;
 pub type Result<T = (), E = anyhow::Error> = std::result::Result<T, E>;
-pub type PatientId = u64;
+
+/// The ID of a patient (`PatID` in the extract), which is the same in every dataset.
+///
+/// This is a newtype rather than a `u64` so patient IDs can't be mixed up with other numbers. It
+/// is stored as a plain number, so saved datasets are unchanged.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PatientId(u64);
+
+impl PatientId {
+    pub const fn new(id: u64) -> Self {
+        PatientId(id)
+    }
+
+    pub fn get(self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for PatientId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl FromStr for PatientId {
+    type Err = Error;
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        input
+            .trim()
+            .parse()
+            .map(PatientId)
+            .map_err(|_| format_err!("\"{input}\" is not a valid patient ID"))
+    }
+}
+
+impl TryFrom<i64> for PatientId {
+    type Error = Error;
+    fn try_from(id: i64) -> Result<Self, Self::Error> {
+        u64::try_from(id)
+            .map(PatientId)
+            .map_err(|_| format_err!("{id} is not a valid patient ID"))
+    }
+}
 
 #[derive(Debug, Clone, Deserialize)]
 struct PatientRaw {
@@ -42,12 +195,16 @@ struct PatientRaw {
     patient_id: PatientId,
     #[serde(rename = "YearOfBirth")]
     year_of_birth: u16,
+    /// Not in every extract.
+    #[serde(rename = "MonthOfBirth", default)]
+    month_of_birth: Option<u8>,
+    /// Converted with [`Sex::from_code`], so we can warn about unexpected codes.
     #[serde(rename = "Sex")]
-    sex: Sex,
+    sex: ArcStr,
     #[serde(rename = "Ethnicity", deserialize_with = "optional_string")]
     ethnicity: Option<ArcStr>,
     #[serde(rename = "LSOA", deserialize_with = "optional_string")]
-    _lsoa: Option<ArcStr>,
+    lsoa: Option<ArcStr>,
     #[serde(rename = "GPCode")]
     _gp_code: ArcStr,
     #[serde(
@@ -69,15 +226,22 @@ struct PatientRaw {
 pub struct Patient {
     pub patient_id: PatientId,
     pub year_of_birth: u16,
+    /// 1 to 12, if the extract has it.
+    pub month_of_birth: Option<u8>,
     pub sex: Sex,
     pub ethnicity: Option<ArcStr>,
+    /// The 2011 Lower Layer Super Output Area the patient lives in.
+    pub lsoa: Option<ArcStr>,
     pub imd: Imd,
     pub charlson: f32,
     /// This should be the earilest lymphoma code, even if a later, more specific one is used
     /// below.
     pub lymphoma_diagnosis_date: Option<NaiveDate>,
-    /// This code should be as specific as possible.
-    pub lymphoma_diagnosis_subtype: Option<LymphomaSubtype>,
+    /// How sure we are of `lymphoma_diagnosis_date`.
+    pub lymphoma_diagnosis_confidence: Option<DateConfidence>,
+    /// Every subtype the patient has a code for. There can be more than one, as patients can be
+    /// diagnosed with more than one type of lymphoma.
+    pub lymphoma_subtypes: BTreeSet<LymphomaSubtype>,
 }
 
 impl From<PatientRaw> for Patient {
@@ -85,46 +249,130 @@ impl From<PatientRaw> for Patient {
         Self {
             patient_id: from.patient_id,
             year_of_birth: from.year_of_birth,
-            sex: from.sex,
+            month_of_birth: from.month_of_birth.filter(|month| (1..=12).contains(month)),
+            sex: Sex::from_code(&from.sex),
             ethnicity: from.ethnicity,
+            lsoa: from.lsoa,
             imd: from.imd,
             charlson: from.charlson,
             lymphoma_diagnosis_date: None,
-            lymphoma_diagnosis_subtype: None,
+            lymphoma_diagnosis_confidence: None,
+            lymphoma_subtypes: BTreeSet::new(),
         }
     }
 }
 
+impl Schema for Patient {
+    const SCHEMA: &'static str = "Patient { patient_id: u64, year_of_birth: u16, month_of_birth: Option<u8>, \
+        sex: Sex, ethnicity: Option<str>, lsoa: Option<str>, imd: Imd, charlson: f32, lymphoma_diagnosis_date: Option<NaiveDate>, \
+        lymphoma_diagnosis_confidence: Option<DateConfidence>, \
+        lymphoma_subtypes: BTreeSet<LymphomaSubtype> }";
+}
+
 impl Patient {
+    /// The patient's age in whole years on `date`.
+    ///
+    /// Without a month of birth we assume the patient has had their birthday that year, so the age
+    /// may be a year too high.
     pub fn age_at(&self, date: impl Datelike) -> i32 {
-        date.year() - self.year_of_birth as i32
+        let age = date.year() - self.year_of_birth as i32;
+        match self.month_of_birth {
+            Some(month) if date.month() < u32::from(month) => age - 1,
+            _ => age,
+        }
+    }
+
+    /// The patient's age on `date`, or `None` if it is negative or more than
+    /// [`MAX_PLAUSIBLE_AGE`].
+    pub fn plausible_age_at(&self, date: impl Datelike) -> Option<u16> {
+        u16::try_from(self.age_at(date))
+            .ok()
+            .filter(|age| *age <= MAX_PLAUSIBLE_AGE)
+    }
+
+    /// The diagnosis date, if we are at least `min` confident of it.
+    ///
+    /// Use this for sensitivity analyses that leave out uncertain dates.
+    pub fn diagnosis_date_at_least(&self, min: DateConfidence) -> Option<NaiveDate> {
+        match self.lymphoma_diagnosis_confidence {
+            Some(confidence) if confidence >= min => self.lymphoma_diagnosis_date,
+            _ => None,
+        }
+    }
+
+    /// The most specific of the patient's subtypes, for reports that need one subtype per
+    /// patient.
+    ///
+    /// This is a subtype that none of the others are more specific than. If there is more than
+    /// one (e.g. Hodgkin and DLBCL), we take the first in the order of [`LymphomaSubtype`].
+    pub fn lymphoma_diagnosis_subtype(&self) -> Option<LymphomaSubtype> {
+        self.lymphoma_subtypes
+            .iter()
+            .find(|subtype| {
+                !self
+                    .lymphoma_subtypes
+                    .iter()
+                    .any(|other| other.is_subtype_of(subtype))
+            })
+            .copied()
     }
 }
 
-/// The parsed list of patients, with a pre-built index for the `id` field.
-pub struct Patients {
-    els: Arc<Vec<Patient>>,
-    id_idx: BTreeMap<u64, usize>,
+impl Record for Patient {
+    type Key = PatientId;
+    const DATASET: &'static str = "patients";
+    fn key(&self) -> PatientId {
+        self.patient_id
+    }
 }
 
+/// The parsed list of patients, with a pre-built index for the `id` field.
+pub struct Patients(Dataset<Patient>);
+
 impl Patients {
+    /// Load the patients from the original extract.
+    ///
+    /// Also returns the problems [`validate::check_patients`] finds: patients who would be older
+    /// than 110 ([`MAX_PLAUSIBLE_AGE`]) or not born yet at the extract date, duplicate patient
+    /// IDs, and Charlson scores out of range. If a patient ID is in the extract more than once,
+    /// we keep the first row.
     pub fn load_orig(
         path: impl AsRef<Path>,
         events: &Events,
         lymphoma_subtype_map: &CodeSubtypeMap,
-    ) -> Result<Self, Error> {
+    ) -> Result<(Self, Vec<validate::Violation>), Error> {
         let patients_raw: Vec<PatientRaw> = load_orig(path)?;
-        let mut patients = Self::new(patients_raw.into_iter().map(Into::into).collect());
+        let unexpected = GroupCounts::from_keys(
+            patients_raw
+                .iter()
+                .filter(|pat| !Sex::from_code(&pat.sex).is_known())
+                .map(|pat| pat.sex.clone()),
+        );
+        for (code, count) in unexpected.iter() {
+            event!(
+                Level::WARN,
+                "{count} patients with sex \"{code}\", read as {}",
+                Sex::from_code(code)
+            );
+        }
+        let mut els: Vec<Patient> = patients_raw.into_iter().map(Into::into).collect();
+        let warnings = validate::check_patients(&els);
+        let before = els.len();
+        let mut seen = HashSet::new();
+        els.retain(|pat| seen.insert(pat.patient_id));
+        audit_filter("patients", "remove duplicate patient IDs", before, els.len());
+
+        let mut patients = Self::new(els);
         patients.calc_lymphoma_data(events, lymphoma_subtype_map);
-        Ok(patients)
+        Ok((patients, warnings))
     }
 
     pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
-        Ok(Self::new(load(path)?))
+        Ok(Patients(Dataset::load(path)?))
     }
 
     pub fn save(&self, path: impl AsRef<Path>) -> Result {
-        Ok(save(&self.els, path)?)
+        self.0.save(path)
     }
 
     /// This takes our mapping for read code/rubric combos and our code to lymphoma mapping and
@@ -133,7 +381,7 @@ impl Patients {
     /// There should always be a mapping because we made it from the events, so we assume
     /// non-mapping events are not lymphoma.
     fn calc_lymphoma_data(&mut self, events: &Events, map: &CodeSubtypeMap) {
-        for event in events.iter() {
+        for event in events.iter_ref() {
             let Some(subtype) = map.get(&event.code_rubric()) else {
                 continue
             };
@@ -142,96 +390,132 @@ impl Patients {
                 continue
             };
 
-            // update diagnosis date if applicable
-            match patient.lymphoma_diagnosis_date {
-                None => patient.lymphoma_diagnosis_date = Some(event.date),
-                Some(v) if v > event.date => patient.lymphoma_diagnosis_date = Some(event.date),
+            // update diagnosis date if applicable (codes with missing dates still give a subtype)
+            match (patient.lymphoma_diagnosis_date, event.date_known()) {
+                (None, Some(date)) => patient.lymphoma_diagnosis_date = Some(date),
+                (Some(v), Some(date)) if v > date => patient.lymphoma_diagnosis_date = Some(date),
                 _ => (),
             }
 
-            if let Some(old_subtype) = &patient.lymphoma_diagnosis_subtype {
-                if subtype.is_subtype_of(old_subtype) {
-                    patient.lymphoma_diagnosis_subtype = Some(subtype);
-                }
-            } else {
-                patient.lymphoma_diagnosis_subtype = Some(subtype);
+            patient.lymphoma_subtypes.insert(subtype);
+        }
+        self.score_diagnosis_dates(events, map, &Registrations::default());
+    }
+
+    /// Score how confident we are in each patient's diagnosis date (see [`diagnosis`]).
+    ///
+    /// Scoring is better with `registrations`, so this should be run again once we have them.
+    /// Where there is more than one lymphoma code on the diagnosis date, the most confident score
+    /// is used.
+    pub fn score_diagnosis_dates(
+        &mut self,
+        events: &Events,
+        map: &CodeSubtypeMap,
+        registrations: &Registrations,
+    ) {
+        for patient in self.0.iter_mut() {
+            patient.lymphoma_diagnosis_confidence = None;
+        }
+        for event in events.iter_ref() {
+            if map.get(&event.code_rubric()).is_none() {
+                continue;
+            }
+            let Some(patient) = self.find_by_id_mut(event.patient_id) else {
+                continue;
+            };
+            if patient.lymphoma_diagnosis_date != Some(event.date) {
+                continue;
             }
+            let confidence = DateConfidence::score(
+                event,
+                registrations.registration_at(event.patient_id, event.date),
+            );
+            patient.lymphoma_diagnosis_confidence =
+                patient.lymphoma_diagnosis_confidence.max(Some(confidence));
         }
     }
 
-    pub fn find_by_id(&self, id: u64) -> Option<&Patient> {
-        let idx = self.id_idx.get(&id)?;
-        let el = self.els.get(*idx)?;
-        Some(el)
+    /// The number of patients with each diagnosis date confidence (`None` for no diagnosis date).
+    pub fn count_diagnosis_confidence(&self) -> BTreeMap<Option<DateConfidence>, usize> {
+        self.group_count(|pat| pat.lymphoma_diagnosis_confidence)
+            .into_map()
+    }
+
+    pub fn find_by_id(&self, id: PatientId) -> Option<&Patient> {
+        self.0.find(id)
     }
 
     /// Note this will clone the patients internally if they are shared. Other clones of `self`
     /// will not be updated
-    pub fn find_by_id_mut(&mut self, id: u64) -> Option<&mut Patient> {
-        let idx = self.id_idx.get(&id)?;
-        let el = Arc::make_mut(&mut self.els).get_mut(*idx)?;
-        Some(el)
+    pub fn find_by_id_mut(&mut self, id: PatientId) -> Option<&mut Patient> {
+        self.0.find_mut(id)
     }
 
     pub fn count_sexes(&self) -> BTreeMap<Sex, usize> {
-        // B Tree so we get a predictable ordering.
-        let mut map = BTreeMap::new();
-        // Manually insert to make sure all categories are included.
-        map.insert(Sex::Male, 0);
-        map.insert(Sex::Female, 0);
-        for el in self.els.iter() {
-            *map.entry(el.sex).or_insert(0) += 1;
-        }
-        map
+        // Make sure all categories are included.
+        self.group_count(|pat| pat.sex)
+            .with_keys([Sex::Male, Sex::Female])
+            .into_map()
     }
 
-    pub fn bucket_ages(&self, ranges: &RangeSet<u16>) -> RangeSetCounts<u16> {
-        let now = Utc::now();
-        ranges.clone().bucket_values(
-            self.iter()
-                .map(|pat| u16::try_from(pat.age_at(now)).unwrap()),
-        )
+    /// Count patients by their age on `reference`.
+    ///
+    /// Implausible ages (see [`Patient::plausible_age_at`]) are counted as missing.
+    pub fn bucket_ages(
+        &self,
+        ranges: &RangeSet<u16>,
+        reference: ReferenceDate,
+    ) -> RangeSetCountsWithMissing<u16> {
+        let date = reference.date();
+        ranges
+            .clone()
+            .bucket_values_with_missing(self.iter().map(|pat| pat.plausible_age_at(date)))
+            .with_missing_label("missing/invalid")
     }
 
     pub fn count_imd(&self) -> BTreeMap<Imd, usize> {
-        // B Tree so we get a predictable ordering.
-        let mut map = BTreeMap::new();
-        // Manually insert to make sure all categories are included.
-        map.insert(Imd::Missing, 0);
-        map.insert(Imd::_1, 0);
-        map.insert(Imd::_2, 0);
-        map.insert(Imd::_3, 0);
-        map.insert(Imd::_4, 0);
-        map.insert(Imd::_5, 0);
-        map.insert(Imd::_6, 0);
-        map.insert(Imd::_7, 0);
-        map.insert(Imd::_8, 0);
-        map.insert(Imd::_9, 0);
-        map.insert(Imd::_10, 0);
-        for el in self.els.iter() {
-            *map.entry(el.imd).or_insert(0) += 1;
-        }
-        map
-    }
-
-    pub fn iter(&self) -> impl Iterator<Item = Patient> + '_ {
-        self.els.iter().cloned()
-    }
-
-    pub fn iter_ref(&self) -> impl Iterator<Item = &Patient> + '_ {
-        self.els.iter()
+        // Make sure all categories are included.
+        self.group_count(|pat| pat.imd)
+            .with_keys([
+                Imd::Missing,
+                Imd::_1,
+                Imd::_2,
+                Imd::_3,
+                Imd::_4,
+                Imd::_5,
+                Imd::_6,
+                Imd::_7,
+                Imd::_8,
+                Imd::_9,
+                Imd::_10,
+            ])
+            .into_map()
+    }
+
+    /// Count patients by IMD quintile (see [`Imd::quintile`]), with `None` for a missing IMD.
+    pub fn count_imd_quintiles(&self) -> BTreeMap<Option<u8>, usize> {
+        // Make sure all categories are included.
+        self.group_count(|pat| pat.imd.quintile())
+            .with_keys([None, Some(1), Some(2), Some(3), Some(4), Some(5)])
+            .into_map()
     }
 
     pub fn filter(&self, f: impl Fn(&Patient) -> bool) -> Self {
-        Patients::new(self.iter().filter(f).collect())
+        Patients(self.0.filter(f))
     }
 
     pub fn retain(&mut self, f: impl Fn(&Patient) -> bool) {
-        Arc::make_mut(&mut self.els).retain(f)
+        self.0.retain(f)
     }
 
-    pub fn term_table(&self) -> term_data_table::Table {
-        term_data_table::Table::from_serde(self.iter()).unwrap()
+    /// Like [`Patients::filter`], but records the filter in the [`audit`] log.
+    pub fn filter_described(&self, description: &str, f: impl Fn(&Patient) -> bool) -> Self {
+        Patients(self.0.filter_described(description, f))
+    }
+
+    /// Like [`Patients::retain`], but records the filter in the [`audit`] log.
+    pub fn retain_described(&mut self, description: &str, f: impl Fn(&Patient) -> bool) {
+        self.0.retain_described(description, f)
     }
 
     pub fn evcxr_display(&self) {
@@ -249,26 +533,14 @@ impl Patients {
     }
 
     fn new(els: Vec<Patient>) -> Self {
-        let mut this = Patients {
-            els: els.into(),
-            id_idx: BTreeMap::new(),
-        };
-        this.rebuild_index();
-        this
-    }
-
-    fn rebuild_index(&mut self) {
-        self.id_idx.clear();
-        for (idx, el) in self.els.iter().enumerate() {
-            self.id_idx.insert(el.patient_id, idx);
-        }
+        Patients(Dataset::new(els))
     }
 }
 
 impl Deref for Patients {
-    type Target = [Patient];
+    type Target = Dataset<Patient>;
     fn deref(&self) -> &Self::Target {
-        &*self.els
+        &self.0
     }
 }
 
@@ -278,37 +550,54 @@ pub struct EventRaw {
     pub patient_id: PatientId,
     #[serde(rename = "EntryDate")]
     pub date: NaiveDate,
-    #[serde(rename = "ReadCode", deserialize_with = "maybe_read")]
-    pub read_code: Option<ReadCode>,
-    #[serde(rename = "Rubric")]
+    #[serde(rename = "ReadCode", deserialize_with = "maybe_read_term")]
+    pub read_code: Option<ReadCodeTerm>,
+    #[serde(rename = "Rubric", deserialize_with = "intern::rubric")]
     pub rubric: ArcStr,
     #[serde(rename = "CodeValue")]
     pub code_value: Option<ArcStr>,
     #[serde(rename = "CodeUnits")]
     pub code_units: Option<ArcStr>,
-    #[serde(rename = "Source")]
+    #[serde(rename = "Source", deserialize_with = "intern::source")]
     pub source: ArcStr,
 }
 
 /// A row in the events dataset
+///
+/// `rubric` and `source` are shared with other events with the same text (see [`intern`]).
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Event {
     pub patient_id: PatientId,
     pub date: NaiveDate,
     pub read_code: ReadCode,
+    /// Which of the code's descriptions was used, if the extract says.
+    pub term_id: Option<TermId>,
+    #[serde(deserialize_with = "intern::rubric")]
     pub rubric: ArcStr,
     pub code_value: Option<ArcStr>,
     pub code_units: Option<ArcStr>,
+    #[serde(deserialize_with = "intern::source")]
     pub source: ArcStr,
 }
 
+impl Schema for Event {
+    const SCHEMA: &'static str = "Event { patient_id: u64, date: NaiveDate, read_code: ReadCode, \
+        term_id: Option<bytes>, rubric: str, code_value: Option<str>, code_units: Option<str>, \
+        source: str }";
+}
+
 impl Event {
     fn from_raw(raw: EventRaw) -> Option<Self> {
         match raw.read_code {
-            Some(read_code) => Some(Event {
+            Some(code_term) => Some(Event {
                 patient_id: raw.patient_id,
-                date: raw.date,
-                read_code,
+                date: if is_missing_date(raw.date) {
+                    missing_date()
+                } else {
+                    raw.date
+                },
+                read_code: code_term.code,
+                term_id: code_term.term,
                 rubric: raw.rubric,
                 code_value: raw.code_value,
                 code_units: raw.code_units,
@@ -318,6 +607,16 @@ impl Event {
         }
     }
 
+    /// The date of this event, or `None` if it wasn't recorded.
+    pub fn date_known(&self) -> Option<NaiveDate> {
+        (!is_missing_date(self.date)).then_some(self.date)
+    }
+
+    /// The text of the description the event was coded with, if we know which one it was.
+    pub fn term_text<'a>(&self, th: &'a Thesaurus) -> Option<&'a ArcStr> {
+        th.term(self.read_code, self.term_id?)
+    }
+
     /// Extract the Read code and free text from this event.
     pub fn code_rubric(&self) -> CodeRubric {
         CodeRubric {
@@ -327,10 +626,26 @@ impl Event {
     }
 }
 
+impl Record for Event {
+    type Key = PatientId;
+    const DATASET: &'static str = "events";
+    fn key(&self) -> PatientId {
+        self.patient_id
+    }
+    /// Each patient's events are indexed in date order.
+    fn cmp_within_key(&self, other: &Self) -> std::cmp::Ordering {
+        self.date.cmp(&other.date)
+    }
+}
+
 /// The parsed list of events, with a pre-built index for the `id` field.
+///
+/// For each patient, their events are indexed in date order.
 pub struct Events {
-    els: Arc<Vec<Event>>,
-    id_idx: BTreeMap<u64, Vec<usize>>,
+    data: Dataset<Event>,
+    /// For each read code, the indices of events with that code. Built the first time it is
+    /// needed.
+    code_idx: OnceCell<HashMap<PackedReadCode, Vec<usize>>>,
 }
 
 impl Events {
@@ -341,49 +656,162 @@ impl Events {
     }
 
     pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
-        Ok(Self::new(load(path)?))
+        Ok(Self::from_dataset(Dataset::load(path)?))
     }
 
     pub fn save(&self, path: impl AsRef<Path>) -> Result {
-        Ok(save(&self.els, path)?)
+        self.data.save(path)
     }
 
+    /// A patient's events, in date order.
     pub fn events_for_patient(
         &self,
         patient_id: PatientId,
     ) -> impl Iterator<Item = &Event> + Clone + '_ {
-        let evt_idxs = match self.id_idx.get(&patient_id) {
-            Some(idxs) => idxs,
-            None => return Either::Left(iter::empty()),
+        self.data.get(patient_id)
+    }
+
+    /// A patient's events from `start` to `end` inclusive, in date order.
+    pub fn for_patient_in_window(
+        &self,
+        patient_id: PatientId,
+        start: NaiveDate,
+        end: NaiveDate,
+    ) -> impl Iterator<Item = &Event> + Clone + '_ {
+        let idxs = self.window(self.data.indices(patient_id), start, end);
+        idxs.iter().map(|idx| &self.els[*idx])
+    }
+
+    /// All events from `start` to `end` inclusive, grouped by patient and in date order for each
+    /// patient.
+    pub fn in_window(&self, start: NaiveDate, end: NaiveDate) -> impl Iterator<Item = &Event> + '_ {
+        self.data.idx.values().flat_map(move |idxs| {
+            self.window(idxs, start, end)
+                .iter()
+                .map(|idx| &self.els[*idx])
+        })
+    }
+
+    /// The part of a patient's (date-sorted) event indices that falls between `start` and `end`.
+    ///
+    /// This is a binary search, so it doesn't depend on how many events the patient has.
+    fn window<'a>(&self, idxs: &'a [usize], start: NaiveDate, end: NaiveDate) -> &'a [usize] {
+        let lo = idxs.partition_point(|idx| self.els[*idx].date < start);
+        let hi = idxs.partition_point(|idx| self.els[*idx].date <= end);
+        &idxs[lo..hi.max(lo)]
+    }
+
+    /// Events with the given read code, in the order they were recorded.
+    pub fn with_code(&self, code: ReadCode) -> impl Iterator<Item = &Event> + Clone + '_ {
+        let idxs = match self.code_idx().get(&code.into()) {
+            Some(idxs) => &idxs[..],
+            None => &[],
         };
-        Either::Right(evt_idxs.iter().map(|idx| {
-            self.els
-                .get(*idx)
-                .expect("inconsistent event patient_id index")
-        }))
+        idxs.iter().map(|idx| &self.els[*idx])
+    }
+
+    /// How many events there are in each top-level Read chapter.
+    pub fn chapter_summary(&self, th: &Thesaurus) -> ChapterSummary {
+        ChapterSummary::new(
+            self.code_idx()
+                .iter()
+                .map(|(code, idxs)| (code.code(), idxs.len())),
+            th,
+        )
+    }
+
+    /// Events with a read code in `codeset`, in the order they were recorded.
+    pub fn with_codeset(&self, codeset: &CodeSet) -> impl Iterator<Item = &Event> + '_ {
+        let code_idx = self.code_idx();
+        let mut idxs: Vec<usize> = codeset
+            .iter()
+            .filter_map(|code| code_idx.get(&code.into()))
+            .flatten()
+            .copied()
+            .collect();
+        idxs.sort_unstable();
+        idxs.into_iter().map(|idx| &self.els[idx])
+    }
+
+    fn code_idx(&self) -> &HashMap<PackedReadCode, Vec<usize>> {
+        self.code_idx.get_or_init(|| {
+            let mut code_idx: HashMap<PackedReadCode, Vec<usize>> = HashMap::new();
+            for (idx, event) in self.els.iter().enumerate() {
+                code_idx
+                    .entry(event.read_code.into())
+                    .or_default()
+                    .push(idx);
+            }
+            code_idx
+        })
     }
 
-    /// Iterate over events in this store.
-    pub fn iter(&self) -> impl Iterator<Item = Event> + '_ {
-        self.els.iter().cloned()
+    /// Iterate over the events that match the filter, without copying them into a new `Events`.
+    pub fn filter_ref<'a>(
+        &'a self,
+        f: impl Fn(&Event) -> bool + 'a,
+    ) -> impl Iterator<Item = &'a Event> + 'a {
+        self.els.iter().filter(move |evt| f(evt))
+    }
+
+    /// Apply `f` to every event.
+    pub fn map<'a, T>(&'a self, f: impl Fn(&Event) -> T + 'a) -> impl Iterator<Item = T> + 'a {
+        self.els.iter().map(f)
     }
 
     /// Get an `Events` object containing only events that match the filter.
     pub fn filter(&self, f: impl Fn(&Event) -> bool) -> Self {
-        Events::new(self.iter().filter(f).collect())
+        Events::new(self.filter_ref(f).cloned().collect())
     }
 
     pub fn retain(&mut self, f: impl Fn(&Event) -> bool) {
-        Arc::make_mut(&mut self.els).retain(f)
+        self.data.retain(f);
+        self.code_idx = OnceCell::new();
+    }
+
+    /// Like [`Events::filter`], but records the filter in the [`audit`] log.
+    pub fn filter_described(&self, description: &str, f: impl Fn(&Event) -> bool) -> Self {
+        Self::from_dataset(self.data.filter_described(description, f))
+    }
+
+    /// Like [`Events::retain`], but records the filter in the [`audit`] log.
+    pub fn retain_described(&mut self, description: &str, f: impl Fn(&Event) -> bool) {
+        self.data.retain_described(description, f);
+        self.code_idx = OnceCell::new();
+    }
+
+    /// Creates a new `Events` object with only those events whose rubric (free text) matches the
+    /// termset.
+    ///
+    /// Useful where the code is non-specific (e.g. "Seen in clinic") but the rubric isn't.
+    pub fn filter_by_termset(&self, termset: &TermSet) -> Self {
+        self.filter_by_termset_with(termset, None)
+    }
+
+    /// Like [`Events::filter_by_termset`], but with `triggers` rubrics that negate the condition,
+    /// or say it is suspected or in the family, are left out (see [`TermSet::is_rubric_match`]).
+    pub fn filter_by_termset_with(
+        &self,
+        termset: &TermSet,
+        triggers: Option<&ContextTriggers>,
+    ) -> Self {
+        // Many events share a rubric, so only match each one once.
+        let mut matches: HashMap<&str, bool> = HashMap::new();
+        let mut out = vec![];
+        for evt in self.els.iter() {
+            let is_match = *matches
+                .entry(&evt.rubric)
+                .or_insert_with(|| termset.is_rubric_match(&evt.rubric, triggers));
+            if is_match {
+                out.push(evt.clone());
+            }
+        }
+        Events::new(out)
     }
 
     /// Creates a new `Events` object with only those events with read codes matching the codeset.
     pub fn filter_by_codeset(&self, codeset: &CodeSet) -> Self {
-        let els = self
-            .iter()
-            .filter(|evt| codeset.contains(evt.read_code))
-            .collect();
-        Events::new(els)
+        Events::new(self.with_codeset(codeset).cloned().collect())
     }
 
     /// Get the earliest code recorded for a particular patient.
@@ -391,23 +819,40 @@ impl Events {
     /// Useful in combination with `filter*` methods. If `None`, then there were no events with
     /// valid dates for the patient.
     pub fn earliest_event_for_patient(&self, id: PatientId) -> Option<NaiveDate> {
-        let _1900_date = NaiveDate::from_ymd_opt(1900, 01, 01).unwrap();
-        self.iter()
-            .filter(|event| {
-                // Dates seem to default to 1900-01-01 when they are missing
-                event.patient_id == id && event.date != _1900_date
-            })
-            .map(|event| event.date)
-            .min()
+        // Events are sorted by date, so any missing dates come first.
+        self.events_for_patient(id).find_map(Event::date_known)
     }
 
-    pub fn filter_by_patient_id(&self, id: PatientId) -> Self {
-        let idxs = match self.id_idx.get(&id) {
-            Some(idxs) => idxs,
-            None => return Self::new(vec![]),
-        };
+    /// Get the latest code recorded for a particular patient.
+    ///
+    /// Like [`Events::earliest_event_for_patient`], events with missing dates are ignored.
+    pub fn latest_event_for_patient(&self, id: PatientId) -> Option<NaiveDate> {
+        self.els[*self.data.indices(id).last()?].date_known()
+    }
 
-        Self::new(idxs.iter().map(|idx| self.els[*idx].clone()).collect())
+    /// The patient's first event with a read code in `codeset`.
+    ///
+    /// Like [`Events::earliest_event_for_patient`], events with missing dates are ignored.
+    pub fn first_code_in(&self, codeset: &CodeSet, patient_id: PatientId) -> Option<&Event> {
+        self.events_for_patient(patient_id)
+            .find(|evt| evt.date_known().is_some() && codeset.contains(evt.read_code))
+    }
+
+    /// The patient's last event with a read code in `codeset`.
+    ///
+    /// Like [`Events::earliest_event_for_patient`], events with missing dates are ignored.
+    pub fn last_code_in(&self, codeset: &CodeSet, patient_id: PatientId) -> Option<&Event> {
+        self.data
+            .indices(patient_id)
+            .iter()
+            .rev()
+            .map(|idx| &self.els[*idx])
+            .take_while(|evt| evt.date_known().is_some())
+            .find(|evt| codeset.contains(evt.read_code))
+    }
+
+    pub fn filter_by_patient_id(&self, id: PatientId) -> Self {
+        Self::new(self.events_for_patient(id).cloned().collect())
     }
 
     // TODO we already have this method as `CodeRubricCounts::from_events`.
@@ -419,10 +864,6 @@ impl Events {
         todo!()
     }
 
-    pub fn term_table(&self) -> term_data_table::Table {
-        term_data_table::Table::from_serde(self.iter()).unwrap()
-    }
-
     pub fn evcxr_display(&self) {
         Table::new(self.els.iter(), |evt, _| {
             (
@@ -448,29 +889,21 @@ impl Events {
     }
 
     fn new(els: Vec<Event>) -> Self {
-        let mut this = Events {
-            els: Arc::new(els),
-            id_idx: BTreeMap::new(),
-        };
-        this.rebuild_id_map();
-        this
+        Self::from_dataset(Dataset::new(els))
     }
 
-    fn rebuild_id_map(&mut self) {
-        self.id_idx.clear();
-        for (idx, event) in self.els.iter().enumerate() {
-            self.id_idx
-                .entry(event.patient_id)
-                .or_insert_with(Vec::new)
-                .push(idx);
+    fn from_dataset(data: Dataset<Event>) -> Self {
+        Events {
+            data,
+            code_idx: OnceCell::new(),
         }
     }
 }
 
 impl Deref for Events {
-    type Target = [Event];
+    type Target = Dataset<Event>;
     fn deref(&self) -> &Self::Target {
-        &*self.els
+        &self.data
     }
 }
 
@@ -482,6 +915,14 @@ impl<'a> IntoIterator for &'a Events {
     }
 }
 
+impl<'a> IntoParallelIterator for &'a Events {
+    type Item = &'a Event;
+    type Iter = rayon::slice::Iter<'a, Event>;
+    fn into_par_iter(self) -> Self::Iter {
+        self.data.par_iter()
+    }
+}
+
 impl FromIterator<Event> for Events {
     fn from_iter<T>(iter: T) -> Self
     where
@@ -494,7 +935,7 @@ impl FromIterator<Event> for Events {
 #[derive(Debug, Deserialize)]
 struct AdaptRaw {
     #[serde(rename = "PatID")]
-    id: u64,
+    id: PatientId,
     diagnosis: ArcStr,
     #[serde(deserialize_with = "opt_adapt_date")]
     #[serde(rename = "diagnosisDate")]
@@ -576,7 +1017,7 @@ struct AdaptRaw {
 /// A row in the adapt dataset
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Adapt {
-    pub id: u64,
+    pub id: PatientId,
     pub diagnosis: ArcStr,
     pub diagnosis_date: Option<NaiveDate>,
     pub treatment_end_date: NaiveDate,
@@ -605,6 +1046,21 @@ pub struct Adapt {
     pub hodgkin_lymphoma_stem_cell_transplant: bool,
 }
 
+impl Schema for Adapt {
+    const SCHEMA: &'static str = "Adapt { id: u64, diagnosis: str, diagnosis_date: Option<NaiveDate>, \
+        treatment_end_date: NaiveDate, last_review_date: NaiveDate, \
+        adapt_form_completed_date: NaiveDate, adapt_form_sent_date: NaiveDate, \
+        chemo_doxorubicin: bool, radiation_heart: bool, \
+        female_sub_50_chemo_doxorubicin_radiation_heart: bool, \
+        chemo_doxorubicin_radiation_heart: bool, radiation_lungs: bool, chemo_bleomycin: bool, \
+        current_or_ex_smoker: bool, female_sub_36_radiation_chest: bool, radiation_thyroid: bool, \
+        male_chemo: bool, any_radiotherapy: bool, radiation_head_neck: bool, \
+        radiation_gullet_stomach: bool, radiation_bowels: bool, \
+        chemo_vincristine_vinblastine: bool, chemo_prednisone_dexamethasone: bool, \
+        low_energy_last_12_months: bool, chemo_cisplatin_carboplatin: bool, \
+        radiation_abdomen_kidney: bool, hodgkin_lymphoma_stem_cell_transplant: bool }";
+}
+
 impl From<AdaptRaw> for Adapt {
     fn from(from: AdaptRaw) -> Self {
         Self {
@@ -640,63 +1096,46 @@ impl From<AdaptRaw> for Adapt {
     }
 }
 
+impl Record for Adapt {
+    type Key = PatientId;
+    const DATASET: &'static str = "adapt";
+    fn key(&self) -> PatientId {
+        self.id
+    }
+}
+
 /// The parsed list of adapt patient records, with a pre-built index for the `id` field.
 ///
 /// The naming is used because it is consistent, not because it is good.
-pub struct Adapts {
-    els: Vec<Adapt>,
-    id_idx: BTreeMap<u64, usize>,
-}
+pub struct Adapts(Dataset<Adapt>);
 
 impl Adapts {
-    fn new(els: Vec<Adapt>) -> Self {
-        let mut this = Self {
-            els,
-            id_idx: BTreeMap::new(),
-        };
-        this.rebuild_index();
-        this
-    }
-
-    fn rebuild_index(&mut self) {
-        self.id_idx = self
-            .els
-            .iter()
-            .enumerate()
-            .map(|(idx, el): (usize, &Adapt)| (el.id, idx))
-            .collect();
-    }
-
     pub fn load_orig(path: impl AsRef<Path>) -> Result<Self, Error> {
         let els: Vec<AdaptRaw> = load_orig(path)?;
-        let els: Vec<Adapt> = els.into_iter().map(Into::into).collect();
-        let id_idx = els
-            .iter()
-            .enumerate()
-            .map(|(idx, el): (usize, &Adapt)| (el.id, idx))
-            .collect();
-        Ok(Self { els, id_idx })
+        Ok(Self::new(els.into_iter().map(Into::into).collect()))
     }
 
     pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
-        Ok(Self::new(load(path)?))
+        Ok(Adapts(Dataset::load(path)?))
     }
 
     pub fn save(&self, path: impl AsRef<Path>) -> Result {
-        Ok(save(&self.els, path)?)
+        self.0.save(path)
     }
 
-    pub fn find_by_id(&self, id: u64) -> Option<&Adapt> {
-        let idx = self.id_idx.get(&id)?;
-        let el = self.els.get(*idx)?;
-        Some(el)
+    pub fn find_by_id(&self, id: PatientId) -> Option<&Adapt> {
+        self.0.find(id)
+    }
+
+    fn new(els: Vec<Adapt>) -> Self {
+        Adapts(Dataset::new(els))
     }
 }
 
 impl Deref for Adapts {
-    type Target = [Adapt];
+    type Target = Dataset<Adapt>;
     fn deref(&self) -> &Self::Target {
-        &*self.els
+        &self.0
     }
 }
 
@@ -707,17 +1146,45 @@ pub struct CodeRubricCount {
     pub code_rubric: CodeRubric,
     /// The description of the read code from the thesaurus.
     pub description: BTreeSet<ArcStr>,
-    /// IDs of patients with an event in their record matching the code_rubric.
-    pub patient_ids: BTreeSet<PatientId>,
+    /// The number of events with the code_rubric for each patient that has one.
+    pub patient_events: BTreeMap<PatientId, usize>,
+    /// The total number of events with the code_rubric.
+    pub event_count: usize,
 }
 
-/// The parsed list of Read code/rubric pairs, with a pre-built index for the `read_code` field.
-pub struct CodeRubricCounts {
-    read_code_idx: BTreeMap<ReadCode, Vec<usize>>,
-    // Safety: this value must be dropped last
-    els: Vec<CodeRubricCount>,
+impl CodeRubricCount {
+    pub fn num_patients(&self) -> usize {
+        self.patient_events.len()
+    }
+
+    /// The mean number of events per patient with the code_rubric.
+    pub fn events_per_patient(&self) -> f64 {
+        self.event_count as f64 / self.num_patients() as f64
+    }
 }
 
+impl Record for CodeRubricCount {
+    type Key = PackedReadCode;
+    const DATASET: &'static str = "code/rubric counts";
+    fn key(&self) -> PackedReadCode {
+        self.code_rubric.code.into()
+    }
+}
+
+/// Rubrics for the same code that are nearly the same, from
+/// [`CodeRubricCounts::group_similar`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RubricGroup {
+    pub code: ReadCode,
+    /// The most used rubric first.
+    pub rubrics: Vec<ArcStr>,
+    pub event_count: usize,
+    pub num_patients: usize,
+}
+
+/// The parsed list of Read code/rubric pairs, with a pre-built index for the `read_code` field.
+pub struct CodeRubricCounts(Dataset<CodeRubricCount>);
+
 impl fmt::Debug for CodeRubricCounts {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("CodeRubricCounts")
@@ -729,43 +1196,133 @@ impl fmt::Debug for CodeRubricCounts {
 impl CodeRubricCounts {
     /// Collect all code/rubric pairs from the given events.
     pub fn from_events(events: &Events, th: &Thesaurus) -> Self {
-        let mut cr = BTreeMap::new();
-        for event in events.iter() {
-            cr.entry(CodeRubric::new(event.read_code, event.rubric))
-                .or_insert(BTreeSet::new())
-                .insert(event.patient_id);
-        }
+        Self::from_events_with(events, th, &RubricNormalisation::NONE)
+    }
 
-        let mut els = Vec::with_capacity(cr.len());
-        for (code_rubric, patient_ids) in cr.into_iter() {
-            let description = th.get(code_rubric.code);
-            els.push(CodeRubricCount {
-                code_rubric,
-                patient_ids,
-                description: description.cloned().unwrap_or(BTreeSet::new()),
+    /// Collect all code/rubric pairs from the given events, counting rubrics that are the same
+    /// after `normalisation` as one.
+    ///
+    /// Events are counted in parallel, keyed by the borrowed rubric so the hot loop doesn't
+    /// allocate or touch reference counts (unless normalisation changes the rubric). Each distinct
+    /// rubric is then interned once.
+    pub fn from_events_with(
+        events: &Events,
+        th: &Thesaurus,
+        normalisation: &RubricNormalisation,
+    ) -> Self {
+        type Counts<'a> = HashMap<(ReadCode, Cow<'a, str>), BTreeMap<PatientId, usize>>;
+
+        let progress = Progress::new(
+            "counting code/rubric combinations",
+            Some(events.len() as u64),
+        );
+        let counts = events
+            .into_par_iter()
+            .fold(Counts::new, |mut counts, event| {
+                *counts
+                    .entry((event.read_code, normalisation.apply(&event.rubric)))
+                    .or_default()
+                    .entry(event.patient_id)
+                    .or_insert(0) += 1;
+                progress.inc();
+                counts
             })
-        }
+            .reduce(Counts::new, |a, b| {
+                // Merge the smaller map into the larger one.
+                let (mut into, from) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+                for (key, patient_events) in from {
+                    let entry = into.entry(key).or_default();
+                    for (patient_id, count) in patient_events {
+                        *entry.entry(patient_id).or_insert(0) += count;
+                    }
+                }
+                into
+            });
+        progress.finish();
+
+        let mut els = counts
+            .into_iter()
+            .map(|((code, rubric), patient_events)| CodeRubricCount {
+                code_rubric: CodeRubric::new(code, intern::RUBRICS.intern(&rubric)),
+                event_count: patient_events.values().sum(),
+                patient_events,
+                description: th.get(code).cloned().unwrap_or_default(),
+            })
+            .collect::<Vec<_>>();
+        els.par_sort_unstable_by(|a, b| a.code_rubric.cmp(&b.code_rubric));
         Self::new(els)
     }
 
+    /// Group the rubrics for each code that are within `max_distance` edits of each other, after
+    /// normalising them with [`RubricNormalisation::ALL`], for review.
+    ///
+    /// Rubrics are grouped transitively, so a group can contain rubrics further apart than
+    /// `max_distance`. Only groups with more than one rubric are returned.
+    pub fn group_similar(&self, max_distance: usize) -> Vec<RubricGroup> {
+        let mut groups = vec![];
+        // `els` is sorted by code, so each code's rubrics are together.
+        let runs = self
+            .els
+            .chunk_by(|a, b| a.code_rubric.code == b.code_rubric.code);
+        for run in runs {
+            let normalised = run
+                .iter()
+                .map(|cr| RubricNormalisation::ALL.apply(&cr.code_rubric.rubric))
+                .collect::<Vec<_>>();
+            // union-find, with the root of each group being its lowest index
+            let mut parent = (0..run.len()).collect::<Vec<_>>();
+            fn root(parent: &mut [usize], mut idx: usize) -> usize {
+                while parent[idx] != idx {
+                    parent[idx] = parent[parent[idx]];
+                    idx = parent[idx];
+                }
+                idx
+            }
+            for i in 0..run.len() {
+                for j in (i + 1)..run.len() {
+                    if edit_distance(&normalised[i], &normalised[j], max_distance).is_some() {
+                        let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+                        parent[a.max(b)] = a.min(b);
+                    }
+                }
+            }
+            let mut members: BTreeMap<usize, Vec<&CodeRubricCount>> = BTreeMap::new();
+            for (idx, cr) in run.iter().enumerate() {
+                members.entry(root(&mut parent, idx)).or_default().push(cr);
+            }
+            for mut group in members.into_values().filter(|group| group.len() > 1) {
+                group.sort_by_key(|cr| cmp::Reverse(cr.event_count));
+                let patients = group
+                    .iter()
+                    .flat_map(|cr| cr.patient_events.keys())
+                    .collect::<BTreeSet<_>>();
+                groups.push(RubricGroup {
+                    code: run[0].code_rubric.code,
+                    event_count: group.iter().map(|cr| cr.event_count).sum(),
+                    num_patients: patients.len(),
+                    rubrics: group
+                        .into_iter()
+                        .map(|cr| cr.code_rubric.rubric.clone())
+                        .collect(),
+                });
+            }
+        }
+        groups
+    }
+
     /// Get all patient IDs that appear at least once.
     pub fn all_patient_ids(&self) -> BTreeSet<PatientId> {
         self.iter().fold(BTreeSet::new(), |mut set, itm| {
-            for id in itm.patient_ids.iter() {
+            for id in itm.patient_events.keys() {
                 set.insert(*id);
             }
             set
         })
     }
 
-    /// Iterate over code rubric counts.
-    pub fn iter(&self) -> impl Iterator<Item = CodeRubricCount> + '_ {
-        self.els.iter().cloned()
-    }
-
     /// Remove records that don't match the predecate.
     pub fn filter(&self, f: impl Fn(&CodeRubricCount) -> bool) -> Self {
-        Self::new(self.iter().filter(f).collect())
+        CodeRubricCounts(self.0.filter(f))
     }
 
     pub fn filter_by_codeset(&self, codeset: &CodeSet) -> Self {
@@ -782,24 +1339,20 @@ impl CodeRubricCounts {
         code: impl TryInto<ReadCode>,
     ) -> impl Iterator<Item = &'a CodeRubricCount> + 'a {
         let code = code.try_into().ok().expect("not a valid read code");
-        let iter = match self.read_code_idx.get(&code) {
-            Some(v) => Either::Left(v.iter()),
-            None => Either::Right(std::iter::empty()),
-        };
-
-        let this = self;
-        iter.filter_map(move |idx| this.els.get(*idx))
+        self.0.get(code.into())
     }
 
     /// Display all or some of the code rubrics.
     ///
     /// Set count to `0` to show all. Set to `None` to let the system decide how many to show.
     pub fn display(&self, count: Option<usize>) {
-        let mut table = Table::new(&self.els, |cr, _| {
+        let mut table = Table::new(&*self.els, |cr, _| {
             (
                 cr.code_rubric.code,
                 &cr.code_rubric.rubric,
-                cr.patient_ids.len(),
+                cr.num_patients(),
+                cr.event_count,
+                format!("{:.1}", cr.events_per_patient()),
                 format!("{:?}", cr.description),
             )
         })
@@ -807,6 +1360,8 @@ impl CodeRubricCounts {
             "Read code",
             "rubric (free text)",
             "number of patients",
+            "number of events",
+            "events per patient",
             "thesaurus",
         ]);
         if let Some(count) = count {
@@ -815,39 +1370,19 @@ impl CodeRubricCounts {
         table.evcxr_display();
     }
 
-    pub fn term_table(&self) -> term_data_table::Table {
-        term_data_table::Table::from_serde(self.iter()).unwrap()
-    }
-
     pub fn evcxr_display(&self) {
         self.display(None)
     }
 
     fn new(els: Vec<CodeRubricCount>) -> Self {
-        let mut this = Self {
-            els,
-            read_code_idx: BTreeMap::new(),
-        };
-        this.rebuild_index();
-        this
-    }
-
-    fn rebuild_index(&mut self) {
-        // Build Read code index.
-        self.read_code_idx.clear();
-        for (idx, el) in self.els.iter().enumerate() {
-            self.read_code_idx
-                .entry(el.code_rubric.code)
-                .or_insert(vec![])
-                .push(idx);
-        }
+        CodeRubricCounts(Dataset::new(els))
     }
 }
 
 impl Deref for CodeRubricCounts {
-    type Target = [CodeRubricCount];
+    type Target = Dataset<CodeRubricCount>;
     fn deref(&self) -> &Self::Target {
-        &*self.els
+        &self.0
     }
 }
 
@@ -872,6 +1407,56 @@ pub enum Imd {
     _10,
 }
 
+impl Imd {
+    /// The IMD for a decile from 1 (most deprived) to 10, or `None` if out of range.
+    pub fn from_decile(decile: u8) -> Option<Self> {
+        Some(match decile {
+            1 => Imd::_1,
+            2 => Imd::_2,
+            3 => Imd::_3,
+            4 => Imd::_4,
+            5 => Imd::_5,
+            6 => Imd::_6,
+            7 => Imd::_7,
+            8 => Imd::_8,
+            9 => Imd::_9,
+            10 => Imd::_10,
+            _ => return None,
+        })
+    }
+
+    /// The decile from 1 (most deprived) to 10, or `None` if it is missing.
+    pub fn as_decile(self) -> Option<u8> {
+        Some(match self {
+            Imd::Missing => return None,
+            Imd::_1 => 1,
+            Imd::_2 => 2,
+            Imd::_3 => 3,
+            Imd::_4 => 4,
+            Imd::_5 => 5,
+            Imd::_6 => 6,
+            Imd::_7 => 7,
+            Imd::_8 => 8,
+            Imd::_9 => 9,
+            Imd::_10 => 10,
+        })
+    }
+
+    /// The quintile from 1 (most deprived) to 5, or `None` if it is missing.
+    pub fn quintile(self) -> Option<u8> {
+        Some(self.as_decile()?.div_ceil(2))
+    }
+
+    /// The range a quintile covers, like the labels for deciles, e.g. "20% - 40%" for quintile
+    /// 2.
+    pub fn quintile_label(quintile: Option<u8>) -> String {
+        match quintile {
+            Some(quintile) => format!("{}% - {}%", (quintile - 1) * 20, quintile * 20),
+            None => "missing".into(),
+        }
+    }
+}
+
 impl fmt::Debug for Imd {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use Imd::*;
@@ -897,8 +1482,9 @@ impl fmt::Display for Imd {
     }
 }
 
-/// Sex is encoded 'M' or 'F'. No other values exist in the data. If another value
-/// is added in the future, this will throw an error, forcing us to handle the situation.
+/// Sex is encoded 'M' or 'F' in the extracts we have had so far. Other codes are read as
+/// `Indeterminate` ('I') or `Unknown` ('U', blank or anything else), so a new code doesn't stop
+/// the import; [`Patients::load_orig`] warns about them so we still notice.
 ///
 /// Ordering is arbitrary.
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, Hash, Ord, PartialOrd)]
@@ -907,6 +1493,37 @@ pub enum Sex {
     Male,
     #[serde(rename = "F", alias = "f")]
     Female,
+    #[serde(rename = "I", alias = "i")]
+    Indeterminate,
+    #[serde(rename = "U", alias = "u")]
+    Unknown,
+}
+
+impl Sex {
+    /// The sex for a code in the extract.
+    pub fn from_code(code: &str) -> Sex {
+        match code.trim() {
+            "M" | "m" => Sex::Male,
+            "F" | "f" => Sex::Female,
+            "I" | "i" => Sex::Indeterminate,
+            _ => Sex::Unknown,
+        }
+    }
+
+    /// The code the extract uses for this sex.
+    pub fn code(self) -> &'static str {
+        match self {
+            Sex::Male => "M",
+            Sex::Female => "F",
+            Sex::Indeterminate => "I",
+            Sex::Unknown => "U",
+        }
+    }
+
+    /// Whether the sex is recorded as male or female.
+    pub fn is_known(self) -> bool {
+        matches!(self, Sex::Male | Sex::Female)
+    }
 }
 
 impl fmt::Display for Sex {
@@ -914,6 +1531,8 @@ impl fmt::Display for Sex {
         match self {
             Sex::Male => f.write_str("Male"),
             Sex::Female => f.write_str("Female"),
+            Sex::Indeterminate => f.write_str("Indeterminate"),
+            Sex::Unknown => f.write_str("Unknown"),
         }
     }
 }
@@ -930,15 +1549,32 @@ pub fn load_codes_vec(path: impl AsRef<Path>) -> io::Result<Vec<String>> {
     load_codes(path)?.collect::<io::Result<Vec<_>>>()
 }
 
+/// Saved files are zstd compressed. Files are checked for these bytes on load, so uncompressed files
+/// from before compression was added still load.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// The default level gives most of the benefit for our data, higher levels are much slower.
+const ZSTD_LEVEL: i32 = 3;
+
 /// Load data into memory.
-fn load<T: DeserializeOwned>(path: impl AsRef<Path>) -> Result<Vec<T>> {
-    fn inner<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>> {
+fn load<T: Schema + DeserializeOwned>(path: impl AsRef<Path>) -> Result<Vec<T>> {
+    fn inner<T: Schema + DeserializeOwned>(path: &Path) -> Result<Vec<T>> {
         let path = output_path(path);
         if let Some(parent) = path.parent() {
             fs::create_dir_all(parent)?;
         }
-        let reader = io::BufReader::new(fs::File::open(path)?);
-        bincode::deserialize_from(reader).map_err(Into::into)
+        let mut reader = io::BufReader::new(fs::File::open(&path)?);
+        provenance::record_input(&path);
+        let els: Vec<T> = if reader.fill_buf()?.starts_with(&ZSTD_MAGIC) {
+            envelope::read(zstd::Decoder::with_buffer(reader)?)?
+        } else {
+            envelope::read(reader)?
+        };
+        audit::record(audit::Action::Load {
+            path,
+            rows: els.len(),
+        });
+        Ok(els)
     }
     let path = path.as_ref();
     check_extension(&path, "bin")?;
@@ -946,9 +1582,29 @@ fn load<T: DeserializeOwned>(path: impl AsRef<Path>) -> Result<Vec<T>> {
     inner(path).with_context(|| format!("unable to load data from \"{}\"", path.display()))
 }
 
+/// The SHA-256 of a file, in lowercase hex.
+///
+/// Saved data files (`.bin`) are hashed without their header, which records when they were
+/// written, so saving the same data again gives the same hash. Other files are hashed as they are.
+pub fn content_sha256(path: impl AsRef<Path>) -> Result<String> {
+    fn inner(path: &Path) -> Result<String> {
+        if path.extension() != Some("bin".as_ref()) {
+            return Ok(provenance::sha256_file(path)?);
+        }
+        let mut reader = io::BufReader::new(fs::File::open(path)?);
+        if reader.fill_buf()?.starts_with(&ZSTD_MAGIC) {
+            envelope::contents_sha256(zstd::Decoder::with_buffer(reader)?)
+        } else {
+            envelope::contents_sha256(reader)
+        }
+    }
+    let path = path.as_ref();
+    inner(path).with_context(|| format!("hashing \"{}\"", path.display()))
+}
+
 /// Save data to disk.
-fn save<T: Serialize>(contents: &[T], path: impl AsRef<Path>) -> Result {
-    fn inner<T: Serialize>(contents: &[T], path: &Path) -> Result {
+fn save<T: Schema + Serialize>(contents: &[T], path: impl AsRef<Path>) -> Result {
+    fn inner<T: Schema + Serialize>(contents: &[T], path: &Path) -> Result {
         if let Some(parent) = path.parent() {
             fs::create_dir_all(parent).context("could not create parent")?;
         }
@@ -961,8 +1617,19 @@ fn save<T: Serialize>(contents: &[T], path: impl AsRef<Path>) -> Result {
                 path.display()
             );
         }
-        let mut out = io::BufWriter::new(fs::File::create(path)?);
-        bincode::serialize_into(&mut out, contents)?;
+        let out = io::BufWriter::new(fs::File::create(path)?);
+        let mut out = zstd::Encoder::new(out, ZSTD_LEVEL)?;
+        let progress = Progress::new(
+            format!("saving \"{}\"", path.display()),
+            Some(contents.len() as u64),
+        );
+        envelope::write(&mut out, contents, progress)?;
+        out.finish()?.flush()?;
+        provenance::write_sidecar(path)?;
+        audit::record(audit::Action::Save {
+            path: path.to_owned(),
+            rows: contents.len(),
+        });
         Ok(())
     }
     let path = path.as_ref();
@@ -972,34 +1639,59 @@ fn save<T: Serialize>(contents: &[T], path: impl AsRef<Path>) -> Result {
     inner(contents, &path).with_context(|| format!("unable to save data to \"{}\"", path.display()))
 }
 
+fn audit_filter(dataset: &str, description: &str, before: usize, after: usize) {
+    audit::record(audit::Action::Filter {
+        dataset: dataset.into(),
+        description: description.into(),
+        before,
+        after,
+    });
+}
+
 /// Load data into memory from the original database extract.
 fn load_orig<T: serde::de::DeserializeOwned>(
     path: impl AsRef<Path>,
 ) -> Result<Vec<T>, anyhow::Error> {
+    fn inner<T: serde::de::DeserializeOwned>(path: &Path) -> Result<Vec<T>> {
+        // progress is measured in bytes, since we don't know how many rows there are.
+        let len = fs::metadata(path)?.len().max(1);
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .trim(csv::Trim::All)
+            .from_path(path)?;
+        provenance::record_input(path);
+        let progress = Progress::new(format!("loading \"{}\"", path.display()), None);
+        let mut out = vec![];
+        let mut rows = reader.deserialize();
+        while let Some(row) = rows.next() {
+            out.push(row?);
+            progress.inc_with_fraction(|| rows.reader().position().byte() as f64 / len as f64);
+        }
+        progress.finish();
+        audit::record(audit::Action::Load {
+            path: path.to_owned(),
+            rows: out.len(),
+        });
+        Ok(out)
+    }
     let path = path.as_ref();
     let path = orig_path(path);
-    csv::ReaderBuilder::new()
-        .has_headers(true)
-        .trim(csv::Trim::All)
-        .from_path(&path)?
-        .into_deserialize()
-        .collect::<Result<Vec<T>, _>>()
-        .with_context(|| format!("while loading \"{}\"", path.display()))
+    inner(&path).with_context(|| format!("while loading \"{}\"", path.display()))
 }
 
 /// Note: No protection from escaping the root directory.
 pub fn orig_path(input: &Path) -> PathBuf {
-    Path::new("../data/sir_data").join(input)
+    DataPaths::current().orig_path(input)
 }
 
 /// Note: No protection from escaping the root directory.
 pub fn output_path(input: &Path) -> PathBuf {
-    Path::new("../data/output").join(input)
+    DataPaths::current().output_path(input)
 }
 
 /// Note: No protection from escaping the root directory.
 pub fn termset_path(input: &Path) -> PathBuf {
-    Path::new("../data/termsets").join(input)
+    DataPaths::current().termset_path(input)
 }
 
 pub fn file_exists(path: &Path) -> io::Result<bool> {
@@ -1018,3 +1710,351 @@ pub fn check_extension(path: &Path, ext: &str) -> Result<()> {
     );
     Ok(())
 }
+
+/// An event for tests, with no rubric, value or source. Use struct update syntax for the rest.
+#[cfg(test)]
+pub(crate) fn test_event(patient_id: u64, date: NaiveDate, code: &str) -> Event {
+    Event {
+        patient_id: PatientId::new(patient_id),
+        date,
+        read_code: code.parse().unwrap(),
+        term_id: None,
+        rubric: "".into(),
+        code_value: None,
+        code_units: None,
+        source: "".into(),
+    }
+}
+
+/// A patient for tests: a woman born in 1970, with no diagnosis. Use struct update syntax for the
+/// rest.
+#[cfg(test)]
+pub(crate) fn test_patient(patient_id: u64) -> Patient {
+    Patient {
+        patient_id: PatientId::new(patient_id),
+        year_of_birth: 1970,
+        month_of_birth: None,
+        sex: Sex::Female,
+        ethnicity: None,
+        lsoa: None,
+        imd: Imd::Missing,
+        charlson: 0.,
+        lymphoma_diagnosis_date: None,
+        lymphoma_diagnosis_confidence: None,
+        lymphoma_subtypes: BTreeSet::new(),
+    }
+}
+
+/// An ADAPT record for tests, reviewed on `last_review_date` and with no flags set. Use struct
+/// update syntax for the rest.
+#[cfg(test)]
+pub(crate) fn test_adapt(id: u64, last_review_date: NaiveDate) -> Adapt {
+    Adapt {
+        id: PatientId::new(id),
+        diagnosis: "".into(),
+        diagnosis_date: None,
+        treatment_end_date: last_review_date,
+        last_review_date,
+        adapt_form_completed_date: last_review_date,
+        adapt_form_sent_date: last_review_date,
+        chemo_doxorubicin: false,
+        radiation_heart: false,
+        female_sub_50_chemo_doxorubicin_radiation_heart: false,
+        chemo_doxorubicin_radiation_heart: false,
+        radiation_lungs: false,
+        chemo_bleomycin: false,
+        current_or_ex_smoker: false,
+        female_sub_36_radiation_chest: false,
+        radiation_thyroid: false,
+        male_chemo: false,
+        any_radiotherapy: false,
+        radiation_head_neck: false,
+        radiation_gullet_stomach: false,
+        radiation_bowels: false,
+        chemo_vincristine_vinblastine: false,
+        chemo_prednisone_dexamethasone: false,
+        low_energy_last_12_months: false,
+        chemo_cisplatin_carboplatin: false,
+        radiation_abdomen_kidney: false,
+        hodgkin_lymphoma_stem_cell_transplant: false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn missing_dates() {
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        let raw = |date| EventRaw {
+            patient_id: PatientId::new(1),
+            date,
+            read_code: Some("B620.".parse().unwrap()),
+            rubric: "".into(),
+            code_value: None,
+            code_units: None,
+            source: "".into(),
+        };
+        let events = Events::new(
+            [date(2015, 6, 1), date(1800, 1, 1), date(1900, 1, 1)]
+                .into_iter()
+                .filter_map(|d| Event::from_raw(raw(d)))
+                .collect(),
+        );
+        let dates: Vec<_> = events.events_for_patient(PatientId::new(1)).map(|e| e.date).collect();
+        assert_eq!(dates, [missing_date(), missing_date(), date(2015, 6, 1)]);
+        assert_eq!(events.earliest_event_for_patient(PatientId::new(1)), Some(date(2015, 6, 1)));
+        assert_eq!(events.latest_event_for_patient(PatientId::new(1)), Some(date(2015, 6, 1)));
+    }
+
+    #[test]
+    fn imd_quintiles() {
+        assert_eq!(Imd::_1.quintile(), Some(1));
+        assert_eq!(Imd::_2.quintile(), Some(1));
+        assert_eq!(Imd::_7.quintile(), Some(4));
+        assert_eq!(Imd::_10.quintile(), Some(5));
+        assert_eq!(Imd::Missing.quintile(), None);
+        assert_eq!(Imd::quintile_label(Some(2)), "20% - 40%");
+    }
+
+    #[test]
+    fn sex_codes() {
+        assert_eq!(Sex::from_code("f"), Sex::Female);
+        assert_eq!(Sex::from_code("I"), Sex::Indeterminate);
+        assert_eq!(Sex::from_code(""), Sex::Unknown);
+        assert_eq!(Sex::from_code("X"), Sex::Unknown);
+        for sex in [Sex::Male, Sex::Female, Sex::Indeterminate, Sex::Unknown] {
+            assert_eq!(Sex::from_code(sex.code()), sex);
+        }
+        assert!(!Sex::Unknown.is_known());
+    }
+
+    #[test]
+    fn events_in_window() {
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        let event = |patient_id, date| test_event(patient_id, date, "B620.");
+        let events = Events::new(vec![
+            event(1, date(2015, 6, 1)),
+            event(2, date(2012, 1, 1)),
+            event(1, date(2010, 1, 1)),
+            event(1, date(2012, 1, 1)),
+            event(1, date(2020, 1, 1)),
+        ]);
+        let dates = |evts: Vec<&Event>| -> Vec<_> { evts.into_iter().map(|e| e.date).collect() };
+
+        let in_window = events
+            .for_patient_in_window(PatientId::new(1), date(2012, 1, 1), date(2015, 6, 1))
+            .collect();
+        assert_eq!(dates(in_window), [date(2012, 1, 1), date(2015, 6, 1)]);
+        let in_window = events
+            .for_patient_in_window(PatientId::new(1), date(2016, 1, 1), date(2011, 1, 1))
+            .collect();
+        assert_eq!(dates(in_window), []);
+        assert_eq!(
+            events
+                .for_patient_in_window(PatientId::new(3), date(2000, 1, 1), date(2030, 1, 1))
+                .count(),
+            0
+        );
+
+        let all: Vec<_> = events
+            .in_window(date(2011, 1, 1), date(2016, 1, 1))
+            .map(|evt| (evt.patient_id.get(), evt.date))
+            .collect();
+        assert_eq!(
+            all,
+            [
+                (1, date(2012, 1, 1)),
+                (1, date(2015, 6, 1)),
+                (2, date(2012, 1, 1))
+            ]
+        );
+    }
+
+    #[test]
+    fn events_with_code() {
+        let date = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
+        let event = |patient_id, code| test_event(patient_id, date, code);
+        let mut events = Events::new(vec![
+            event(1, "B620."),
+            event(2, "B621."),
+            event(3, "B620."),
+            event(4, "1371."),
+        ]);
+        let ids =
+            |evts: Vec<&Event>| -> Vec<_> { evts.into_iter().map(|e| e.patient_id.get()).collect() };
+
+        assert_eq!(
+            ids(events.with_code("B620.".parse().unwrap()).collect()),
+            [1, 3]
+        );
+        let mut codeset = CodeSet::default();
+        codeset.insert("B621.".parse().unwrap());
+        codeset.insert("1371.".parse().unwrap());
+        assert_eq!(ids(events.with_codeset(&codeset).collect()), [2, 4]);
+
+        // The index is rebuilt after removing events.
+        events.retain(|evt| evt.patient_id != PatientId::new(2));
+        assert_eq!(ids(events.with_codeset(&codeset).collect()), [4]);
+    }
+
+    #[test]
+    fn code_rubric_counts() {
+        let date = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
+        let event = |patient_id, code, rubric: &str| Event {
+            rubric: rubric.into(),
+            ..test_event(patient_id, date, code)
+        };
+        let events = Events::new(vec![
+            event(1, "B620.", "lymphoma"),
+            event(1, "B620.", "lymphoma"),
+            event(2, "B620.", "lymphoma"),
+            event(2, "B620.", "Lymphoma"),
+            event(3, "1371.", ""),
+        ]);
+        let counts = CodeRubricCounts::from_events(&events, &Thesaurus::default());
+        let summary = counts
+            .iter()
+            .map(|cr| {
+                (
+                    cr.code_rubric.code.to_string(),
+                    cr.code_rubric.rubric.to_string(),
+                    cr.event_count,
+                    cr.num_patients(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                ("1371.".into(), "".into(), 1, 1),
+                ("B620.".into(), "Lymphoma".into(), 1, 1),
+                ("B620.".into(), "lymphoma".into(), 3, 2),
+            ]
+        );
+        let lymphoma = counts.find_by_code("B620.").last().unwrap();
+        assert_eq!(lymphoma.patient_events, [(PatientId::new(1), 2), (PatientId::new(2), 1)].into());
+    }
+
+    #[test]
+    fn events_by_termset() {
+        let date = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
+        let event = |patient_id, rubric: &str| Event {
+            rubric: rubric.into(),
+            ..test_event(patient_id, date, "9N1C.")
+        };
+        let events = Events::new(vec![
+            event(1, "Seen in haematology clinic - lymphoma"),
+            event(2, "No evidence of lymphoma"),
+            event(3, "Seen in clinic"),
+            event(4, "Seen in haematology clinic - lymphoma"),
+        ]);
+        let termset =
+            TermSet::new(None, None, ["lymphoma".into()], Vec::<ArcStr>::new(), None).unwrap();
+        let ids = |events: Events| events.iter_ref().map(|e| e.patient_id.get()).collect::<Vec<_>>();
+
+        assert_eq!(ids(events.filter_by_termset(&termset)), [1, 2, 4]);
+        let triggers = ContextTriggers::default();
+        assert_eq!(
+            ids(events.filter_by_termset_with(&termset, Some(&triggers))),
+            [1, 4]
+        );
+    }
+
+    #[test]
+    fn similar_rubrics() {
+        let date = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
+        let event = |patient_id, rubric: &str| Event {
+            rubric: rubric.into(),
+            ..test_event(patient_id, date, "B620.")
+        };
+        let events = Events::new(vec![
+            event(1, "Hodgkin's lymphoma"),
+            event(2, "hodgkin's  lymphoma."),
+            event(3, "Hodgkins lymphoma"),
+            event(3, "Hodgkins lymphoma"),
+            event(4, "Follow up"),
+        ]);
+        let th = Thesaurus::default();
+        let normalised =
+            CodeRubricCounts::from_events_with(&events, &th, &RubricNormalisation::ALL);
+        assert_eq!(normalised.len(), 3);
+
+        let groups = CodeRubricCounts::from_events(&events, &th).group_similar(1);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].rubrics[0].as_ref(), "Hodgkins lymphoma");
+        assert_eq!(groups[0].rubrics.len(), 3);
+        assert_eq!(groups[0].event_count, 4);
+        assert_eq!(groups[0].num_patients, 3);
+    }
+
+    #[test]
+    fn most_specific_subtype() {
+        use subtypes::NonHodgkinSubtype;
+        let patient = |subtypes: &[LymphomaSubtype]| Patient {
+            patient_id: PatientId::new(1),
+            year_of_birth: 1970,
+            month_of_birth: None,
+            sex: Sex::Female,
+            ethnicity: None,
+            lsoa: None,
+            imd: Imd::Missing,
+            charlson: 0.,
+            lymphoma_diagnosis_date: None,
+            lymphoma_diagnosis_confidence: None,
+            lymphoma_subtypes: subtypes.iter().copied().collect(),
+        };
+        let nh = LymphomaSubtype::NonHodgkin;
+        assert_eq!(patient(&[]).lymphoma_diagnosis_subtype(), None);
+        assert_eq!(
+            patient(&[
+                LymphomaSubtype::Unspecified,
+                nh(NonHodgkinSubtype::Unspecified),
+                nh(NonHodgkinSubtype::Follicular),
+            ])
+            .lymphoma_diagnosis_subtype(),
+            Some(nh(NonHodgkinSubtype::Follicular))
+        );
+        assert_eq!(
+            patient(&[LymphomaSubtype::Hodgkin, nh(NonHodgkinSubtype::DLBCL)])
+                .lymphoma_diagnosis_subtype(),
+            Some(LymphomaSubtype::Hodgkin)
+        );
+    }
+
+    #[test]
+    fn ages() {
+        let patient = |year_of_birth, month_of_birth| Patient {
+            patient_id: PatientId::new(1),
+            year_of_birth,
+            month_of_birth,
+            sex: Sex::Female,
+            ethnicity: None,
+            lsoa: None,
+            imd: Imd::Missing,
+            charlson: 0.,
+            lymphoma_diagnosis_date: None,
+            lymphoma_diagnosis_confidence: None,
+            lymphoma_subtypes: BTreeSet::new(),
+        };
+        let date = NaiveDate::from_ymd_opt(2021, 6, 15).unwrap();
+        assert_eq!(patient(1970, None).age_at(date), 51);
+        assert_eq!(patient(1970, Some(6)).age_at(date), 51);
+        assert_eq!(patient(1970, Some(7)).age_at(date), 50);
+        assert_eq!(patient(2022, None).plausible_age_at(date), None);
+        assert_eq!(patient(1880, None).plausible_age_at(date), None);
+        assert_eq!(patient(1911, None).plausible_age_at(date), Some(110));
+        assert_eq!(patient(1910, None).plausible_age_at(date), None);
+
+        let patients = Patients::new(vec![patient(1970, Some(11)), patient(2030, None)]);
+        let ranges = RangeSet::new(vec![Range::new(0, Some(50)), Range::new(50, None)]);
+        let counts = patients.bucket_ages(&ranges, ReferenceDate::default());
+        let counts = counts
+            .for_display()
+            .map(|(label, count)| (label.to_string(), count))
+            .collect::<Vec<_>>();
+        assert_eq!(counts[1].1, 1);
+        assert_eq!(counts[2], ("missing/invalid".to_string(), 1));
+    }
+}
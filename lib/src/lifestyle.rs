@@ -0,0 +1,215 @@
+//! Lifestyle factors recorded in the GP record.
+use crate::{
+    read2::CodeSet, render::TextTable, Adapts, DisclosureControl, Event, Events, ReadCode,
+};
+use chrono::NaiveDate;
+use once_cell::sync::Lazy;
+use std::{fmt, iter};
+use term_data_table as tdt;
+
+/// Codes meaning the patient has never smoked.
+///
+/// "Current non-smoker" (`137L.`) doesn't say whether they used to smoke, so it is treated as never
+/// unless there is an earlier smoking code (see [`smoking_status`]).
+const NEVER_SMOKED: &[&str] = &["1371.", "137L."];
+/// Codes meaning the patient currently smokes, including amounts smoked and attempts to stop.
+const CURRENT_SMOKER: &[&str] = &[
+    "137..", "1372.", "1373.", "1374.", "1375.", "1376.", "137C.", "137G.", "137H.", "137J.",
+    "137M.", "137P.", "137Q.", "137R.", "137V.", "137b.", "137c.", "137d.", "137e.", "137h.",
+    "137m.",
+];
+/// Codes meaning the patient used to smoke.
+const EX_SMOKER: &[&str] = &[
+    "1377.", "1378.", "1379.", "137A.", "137B.", "137F.", "137K.", "137K0", "137N.", "137O.",
+    "137S.", "137T.", "137j.", "137l.",
+];
+
+/// The smoking codes, parsed once, in the order they are checked.
+static SMOKING_CODES: Lazy<[(CodeSet, SmokingStatus); 3]> = Lazy::new(|| {
+    let codes = |codes: &[&str]| {
+        codes
+            .iter()
+            .map(|code| ReadCode::from_str(code).unwrap())
+            .collect()
+    };
+    [
+        (codes(NEVER_SMOKED), SmokingStatus::Never),
+        (codes(EX_SMOKER), SmokingStatus::Ex),
+        (codes(CURRENT_SMOKER), SmokingStatus::Current),
+    ]
+});
+
+/// Smoking status, as recorded in the GP record.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SmokingStatus {
+    Never,
+    Ex,
+    Current,
+    /// No smoking code has been recorded.
+    Unknown,
+}
+
+impl SmokingStatus {
+    pub const ALL: [SmokingStatus; 4] = [
+        SmokingStatus::Never,
+        SmokingStatus::Ex,
+        SmokingStatus::Current,
+        SmokingStatus::Unknown,
+    ];
+
+    /// The status a single Read code records, if it is a smoking code.
+    pub fn from_code(code: ReadCode) -> Option<Self> {
+        SMOKING_CODES
+            .iter()
+            .find_map(|(codes, status)| codes.contains(code).then_some(*status))
+    }
+
+    /// Whether the patient has ever smoked, or `None` if we don't know.
+    pub fn ever_smoked(self) -> Option<bool> {
+        match self {
+            SmokingStatus::Never => Some(false),
+            SmokingStatus::Ex | SmokingStatus::Current => Some(true),
+            SmokingStatus::Unknown => None,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SmokingStatus::Never => "never",
+            SmokingStatus::Ex => "ex",
+            SmokingStatus::Current => "current",
+            SmokingStatus::Unknown => "unknown",
+        }
+    }
+}
+
+impl fmt::Display for SmokingStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// A patient's smoking status at `date`, from their events.
+///
+/// The most recent smoking code on or before `date` wins. If there is more than one code on that
+/// day, current beats ex beats never. A "never" code after an earlier smoking code is taken to mean
+/// the patient is an ex-smoker, since people often say they don't smoke when they have stopped.
+pub fn smoking_status<'a>(
+    events: impl IntoIterator<Item = &'a Event>,
+    date: NaiveDate,
+) -> SmokingStatus {
+    let mut latest: Option<(NaiveDate, SmokingStatus)> = None;
+    let mut ever_smoked = false;
+    for evt in events.into_iter().filter(|evt| evt.date <= date) {
+        let Some(status) = SmokingStatus::from_code(evt.read_code) else {
+            continue;
+        };
+        if status != SmokingStatus::Never {
+            ever_smoked = true;
+        }
+        if latest.map(|l| (evt.date, status) > l).unwrap_or(true) {
+            latest = Some((evt.date, status));
+        }
+    }
+    match latest {
+        Some((_, SmokingStatus::Never)) if ever_smoked => SmokingStatus::Ex,
+        Some((_, status)) => status,
+        None => SmokingStatus::Unknown,
+    }
+}
+
+/// How well the GP record agrees with the `current_or_ex_smoker` field on the ADAPT form.
+#[derive(Debug, Default, Clone)]
+pub struct SmokingAgreement {
+    /// Counts indexed by `[adapt current_or_ex_smoker][SmokingStatus as usize]`.
+    counts: [[usize; 4]; 2],
+}
+
+impl SmokingAgreement {
+    /// Compare each ADAPT record with the GP record at the date of the patient's last review.
+    pub fn new(events: &Events, adapts: &Adapts) -> Self {
+        let mut this = Self::default();
+        for adapt in adapts.iter() {
+            let status =
+                smoking_status(events.events_for_patient(adapt.id), adapt.last_review_date);
+            this.counts[adapt.current_or_ex_smoker as usize][status as usize] += 1;
+        }
+        this
+    }
+
+    pub fn count(&self, adapt_smoker: bool, status: SmokingStatus) -> usize {
+        self.counts[adapt_smoker as usize][status as usize]
+    }
+
+    /// The number of patients where the GP record has a smoking status and it agrees with ADAPT,
+    /// and the number where it has a smoking status at all.
+    pub fn agreed_of_known(&self) -> (usize, usize) {
+        let mut agreed = 0;
+        let mut known = 0;
+        for adapt_smoker in [false, true] {
+            for status in SmokingStatus::ALL {
+                let Some(ever_smoked) = status.ever_smoked() else {
+                    continue;
+                };
+                let count = self.count(adapt_smoker, status);
+                known += count;
+                if ever_smoked == adapt_smoker {
+                    agreed += count;
+                }
+            }
+        }
+        (agreed, known)
+    }
+
+    pub fn term_table_with(&self, dc: &DisclosureControl) -> tdt::Table<'_> {
+        self.text_table(dc).into_term_table()
+    }
+
+    pub fn text_table(&self, dc: &DisclosureControl) -> TextTable {
+        let headers = iter::once("ADAPT current or ex smoker".to_string())
+            .chain(SmokingStatus::ALL.map(|status| format!("GP record: {}", status)));
+        let mut table = TextTable::new(headers);
+        for adapt_smoker in [false, true] {
+            table.push_row(
+                iter::once(if adapt_smoker { "yes" } else { "no" }.to_string()).chain(
+                    SmokingStatus::ALL
+                        .map(|status| dc.count(self.count(adapt_smoker, status)).to_string()),
+                ),
+            );
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_event;
+
+    fn event(y: i32, code: &str) -> Event {
+        test_event(1, NaiveDate::from_ymd_opt(y, 1, 1).unwrap(), code)
+    }
+
+    #[test]
+    fn status() {
+        let date = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
+        assert_eq!(smoking_status(&[], date), SmokingStatus::Unknown);
+        // unrelated codes are ignored
+        let events = [event(2010, "1371."), event(2015, "2469.")];
+        assert_eq!(smoking_status(&events, date), SmokingStatus::Never);
+        // most recent wins
+        let events = [event(2010, "137R."), event(2015, "137S.")];
+        assert_eq!(smoking_status(&events, date), SmokingStatus::Ex);
+        let events = [event(2015, "137S."), event(2010, "137R.")];
+        assert_eq!(smoking_status(&events, date), SmokingStatus::Ex);
+        // codes after the date are ignored
+        let events = [event(2010, "137R."), event(2021, "137S.")];
+        assert_eq!(smoking_status(&events, date), SmokingStatus::Current);
+        // never after smoking means ex
+        let events = [event(2010, "137R."), event(2015, "1371.")];
+        assert_eq!(smoking_status(&events, date), SmokingStatus::Ex);
+        // same day, current wins
+        let events = [event(2015, "137R."), event(2015, "1371.")];
+        assert_eq!(smoking_status(&events, date), SmokingStatus::Current);
+    }
+}
@@ -1,12 +1,20 @@
 //! Long term conditions.
-use crate::{date_of_extract, read2, Event, Events, PatientId, Patients};
-use anyhow::Result;
-use chrono::{Datelike, NaiveDate};
+use crate::{
+    date_of_extract, dates,
+    late_effects::secondary_malignancy::{CancerSite, SiteMapping},
+    measurements::kidney,
+    read2,
+    render::TextTable,
+    Counts, DataPaths, DisclosureControl, Event, Events, GroupCounts, Patient, PatientId, Patients,
+    RangeSet, Sex,
+};
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
 use itertools::chain;
-use noisy_float::prelude::*;
+use serde::Serialize;
 use statrs::distribution::{Binomial, DiscreteCDF};
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeSet, HashMap},
     iter,
     path::Path,
 };
@@ -60,6 +68,10 @@ pub struct Conditions {
     pub sin149: read2::CodeSetMatcher,
     pub str130: read2::CodeSetMatcher,
     pub thy179: read2::CodeSetMatcher,
+    /// Codes recording that asthma has resolved.
+    pub ast_resolved: read2::CodeSetMatcher,
+    /// Codes recording that epilepsy has resolved.
+    pub epi_resolved: read2::CodeSetMatcher,
 
     lymphoma_leukaemia: read2::CodeSetMatcher,
 }
@@ -93,13 +105,13 @@ impl Conditions {
         let med_code = events.any(|evt| {
             (self.anx140.contains(evt.read_code) || self.dep152.contains(evt.read_code))
                 && evt.date <= date
-                && evt.date > date_y(date, -1)
+                && evt.date > dates::saturating_add_years(date, -1)
         });
         let prod_code = events
             .filter(|evt| {
                 (self.anx141.contains(evt.read_code) || self.dep153.contains(evt.read_code))
                     && evt.date <= date
-                    && evt.date > date_y(date, -1)
+                    && evt.date > dates::saturating_add_years(date, -1)
             })
             .count()
             >= 4;
@@ -107,14 +119,17 @@ impl Conditions {
     }
 
     /// Asthma (currently treated)
+    ///
+    /// Not counted if the latest asthma code is followed by an asthma resolved code.
     pub fn test_ast<'a>(
         &'a self,
-        mut events: impl Iterator<Item = &'a Event>,
+        events: impl Iterator<Item = &'a Event>,
         date: NaiveDate,
     ) -> bool {
-        let diag_code = events.any(|evt| evt.date <= date && self.ast142.contains(evt.read_code));
-        let prod_code = events.any(|evt| {
-            evt.date <= date && evt.date > date_y(date, -1) && self.ast127.contains(evt.read_code)
+        let events: Vec<_> = events.filter(|evt| evt.date <= date).collect();
+        let diag_code = unresolved(&events, &self.ast142, &self.ast_resolved);
+        let prod_code = events.iter().any(|evt| {
+            evt.date > dates::saturating_add_years(date, -1) && self.ast127.contains(evt.read_code)
         });
         diag_code && prod_code
     }
@@ -168,26 +183,57 @@ impl Conditions {
             }
         }
 
-        diags.values().any(|d| *d > date_y(date, -5))
+        diags
+            .values()
+            .any(|d| *d > dates::saturating_add_years(date, -5))
     }
 
-    /// Get all non-lymphoma cancer diagnoses
+    /// Get all non-lymphoma cancer diagnoses, with the site of each cancer.
     ///
     /// This method is for inspecting returned codes, to ensure our method is not bringing in
     /// lymphoma diagnoses.
     pub fn get_can<'a>(
         &'a self,
         events: impl Iterator<Item = &'a Event>,
-    ) -> Vec<(read2::ReadCode, NaiveDate)> {
+        sites: &SiteMapping,
+    ) -> Vec<(read2::ReadCode, NaiveDate, CancerSite)> {
         events
             .filter(|evt| {
                 self.can146.contains(evt.read_code)
                     && !self.lymphoma_leukaemia.contains(evt.read_code)
             })
-            .map(|evt| (evt.read_code, evt.date))
+            .map(|evt| (evt.read_code, evt.date, sites.site(evt.read_code)))
             .collect()
     }
 
+    /// The number of patients with a non-lymphoma cancer code at each site (at any time).
+    ///
+    /// Patients with cancers at more than one site are counted once for each.
+    pub fn cancer_sites(
+        &self,
+        patients: &Patients,
+        events: &Events,
+        sites: &SiteMapping,
+    ) -> CancerSites {
+        let mut patients_with_cancer = 0;
+        let mut patient_sites = vec![];
+        for pat in patients.iter_ref() {
+            let found = self
+                .get_can(events.events_for_patient(pat.patient_id), sites)
+                .into_iter()
+                .map(|(.., site)| site)
+                .collect::<BTreeSet<_>>();
+            if !found.is_empty() {
+                patients_with_cancer += 1;
+            }
+            patient_sites.extend(found);
+        }
+        CancerSites {
+            patients_with_cancer,
+            counts: GroupCounts::from_keys(patient_sites),
+        }
+    }
+
     /// Coronary heart disease
     pub fn test_chd<'a>(
         &'a self,
@@ -198,30 +244,18 @@ impl Conditions {
     }
 
     /// Chronic kidney disease
+    ///
+    /// See [`kidney::ckd_stage`] for how we decide if someone has CKD.
     pub fn test_ckd<'a>(
         &'a self,
         events: impl Iterator<Item = &'a Event>,
         date: NaiveDate,
     ) -> bool {
-        let mut levels: BTreeMap<NaiveDate, R64> = BTreeMap::new();
-        for event in events.filter(|evt| evt.date <= date && self.ckd147.contains(evt.read_code)) {
-            if let Some(val) = parse_egfr(event) {
-                levels.insert(event.date, val);
-            }
-        }
-        let mut val_iter = levels.values().rev();
-        let mut first = match val_iter.next() {
-            Some(v) => *v,
-            // assume no ckd if no eGFR tests
-            None => return false,
-        };
-        // take the highest of the first 2
-        if let Some(second) = val_iter.next() {
-            if *second > first {
-                first = *second;
-            }
-        }
-        first < 60.
+        let readings: Vec<_> = events
+            .filter(|evt| evt.date <= date && self.ckd147.contains(evt.read_code))
+            .filter_map(|evt| Some((evt.date, evt.numeric_value()?)))
+            .collect();
+        kidney::ckd_stage(&readings, date).is_some()
     }
 
     /// Chronic liver disease and viral hepititis
@@ -242,7 +276,7 @@ impl Conditions {
         events
             .filter(|evt| {
                 evt.date <= date
-                    && evt.date > date_y(date, -1)
+                    && evt.date > dates::saturating_add_years(date, -1)
                     && self.con150.contains(evt.read_code)
             })
             .count()
@@ -286,14 +320,17 @@ impl Conditions {
     }
 
     /// Epilepsy (currently treated)
+    ///
+    /// Not counted if the latest epilepsy code is followed by an epilepsy resolved code.
     pub fn test_epi<'a>(
         &'a self,
-        mut events: impl Iterator<Item = &'a Event>,
+        events: impl Iterator<Item = &'a Event>,
         date: NaiveDate,
     ) -> bool {
-        let medcode = events.any(|evt| evt.date <= date && self.epi155.contains(evt.read_code));
-        let prodcode = events.any(|evt| {
-            evt.date <= date && evt.date > date_y(date, -1) && self.epi156.contains(evt.read_code)
+        let events: Vec<_> = events.filter(|evt| evt.date <= date).collect();
+        let medcode = unresolved(&events, &self.epi155, &self.epi_resolved);
+        let prodcode = events.iter().any(|evt| {
+            evt.date > dates::saturating_add_years(date, -1) && self.epi156.contains(evt.read_code)
         });
         medcode && prodcode
     }
@@ -345,7 +382,7 @@ impl Conditions {
         let prodcode = events
             .filter(|evt| {
                 evt.date <= date
-                    && evt.date > date_y(date, -1)
+                    && evt.date > dates::saturating_add_years(date, -1)
                     && self.ibs162.contains(evt.read_code)
             })
             .count()
@@ -372,7 +409,7 @@ impl Conditions {
         events
             .filter(|evt| {
                 evt.date <= date
-                    && evt.date > date_y(date, -1)
+                    && evt.date > dates::saturating_add_years(date, -1)
                     && self.mig164.contains(evt.read_code)
             })
             .count()
@@ -407,7 +444,7 @@ impl Conditions {
             .clone()
             .filter(|evt| {
                 evt.date <= date
-                    && evt.date > date_y(date, -1)
+                    && evt.date > dates::saturating_add_years(date, -1)
                     && self.pnc166.contains(evt.read_code)
             })
             .count()
@@ -416,7 +453,7 @@ impl Conditions {
             .clone()
             .filter(|evt| {
                 evt.date <= date
-                    && evt.date > date_y(date, -1)
+                    && evt.date > dates::saturating_add_years(date, -1)
                     && self.pnc167.contains(evt.read_code)
             })
             .count()
@@ -462,7 +499,7 @@ impl Conditions {
             .clone()
             .filter(|evt| {
                 evt.date <= date
-                    && evt.date > date_y(date, -1)
+                    && evt.date > dates::saturating_add_years(date, -1)
                     && self.pso172.contains(evt.read_code)
             })
             .count()
@@ -536,11 +573,16 @@ impl Conditions {
     ) -> ConditionsReport {
         // count of people who got their diagnosis more than 5 years ago
         let extract_date = date_of_extract();
-        let y5 = date_y(extract_date, -5);
-        let total5 = diagnosis_dates.values().filter(|d| **d < y5).count();
+        let dates = || {
+            patients
+                .iter_ref()
+                .filter_map(|pat| diagnosis_dates.get(&pat.patient_id))
+        };
+        let y5 = dates::saturating_add_years(extract_date, -5);
+        let total5 = dates().filter(|d| **d < y5).count();
         // count of people who got their diagnosis more than 10 years ago
-        let y10 = date_y(extract_date, -10);
-        let total10 = diagnosis_dates.values().filter(|d| **d < y10).count();
+        let y10 = dates::saturating_add_years(extract_date, -10);
+        let total10 = dates().filter(|d| **d < y10).count();
         let mut report = ConditionsReport::new([patients.len(), total5, total10]);
 
         for pat in patients.iter() {
@@ -549,15 +591,20 @@ impl Conditions {
                 Some(date) => *date,
                 None => continue,
             };
-            let date5 = date_y(date, 5);
-            let date10 = date_y(date, 10);
+            let date5 = dates::saturating_add_years(date, 5);
+            let date10 = dates::saturating_add_years(date, 10);
+            // The conditions at diagnosis, in the same order as `ConditionsReport::iter`.
+            let mut flags = 0u64;
+            let mut idx = 0;
 
             macro_rules! ltc_test {
                 ($field:ident, $test:ident) => {
                     let row = &mut report.$field;
                     if self.$test(evts.clone(), date) {
                         row.y0 += 1;
+                        flags |= 1 << idx;
                     }
+                    idx += 1;
                     if date5 <= extract_date && self.$test(evts.clone(), date5) {
                         row.y5 += 1;
                     }
@@ -604,76 +651,115 @@ impl Conditions {
             ltc_test!(sin, test_sin);
             ltc_test!(str_, test_str);
             ltc_test!(thy, test_thy);
+            debug_assert_eq!(idx, report.iter().count());
+            report.patient_flags.push(flags);
         }
         report
     }
 
+    /// Like [`Conditions::report`], but with a separate report for each sex and age band at
+    /// diagnosis, since prevalence depends strongly on both.
+    ///
+    /// Patients without a diagnosis date are left out, as they have no age at diagnosis. Patients
+    /// whose age isn't in any of `age_bands` (or isn't plausible) are in a "missing/invalid" band.
+    pub fn report_stratified(
+        &self,
+        patients: &Patients,
+        events: &Events,
+        diagnosis_dates: &HashMap<PatientId, NaiveDate>,
+        age_bands: &RangeSet<u16>,
+    ) -> StratifiedReport {
+        let stratum = |pat: &Patient| {
+            let date = diagnosis_dates.get(&pat.patient_id)?;
+            let band = pat
+                .plausible_age_at(*date)
+                .and_then(|age| age_bands.iter().position(|band| band.contains(&age)));
+            Some((pat.sex, band))
+        };
+        let strata = patients
+            .group_count(stratum)
+            .into_iter()
+            .filter_map(|(key, _)| key)
+            .map(|key| {
+                let stratum_patients = patients.filter(|pat| stratum(pat) == Some(key));
+                let (sex, band) = key;
+                let age_band = match band.and_then(|idx| age_bands.iter().nth(idx)) {
+                    Some(band) => band.to_string(),
+                    None => "missing/invalid".to_string(),
+                };
+                (
+                    Stratum { sex, age_band },
+                    self.report(&stratum_patients, events, diagnosis_dates),
+                )
+            })
+            .collect();
+        StratifiedReport { strata }
+    }
+
     /// Load codesets from disk
     pub fn load() -> Result<Self> {
-        let data_path = Path::new("../data");
-        let termset_path = data_path.join("termsets");
-        let camb_codeset_path = data_path.join("camb_codesets");
-
-        macro_rules! camb {
-            ($path:expr) => {
-                read2::CodeSet::load_camb(camb_codeset_path.join($path))?.into_matcher()
-            };
-        }
+        Self::load_from(&DataPaths::current())
+    }
 
-        macro_rules! term {
-            ($path:expr) => {
-                read2::CodeSet::load(termset_path.join($path).join("codes.txt"))?.into_matcher()
+    /// Load codesets from the given data directories.
+    pub fn load_from(paths: &DataPaths) -> Result<Self> {
+        let registry = read2::CodesetRegistry::builtin();
+        macro_rules! codeset {
+            ($name:expr) => {
+                registry.load_codeset($name, paths)?.into_matcher()
             };
         }
 
-        let alc138 = camb!("alc138_mc.csv");
-        let ano139 = camb!("ano139_mc.csv");
-        let anx140 = camb!("anx140_mc.csv");
-        let anx141 = term!("anxiety_meds");
-        let ast127 = term!("asthma_meds");
-        let ast142 = camb!("ast142_mc.csv");
-        let atr143 = camb!("atr143_mc.csv");
-        let bli144 = camb!("bli144_mc.csv");
-        let bro145 = camb!("bro145_mc.csv");
-        let can146 = camb!("can146_mc.csv");
-        let chd126 = camb!("chd126_mc.csv");
-        let ckd147 = camb!("ckd147_mc.csv");
-        let cld148 = camb!("cld148_mc.csv");
-        let con150 = term!("constipation_meds");
-        let cop151 = camb!("cop151_mc.csv");
-        let dem131 = camb!("dem131_mc.csv");
-        let dep152 = camb!("dep152_mc.csv");
-        let dep153 = term!("depression_meds");
-        let dib128 = camb!("dib128_mc.csv");
-        let div154 = camb!("div154_mc.csv");
-        let epi155 = camb!("epi155_mc.csv");
-        let epi156 = term!("epilepsy_meds");
-        let hef158 = camb!("hef158_mc.csv");
-        let hel157 = camb!("hel157_mc.csv");
-        let hyp159 = camb!("hyp159_mc.csv");
-        let ibd160 = camb!("ibd160_mc.csv");
-        let ibs161 = camb!("ibs161_mc.csv");
-        let ibs162 = term!("ibs_meds");
-        let lea163 = camb!("lea163_mc.csv");
-        let mig164 = term!("migraine_meds");
-        let msc165 = camb!("msc165_mc.csv");
-        let pep135 = camb!("pep135_mc.csv");
-        let pnc166 = term!("analgesics_ex_migraine_meds");
-        let pnc167 = term!("epilepsy_ex_benzos_meds");
-        let prk169 = camb!("prk169_mc.csv");
-        let pro170 = camb!("pro170_mc.csv");
-        let psm173 = camb!("psm173_mc.csv");
-        let pso171 = camb!("pso171_mc.csv");
-        let pso172 = term!("psoriasis_eczema_meds");
-        let pvd168 = camb!("pvd168_mc.csv");
-        let rhe174 = camb!("rhe174_mc.csv");
-        let scz175 = camb!("scz175_mc.csv");
-        let scz176 = term!("schizophrenia_meds");
-        let sin149 = camb!("sin149_mc.csv");
-        let str130 = camb!("str130_mc.csv");
-        let thy179 = camb!("thy179_mc.csv");
-
-        let lymphoma_leukaemia = term!("lymphoma_leukaemia");
+        let alc138 = codeset!("alc138_mc");
+        let ano139 = codeset!("ano139_mc");
+        let anx140 = codeset!("anx140_mc");
+        let anx141 = codeset!("anxiety_meds");
+        let ast127 = codeset!("asthma_meds");
+        let ast142 = codeset!("ast142_mc");
+        let atr143 = codeset!("atr143_mc");
+        let bli144 = codeset!("bli144_mc");
+        let bro145 = codeset!("bro145_mc");
+        let can146 = codeset!("can146_mc");
+        let chd126 = codeset!("chd126_mc");
+        let ckd147 = codeset!("ckd147_mc");
+        let cld148 = codeset!("cld148_mc");
+        let con150 = codeset!("constipation_meds");
+        let cop151 = codeset!("cop151_mc");
+        let dem131 = codeset!("dem131_mc");
+        let dep152 = codeset!("dep152_mc");
+        let dep153 = codeset!("depression_meds");
+        let dib128 = codeset!("dib128_mc");
+        let div154 = codeset!("div154_mc");
+        let epi155 = codeset!("epi155_mc");
+        let epi156 = codeset!("epilepsy_meds");
+        let hef158 = codeset!("hef158_mc");
+        let hel157 = codeset!("hel157_mc");
+        let hyp159 = codeset!("hyp159_mc");
+        let ibd160 = codeset!("ibd160_mc");
+        let ibs161 = codeset!("ibs161_mc");
+        let ibs162 = codeset!("ibs_meds");
+        let lea163 = codeset!("lea163_mc");
+        let mig164 = codeset!("migraine_meds");
+        let msc165 = codeset!("msc165_mc");
+        let pep135 = codeset!("pep135_mc");
+        let pnc166 = codeset!("analgesics_ex_migraine_meds");
+        let pnc167 = codeset!("epilepsy_ex_benzos_meds");
+        let prk169 = codeset!("prk169_mc");
+        let pro170 = codeset!("pro170_mc");
+        let psm173 = codeset!("psm173_mc");
+        let pso171 = codeset!("pso171_mc");
+        let pso172 = codeset!("psoriasis_eczema_meds");
+        let pvd168 = codeset!("pvd168_mc");
+        let rhe174 = codeset!("rhe174_mc");
+        let scz175 = codeset!("scz175_mc");
+        let scz176 = codeset!("schizophrenia_meds");
+        let sin149 = codeset!("sin149_mc");
+        let str130 = codeset!("str130_mc");
+        let thy179 = codeset!("thy179_mc");
+        let ast_resolved = codeset!("asthma_resolved");
+        let epi_resolved = codeset!("epilepsy_resolved");
+
+        let lymphoma_leukaemia = codeset!("lymphoma_leukaemia");
 
         Ok(Conditions {
             alc138,
@@ -722,6 +808,8 @@ impl Conditions {
             sin149,
             str130,
             thy179,
+            ast_resolved,
+            epi_resolved,
             lymphoma_leukaemia,
         })
     }
@@ -730,6 +818,9 @@ impl Conditions {
 #[derive(Default, Debug)]
 pub struct ConditionsReport {
     totals: [usize; 3],
+    /// For each patient with a diagnosis date, a bit for each condition they had at diagnosis
+    /// (in the order of [`ConditionsReport::iter`]).
+    patient_flags: Vec<u64>,
 
     alc: ReportRow,
     ano: ReportRow,
@@ -818,29 +909,39 @@ impl ConditionsReport {
         }
     }
 
+    /// The number of patients at 0, 5 and 10 years after diagnosis.
+    pub fn totals(&self) -> [usize; 3] {
+        self.totals
+    }
+
     pub fn term_table(&self) -> tdt::Table {
-        use tdt::{Cell, Row, Table};
-        let mut table = Table::new()
-            .with_row(
-                Row::new()
-                    .with_cell(Cell::from("Condition"))
-                    .with_cell(Cell::from("0 years"))
-                    .with_cell(Cell::from("5 years"))
-                    .with_cell(Cell::from("10 years")),
-            )
-            .with_row(
-                Row::new()
-                    .with_cell(Cell::from("Totals"))
-                    .with_cell(Cell::from(self.totals[0].to_string()))
-                    .with_cell(Cell::from(self.totals[1].to_string()))
-                    .with_cell(Cell::from(self.totals[2].to_string())),
-            );
+        self.term_table_with(&DisclosureControl::NONE)
+    }
+
+    /// The report table, with counts (and so percentages) protected by `dc`.
+    pub fn term_table_with(&self, dc: &DisclosureControl) -> tdt::Table<'_> {
+        self.text_table(dc).into_term_table()
+    }
+
+    pub fn text_table(&self, dc: &DisclosureControl) -> TextTable {
+        let mut table = TextTable::new(["Condition", "0 years", "5 years", "10 years"]).with_row(
+            iter::once("Totals".to_string())
+                .chain(self.totals.map(|total| dc.count(total).to_string())),
+        );
         for (name, data, _) in self.iter() {
-            table = table.with_row(data.term_table(name, self.totals));
+            table.push_row(data.cells(name, self.totals, dc));
         }
         table
     }
 
+    pub fn to_markdown(&self, dc: &DisclosureControl) -> String {
+        self.text_table(dc).to_markdown()
+    }
+
+    pub fn to_latex(&self, dc: &DisclosureControl) -> String {
+        self.text_table(dc).to_latex()
+    }
+
     /// Perform significance testing
     ///
     /// Params
@@ -916,6 +1017,69 @@ impl ConditionsReport {
         SignificanceTable { rows }
     }
 
+    /// The report in long format, one row per condition and time after diagnosis, with counts
+    /// protected by `dc`.
+    pub fn to_tidy(&self, dc: &DisclosureControl) -> Vec<TidyPrevalence> {
+        let mut rows = vec![];
+        for (condition, data, reference_prevalence) in self.iter() {
+            for ((offset_years, count), total) in
+                [0, 5, 10].into_iter().zip(data.counts()).zip(self.totals)
+            {
+                let count = dc.count(count).value();
+                let denominator = dc.count(total).value();
+                let prevalence = match (count, denominator) {
+                    (Some(count), Some(total)) if total > 0 => Some(count as f64 / total as f64),
+                    _ => None,
+                };
+                rows.push(TidyPrevalence {
+                    condition,
+                    offset_years,
+                    count,
+                    denominator,
+                    prevalence,
+                    reference_prevalence,
+                });
+            }
+        }
+        rows
+    }
+
+    /// Write [`ConditionsReport::to_tidy`] to a CSV file.
+    pub fn save_tidy_csv(&self, path: impl AsRef<Path>, dc: &DisclosureControl) -> Result<()> {
+        let path = path.as_ref();
+        let mut out = csv::Writer::from_path(path)
+            .with_context(|| format!("creating \"{}\"", path.display()))?;
+        for row in self.to_tidy(dc) {
+            out.serialize(row)?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// How often each pair of conditions occurs together at diagnosis.
+    pub fn cooccurrence_matrix(&self) -> CooccurrenceMatrix {
+        let labels: Vec<_> = self.iter().map(|(label, _, _)| label).collect();
+        let n = labels.len();
+        let mut counts = vec![vec![0; n]; n];
+        for flags in self.patient_flags.iter() {
+            for i in 0..n {
+                if flags & (1 << i) == 0 {
+                    continue;
+                }
+                for j in 0..n {
+                    if flags & (1 << j) != 0 {
+                        counts[i][j] += 1;
+                    }
+                }
+            }
+        }
+        CooccurrenceMatrix {
+            labels,
+            total: self.patient_flags.len(),
+            counts,
+        }
+    }
+
     // Make it easier to iterate through conditions
     pub fn iter(&self) -> impl Iterator<Item = (&'static str, &ReportRow, f64)> {
         macro_rules! iter_impl {
@@ -973,6 +1137,59 @@ impl ConditionsReport {
     }
 }
 
+/// One group of patients in a [`StratifiedReport`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stratum {
+    pub sex: Sex,
+    /// The age band at diagnosis, e.g. "40 - 60".
+    pub age_band: String,
+}
+
+/// LTC prevalence for each sex and age band, from [`Conditions::report_stratified`].
+#[derive(Debug)]
+pub struct StratifiedReport {
+    /// In order of sex, then age band.
+    strata: Vec<(Stratum, ConditionsReport)>,
+}
+
+impl StratifiedReport {
+    pub fn iter(&self) -> impl Iterator<Item = (&Stratum, &ConditionsReport)> {
+        self.strata
+            .iter()
+            .map(|(stratum, report)| (stratum, report))
+    }
+
+    /// One row per condition per stratum, with the totals for each stratum first.
+    pub fn text_table(&self, dc: &DisclosureControl) -> TextTable {
+        let mut table = TextTable::new([
+            "Sex",
+            "Age band",
+            "Condition",
+            "0 years",
+            "5 years",
+            "10 years",
+        ]);
+        for (stratum, report) in self.iter() {
+            let stratum_cells = [stratum.sex.to_string(), stratum.age_band.clone()];
+            table.push_row(
+                stratum_cells
+                    .iter()
+                    .cloned()
+                    .chain(iter::once("Totals".to_string()))
+                    .chain(report.totals.map(|total| dc.count(total).to_string())),
+            );
+            for (name, data, _) in report.iter() {
+                table.push_row(stratum_cells.iter().cloned().chain(data.cells(
+                    name,
+                    report.totals,
+                    dc,
+                )));
+            }
+        }
+        table
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct ReportRow {
     /// 0 years after diagnosis
@@ -984,23 +1201,36 @@ pub struct ReportRow {
 }
 
 impl ReportRow {
-    fn prevalence(&self, totals: [usize; 3]) -> [f64; 3] {
+    /// The number of patients with the condition at 0, 5 and 10 years after diagnosis.
+    pub fn counts(&self) -> [usize; 3] {
+        [self.y0, self.y5, self.y10]
+    }
+
+    fn cells(&self, title: &str, totals: [usize; 3], dc: &DisclosureControl) -> [String; 4] {
         [
-            self.y0 as f64 / totals[0] as f64,
-            self.y5 as f64 / totals[1] as f64,
-            self.y10 as f64 / totals[2] as f64,
+            title.to_string(),
+            dc.count_with_percentage(self.y0, totals[0]),
+            dc.count_with_percentage(self.y5, totals[1]),
+            dc.count_with_percentage(self.y10, totals[2]),
         ]
     }
+}
 
-    fn term_table<'a>(&'a self, title: &'a str, totals: [usize; 3]) -> tdt::Row<'a> {
-        use tdt::{Cell, Row};
-        let [py0, py5, py10] = self.prevalence(totals);
-        Row::new()
-            .with_cell(Cell::from(title))
-            .with_cell(Cell::from(format!("{} ({:.1}%)", self.y0, py0 * 100.)))
-            .with_cell(Cell::from(format!("{} ({:.1}%)", self.y5, py5 * 100.)))
-            .with_cell(Cell::from(format!("{} ({:.1}%)", self.y10, py10 * 100.)))
-    }
+/// The prevalence of one condition at one time after diagnosis (see
+/// [`ConditionsReport::to_tidy`]).
+#[derive(Debug, Clone, Serialize)]
+pub struct TidyPrevalence {
+    pub condition: &'static str,
+    /// 0, 5 or 10 years after diagnosis.
+    pub offset_years: u32,
+    /// `None` if suppressed.
+    pub count: Option<usize>,
+    /// The number of patients followed up this long, `None` if suppressed.
+    pub denominator: Option<usize>,
+    /// `count / denominator`, as a proportion.
+    pub prevalence: Option<f64>,
+    /// The prevalence in the general population, as a proportion.
+    pub reference_prevalence: f64,
 }
 
 pub struct SignificanceTable {
@@ -1009,12 +1239,23 @@ pub struct SignificanceTable {
 
 impl SignificanceTable {
     pub fn term_table(&self) -> tdt::Table {
-        use tdt::Table;
-        let mut tbl = Table::new();
+        self.text_table().into_term_table()
+    }
+
+    pub fn text_table(&self) -> TextTable {
+        let mut table = TextTable::new(["Condition", "0 years", "5 years", "10 years"]);
         for row in self.rows.iter() {
-            tbl.add_row(row.term_table());
+            table.push_row(row.cells());
         }
-        tbl
+        table
+    }
+
+    pub fn to_markdown(&self) -> String {
+        self.text_table().to_markdown()
+    }
+
+    pub fn to_latex(&self) -> String {
+        self.text_table().to_latex()
     }
 }
 
@@ -1029,50 +1270,264 @@ struct SignificanceRow {
 }
 
 impl SignificanceRow {
-    fn term_table(&self) -> tdt::Row {
-        use tdt::{Cell, Row};
-        Row::new()
-            .with_cell(Cell::from(self.label))
-            .with_cell(format!(
-                "[{}, {}]{}",
-                self.null_range_0y.0,
-                self.null_range_0y.1,
-                if self.significant_0y {
-                    " significant"
-                } else {
-                    ""
-                }
-            ))
-            .with_cell(format!(
+    fn cells(&self) -> [String; 4] {
+        let range = |(low, high): (u64, u64), significant: bool| {
+            format!(
                 "[{}, {}]{}",
-                self.null_range_5y.0,
-                self.null_range_5y.1,
-                if self.significant_5y {
-                    " significant"
-                } else {
-                    ""
-                }
-            ))
-            .with_cell(format!(
-                "[{}, {}]{}",
-                self.null_range_10y.0,
-                self.null_range_10y.1,
-                if self.significant_10y {
-                    " significant"
-                } else {
-                    ""
-                }
-            ))
+                low,
+                high,
+                if significant { " significant" } else { "" }
+            )
+        };
+        [
+            self.label.to_string(),
+            range(self.null_range_0y, self.significant_0y),
+            range(self.null_range_5y, self.significant_5y),
+            range(self.null_range_10y, self.significant_10y),
+        ]
     }
 }
 
-/// add years from a date
-fn date_y(date: NaiveDate, years: i32) -> NaiveDate {
-    date.with_year(date.year() + years).unwrap()
+/// How often pairs of long term conditions occur together in the same patient.
+#[derive(Debug, Clone)]
+pub struct CooccurrenceMatrix {
+    pub labels: Vec<&'static str>,
+    /// The number of patients.
+    pub total: usize,
+    /// `counts[i][j]` is the number of patients with conditions `i` and `j`. The diagonal is the
+    /// number with each condition.
+    pub counts: Vec<Vec<usize>>,
+}
+
+/// One cell of a [`CooccurrenceMatrix`], after disclosure control.
+#[derive(Debug, Clone, Serialize)]
+pub struct CooccurrenceCell {
+    pub condition_a: &'static str,
+    pub condition_b: &'static str,
+    /// `None` if suppressed.
+    pub observed: Option<usize>,
+    /// The number we would expect if the conditions were independent.
+    pub expected: Option<f64>,
+    pub observed_expected_ratio: Option<f64>,
 }
 
-fn parse_egfr(evt: &Event) -> Option<R64> {
-    let val = evt.code_value.as_ref()?;
-    let val = val.parse::<f64>().ok()?;
-    R64::try_new(val)
+impl CooccurrenceMatrix {
+    /// The number of patients we would expect to have both `i` and `j` if they were independent.
+    pub fn expected(&self, i: usize, j: usize) -> Option<f64> {
+        (self.total > 0)
+            .then(|| self.counts[i][i] as f64 * self.counts[j][j] as f64 / self.total as f64)
+    }
+
+    /// Observed over expected co-occurrence. `None` if we wouldn't expect any patients to have
+    /// both.
+    pub fn observed_expected(&self, i: usize, j: usize) -> Option<f64> {
+        let expected = self.expected(i, j)?;
+        (expected > 0.).then(|| self.counts[i][j] as f64 / expected)
+    }
+
+    /// Every pair of conditions (including each condition with itself), with counts protected by
+    /// `dc`. Expected counts and ratios are worked out from the rounded counts.
+    pub fn cells(&self, dc: &DisclosureControl) -> Vec<CooccurrenceCell> {
+        let rounded = CooccurrenceMatrix {
+            labels: self.labels.clone(),
+            total: dc.count(self.total).value().unwrap_or(0),
+            counts: self
+                .counts
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .map(|count| dc.count(*count).value().unwrap_or(0))
+                        .collect()
+                })
+                .collect(),
+        };
+        let mut cells = vec![];
+        for (i, condition_a) in self.labels.iter().enumerate() {
+            for (j, condition_b) in self.labels.iter().enumerate() {
+                let observed = dc.count(self.counts[i][j]).value();
+                let disclosed = observed.is_some()
+                    && dc.count(self.counts[i][i]).value().is_some()
+                    && dc.count(self.counts[j][j]).value().is_some();
+                cells.push(CooccurrenceCell {
+                    condition_a,
+                    condition_b,
+                    observed,
+                    expected: disclosed.then(|| rounded.expected(i, j)).flatten(),
+                    observed_expected_ratio: disclosed
+                        .then(|| rounded.observed_expected(i, j))
+                        .flatten(),
+                });
+            }
+        }
+        cells
+    }
+
+    /// Pairs of different conditions that at least one patient has, with the most over-represented
+    /// first.
+    pub fn term_table(&self, dc: &DisclosureControl) -> tdt::Table<'_> {
+        use tdt::{Row, Table};
+        let n = self.labels.len();
+        let mut cells: Vec<_> = self
+            .cells(dc)
+            .into_iter()
+            .enumerate()
+            .filter(|(idx, _)| idx / n < idx % n)
+            .filter(|(idx, _)| self.counts[idx / n][idx % n] > 0)
+            .collect();
+        cells.sort_by(|(_, a), (_, b)| {
+            let ratio = |cell: &CooccurrenceCell| cell.observed_expected_ratio.unwrap_or(-1.);
+            ratio(b).total_cmp(&ratio(a))
+        });
+        let mut table = Table::new().with_row(
+            Row::new()
+                .with_cell("Condition")
+                .with_cell("Condition")
+                .with_cell("Both")
+                .with_cell("Expected")
+                .with_cell("Observed / expected"),
+        );
+        for (idx, cell) in cells {
+            table.add_row(
+                Row::new()
+                    .with_cell(cell.condition_a)
+                    .with_cell(cell.condition_b)
+                    .with_cell(dc.count(self.counts[idx / n][idx % n]).to_string())
+                    .with_cell(match cell.expected {
+                        Some(v) => format!("{v:.1}"),
+                        None => "-".into(),
+                    })
+                    .with_cell(match cell.observed_expected_ratio {
+                        Some(v) => format!("{v:.2}"),
+                        None => "-".into(),
+                    }),
+            );
+        }
+        table
+    }
+
+    /// Write every cell of the matrix to a CSV file, one row per pair, for heat-mapping.
+    pub fn save_csv(&self, path: impl AsRef<Path>, dc: &DisclosureControl) -> Result<()> {
+        let path = path.as_ref();
+        let mut out = csv::Writer::from_path(path)
+            .with_context(|| format!("creating \"{}\"", path.display()))?;
+        for cell in self.cells(dc) {
+            out.serialize(cell)?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// Whether any of `events` has a code in `diagnosis`, and the latest one isn't followed by a code
+/// in `resolved`.
+///
+/// A resolved code on the same day as the latest diagnosis counts as resolving it, but a new
+/// diagnosis after a resolved code means the patient has the condition again.
+fn unresolved(
+    events: &[&Event],
+    diagnosis: &read2::CodeSetMatcher,
+    resolved: &read2::CodeSetMatcher,
+) -> bool {
+    let latest = |codes: &read2::CodeSetMatcher| {
+        events
+            .iter()
+            .filter(|evt| codes.contains(evt.read_code))
+            .map(|evt| evt.date)
+            .max()
+    };
+    match (latest(diagnosis), latest(resolved)) {
+        (Some(diagnosed), Some(resolved)) => diagnosed > resolved,
+        (diagnosed, _) => diagnosed.is_some(),
+    }
+}
+
+/// Patients with non-lymphoma cancers by site, from [`Conditions::cancer_sites`].
+#[derive(Debug, Clone)]
+pub struct CancerSites {
+    /// Patients with a cancer at any site.
+    pub patients_with_cancer: usize,
+    pub counts: GroupCounts<CancerSite>,
+}
+
+impl CancerSites {
+    /// Percentages are of the patients with a cancer at any site.
+    pub fn text_table(&self, dc: &DisclosureControl) -> TextTable {
+        let mut table = self
+            .counts
+            .text_table("Cancer site", self.patients_with_cancer, dc);
+        table.push_row([
+            "Any site".to_string(),
+            dc.count(self.patients_with_cancer).to_string(),
+        ]);
+        table
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tidy() {
+        let mut report = ConditionsReport::new([200, 100, 0]);
+        report.alc = ReportRow {
+            y0: 20,
+            y5: 10,
+            y10: 0,
+        };
+        let rows = report.to_tidy(&DisclosureControl::NONE);
+        assert_eq!(rows.len(), report.iter().count() * 3);
+        let alc = &rows[..3];
+        assert_eq!(alc[0].condition, "Alcohol problems");
+        assert_eq!(
+            alc.iter().map(|row| row.offset_years).collect::<Vec<_>>(),
+            [0, 5, 10]
+        );
+        assert_eq!(alc[0].prevalence, Some(0.1));
+        assert_eq!(alc[1].count, Some(10));
+        assert_eq!(alc[2].prevalence, None);
+        assert_eq!(alc[0].reference_prevalence, ConditionsReport::PRE_ALC);
+    }
+
+    #[test]
+    fn stratified_table() {
+        let mut report = ConditionsReport::new([20, 10, 0]);
+        report.alc.y0 = 5;
+        let stratified = StratifiedReport {
+            strata: vec![(
+                Stratum {
+                    sex: Sex::Female,
+                    age_band: "40 - 60".into(),
+                },
+                report,
+            )],
+        };
+        let table = stratified.text_table(&DisclosureControl::NONE);
+        assert_eq!(
+            table.rows()[0],
+            ["Female", "40 - 60", "Totals", "20", "10", "0"]
+        );
+        assert_eq!(
+            table.rows()[1][..4],
+            ["Female", "40 - 60", "Alcohol problems", "5 (25.0%)"]
+        );
+        assert_eq!(
+            table.rows().len(),
+            1 + stratified.strata[0].1.iter().count()
+        );
+    }
+
+    #[test]
+    fn cooccurrence() {
+        let mut report = ConditionsReport::new([4, 0, 0]);
+        // Alcohol problems and anorexia together, alcohol alone, and nothing.
+        report.patient_flags = vec![0b11, 0b11, 0b1, 0];
+        let matrix = report.cooccurrence_matrix();
+        assert_eq!(matrix.counts[0][0], 3);
+        assert_eq!(matrix.counts[0][1], 2);
+        assert_eq!(matrix.counts[1][0], 2);
+        assert_eq!(matrix.expected(0, 1), Some(1.5));
+        assert_eq!(matrix.observed_expected(0, 1), Some(2. / 1.5));
+        assert_eq!(matrix.observed_expected(0, 2), None);
+    }
 }
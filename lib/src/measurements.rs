@@ -0,0 +1,379 @@
+//! Numeric results (lab tests, observations) recorded against events.
+//!
+//! The extract stores results as free text in `code_value` and `code_units`. Here we parse them
+//! into a [`Measurement`], converting the units to a canonical form where that can be done
+//! without knowing what was measured. Converting between mass and molar concentrations (e.g.
+//! mg/dL to mmol/L) depends on the analyte, so that needs [`Measurement::convert`].
+mod blood_pressure;
+pub mod body;
+pub mod kidney;
+pub mod reference;
+
+pub use blood_pressure::{blood_pressure, latest_before, BpCodes, BpReading, BpThreshold};
+
+use crate::{read2::CodeSet, ArcStr, Event, Events, GroupCounts, PatientId};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt};
+
+/// The units we convert results into.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Unit {
+    MmolPerL,
+    MgPerDl,
+    UmolPerL,
+    /// eGFR.
+    MlPerMinPer173M2,
+    MmHg,
+    Kg,
+    Cm,
+    KgPerM2,
+    Percent,
+    /// HbA1c (IFCC).
+    MmolPerMol,
+    GPerL,
+    /// e.g. TSH.
+    MuPerL,
+    /// Cell counts, 10^9 per litre.
+    E9PerL,
+}
+
+impl Unit {
+    /// Parse a `code_units` string, returning the canonical unit and the factor to multiply the
+    /// value by to get it in that unit.
+    pub fn parse(raw: &str) -> Option<(Unit, f64)> {
+        let norm: String = raw
+            .trim()
+            .chars()
+            .filter(|ch| !ch.is_whitespace())
+            .map(|ch| match ch {
+                'µ' | 'μ' => 'u',
+                '²' => '2',
+                ch => ch.to_ascii_lowercase(),
+            })
+            .collect();
+        // The spellings seen in the extract. Add to this when `Events::unmapped_units` turns up
+        // a new one.
+        Some(match norm.as_str() {
+            "mmol/l" | "mmoll" | "mmol/litre" | "mmol/ltr" => (Unit::MmolPerL, 1.),
+            "mg/dl" => (Unit::MgPerDl, 1.),
+            "umol/l" | "umoll" | "micromol/l" | "umol/litre" | "micmol/l" => (Unit::UmolPerL, 1.),
+            "mmol/mol" | "mmol/molhb" => (Unit::MmolPerMol, 1.),
+            "ml/min/1.73m2" | "ml/min/1.73sqm" | "ml/min/1.73m^2" | "ml/min/1.73"
+            | "mls/min/1.73m2" | "ml/min/1.73msq" => (Unit::MlPerMinPer173M2, 1.),
+            "mmhg" | "mm[hg]" => (Unit::MmHg, 1.),
+            "kg" => (Unit::Kg, 1.),
+            "g" => (Unit::Kg, 0.001),
+            "cm" => (Unit::Cm, 1.),
+            "m" => (Unit::Cm, 100.),
+            "kg/m2" | "kg/m^2" => (Unit::KgPerM2, 1.),
+            "%" => (Unit::Percent, 1.),
+            "g/l" => (Unit::GPerL, 1.),
+            "g/dl" => (Unit::GPerL, 10.),
+            "mu/l" | "miu/l" => (Unit::MuPerL, 1.),
+            "mu/ml" | "miu/ml" => (Unit::MuPerL, 1000.),
+            "x10^9/l" | "10^9/l" | "x10*9/l" | "10*9/l" | "x10e9/l" | "10e9/l" | "*10^9/l"
+            | "x10(9)/l" | "10(9)/l" | "x10~9/l" | "10~9/l" | "x109/l" | "10⁹/l" => {
+                (Unit::E9PerL, 1.)
+            }
+            _ => return None,
+        })
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Unit::MmolPerL => "mmol/L",
+            Unit::MgPerDl => "mg/dL",
+            Unit::UmolPerL => "µmol/L",
+            Unit::MlPerMinPer173M2 => "mL/min/1.73m²",
+            Unit::MmHg => "mmHg",
+            Unit::Kg => "kg",
+            Unit::Cm => "cm",
+            Unit::KgPerM2 => "kg/m²",
+            Unit::Percent => "%",
+            Unit::MmolPerMol => "mmol/mol",
+            Unit::GPerL => "g/L",
+            Unit::MuPerL => "mU/L",
+            Unit::E9PerL => "10⁹/L",
+        }
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Things we know how to convert between mass and molar concentrations for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Analyte {
+    Cholesterol,
+    Triglycerides,
+    Glucose,
+    Creatinine,
+}
+
+impl Analyte {
+    /// The (mass unit, molar unit, factor) such that `molar = mass * factor`.
+    fn factor(self) -> (Unit, Unit, f64) {
+        match self {
+            Analyte::Cholesterol => (Unit::MgPerDl, Unit::MmolPerL, 0.02586),
+            Analyte::Triglycerides => (Unit::MgPerDl, Unit::MmolPerL, 0.01129),
+            Analyte::Glucose => (Unit::MgPerDl, Unit::MmolPerL, 0.05551),
+            Analyte::Creatinine => (Unit::MgPerDl, Unit::UmolPerL, 88.42),
+        }
+    }
+}
+
+/// The units of a [`Measurement`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Units {
+    /// No units were recorded.
+    None,
+    Known(Unit),
+    /// Units were recorded, but [`Unit::parse`] doesn't recognise them.
+    Unrecognised,
+}
+
+impl Units {
+    /// The unit, if we recognised it.
+    pub fn known(self) -> Option<Unit> {
+        match self {
+            Units::Known(unit) => Some(unit),
+            Units::None | Units::Unrecognised => None,
+        }
+    }
+}
+
+/// A numeric result.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Measurement {
+    pub value: f64,
+    pub units: Units,
+}
+
+impl Measurement {
+    /// Parse a value and (optional) units.
+    ///
+    /// Results reported as a bound (e.g. eGFR ">90") are taken to be the bound.
+    pub fn parse(value: &str, units: Option<&str>) -> Option<Self> {
+        let value = value.trim().trim_start_matches(['<', '>', '=']).trim();
+        let value = value.parse::<f64>().ok().filter(|v| v.is_finite())?;
+        let units = units.map(str::trim).filter(|units| !units.is_empty());
+        Some(match units.map(Unit::parse) {
+            Some(Some((unit, factor))) => Measurement {
+                value: value * factor,
+                units: Units::Known(unit),
+            },
+            Some(None) => Measurement {
+                value,
+                units: Units::Unrecognised,
+            },
+            None => Measurement {
+                value,
+                units: Units::None,
+            },
+        })
+    }
+
+    /// Convert to `to`, using `analyte` to convert between mass and molar concentrations.
+    ///
+    /// Returns `None` if the conversion isn't possible, including when we don't know the units.
+    pub fn convert(self, to: Unit, analyte: Option<Analyte>) -> Option<Self> {
+        let from = self.units.known()?;
+        if from == to {
+            return Some(self);
+        }
+        let (mass, molar, factor) = analyte?.factor();
+        let value = if (from, to) == (mass, molar) {
+            self.value * factor
+        } else if (from, to) == (molar, mass) {
+            self.value / factor
+        } else {
+            return None;
+        };
+        Some(Measurement {
+            value,
+            units: Units::Known(to),
+        })
+    }
+
+    /// Whether the result is in `unit`, or has no units (which we assume means the usual units).
+    ///
+    /// Results in units we don't recognise are never in `unit`.
+    pub fn is_in(&self, unit: Unit) -> bool {
+        match self.units {
+            Units::Known(u) => u == unit,
+            Units::None => true,
+            Units::Unrecognised => false,
+        }
+    }
+}
+
+impl fmt::Display for Measurement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.units {
+            Units::Known(unit) => write!(f, "{} {}", self.value, unit),
+            Units::None => write!(f, "{}", self.value),
+            Units::Unrecognised => write!(f, "{} (unrecognised units)", self.value),
+        }
+    }
+}
+
+impl Event {
+    /// The event's result as a number, if it has one.
+    pub fn numeric_value(&self) -> Option<Measurement> {
+        Measurement::parse(self.code_value.as_deref()?, self.code_units.as_deref())
+    }
+}
+
+impl Events {
+    /// All events with a numeric result, along with the result.
+    pub fn numeric_values(&self) -> impl Iterator<Item = (&Event, Measurement)> + '_ {
+        self.els
+            .iter()
+            .filter_map(|evt| Some((evt, evt.numeric_value()?)))
+    }
+
+    /// How many events have each `code_units` string that [`Unit::parse`] doesn't recognise.
+    ///
+    /// Results in these units have [`Units::Unrecognised`], so can't be converted or compared with
+    /// other results, and are worth checking.
+    pub fn unmapped_units(&self) -> GroupCounts<ArcStr> {
+        GroupCounts::from_keys(
+            self.els
+                .iter()
+                .filter_map(|evt| evt.code_units.as_ref())
+                .filter(|units| !units.trim().is_empty() && Unit::parse(units).is_none())
+                .cloned(),
+        )
+    }
+
+    /// Replace each `code_units` spelling of a canonical unit (e.g. "MMOL/L") with the canonical
+    /// one ("mmol/L"), returning how many events were changed.
+    ///
+    /// Units that need the value scaling (e.g. "g" to "kg") are left alone, because
+    /// [`Event::numeric_value`] already converts them.
+    pub fn canonicalise_units(&mut self) -> usize {
+        let mut canonical: BTreeMap<Unit, ArcStr> = BTreeMap::new();
+        let mut changed = 0;
+        for evt in self.data.iter_mut() {
+            let Some(units) = evt.code_units.as_ref() else {
+                continue
+            };
+            let Some((unit, factor)) = Unit::parse(units) else {
+                continue
+            };
+            if factor != 1. || **units == *unit.as_str() {
+                continue;
+            }
+            let canonical = canonical
+                .entry(unit)
+                .or_insert_with(|| unit.as_str().into());
+            evt.code_units = Some(canonical.clone());
+            changed += 1;
+        }
+        changed
+    }
+
+    /// Numeric results for events with codes in `codeset`, grouped by patient and in date order.
+    pub fn measurements(
+        &self,
+        codeset: &CodeSet,
+    ) -> BTreeMap<PatientId, Vec<(NaiveDate, Measurement)>> {
+        let mut out: BTreeMap<PatientId, Vec<(NaiveDate, Measurement)>> = BTreeMap::new();
+        for (evt, value) in self
+            .numeric_values()
+            .filter(|(evt, _)| codeset.contains(evt.read_code))
+        {
+            out.entry(evt.patient_id)
+                .or_default()
+                .push((evt.date, value));
+        }
+        for values in out.values_mut() {
+            values.sort_by_key(|(date, _)| *date);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_event;
+
+    #[test]
+    fn parse() {
+        let m = Measurement::parse(" 5.2 ", Some("MMOL/L")).unwrap();
+        assert_eq!(m.value, 5.2);
+        assert_eq!(m.units, Units::Known(Unit::MmolPerL));
+        let m = Measurement::parse(">90", Some("mL/min/1.73m²")).unwrap();
+        assert_eq!(m.value, 90.);
+        assert_eq!(m.units, Units::Known(Unit::MlPerMinPer173M2));
+        let m = Measurement::parse("1650", Some("g")).unwrap();
+        assert!((m.value - 1.65).abs() < 1e-9);
+        assert_eq!(m.units, Units::Known(Unit::Kg));
+        let m = Measurement::parse("3", Some("furlongs")).unwrap();
+        assert_eq!(m.units, Units::Unrecognised);
+        assert!(!m.is_in(Unit::MmolPerL));
+        let m = Measurement::parse("3", Some(" ")).unwrap();
+        assert_eq!(m.units, Units::None);
+        assert!(m.is_in(Unit::MmolPerL));
+        assert!(Measurement::parse("positive", None).is_none());
+    }
+
+    #[test]
+    fn canonical_units() {
+        for unit in [
+            Unit::MmolPerL,
+            Unit::UmolPerL,
+            Unit::MlPerMinPer173M2,
+            Unit::KgPerM2,
+            Unit::MuPerL,
+            Unit::E9PerL,
+        ] {
+            assert_eq!(Unit::parse(unit.as_str()), Some((unit, 1.)));
+        }
+        assert_eq!(Unit::parse("x 10(9)/L"), Some((Unit::E9PerL, 1.)));
+
+        let date = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
+        let event = |units: &str| Event {
+            code_value: Some("5".into()),
+            code_units: Some(units.into()),
+            ..test_event(1, date, "44J3.")
+        };
+        let mut events = Events::new(
+            ["MMOL/L", "mmol/L", "mmol/l", "g", "furlongs", "furlongs"]
+                .into_iter()
+                .map(event)
+                .collect(),
+        );
+        assert_eq!(events.canonicalise_units(), 2);
+        let units = events
+            .iter_ref()
+            .map(|evt| evt.code_units.as_deref().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            units,
+            ["mmol/L", "mmol/L", "mmol/L", "g", "furlongs", "furlongs"]
+        );
+        let unmapped = events.unmapped_units();
+        assert_eq!(unmapped.get(&"furlongs".into()), 2);
+        assert_eq!(unmapped.len(), 1);
+    }
+
+    #[test]
+    fn convert() {
+        let m = Measurement::parse("200", Some("mg/dL")).unwrap();
+        assert!(m.convert(Unit::MmolPerL, None).is_none());
+        let mmol = m
+            .convert(Unit::MmolPerL, Some(Analyte::Cholesterol))
+            .unwrap();
+        assert!((mmol.value - 5.172).abs() < 1e-9);
+        let back = mmol
+            .convert(Unit::MgPerDl, Some(Analyte::Cholesterol))
+            .unwrap();
+        assert!((back.value - 200.).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,190 @@
+//! Blood pressure readings.
+//!
+//! Systolic and diastolic pressures are usually coded as separate events on the same day, so we
+//! pair them up. Some practices instead record both in a single event as e.g. `"120/80"`.
+use super::{Measurement, Unit};
+use crate::{
+    read2::{CodeSet, CodesetRegistry},
+    DataPaths, Events, PatientId, ReadCode,
+};
+use chrono::NaiveDate;
+use qu::ick_use::*;
+use std::collections::BTreeMap;
+
+/// Codes for systolic pressures, excluding targets and centiles.
+const SYSTOLIC: &[&str] = &[
+    "2469.", "246N.", "246Q.", "246S.", "246W.", "246Y.", "246b.", "246d.", "246e.", "246l.",
+    "246n1", "246o0",
+];
+/// Codes for diastolic pressures, excluding targets and centiles.
+const DIASTOLIC: &[&str] = &[
+    "246A.", "246P.", "246R.", "246T.", "246V.", "246X.", "246a.", "246c.", "246f.", "246m.",
+    "246n0", "246o1",
+];
+
+/// A systolic/diastolic pair, in mmHg.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BpReading {
+    pub date: NaiveDate,
+    pub systolic: f64,
+    pub diastolic: f64,
+}
+
+impl BpReading {
+    /// Whether either pressure is at or above the threshold.
+    pub fn is_above(&self, threshold: BpThreshold) -> bool {
+        self.systolic >= threshold.systolic || self.diastolic >= threshold.diastolic
+    }
+}
+
+/// A level above which blood pressure is considered raised.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BpThreshold {
+    pub systolic: f64,
+    pub diastolic: f64,
+}
+
+impl BpThreshold {
+    /// The NICE threshold for clinic readings (NG136).
+    pub const CLINIC: Self = Self {
+        systolic: 140.,
+        diastolic: 90.,
+    };
+}
+
+/// Which of the codes in the blood pressure termset mean what.
+#[derive(Debug, Clone)]
+pub struct BpCodes {
+    systolic: CodeSet,
+    diastolic: CodeSet,
+    /// Any other BP code, which might have a combined `"120/80"` value.
+    other: CodeSet,
+}
+
+impl BpCodes {
+    /// Split a blood pressure termset into systolic, diastolic and other codes.
+    pub fn from_termset(termset: &CodeSet) -> Self {
+        let parse = |codes: &[&str]| -> CodeSet {
+            codes
+                .iter()
+                .map(|code| ReadCode::from_str(code).unwrap())
+                .filter(|code| termset.contains(*code))
+                .collect()
+        };
+        let systolic = parse(SYSTOLIC);
+        let diastolic = parse(DIASTOLIC);
+        let other = termset
+            .iter()
+            .filter(|code| !systolic.contains(*code) && !diastolic.contains(*code))
+            .collect();
+        Self {
+            systolic,
+            diastolic,
+            other,
+        }
+    }
+
+    /// Use the `blood_pressure_measurement` termset.
+    pub fn load() -> Result<Self> {
+        let termset = CodesetRegistry::builtin()
+            .load_codeset("blood_pressure_measurement", &DataPaths::current())?;
+        Ok(Self::from_termset(&termset))
+    }
+}
+
+/// Blood pressure readings for each patient, in date order.
+///
+/// Where there is more than one reading on a day we use the lowest systolic and lowest diastolic,
+/// as is usual when a reading is repeated because the first was high. Days with only one of the
+/// pair are skipped.
+pub fn blood_pressure(events: &Events, codes: &BpCodes) -> BTreeMap<PatientId, Vec<BpReading>> {
+    // patient -> date -> (lowest systolic, lowest diastolic)
+    let mut days: BTreeMap<PatientId, BTreeMap<NaiveDate, (Option<f64>, Option<f64>)>> =
+        BTreeMap::new();
+    for evt in events.els.iter() {
+        let (systolic, diastolic) = if codes.systolic.contains(evt.read_code) {
+            (plausible(evt.numeric_value()), None)
+        } else if codes.diastolic.contains(evt.read_code) {
+            (None, plausible(evt.numeric_value()))
+        } else if codes.other.contains(evt.read_code) {
+            match evt.code_value.as_deref().and_then(parse_combined) {
+                Some((s, d)) => (Some(s), Some(d)),
+                None => continue,
+            }
+        } else {
+            continue;
+        };
+        let day = days
+            .entry(evt.patient_id)
+            .or_default()
+            .entry(evt.date)
+            .or_default();
+        day.0 = min(day.0, systolic);
+        day.1 = min(day.1, diastolic);
+    }
+
+    days.into_iter()
+        .map(|(patient_id, days)| {
+            let readings = days
+                .into_iter()
+                .filter_map(|(date, pair)| match pair {
+                    (Some(systolic), Some(diastolic)) => Some(BpReading {
+                        date,
+                        systolic,
+                        diastolic,
+                    }),
+                    _ => None,
+                })
+                .collect();
+            (patient_id, readings)
+        })
+        .collect()
+}
+
+/// The most recent reading on or before `date`.
+pub fn latest_before(readings: &[BpReading], date: NaiveDate) -> Option<BpReading> {
+    readings
+        .iter()
+        .filter(|reading| reading.date <= date)
+        .max_by_key(|reading| reading.date)
+        .copied()
+}
+
+/// Plausible values only, in mmHg.
+fn plausible(m: Option<Measurement>) -> Option<f64> {
+    let m = m?;
+    if m.is_in(Unit::MmHg) && m.value > 20. && m.value < 300. {
+        Some(m.value)
+    } else {
+        None
+    }
+}
+
+fn min(a: Option<f64>, b: Option<f64>) -> Option<f64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+/// Parse `"120/80"`.
+fn parse_combined(value: &str) -> Option<(f64, f64)> {
+    let (systolic, diastolic) = value.split_once('/')?;
+    let systolic = plausible(Measurement::parse(systolic, None))?;
+    let diastolic = plausible(Measurement::parse(diastolic, None))?;
+    Some((systolic, diastolic))
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_combined;
+
+    #[test]
+    fn combined() {
+        assert_eq!(parse_combined("120/80"), Some((120., 80.)));
+        assert_eq!(parse_combined(" 135 / 85 "), Some((135., 85.)));
+        assert_eq!(parse_combined("120"), None);
+        assert_eq!(parse_combined("1/2"), None);
+    }
+}
@@ -0,0 +1,243 @@
+//! Height, weight and body mass index.
+//!
+//! These are recorded with plenty of typos (heights in metres with no units, weights in stones,
+//! etc.) so we drop any value outside the range that could plausibly be right for an adult.
+use super::{Unit, Units};
+use crate::{read2::CodeSet, Event, Events, PatientId, ReadCode};
+use chrono::NaiveDate;
+use once_cell::sync::Lazy;
+use std::{collections::BTreeMap, fmt, ops::RangeInclusive};
+
+/// Height codes that carry a value.
+const HEIGHT: &[&str] = &["229.."];
+/// Weight codes that carry a value, excluding e.g. weight loss.
+const WEIGHT: &[&str] = &["22A.."];
+/// BMI codes that carry a value, excluding targets and centiles.
+const BMI: &[&str] = &[
+    "22K..", "22K1.", "22K2.", "22K3.", "22K4.", "22K5.", "22K6.", "22K7.", "22K8.", "22KB.",
+    "22KC.", "22KD.", "22KE.",
+];
+
+/// [`HEIGHT`], [`WEIGHT`] and [`BMI`], parsed once.
+static CODES: Lazy<[CodeSet; 3]> = Lazy::new(|| {
+    [HEIGHT, WEIGHT, BMI].map(|codes| {
+        codes
+            .iter()
+            .map(|code| ReadCode::from_str(code).unwrap())
+            .collect()
+    })
+});
+
+/// The body size measurements we extract.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BodyMeasure {
+    /// In cm.
+    Height,
+    /// In kg.
+    Weight,
+    /// In kg/m².
+    Bmi,
+}
+
+impl BodyMeasure {
+    fn codes(self) -> &'static CodeSet {
+        match self {
+            BodyMeasure::Height => &CODES[0],
+            BodyMeasure::Weight => &CODES[1],
+            BodyMeasure::Bmi => &CODES[2],
+        }
+    }
+
+    pub fn unit(self) -> Unit {
+        match self {
+            BodyMeasure::Height => Unit::Cm,
+            BodyMeasure::Weight => Unit::Kg,
+            BodyMeasure::Bmi => Unit::KgPerM2,
+        }
+    }
+
+    /// Values outside this range are taken to be errors.
+    pub fn plausible_range(self) -> RangeInclusive<f64> {
+        match self {
+            BodyMeasure::Height => 120. ..=220.,
+            BodyMeasure::Weight => 30. ..=250.,
+            BodyMeasure::Bmi => 12. ..=80.,
+        }
+    }
+
+    fn matches(self, code: ReadCode) -> bool {
+        self.codes().contains(code)
+    }
+
+    /// The plausible value of an event, in [`Self::unit`].
+    pub fn value(self, evt: &Event) -> Option<f64> {
+        if !self.matches(evt.read_code) {
+            return None;
+        }
+        let m = evt.numeric_value()?;
+        let value = match m.units {
+            Units::Known(unit) if unit == self.unit() => m.value,
+            Units::Known(_) | Units::Unrecognised => return None,
+            // Heights are often recorded in metres with no units.
+            Units::None if self == BodyMeasure::Height && m.value < 3. => m.value * 100.,
+            Units::None => m.value,
+        };
+        Some(value).filter(|v| self.plausible_range().contains(v))
+    }
+}
+
+/// All plausible values of `measure` for each patient, in date order.
+pub fn body_measures(
+    events: &Events,
+    measure: BodyMeasure,
+) -> BTreeMap<PatientId, Vec<(NaiveDate, f64)>> {
+    let mut out: BTreeMap<PatientId, Vec<(NaiveDate, f64)>> = BTreeMap::new();
+    for evt in events.els.iter() {
+        if let Some(value) = measure.value(evt) {
+            out.entry(evt.patient_id)
+                .or_default()
+                .push((evt.date, value));
+        }
+    }
+    for values in out.values_mut() {
+        values.sort_by_key(|(date, _)| *date);
+    }
+    out
+}
+
+/// Each patient's BMI at `date`.
+///
+/// This is the most recent recorded BMI on or before `date`, unless there is a more recent weight,
+/// in which case we calculate BMI from that weight and the most recent height. Patients with
+/// neither are left out.
+pub fn bmi_at(events: &Events, date: NaiveDate) -> BTreeMap<PatientId, f64> {
+    let bmis = body_measures(events, BodyMeasure::Bmi);
+    let weights = body_measures(events, BodyMeasure::Weight);
+    let heights = body_measures(events, BodyMeasure::Height);
+
+    let mut patient_ids: Vec<PatientId> = bmis.keys().chain(weights.keys()).copied().collect();
+    patient_ids.sort();
+    patient_ids.dedup();
+
+    let latest = |map: &BTreeMap<PatientId, Vec<(NaiveDate, f64)>>, id: PatientId| {
+        map.get(&id)?
+            .iter()
+            .rev()
+            .find(|(d, _)| *d <= date)
+            .copied()
+    };
+    patient_ids
+        .into_iter()
+        .filter_map(|id| {
+            let recorded = latest(&bmis, id);
+            let calculated = latest(&weights, id).and_then(|(d, weight)| {
+                let (_, height) = latest(&heights, id)?;
+                Some((d, calculate_bmi(weight, height)))
+            });
+            let bmi = match (recorded, calculated) {
+                (Some(r), Some(c)) if c.0 > r.0 => c.1,
+                (Some(r), _) => r.1,
+                (None, Some(c)) => c.1,
+                (None, None) => return None,
+            };
+            Some((id, bmi)).filter(|(_, bmi)| BodyMeasure::Bmi.plausible_range().contains(bmi))
+        })
+        .collect()
+}
+
+/// BMI from weight in kg and height in cm.
+pub fn calculate_bmi(weight: f64, height: f64) -> f64 {
+    let height = height / 100.;
+    weight / (height * height)
+}
+
+/// Adult BMI categories (NICE CG189).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BmiCategory {
+    /// Below 18.5.
+    Underweight,
+    /// 18.5 to 24.9.
+    Healthy,
+    /// 25 to 29.9.
+    Overweight,
+    /// 30 to 39.9.
+    Obese,
+    /// 40 or above.
+    SeverelyObese,
+}
+
+impl BmiCategory {
+    pub const ALL: [BmiCategory; 5] = [
+        BmiCategory::Underweight,
+        BmiCategory::Healthy,
+        BmiCategory::Overweight,
+        BmiCategory::Obese,
+        BmiCategory::SeverelyObese,
+    ];
+
+    pub fn from_bmi(bmi: f64) -> Self {
+        if bmi < 18.5 {
+            BmiCategory::Underweight
+        } else if bmi < 25. {
+            BmiCategory::Healthy
+        } else if bmi < 30. {
+            BmiCategory::Overweight
+        } else if bmi < 40. {
+            BmiCategory::Obese
+        } else {
+            BmiCategory::SeverelyObese
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            BmiCategory::Underweight => "underweight (<18.5)",
+            BmiCategory::Healthy => "healthy (18.5 - 25)",
+            BmiCategory::Overweight => "overweight (25 - 30)",
+            BmiCategory::Obese => "obese (30 - 40)",
+            BmiCategory::SeverelyObese => "severely obese (40+)",
+        }
+    }
+}
+
+impl fmt::Display for BmiCategory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_event;
+    use std::sync::Arc;
+
+    fn event(code: &str, value: &str, units: Option<&str>) -> Event {
+        Event {
+            code_value: Some(Arc::from(value)),
+            code_units: units.map(Arc::from),
+            ..test_event(1, NaiveDate::from_ymd_opt(2020, 1, 1).unwrap(), code)
+        }
+    }
+
+    #[test]
+    fn values() {
+        let height = BodyMeasure::Height;
+        assert_eq!(height.value(&event("229..", "1.8", None)), Some(180.));
+        assert_eq!(height.value(&event("229..", "1.8", Some("m"))), Some(180.));
+        assert_eq!(height.value(&event("229..", "180", Some("cm"))), Some(180.));
+        assert_eq!(height.value(&event("229..", "18", Some("cm"))), None);
+        assert_eq!(height.value(&event("22A..", "80", None)), None);
+        let weight = BodyMeasure::Weight;
+        assert_eq!(weight.value(&event("22A..", "80", Some("kg"))), Some(80.));
+        assert_eq!(weight.value(&event("22A..", "80", Some("cm"))), None);
+        assert_eq!(BodyMeasure::Bmi.value(&event("22K9.", "50", None)), None);
+    }
+
+    #[test]
+    fn categories() {
+        assert!((calculate_bmi(80., 180.) - 24.69).abs() < 0.01);
+        assert_eq!(BmiCategory::from_bmi(24.99), BmiCategory::Healthy);
+        assert_eq!(BmiCategory::from_bmi(25.), BmiCategory::Overweight);
+    }
+}
@@ -0,0 +1,144 @@
+//! Chronic kidney disease staging from eGFR results.
+//!
+//! Following NICE guidance (NG203), CKD is only diagnosed when eGFR has been below 60 for at least
+//! 3 months, so a single low reading (e.g. during an acute illness) doesn't count.
+use super::{Measurement, Unit};
+use chrono::{Duration, NaiveDate};
+use std::fmt;
+
+/// How long eGFR must stay low to count as CKD.
+const MIN_DURATION_DAYS: i64 = 90;
+
+/// GFR categories from the KDIGO classification.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CkdStage {
+    /// eGFR 90 or above.
+    G1,
+    /// eGFR 60-89.
+    G2,
+    /// eGFR 45-59.
+    G3a,
+    /// eGFR 30-44.
+    G3b,
+    /// eGFR 15-29.
+    G4,
+    /// eGFR below 15.
+    G5,
+}
+
+impl CkdStage {
+    /// The category of a single eGFR value.
+    pub fn from_egfr(egfr: f64) -> Self {
+        if egfr >= 90. {
+            CkdStage::G1
+        } else if egfr >= 60. {
+            CkdStage::G2
+        } else if egfr >= 45. {
+            CkdStage::G3a
+        } else if egfr >= 30. {
+            CkdStage::G3b
+        } else if egfr >= 15. {
+            CkdStage::G4
+        } else {
+            CkdStage::G5
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            CkdStage::G1 => "G1",
+            CkdStage::G2 => "G2",
+            CkdStage::G3a => "G3a",
+            CkdStage::G3b => "G3b",
+            CkdStage::G4 => "G4",
+            CkdStage::G5 => "G5",
+        }
+    }
+}
+
+impl fmt::Display for CkdStage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// The CKD stage at `date`, or `None` if the patient didn't have CKD.
+///
+/// We look at the run of eGFR results below 60 leading up to the most recent result on or before
+/// `date`. If that run covers at least 90 days the patient has CKD, staged using the best result in
+/// the final 90 days of the run (so one bad reading doesn't overstate the stage). A normal result
+/// after low ones ends the run.
+///
+/// Results in units other than mL/min/1.73m² are ignored. Results with no units are assumed to be
+/// in mL/min/1.73m².
+pub fn ckd_stage(readings: &[(NaiveDate, Measurement)], date: NaiveDate) -> Option<CkdStage> {
+    let mut readings: Vec<(NaiveDate, f64)> = readings
+        .iter()
+        .filter(|(d, m)| *d <= date && m.is_in(Unit::MlPerMinPer173M2))
+        .map(|(d, m)| (*d, m.value))
+        .collect();
+    readings.sort_by_key(|(d, _)| *d);
+
+    // the run of low readings at the end
+    let run_start = readings
+        .iter()
+        .rposition(|(_, egfr)| *egfr >= 60.)
+        .map(|idx| idx + 1)
+        .unwrap_or(0);
+    let run = &readings[run_start..];
+    let (first, _) = *run.first()?;
+    let (last, _) = *run.last()?;
+    if last - first < Duration::days(MIN_DURATION_DAYS) {
+        return None;
+    }
+    let best = run
+        .iter()
+        .filter(|(d, _)| last - *d <= Duration::days(MIN_DURATION_DAYS))
+        .map(|(_, egfr)| *egfr)
+        .fold(f64::NEG_INFINITY, f64::max);
+    Some(CkdStage::from_egfr(best))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::measurements::Units;
+
+    fn reading(y: i32, m: u32, d: u32, egfr: f64) -> (NaiveDate, Measurement) {
+        (
+            NaiveDate::from_ymd_opt(y, m, d).unwrap(),
+            Measurement {
+                value: egfr,
+                units: Units::None,
+            },
+        )
+    }
+
+    #[test]
+    fn staging() {
+        let end = NaiveDate::from_ymd_opt(2021, 1, 1).unwrap();
+        // two low readings too close together
+        let readings = [reading(2020, 1, 1, 50.), reading(2020, 2, 1, 40.)];
+        assert_eq!(ckd_stage(&readings, end), None);
+        // ...and far enough apart
+        let readings = [reading(2020, 1, 1, 50.), reading(2020, 6, 1, 40.)];
+        assert_eq!(ckd_stage(&readings, end), Some(CkdStage::G3b));
+        // recovered
+        let readings = [
+            reading(2020, 1, 1, 50.),
+            reading(2020, 6, 1, 40.),
+            reading(2020, 7, 1, 70.),
+        ];
+        assert_eq!(ckd_stage(&readings, end), None);
+        // the best result in the last 90 days is used
+        let readings = [
+            reading(2020, 1, 1, 50.),
+            reading(2020, 5, 1, 25.),
+            reading(2020, 6, 1, 40.),
+        ];
+        assert_eq!(ckd_stage(&readings, end), Some(CkdStage::G3b));
+        // readings after `date` are ignored
+        let readings = [reading(2020, 1, 1, 50.), reading(2021, 6, 1, 40.)];
+        assert_eq!(ckd_stage(&readings, end), None);
+    }
+}
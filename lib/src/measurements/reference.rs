@@ -0,0 +1,254 @@
+//! Flagging numeric results as low, normal or high against reference ranges.
+//!
+//! The ranges are read from a TOML file, with a list of `[[range]]` tables. The ranges we use by
+//! default are in `measurements/reference_ranges.toml`, and look like
+//!
+//! ```toml
+//! [[range]]
+//! name = "Haemoglobin (female)"
+//! codes = ["423.."]
+//! unit = "g/L"
+//! sex = "F"
+//! min_age = 18
+//! low = 120
+//! high = 150
+//! ```
+use super::{Unit, Units};
+use crate::{read2::ReadCode, Event, Patient, Sex};
+use qu::ick_use::*;
+use serde::{Deserialize, Deserializer};
+use std::{fmt, fs, path::Path};
+
+const DEFAULT_RANGES: &str = include_str!("reference_ranges.toml");
+
+/// Where a result falls compared with its reference range.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Flag {
+    Low,
+    Normal,
+    High,
+}
+
+impl Flag {
+    pub const ALL: [Flag; 3] = [Flag::Low, Flag::Normal, Flag::High];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Flag::Low => "Low",
+            Flag::Normal => "Normal",
+            Flag::High => "High",
+        }
+    }
+
+    pub fn is_abnormal(self) -> bool {
+        self != Flag::Normal
+    }
+}
+
+impl fmt::Display for Flag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// The normal range of results for some codes, for some patients.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReferenceRange {
+    pub name: String,
+    /// The codes the results are recorded with.
+    pub codes: Vec<ReadCode>,
+    /// The units of `low` and `high`.
+    #[serde(deserialize_with = "unit")]
+    pub unit: Unit,
+    /// Only for patients of this sex, if set.
+    #[serde(default)]
+    pub sex: Option<Sex>,
+    /// The youngest age (in whole years) the range is for.
+    #[serde(default)]
+    pub min_age: Option<i32>,
+    /// The oldest age (in whole years) the range is for.
+    #[serde(default)]
+    pub max_age: Option<i32>,
+    /// Results below this are low.
+    #[serde(default)]
+    pub low: Option<f64>,
+    /// Results above this are high.
+    #[serde(default)]
+    pub high: Option<f64>,
+}
+
+impl ReferenceRange {
+    /// Whether the range is for results with `code` for a patient of `sex` aged `age`.
+    pub fn applies(&self, code: ReadCode, sex: Sex, age: i32) -> bool {
+        self.codes.contains(&code)
+            && self.sex.map(|s| s == sex).unwrap_or(true)
+            && self.min_age.map(|min| age >= min).unwrap_or(true)
+            && self.max_age.map(|max| age <= max).unwrap_or(true)
+    }
+
+    /// Flag `value`, which must be in [`ReferenceRange::unit`].
+    pub fn flag(&self, value: f64) -> Flag {
+        if self.low.map(|low| value < low).unwrap_or(false) {
+            Flag::Low
+        } else if self.high.map(|high| value > high).unwrap_or(false) {
+            Flag::High
+        } else {
+            Flag::Normal
+        }
+    }
+}
+
+/// A list of [`ReferenceRange`]s.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReferenceRanges {
+    #[serde(rename = "range")]
+    pub ranges: Vec<ReferenceRange>,
+}
+
+impl ReferenceRanges {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        fn inner(path: &Path) -> Result<ReferenceRanges> {
+            ReferenceRanges::parse(&fs::read_to_string(path)?)
+        }
+        let path = path.as_ref();
+        inner(path).with_context(|| format!("while loading \"{}\"", path.display()))
+    }
+
+    /// The ranges in `measurements/reference_ranges.toml`.
+    pub fn builtin() -> Self {
+        Self::parse(DEFAULT_RANGES).expect("built-in reference ranges are invalid")
+    }
+
+    fn parse(input: &str) -> Result<Self> {
+        let ranges: Self = toml::from_str(input)?;
+        for range in ranges.ranges.iter() {
+            ensure!(
+                !range.codes.is_empty(),
+                "reference range \"{}\" has no codes",
+                range.name
+            );
+            ensure!(
+                range.low.is_some() || range.high.is_some(),
+                "reference range \"{}\" needs a low or high limit",
+                range.name
+            );
+            if let (Some(low), Some(high)) = (range.low, range.high) {
+                ensure!(
+                    low <= high,
+                    "reference range \"{}\" has its low limit above its high limit",
+                    range.name
+                );
+            }
+        }
+        Ok(ranges)
+    }
+
+    /// The first range for results with `code` for a patient of `sex` aged `age`.
+    pub fn find(&self, code: ReadCode, sex: Sex, age: i32) -> Option<&ReferenceRange> {
+        self.ranges
+            .iter()
+            .find(|range| range.applies(code, sex, age))
+    }
+}
+
+/// Parse units with [`Unit::parse`], only allowing units that don't need the value converting.
+fn unit<'de, D>(d: D) -> Result<Unit, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let raw = String::deserialize(d)?;
+    match Unit::parse(&raw) {
+        Some((unit, 1.)) => Ok(unit),
+        _ => Err(serde::de::Error::custom(format!(
+            "unrecognised unit \"{raw}\""
+        ))),
+    }
+}
+
+impl Event {
+    /// Flag the event's result against the first range in `ranges` that applies to it and
+    /// `patient` (whose event it must be).
+    ///
+    /// Returns `None` if the event has no numeric result or date, no range applies, or the result
+    /// is in units that we don't recognise or can't convert to the range's. Results without units
+    /// are assumed to be in the range's units.
+    pub fn flag(&self, ranges: &ReferenceRanges, patient: &Patient) -> Option<Flag> {
+        debug_assert_eq!(self.patient_id, patient.patient_id);
+        let age = patient.age_at(self.date_known()?);
+        let range = ranges.find(self.read_code, patient.sex, age)?;
+        let measurement = self.numeric_value()?;
+        let value = match measurement.units {
+            Units::Known(_) => measurement.convert(range.unit, None)?.value,
+            Units::None => measurement.value,
+            Units::Unrecognised => return None,
+        };
+        Some(range.flag(value))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_event;
+
+    #[test]
+    fn builtin() {
+        let ranges = ReferenceRanges::builtin();
+        let hb = "423..".parse().unwrap();
+        let female = ranges.find(hb, Sex::Female, 40).unwrap();
+        assert_eq!(female.name, "Haemoglobin (female)");
+        assert_eq!(female.flag(115.), Flag::Low);
+        assert_eq!(female.flag(120.), Flag::Normal);
+        assert_eq!(
+            ranges.find(hb, Sex::Male, 40).unwrap().flag(160.),
+            Flag::Normal
+        );
+        assert!(ranges.find(hb, Sex::Male, 12).is_none());
+
+        let cholesterol = ranges
+            .find("44P..".parse().unwrap(), Sex::Male, 60)
+            .unwrap();
+        assert_eq!(cholesterol.flag(0.5), Flag::Normal);
+        assert_eq!(cholesterol.flag(6.2), Flag::High);
+    }
+
+    #[test]
+    fn flag_event() {
+        use crate::{Imd, PatientId};
+        use chrono::NaiveDate;
+        let patient = Patient {
+            patient_id: PatientId::new(1),
+            year_of_birth: 1970,
+            month_of_birth: None,
+            sex: Sex::Female,
+            ethnicity: None,
+            lsoa: None,
+            imd: Imd::Missing,
+            charlson: 0.,
+            lymphoma_diagnosis_date: None,
+            lymphoma_diagnosis_confidence: None,
+            lymphoma_subtypes: Default::default(),
+        };
+        let date = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
+        let event = |units: Option<&str>| Event {
+            code_value: Some("110".into()),
+            code_units: units.map(Into::into),
+            ..test_event(1, date, "423..")
+        };
+        let ranges = ReferenceRanges::builtin();
+        assert_eq!(event(Some("g/L")).flag(&ranges, &patient), Some(Flag::Low));
+        assert_eq!(event(None).flag(&ranges, &patient), Some(Flag::Low));
+        assert_eq!(event(Some(" ")).flag(&ranges, &patient), Some(Flag::Low));
+        assert_eq!(event(Some("furlongs")).flag(&ranges, &patient), None);
+    }
+
+    #[test]
+    fn invalid() {
+        let input = "[[range]]\nname = \"x\"\ncodes = [\"423..\"]\nunit = \"g/L\"\n";
+        assert!(ReferenceRanges::parse(input).is_err());
+        let input = format!("{input}low = 2\nhigh = 1\n");
+        assert!(ReferenceRanges::parse(&input).is_err());
+        let input = "[[range]]\nname = \"x\"\ncodes = [\"423..\"]\nunit = \"g\"\nlow = 1\n";
+        assert!(ReferenceRanges::parse(input).is_err());
+    }
+}
@@ -0,0 +1,257 @@
+//! Holistic needs assessments (eHNA), from the study questionnaires.
+//!
+//! Each row is one completed questionnaire. Patients score how much each area of their life
+//! concerns them from 0 (not at all) to 10 (a great deal), and can list specific concerns.
+use crate::{
+    envelope::Schema, join::Join, load_orig, util::optional_string, Adapt, Adapts, ArcStr, Dataset,
+    Error, Patient, PatientId, Patients, Record, Result,
+};
+use chrono::NaiveDate;
+use qu::ick_use::*;
+use serde::{Deserialize, Serialize};
+use std::{cmp::Ordering, collections::BTreeMap, fmt, ops::Deref, path::Path};
+
+/// The highest score for a domain.
+const MAX_SCORE: u8 = 10;
+
+#[derive(Debug, Deserialize)]
+struct NeedsAssessmentRaw {
+    #[serde(rename = "PatID")]
+    patient_id: PatientId,
+    #[serde(rename = "CompletedDate")]
+    completed_date: NaiveDate,
+    #[serde(rename = "Physical")]
+    physical: Option<u8>,
+    #[serde(rename = "Emotional")]
+    emotional: Option<u8>,
+    #[serde(rename = "Practical")]
+    practical: Option<u8>,
+    #[serde(rename = "Family")]
+    family: Option<u8>,
+    #[serde(rename = "Spiritual")]
+    spiritual: Option<u8>,
+    #[serde(rename = "Lifestyle")]
+    lifestyle: Option<u8>,
+    /// Concerns separated by `;`.
+    #[serde(rename = "Concerns", deserialize_with = "optional_string")]
+    concerns: Option<ArcStr>,
+}
+
+/// The areas of concern on the questionnaire.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum NeedsDomain {
+    Physical,
+    Emotional,
+    Practical,
+    Family,
+    Spiritual,
+    Lifestyle,
+}
+
+impl NeedsDomain {
+    pub const ALL: [NeedsDomain; 6] = [
+        NeedsDomain::Physical,
+        NeedsDomain::Emotional,
+        NeedsDomain::Practical,
+        NeedsDomain::Family,
+        NeedsDomain::Spiritual,
+        NeedsDomain::Lifestyle,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            NeedsDomain::Physical => "Physical",
+            NeedsDomain::Emotional => "Emotional",
+            NeedsDomain::Practical => "Practical",
+            NeedsDomain::Family => "Family and relationships",
+            NeedsDomain::Spiritual => "Spiritual",
+            NeedsDomain::Lifestyle => "Lifestyle and information",
+        }
+    }
+}
+
+impl fmt::Display for NeedsDomain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// A row in the needs dataset.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NeedsAssessment {
+    pub patient_id: PatientId,
+    pub completed_date: NaiveDate,
+    /// The score for each domain the patient answered.
+    pub scores: BTreeMap<NeedsDomain, u8>,
+    pub concerns: Vec<ArcStr>,
+}
+
+impl Schema for NeedsAssessment {
+    const SCHEMA: &'static str = "NeedsAssessment { patient_id: u64, completed_date: NaiveDate, \
+        scores: Map<NeedsDomain, u8>, concerns: Vec<str> }";
+}
+
+impl TryFrom<NeedsAssessmentRaw> for NeedsAssessment {
+    type Error = Error;
+
+    fn try_from(raw: NeedsAssessmentRaw) -> Result<Self> {
+        let scores = [
+            (NeedsDomain::Physical, raw.physical),
+            (NeedsDomain::Emotional, raw.emotional),
+            (NeedsDomain::Practical, raw.practical),
+            (NeedsDomain::Family, raw.family),
+            (NeedsDomain::Spiritual, raw.spiritual),
+            (NeedsDomain::Lifestyle, raw.lifestyle),
+        ]
+        .into_iter()
+        .filter_map(|(domain, score)| Some((domain, score?)))
+        .collect::<BTreeMap<_, _>>();
+        for (domain, score) in scores.iter() {
+            ensure!(
+                *score <= MAX_SCORE,
+                "{} score of {} for patient {} is more than {}",
+                domain,
+                score,
+                raw.patient_id,
+                MAX_SCORE
+            );
+        }
+        let concerns = match &raw.concerns {
+            Some(concerns) => concerns
+                .split(';')
+                .map(str::trim)
+                .filter(|concern| !concern.is_empty())
+                .map(ArcStr::from)
+                .collect(),
+            None => vec![],
+        };
+        Ok(NeedsAssessment {
+            patient_id: raw.patient_id,
+            completed_date: raw.completed_date,
+            scores,
+            concerns,
+        })
+    }
+}
+
+impl NeedsAssessment {
+    pub fn score(&self, domain: NeedsDomain) -> Option<u8> {
+        self.scores.get(&domain).copied()
+    }
+}
+
+impl Record for NeedsAssessment {
+    type Key = PatientId;
+    const DATASET: &'static str = "needs";
+    fn key(&self) -> PatientId {
+        self.patient_id
+    }
+    fn cmp_within_key(&self, other: &Self) -> Ordering {
+        self.completed_date.cmp(&other.completed_date)
+    }
+}
+
+/// The parsed list of needs assessments, with a pre-built index for the `id` field.
+pub struct Needs(Dataset<NeedsAssessment>);
+
+impl Needs {
+    pub fn load_orig(path: impl AsRef<Path>) -> Result<Self> {
+        let els: Vec<NeedsAssessmentRaw> = load_orig(path)?;
+        let els = els
+            .into_iter()
+            .map(TryFrom::try_from)
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Needs(Dataset::new(els)))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Needs(Dataset::load(path)?))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result {
+        self.0.save(path)
+    }
+
+    /// A patient's assessments, earliest first.
+    pub fn for_patient(
+        &self,
+        patient_id: PatientId,
+    ) -> impl Iterator<Item = &NeedsAssessment> + Clone + '_ {
+        self.0.get(patient_id)
+    }
+
+    /// A patient's most recent assessment.
+    pub fn latest_for_patient(&self, patient_id: PatientId) -> Option<&NeedsAssessment> {
+        self.for_patient(patient_id).last()
+    }
+}
+
+impl Deref for Needs {
+    type Target = Dataset<NeedsAssessment>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// A patient with their needs assessments, earliest first.
+#[derive(Debug, Clone)]
+pub struct PatientNeeds<'a> {
+    pub patient: &'a Patient,
+    pub needs: Vec<&'a NeedsAssessment>,
+}
+
+/// An ADAPT record with the patient's needs assessments, earliest first.
+#[derive(Debug, Clone)]
+pub struct AdaptNeeds<'a> {
+    pub adapt: &'a Adapt,
+    pub needs: Vec<&'a NeedsAssessment>,
+}
+
+impl Patients {
+    /// Link each patient to their needs assessments.
+    ///
+    /// Patients who haven't completed an assessment are in `left_unmatched`.
+    pub fn join_needs<'a>(&'a self, needs: &'a Needs) -> Join<PatientId, PatientNeeds<'a>> {
+        self.0
+            .join_grouped(needs)
+            .map(|(patient, needs)| PatientNeeds { patient, needs })
+    }
+}
+
+impl Adapts {
+    /// Link each ADAPT record to the patient's needs assessments.
+    ///
+    /// ADAPTed patients who haven't completed an assessment are in `left_unmatched`.
+    pub fn join_needs<'a>(&'a self, needs: &'a Needs) -> Join<PatientId, AdaptNeeds<'a>> {
+        self.0
+            .join_grouped(needs)
+            .map(|(adapt, needs)| AdaptNeeds { adapt, needs })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn from_raw() {
+        let raw = |physical, concerns: &str| NeedsAssessmentRaw {
+            patient_id: PatientId::new(1),
+            completed_date: NaiveDate::from_ymd_opt(2020, 1, 1).unwrap(),
+            physical,
+            emotional: Some(3),
+            practical: None,
+            family: None,
+            spiritual: None,
+            lifestyle: None,
+            concerns: Some(concerns.into()),
+        };
+        let needs = NeedsAssessment::try_from(raw(Some(7), "Fatigue; Money worries;")).unwrap();
+        assert_eq!(needs.score(NeedsDomain::Physical), Some(7));
+        assert_eq!(needs.score(NeedsDomain::Practical), None);
+        assert_eq!(needs.scores.len(), 2);
+        assert_eq!(needs.concerns.len(), 2);
+        assert_eq!(&*needs.concerns[1], "Money worries");
+        assert!(NeedsAssessment::try_from(raw(Some(11), "")).is_err());
+    }
+}
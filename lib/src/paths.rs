@@ -0,0 +1,182 @@
+//! Where our data lives on disk.
+//!
+//! The root data directory is taken from (in order)
+//!
+//!  1. an explicit path (e.g. the `--data-dir` command line option),
+//!  2. the `EADAPT_DATA_DIR` environment variable,
+//!  3. `data_dir` in the config file,
+//!  4. `../data` (for running from the `lib` directory of this repository).
+//!
+//! The config file is read from `$EADAPT_CONFIG` if set, otherwise `eadapt.toml` in the current
+//! directory, otherwise `$HOME/.config/eadapt/config.toml`. It can also move individual
+//! subdirectories, for example to write output somewhere other than the (read-only) data folder:
+//!
+//! ```toml
+//! data_dir = "/secure/eadapt/data"
+//! output = "/scratch/eadapt/output"
+//! ```
+//!
+//! Relative paths in the config file are relative to the directory containing the file.
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use qu::ick_use::*;
+use serde::Deserialize;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+const DATA_DIR_VAR: &str = "EADAPT_DATA_DIR";
+const CONFIG_VAR: &str = "EADAPT_CONFIG";
+const DEFAULT_DATA_DIR: &str = "../data";
+
+static CURRENT: Lazy<RwLock<DataPaths>> = Lazy::new(|| {
+    RwLock::new(DataPaths::resolve(None).unwrap_or_else(|e| {
+        event!(
+            Level::WARN,
+            "could not resolve data paths, using \"{}\": {:?}",
+            DEFAULT_DATA_DIR,
+            e
+        );
+        DataPaths::new(DEFAULT_DATA_DIR)
+    }))
+});
+
+/// The locations of all the data we read and write.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataPaths {
+    /// The root data directory.
+    pub root: PathBuf,
+    /// The original data extract.
+    pub sir_data: PathBuf,
+    /// Where we write processed data.
+    pub output: PathBuf,
+    /// Our termsets (one directory per termset).
+    pub termsets: PathBuf,
+    /// Code sets from the Cambridge multimorbidity study.
+    pub camb_codesets: PathBuf,
+    /// The Read v2 thesaurus files.
+    pub read_db: PathBuf,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    data_dir: Option<PathBuf>,
+    sir_data: Option<PathBuf>,
+    output: Option<PathBuf>,
+    termsets: Option<PathBuf>,
+    camb_codesets: Option<PathBuf>,
+    read_db: Option<PathBuf>,
+}
+
+impl DataPaths {
+    /// Use the standard layout under `root`.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        Self {
+            sir_data: root.join("sir_data"),
+            output: root.join("output"),
+            termsets: root.join("termsets"),
+            camb_codesets: root.join("camb_codesets"),
+            read_db: root.join("read_db"),
+            root,
+        }
+    }
+
+    /// Work out the data paths from an explicit root, the environment, and the config file (see
+    /// module docs).
+    pub fn resolve(explicit: Option<&Path>) -> Result<Self> {
+        let config = match config_file_path() {
+            Some(path) => Some(
+                load_config(&path)
+                    .with_context(|| format!("reading config file \"{}\"", path.display()))?,
+            ),
+            None => None,
+        };
+        let (config_dir, config) = config.unwrap_or_default();
+
+        let root = if let Some(root) = explicit {
+            root.to_owned()
+        } else if let Some(root) = env::var_os(DATA_DIR_VAR) {
+            PathBuf::from(root)
+        } else if let Some(root) = &config.data_dir {
+            config_dir.join(root)
+        } else {
+            PathBuf::from(DEFAULT_DATA_DIR)
+        };
+
+        let mut paths = Self::new(root);
+        let overrides = [
+            (&mut paths.sir_data, config.sir_data),
+            (&mut paths.output, config.output),
+            (&mut paths.termsets, config.termsets),
+            (&mut paths.camb_codesets, config.camb_codesets),
+            (&mut paths.read_db, config.read_db),
+        ];
+        for (path, over) in overrides {
+            if let Some(over) = over {
+                *path = config_dir.join(over);
+            }
+        }
+        Ok(paths)
+    }
+
+    /// Use these paths for all loaders that don't take paths explicitly.
+    pub fn install(self) {
+        event!(Level::DEBUG, "using data paths {:?}", self);
+        *CURRENT.write() = self;
+    }
+
+    /// The paths currently in use.
+    pub fn current() -> Self {
+        CURRENT.read().clone()
+    }
+
+    /// Note: No protection from escaping the root directory.
+    pub fn orig_path(&self, input: impl AsRef<Path>) -> PathBuf {
+        self.sir_data.join(input)
+    }
+
+    /// Note: No protection from escaping the root directory.
+    pub fn output_path(&self, input: impl AsRef<Path>) -> PathBuf {
+        self.output.join(input)
+    }
+
+    /// Note: No protection from escaping the root directory.
+    pub fn termset_path(&self, input: impl AsRef<Path>) -> PathBuf {
+        self.termsets.join(input)
+    }
+
+    /// Note: No protection from escaping the root directory.
+    pub fn camb_codeset_path(&self, input: impl AsRef<Path>) -> PathBuf {
+        self.camb_codesets.join(input)
+    }
+
+    /// Note: No protection from escaping the root directory.
+    pub fn read_db_path(&self, input: impl AsRef<Path>) -> PathBuf {
+        self.read_db.join(input)
+    }
+}
+
+fn config_file_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os(CONFIG_VAR) {
+        return Some(path.into());
+    }
+    let local = PathBuf::from("eadapt.toml");
+    if local.is_file() {
+        return Some(local);
+    }
+    let home = PathBuf::from(env::var_os("HOME")?).join(".config/eadapt/config.toml");
+    home.is_file().then_some(home)
+}
+
+/// Returns the config and the directory it is in (for relative paths).
+fn load_config(path: &Path) -> Result<(PathBuf, ConfigFile)> {
+    let config = toml::from_str(&fs::read_to_string(path)?)?;
+    let dir = path
+        .parent()
+        .map(Path::to_owned)
+        .unwrap_or_else(|| PathBuf::from("."));
+    Ok((dir, config))
+}
@@ -0,0 +1,297 @@
+//! Bar charts of our results, saved as SVG or PNG or shown inline in evcxr.
+//!
+//! Charts are drawn from the same numbers as the report tables, with disclosure control applied,
+//! so figures don't have to be re-entered into a spreadsheet. Suppressed values have no bar.
+//!
+//! Bars are horizontal, so that long category names (e.g. conditions) stay readable.
+use crate::{
+    lemp::Stats, ltcs::ConditionsReport, Counts, DisclosureControl, Patients, RangeSetCounts,
+    RangeSetCountsWithMissing,
+};
+use plotters::{coord::Shift, prelude::*};
+use qu::ick_use::*;
+use std::{fmt, path::Path};
+
+const WIDTH: u32 = 1024;
+/// The height of the chart for each category.
+const CATEGORY_HEIGHT: u32 = 32;
+/// Space for the title, axis and legend.
+const MARGIN_HEIGHT: u32 = 120;
+const COLOURS: [RGBColor; 4] = [
+    RGBColor(31, 119, 180),
+    RGBColor(255, 127, 14),
+    RGBColor(44, 160, 44),
+    RGBColor(214, 39, 40),
+];
+
+/// One set of bars in a [`BarChart`].
+#[derive(Debug, Clone)]
+pub struct Series {
+    pub name: String,
+    /// One value per category. Suppressed values are `None`.
+    pub values: Vec<Option<f64>>,
+}
+
+/// A horizontal bar chart, with a bar for each series in each category.
+#[derive(Debug, Clone)]
+pub struct BarChart {
+    pub title: String,
+    /// What the values are, e.g. "Patients".
+    pub value_label: String,
+    pub categories: Vec<String>,
+    pub series: Vec<Series>,
+}
+
+impl BarChart {
+    pub fn new(
+        title: impl Into<String>,
+        value_label: impl Into<String>,
+        categories: impl IntoIterator<Item = impl fmt::Display>,
+    ) -> Self {
+        BarChart {
+            title: title.into(),
+            value_label: value_label.into(),
+            categories: categories.into_iter().map(|cat| cat.to_string()).collect(),
+            series: vec![],
+        }
+    }
+
+    pub fn with_series(
+        mut self,
+        name: impl Into<String>,
+        values: impl IntoIterator<Item = Option<f64>>,
+    ) -> Self {
+        self.series.push(Series {
+            name: name.into(),
+            values: values.into_iter().collect(),
+        });
+        self
+    }
+
+    /// A chart of one count for each category, with `dc` applied.
+    pub fn counts(
+        title: impl Into<String>,
+        counts: impl IntoIterator<Item = (impl fmt::Display, usize)>,
+        dc: &DisclosureControl,
+    ) -> Self {
+        let (categories, values): (Vec<_>, Vec<_>) = counts
+            .into_iter()
+            .map(|(label, count)| (label.to_string(), dc.count(count).value().map(|v| v as f64)))
+            .unzip();
+        BarChart::new(title, "Patients", categories).with_series("Patients", values)
+    }
+
+    /// Save the chart, as SVG or PNG depending on the extension of `path`.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result {
+        fn inner(this: &BarChart, path: &Path) -> Result {
+            let size = this.size();
+            match path.extension().and_then(|ext| ext.to_str()) {
+                Some("svg") => this.draw(SVGBackend::new(path, size).into_drawing_area()),
+                Some("png") => this.draw(BitMapBackend::new(path, size).into_drawing_area()),
+                _ => bail!("charts can only be saved as .svg or .png"),
+            }
+        }
+        let path = path.as_ref();
+        inner(self, path).with_context(|| format!("saving chart to \"{}\"", path.display()))
+    }
+
+    pub fn to_svg(&self) -> Result<String> {
+        let mut out = String::new();
+        self.draw(SVGBackend::with_string(&mut out, self.size()).into_drawing_area())?;
+        Ok(out)
+    }
+
+    /// Display this chart in the evcxr window.
+    pub fn evcxr_display(&self) {
+        match self.to_svg() {
+            Ok(svg) => println!(
+                "EVCXR_BEGIN_CONTENT image/svg+xml\n{}\nEVCXR_END_CONTENT",
+                svg
+            ),
+            Err(e) => println!("error drawing chart: {}", e),
+        }
+    }
+
+    fn size(&self) -> (u32, u32) {
+        let categories = u32::try_from(self.categories.len()).unwrap_or(u32::MAX);
+        (
+            WIDTH,
+            MARGIN_HEIGHT.saturating_add(categories.saturating_mul(CATEGORY_HEIGHT)),
+        )
+    }
+
+    fn draw<DB>(&self, root: DrawingArea<DB, Shift>) -> Result
+    where
+        DB: DrawingBackend,
+        DB::ErrorType: 'static,
+    {
+        let draw_err = |e: DrawingAreaErrorKind<DB::ErrorType>| format_err!("{}", e);
+        root.fill(&WHITE).map_err(draw_err)?;
+
+        let n = self.categories.len();
+        let max = self
+            .series
+            .iter()
+            .flat_map(|series| series.values.iter().flatten())
+            .fold(0f64, |max, v| max.max(*v));
+        // Categories are drawn top to bottom, centred on whole numbers.
+        let mut chart = ChartBuilder::on(&root)
+            .caption(&self.title, ("sans-serif", 24))
+            .margin(10)
+            .x_label_area_size(40)
+            .y_label_area_size(320)
+            .build_cartesian_2d(0f64..(max * 1.1).max(1.), -0.5..n as f64 - 0.5)
+            .map_err(draw_err)?;
+        let label = |y: &f64| {
+            if (y - y.round()).abs() > 1e-6 {
+                return String::new();
+            }
+            let idx = n as isize - 1 - y.round() as isize;
+            usize::try_from(idx)
+                .ok()
+                .and_then(|idx| self.categories.get(idx))
+                .cloned()
+                .unwrap_or_default()
+        };
+        chart
+            .configure_mesh()
+            .disable_y_mesh()
+            .y_labels(n)
+            .x_desc(&self.value_label)
+            .y_label_formatter(&label)
+            .draw()
+            .map_err(draw_err)?;
+
+        let bar_height = 0.8 / self.series.len().max(1) as f64;
+        for (series_idx, series) in self.series.iter().enumerate() {
+            let colour = COLOURS[series_idx % COLOURS.len()];
+            let bars = series
+                .values
+                .iter()
+                .enumerate()
+                .filter_map(|(idx, value)| Some((idx, (*value)?)))
+                .map(|(idx, value)| {
+                    let top = (n - 1 - idx) as f64 + 0.4 - series_idx as f64 * bar_height;
+                    Rectangle::new([(0., top), (value, top - bar_height)], colour.filled())
+                });
+            chart
+                .draw_series(bars)
+                .map_err(draw_err)?
+                .label(&series.name)
+                .legend(move |(x, y)| {
+                    Rectangle::new([(x, y - 5), (x + 10, y + 5)], colour.filled())
+                });
+        }
+        if self.series.len() > 1 {
+            chart
+                .configure_series_labels()
+                .background_style(WHITE.mix(0.8))
+                .border_style(BLACK)
+                .position(SeriesLabelPosition::LowerRight)
+                .draw()
+                .map_err(draw_err)?;
+        }
+        root.present().map_err(draw_err)?;
+        Ok(())
+    }
+}
+
+impl<T: fmt::Display> RangeSetCounts<T> {
+    pub fn bar_chart(&self, title: impl Into<String>, dc: &DisclosureControl) -> BarChart {
+        BarChart::counts(title, self.for_display(), dc)
+    }
+
+    /// Show the counts as a chart in the evcxr window.
+    pub fn evcxr_plot(&self) {
+        self.bar_chart("", &DisclosureControl::NONE).evcxr_display()
+    }
+}
+
+impl<T: fmt::Display> RangeSetCountsWithMissing<T> {
+    pub fn bar_chart(&self, title: impl Into<String>, dc: &DisclosureControl) -> BarChart {
+        BarChart::counts(title, self.for_display(), dc)
+    }
+
+    /// Show the counts as a chart in the evcxr window.
+    pub fn evcxr_plot(&self) {
+        self.bar_chart("", &DisclosureControl::NONE).evcxr_display()
+    }
+}
+
+impl Patients {
+    /// The number of patients in each IMD decile.
+    pub fn imd_chart(&self, dc: &DisclosureControl) -> BarChart {
+        BarChart::counts("IMD", self.count_imd(), dc)
+    }
+}
+
+impl ConditionsReport {
+    /// The prevalence of each condition at diagnosis, next to the prevalence in the general
+    /// population.
+    pub fn prevalence_chart(&self, dc: &DisclosureControl) -> BarChart {
+        let totals = self.totals();
+        let rows = self.iter().collect::<Vec<_>>();
+        BarChart::new(
+            "Long-term conditions at diagnosis",
+            "Prevalence (%)",
+            rows.iter().map(|(name, ..)| name),
+        )
+        .with_series(
+            "Cohort",
+            rows.iter()
+                .map(|(_, row, _)| dc.percentage(row.counts()[0], totals[0])),
+        )
+        .with_series(
+            "General population",
+            rows.iter().map(|(.., prevalence)| Some(prevalence * 100.)),
+        )
+    }
+
+    /// Show the prevalence chart in the evcxr window.
+    pub fn evcxr_plot(&self) {
+        self.prevalence_chart(&DisclosureControl::NONE)
+            .evcxr_display()
+    }
+}
+
+impl Stats {
+    /// The spread of test rates. There are no bars if there are too few people to release.
+    pub fn bar_chart(&self, title: impl Into<String>, dc: &DisclosureControl) -> BarChart {
+        let released = dc.count(self.num_people).value().is_some();
+        BarChart::new(
+            title,
+            "Tests per year",
+            ["Mean", "25th percentile", "Median", "75th percentile"],
+        )
+        .with_series(
+            "Test rate",
+            [
+                self.rate_mean,
+                self.rate_25_percentile,
+                self.rate_50_percentile,
+                self.rate_75_percentile,
+            ]
+            .map(|rate| (released && rate.is_finite()).then_some(rate)),
+        )
+    }
+
+    /// Show the spread of test rates in the evcxr window.
+    pub fn evcxr_plot(&self) {
+        self.bar_chart("", &DisclosureControl::NONE).evcxr_display()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn counts_chart() {
+        let dc = DisclosureControl::new(true);
+        let chart = BarChart::counts("Ages", [("0 - 18", 3), ("18 - 35", 120)], &dc);
+        assert_eq!(chart.series[0].values, [None, Some(120.)]);
+        let svg = chart.to_svg().unwrap();
+        assert!(svg.contains("18 - 35"));
+        assert!(svg.contains("Ages"));
+    }
+}
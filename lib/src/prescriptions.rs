@@ -0,0 +1,155 @@
+//! Prescriptions, from the therapy file in the follow-on extract.
+//!
+//! Drugs are coded using Read v2 drug codes (the lower-case chapters), so the same termsets and
+//! [`CodeSet`]s work here as for events.
+use crate::{
+    dataset::{Dataset, Record},
+    envelope::Schema,
+    load_orig,
+    read2::CodeSet,
+    util::{maybe_read, optional_string},
+    ArcStr, PatientId, ReadCode, Result,
+};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::{ops::Deref, path::Path};
+
+#[derive(Debug, Deserialize)]
+struct PrescriptionRaw {
+    #[serde(rename = "PatID")]
+    patient_id: PatientId,
+    #[serde(rename = "IssueDate")]
+    date: NaiveDate,
+    #[serde(rename = "ProductCode", deserialize_with = "maybe_read")]
+    product_code: Option<ReadCode>,
+    #[serde(rename = "Quantity")]
+    quantity: Option<f64>,
+    #[serde(rename = "Dose", deserialize_with = "optional_string")]
+    dose: Option<ArcStr>,
+}
+
+/// A row in the prescriptions dataset.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Prescription {
+    pub patient_id: PatientId,
+    /// The date the prescription was issued.
+    pub date: NaiveDate,
+    pub product_code: ReadCode,
+    /// The number of units (tablets, ml, etc.) issued.
+    pub quantity: Option<f64>,
+    /// Dosage instructions, as free text (e.g. "ONE TO BE TAKEN TWICE A DAY").
+    pub dose: Option<ArcStr>,
+}
+
+impl Schema for Prescription {
+    const SCHEMA: &'static str = "Prescription { patient_id: u64, date: NaiveDate, \
+        product_code: ReadCode, quantity: Option<f64>, dose: Option<str> }";
+}
+
+impl Prescription {
+    fn from_raw(raw: PrescriptionRaw) -> Option<Self> {
+        Some(Prescription {
+            patient_id: raw.patient_id,
+            date: raw.date,
+            product_code: raw.product_code?,
+            quantity: raw.quantity,
+            dose: raw.dose,
+        })
+    }
+}
+
+impl Record for Prescription {
+    type Key = PatientId;
+    const DATASET: &'static str = "prescriptions";
+    fn key(&self) -> PatientId {
+        self.patient_id
+    }
+    /// Each patient's prescriptions are indexed in date order.
+    fn cmp_within_key(&self, other: &Self) -> std::cmp::Ordering {
+        self.date.cmp(&other.date)
+    }
+}
+
+/// The parsed list of prescriptions, with a pre-built index for the `id` field.
+pub struct Prescriptions(Dataset<Prescription>);
+
+impl Prescriptions {
+    /// Load the original extract. Rows without a valid product code are dropped.
+    pub fn load_orig(path: impl AsRef<Path>) -> Result<Self> {
+        let els: Vec<PrescriptionRaw> = load_orig(path)?;
+        let els = els.into_iter().filter_map(Prescription::from_raw).collect();
+        Ok(Self::new(els))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Prescriptions(Dataset::load(path)?))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result {
+        self.0.save(path)
+    }
+
+    /// A patient's prescriptions, in date order.
+    pub fn prescriptions_for_patient(
+        &self,
+        patient_id: PatientId,
+    ) -> impl Iterator<Item = &Prescription> + Clone + '_ {
+        self.0.get(patient_id)
+    }
+
+    /// Get a `Prescriptions` object containing only prescriptions that match the filter.
+    pub fn filter(&self, f: impl Fn(&Prescription) -> bool) -> Self {
+        Prescriptions(self.0.filter(f))
+    }
+
+    pub fn retain(&mut self, f: impl Fn(&Prescription) -> bool) {
+        self.0.retain(f)
+    }
+
+    /// Like [`Prescriptions::filter`], but records the filter in the [`audit`](crate::audit) log.
+    pub fn filter_described(&self, description: &str, f: impl Fn(&Prescription) -> bool) -> Self {
+        Prescriptions(self.0.filter_described(description, f))
+    }
+
+    /// Like [`Prescriptions::retain`], but records the filter in the [`audit`](crate::audit) log.
+    pub fn retain_described(&mut self, description: &str, f: impl Fn(&Prescription) -> bool) {
+        self.0.retain_described(description, f)
+    }
+
+    /// Only those prescriptions with product codes in the codeset.
+    pub fn filter_by_codeset(&self, codeset: &CodeSet) -> Self {
+        self.filter(|el| codeset.contains(el.product_code))
+    }
+
+    pub fn filter_by_patient_id(&self, id: PatientId) -> Self {
+        Self::new(self.prescriptions_for_patient(id).cloned().collect())
+    }
+
+    fn new(els: Vec<Prescription>) -> Self {
+        Prescriptions(Dataset::new(els))
+    }
+}
+
+impl Deref for Prescriptions {
+    type Target = Dataset<Prescription>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<'a> IntoIterator for &'a Prescriptions {
+    type IntoIter = <&'a [Prescription] as IntoIterator>::IntoIter;
+    type Item = &'a Prescription;
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.els.iter()
+    }
+}
+
+impl FromIterator<Prescription> for Prescriptions {
+    fn from_iter<T>(iter: T) -> Self
+    where
+        T: IntoIterator<Item = Prescription>,
+    {
+        Self::new(iter.into_iter().collect())
+    }
+}
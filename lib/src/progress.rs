@@ -0,0 +1,248 @@
+//! Progress reporting for long-running operations.
+//!
+//! Importing the full events extract takes minutes, so loaders and other slow functions report
+//! their progress to a global handler. By default nothing is reported; binaries that want feedback
+//! should install a handler at startup with [`set_handler`] (or [`indicatif_handler`] with the
+//! `indicatif` feature).
+use once_cell::sync::Lazy;
+use parking_lot::{Mutex, RwLock};
+use qu::ick_use::*;
+use std::{
+    io::{self, Read},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+type Handler = Box<dyn Fn(&Update) + Send + Sync>;
+
+static HANDLER: Lazy<RwLock<Option<Handler>>> = Lazy::new(|| RwLock::new(None));
+
+/// How often to report progress.
+const REPORT_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Install a function to be called with progress updates.
+///
+/// Updates are throttled, so the handler doesn't need to be particularly fast.
+pub fn set_handler(f: impl Fn(&Update) + Send + Sync + 'static) {
+    *HANDLER.write() = Some(Box::new(f));
+}
+
+/// Remove any installed progress handler.
+pub fn clear_handler() {
+    *HANDLER.write() = None;
+}
+
+/// A handler that logs progress using `tracing`.
+pub fn log_handler() -> impl Fn(&Update) + Send + Sync + 'static {
+    |update: &Update| {
+        if update.finished {
+            event!(
+                Level::INFO,
+                "{}: finished {} rows in {:.1}s",
+                update.task,
+                update.rows,
+                update.elapsed.as_secs_f64()
+            );
+        } else if let Some(eta) = update.eta() {
+            event!(
+                Level::INFO,
+                "{}: {} rows, {:.0}% (ETA {:.0}s)",
+                update.task,
+                update.rows,
+                update.fraction.unwrap_or(0.) * 100.,
+                eta.as_secs_f64()
+            );
+        } else {
+            event!(Level::INFO, "{}: {} rows", update.task, update.rows);
+        }
+    }
+}
+
+/// A handler that draws a progress bar for each running task.
+#[cfg(feature = "indicatif")]
+pub fn indicatif_handler() -> impl Fn(&Update) + Send + Sync + 'static {
+    use indicatif::{ProgressBar, ProgressStyle};
+    use parking_lot::Mutex;
+    use std::collections::BTreeMap;
+
+    // The bar length is in thousandths of the task, so we can display any fraction.
+    const LEN: u64 = 1000;
+
+    let bars: Mutex<BTreeMap<String, ProgressBar>> = Mutex::new(BTreeMap::new());
+    move |update: &Update| {
+        let mut bars = bars.lock();
+        let bar = bars.entry(update.task.to_string()).or_insert_with(|| {
+            let bar = match update.fraction {
+                Some(_) => ProgressBar::new(LEN).with_style(
+                    ProgressStyle::with_template("{prefix} [{bar:40}] {msg} (ETA {eta})")
+                        .unwrap()
+                        .progress_chars("=> "),
+                ),
+                None => ProgressBar::new_spinner().with_style(
+                    ProgressStyle::with_template("{prefix} {spinner} {msg}").unwrap(),
+                ),
+            };
+            bar.with_prefix(update.task.to_string())
+        });
+        if let Some(fraction) = update.fraction {
+            bar.set_position((fraction * LEN as f64) as u64);
+        } else {
+            bar.tick();
+        }
+        bar.set_message(format!("{} rows", update.rows));
+        if update.finished {
+            bar.finish();
+            bars.remove(update.task);
+        }
+    }
+}
+
+/// A snapshot of the progress of a task.
+#[derive(Debug, Clone)]
+pub struct Update<'a> {
+    /// A short description of what is being done.
+    pub task: &'a str,
+    /// The number of rows processed so far.
+    pub rows: u64,
+    /// How far through the task we are (0 - 1), if known.
+    pub fraction: Option<f64>,
+    /// The time since the task started.
+    pub elapsed: Duration,
+    /// Whether this is the last update for this task.
+    pub finished: bool,
+}
+
+impl Update<'_> {
+    /// Estimated time remaining, assuming progress continues at the same rate.
+    pub fn eta(&self) -> Option<Duration> {
+        let fraction = self.fraction?;
+        if fraction <= 0. || self.finished {
+            return None;
+        }
+        Some(self.elapsed.mul_f64((1. - fraction) / fraction))
+    }
+}
+
+/// Tracks the progress of a single task, sending updates to the installed handler.
+///
+/// Rows can be recorded from several threads at once (e.g. inside a rayon fold).
+pub(crate) struct Progress {
+    task: String,
+    start: Instant,
+    last_report: Mutex<Instant>,
+    rows: AtomicU64,
+    total: Option<u64>,
+}
+
+impl Progress {
+    /// Start a new task. If `total` is known then `fraction` will be `rows / total`.
+    pub fn new(task: impl Into<String>, total: Option<u64>) -> Self {
+        let now = Instant::now();
+        Self {
+            task: task.into(),
+            start: now,
+            last_report: Mutex::new(now),
+            rows: AtomicU64::new(0),
+            total,
+        }
+    }
+
+    /// Record that another row has been processed.
+    pub fn inc(&self) {
+        let rows = self.rows.fetch_add(1, Ordering::Relaxed) + 1;
+        // don't check the time on every row, it's too expensive.
+        if rows.is_multiple_of(1024) {
+            self.maybe_report(self.default_fraction());
+        }
+    }
+
+    /// Record that another row has been processed, where progress is measured by something other
+    /// than rows (e.g. bytes read).
+    pub fn inc_with_fraction(&self, fraction: impl FnOnce() -> f64) {
+        let rows = self.rows.fetch_add(1, Ordering::Relaxed) + 1;
+        if rows.is_multiple_of(1024) {
+            self.maybe_report(Some(fraction()));
+        }
+    }
+
+    /// Report a fraction without recording any rows.
+    pub fn set_fraction(&self, fraction: f64) {
+        self.maybe_report(Some(fraction));
+    }
+
+    /// Set the number of rows processed (used when we only know the count at the end).
+    pub fn set_rows(&self, rows: u64) {
+        self.rows.store(rows, Ordering::Relaxed);
+    }
+
+    pub fn finish(self) {
+        self.report(Some(1.), true);
+    }
+
+    fn default_fraction(&self) -> Option<f64> {
+        self.total
+            .map(|total| self.rows.load(Ordering::Relaxed) as f64 / total.max(1) as f64)
+    }
+
+    fn maybe_report(&self, fraction: Option<f64>) {
+        // If another thread is already reporting, there's no need for this one to.
+        let Some(mut last_report) = self.last_report.try_lock() else {
+            return
+        };
+        let now = Instant::now();
+        if now - *last_report >= REPORT_INTERVAL {
+            *last_report = now;
+            drop(last_report);
+            self.report(fraction, false);
+        }
+    }
+
+    fn report(&self, fraction: Option<f64>, finished: bool) {
+        if let Some(handler) = &*HANDLER.read() {
+            handler(&Update {
+                task: &self.task,
+                rows: self.rows.load(Ordering::Relaxed),
+                fraction,
+                elapsed: self.start.elapsed(),
+                finished,
+            });
+        }
+    }
+}
+
+/// Wraps a reader, reporting the fraction of bytes read.
+pub(crate) struct ProgressReader<R> {
+    inner: R,
+    read: u64,
+    len: u64,
+    progress: Progress,
+}
+
+impl<R> ProgressReader<R> {
+    pub fn new(inner: R, len: u64, progress: Progress) -> Self {
+        Self {
+            inner,
+            read: 0,
+            len,
+            progress,
+        }
+    }
+
+    pub fn set_rows(&self, rows: u64) {
+        self.progress.set_rows(rows)
+    }
+
+    pub fn finish(self) {
+        self.progress.finish()
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        self.progress
+            .set_fraction(self.read as f64 / self.len.max(1) as f64);
+        Ok(n)
+    }
+}
@@ -0,0 +1,124 @@
+//! Records where our saved data came from.
+//!
+//! For data governance we need to be able to say which raw extract, and which version of the
+//! code, produced any given file. Every file we read through the loaders in this crate is hashed
+//! and remembered, and when we save a dataset we write a sidecar `<file>.meta.json` alongside it
+//! containing
+//!
+//!  - the SHA-256 of the saved file itself,
+//!  - the SHA-256 of every input read by the process up to that point,
+//!  - the crate version and command line,
+//!  - when the file was written.
+//!
+//! Termsets and code sets are recorded as inputs, but don't get sidecars of their own since they
+//! are kept under version control.
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use qu::ick_use::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+static INPUTS: Lazy<Mutex<BTreeMap<PathBuf, String>>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+/// The contents of a `.meta.json` sidecar file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Provenance {
+    /// The SHA-256 of the file this describes.
+    pub sha256: String,
+    /// The SHA-256 of every file read before this one was written.
+    pub inputs: BTreeMap<PathBuf, String>,
+    pub crate_version: String,
+    pub command_line: Vec<String>,
+    pub created: DateTime<Utc>,
+}
+
+impl Provenance {
+    /// Load the provenance for a file from its sidecar.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = sidecar_path(path.as_ref());
+        let text = fs::read_to_string(&path)
+            .with_context(|| format!("reading \"{}\"", path.display()))?;
+        serde_json::from_str(&text).with_context(|| format!("parsing \"{}\"", path.display()))
+    }
+
+    /// Check that the file still matches the hash recorded when it was written.
+    pub fn verify(&self, path: impl AsRef<Path>) -> Result {
+        let path = path.as_ref();
+        let actual = sha256_file(path)?;
+        ensure!(
+            actual == self.sha256,
+            "\"{}\" has changed since it was written (expected SHA-256 {}, found {})",
+            path.display(),
+            self.sha256,
+            actual
+        );
+        Ok(())
+    }
+}
+
+/// Remember that we read `path`, so it is listed as an input of anything we save later.
+///
+/// Failures are logged rather than returned, so that provenance can't stop an analysis running.
+pub fn record_input(path: impl AsRef<Path>) {
+    let path = path.as_ref();
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+    if INPUTS.lock().contains_key(&path) {
+        return;
+    }
+    match sha256_file(&path) {
+        Ok(hash) => {
+            INPUTS.lock().insert(path, hash);
+        }
+        Err(e) => event!(
+            Level::WARN,
+            "could not hash input \"{}\" for provenance: {}",
+            path.display(),
+            e
+        ),
+    }
+}
+
+/// All inputs recorded so far.
+pub fn inputs() -> BTreeMap<PathBuf, String> {
+    INPUTS.lock().clone()
+}
+
+/// Write the sidecar for a file we have just saved.
+pub fn write_sidecar(path: impl AsRef<Path>) -> Result {
+    fn inner(path: &Path) -> Result {
+        let provenance = Provenance {
+            sha256: sha256_file(path)?,
+            inputs: inputs(),
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            command_line: env::args().collect(),
+            created: Utc::now(),
+        };
+        fs::write(
+            sidecar_path(path),
+            serde_json::to_string_pretty(&provenance)?,
+        )?;
+        Ok(())
+    }
+    let path = path.as_ref();
+    inner(path).with_context(|| format!("writing provenance for \"{}\"", path.display()))
+}
+
+/// `events.bin` -> `events.bin.meta.json`
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".meta.json");
+    path.with_file_name(name)
+}
+
+/// The SHA-256 of a file's contents, in lowercase hex.
+pub fn sha256_file(path: impl AsRef<Path>) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
@@ -0,0 +1,233 @@
+//! Replacing patient IDs with study IDs.
+//!
+//! The IDs in the extract can be linked back to the provider's records, so before data can move
+//! to a less secure analysis environment every `PatientId` must be replaced. A [`PseudoMap`] maps
+//! each patient ID to a study ID, either derived from a secret key (so the same key always gives
+//! the same study IDs) or loaded from a lookup file saved earlier.
+//!
+//! The lookup file is the only way to get from study IDs back to patients, so it must be kept in
+//! the secure environment, separately from the pseudonymised data.
+//!
+//! A patient's LSOA and month of birth would make them much easier to pick out, so pseudonymised
+//! patients don't have them. Their IMD decile and year of birth are kept.
+use crate::{Adapts, Events, PatientId, Patients};
+use qu::ick_use::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, fs, path::Path};
+
+/// A mapping from patient IDs to study IDs.
+#[derive(Debug, Clone, Default)]
+pub struct PseudoMap {
+    forward: BTreeMap<PatientId, PatientId>,
+    reverse: BTreeMap<PatientId, PatientId>,
+}
+
+/// A row in the lookup file.
+#[derive(Debug, Serialize, Deserialize)]
+struct LookupRow {
+    study_id: PatientId,
+    patient_id: PatientId,
+}
+
+impl PseudoMap {
+    /// Derive study IDs from a keyed hash of the patient IDs.
+    ///
+    /// Study IDs are kept below 2^63 so they survive a round trip through sqlite and polars.
+    pub fn keyed(key: &[u8], patient_ids: impl IntoIterator<Item = PatientId>) -> Result<Self> {
+        ensure!(!key.is_empty(), "pseudonymisation key must not be empty");
+        let mut map = Self::default();
+        for patient_id in patient_ids {
+            let hash = Sha256::new()
+                .chain_update(key)
+                .chain_update(patient_id.get().to_le_bytes())
+                .finalize();
+            let study_id = PatientId::new(u64::from_le_bytes(hash[..8].try_into().unwrap()) >> 1);
+            map.insert(patient_id, study_id)?;
+        }
+        Ok(map)
+    }
+
+    /// Load a lookup written by [`PseudoMap::save_lookup`].
+    pub fn load_lookup(path: impl AsRef<Path>) -> Result<Self> {
+        fn inner(path: &Path) -> Result<PseudoMap> {
+            let mut map = PseudoMap::default();
+            for row in csv::Reader::from_path(path)?.into_deserialize() {
+                let row: LookupRow = row?;
+                map.insert(row.patient_id, row.study_id)?;
+            }
+            Ok(map)
+        }
+        let path = path.as_ref();
+        inner(path).with_context(|| format!("loading ID lookup from \"{}\"", path.display()))
+    }
+
+    /// Save the lookup from study IDs to patient IDs, as CSV.
+    ///
+    /// This file re-identifies the data, so it must stay in the secure environment.
+    pub fn save_lookup(&self, path: impl AsRef<Path>) -> Result {
+        fn inner(map: &PseudoMap, path: &Path) -> Result {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).context("could not create parent")?;
+            }
+            let mut out = csv::Writer::from_path(path)?;
+            for (&study_id, &patient_id) in map.reverse.iter() {
+                out.serialize(LookupRow {
+                    study_id,
+                    patient_id,
+                })?;
+            }
+            out.flush()?;
+            Ok(())
+        }
+        let path = path.as_ref();
+        inner(self, path).with_context(|| format!("saving ID lookup to \"{}\"", path.display()))
+    }
+
+    fn insert(&mut self, patient_id: PatientId, study_id: PatientId) -> Result {
+        if let Some(&existing) = self.forward.get(&patient_id) {
+            ensure!(
+                existing == study_id,
+                "patient {} mapped to more than one study ID",
+                patient_id
+            );
+            return Ok(());
+        }
+        if let Some(&existing) = self.reverse.get(&study_id) {
+            bail!(
+                "study ID {} used for patients {} and {}",
+                study_id,
+                existing,
+                patient_id
+            );
+        }
+        self.forward.insert(patient_id, study_id);
+        self.reverse.insert(study_id, patient_id);
+        Ok(())
+    }
+
+    /// The study ID for a patient.
+    pub fn study_id(&self, patient_id: PatientId) -> Option<PatientId> {
+        self.forward.get(&patient_id).copied()
+    }
+
+    /// The patient ID for a study ID.
+    pub fn patient_id(&self, study_id: PatientId) -> Option<PatientId> {
+        self.reverse.get(&study_id).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.forward.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.forward.is_empty()
+    }
+
+    fn map(&self, patient_id: PatientId) -> Result<PatientId> {
+        self.study_id(patient_id)
+            .ok_or_else(|| format_err!("no study ID for patient {}", patient_id))
+    }
+}
+
+impl Patients {
+    /// A copy of these patients with their IDs replaced by study IDs, and their LSOA and month of
+    /// birth removed.
+    ///
+    /// Fails if any patient isn't in `map`.
+    pub fn pseudonymise(&self, map: &PseudoMap) -> Result<Self> {
+        let els = self
+            .els
+            .iter()
+            .map(|pat| {
+                let mut pat = pat.clone();
+                pat.patient_id = map.map(pat.patient_id)?;
+                pat.lsoa = None;
+                pat.month_of_birth = None;
+                Ok(pat)
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self::new(els))
+    }
+}
+
+impl Events {
+    /// A copy of these events with their patient IDs replaced by study IDs.
+    ///
+    /// Fails if any event's patient isn't in `map`.
+    pub fn pseudonymise(&self, map: &PseudoMap) -> Result<Self> {
+        let els = self
+            .els
+            .iter()
+            .map(|evt| {
+                let mut evt = evt.clone();
+                evt.patient_id = map.map(evt.patient_id)?;
+                Ok(evt)
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self::new(els))
+    }
+}
+
+impl Adapts {
+    /// A copy of the ADAPT records with their IDs replaced by study IDs.
+    ///
+    /// Fails if any record's patient isn't in `map`.
+    pub fn pseudonymise(&self, map: &PseudoMap) -> Result<Self> {
+        let els = self
+            .els
+            .iter()
+            .map(|adapt| {
+                let mut adapt = adapt.clone();
+                adapt.id = map.map(adapt.id)?;
+                Ok(adapt)
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self::new(els))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PseudoMap;
+    use crate::{ArcStr, Imd, Patient, PatientId, Patients, Sex};
+
+    #[test]
+    fn keyed() {
+        let ids = |ids: [u64; 3]| ids.map(PatientId::new);
+        let a = PseudoMap::keyed(b"secret", ids([1, 2, 3])).unwrap();
+        let b = PseudoMap::keyed(b"secret", ids([3, 2, 1])).unwrap();
+        let c = PseudoMap::keyed(b"other", ids([1, 2, 3])).unwrap();
+        for id in (1..=3).map(PatientId::new) {
+            let study_id = a.study_id(id).unwrap();
+            assert!(study_id.get() < 1 << 63);
+            assert_eq!(b.study_id(id), Some(study_id));
+            assert_ne!(c.study_id(id), Some(study_id));
+            assert_eq!(a.patient_id(study_id), Some(id));
+        }
+    }
+
+    #[test]
+    fn patients() {
+        let patients = Patients::new(vec![Patient {
+            patient_id: PatientId::new(1),
+            year_of_birth: 1970,
+            month_of_birth: Some(3),
+            sex: Sex::Female,
+            ethnicity: None,
+            lsoa: Some(ArcStr::from("E01000001")),
+            imd: Imd::_4,
+            charlson: 0.,
+            lymphoma_diagnosis_date: None,
+            lymphoma_diagnosis_confidence: None,
+            lymphoma_subtypes: Default::default(),
+        }]);
+        let map = PseudoMap::keyed(b"secret", [PatientId::new(1)]).unwrap();
+        let pseudo = patients.pseudonymise(&map).unwrap();
+        let pat = pseudo.iter_ref().next().unwrap();
+        assert_eq!(pat.patient_id, map.study_id(PatientId::new(1)).unwrap());
+        assert_eq!((pat.lsoa.as_ref(), pat.month_of_birth), (None, None));
+        assert_eq!((pat.year_of_birth, pat.imd), (1970, Imd::_4));
+        assert!(patients.pseudonymise(&PseudoMap::default()).is_err());
+    }
+}
@@ -0,0 +1,276 @@
+//! A profile of every field in the data, for the data quality appendix.
+//!
+//! Rather than list the fields by hand, we serialize each row and profile whatever comes out, so
+//! new fields are picked up automatically. A value counts as missing if it serializes to `null` or
+//! an empty string (as `Imd::Missing` does).
+use crate::{progress::Progress, Adapts, ArcStr, Events, Patients};
+use qu::ick_use::*;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use term_data_table as tdt;
+
+/// How many of the most common rubrics to include.
+const TOP_RUBRICS: usize = 20;
+
+#[derive(Debug, Serialize)]
+pub struct QualityReport {
+    pub fields: Vec<FieldProfile>,
+    /// The most common rubrics, with their counts.
+    pub top_rubrics: Vec<(ArcStr, usize)>,
+    /// The total number of events.
+    pub events: usize,
+    /// Events whose date wasn't recorded.
+    pub missing_dates: usize,
+    /// Events with a `code_value`.
+    pub code_values: usize,
+    /// Events whose `code_value` parses as a number.
+    pub code_values_numeric: usize,
+}
+
+/// Summary of a single field in a table.
+#[derive(Debug, Serialize)]
+pub struct FieldProfile {
+    pub table: &'static str,
+    pub field: String,
+    pub rows: usize,
+    pub missing: usize,
+    pub distinct: usize,
+    /// The smallest value, compared as numbers if all values are numbers, otherwise as text (which
+    /// works for dates).
+    pub min: Option<String>,
+    pub max: Option<String>,
+}
+
+impl FieldProfile {
+    pub fn missing_percentage(&self) -> f64 {
+        if self.rows == 0 {
+            return 0.;
+        }
+        self.missing as f64 / self.rows as f64 * 100.
+    }
+}
+
+impl QualityReport {
+    pub fn new(patients: &Patients, events: &Events, adapts: &Adapts) -> Result<Self> {
+        let mut fields = profile("patients", patients.els.iter())?;
+        fields.extend(profile("adapt", adapts.els.iter())?);
+        fields.extend(profile("events", events.els.iter())?);
+
+        let mut rubrics: HashMap<&ArcStr, usize> = HashMap::new();
+        let mut missing_dates = 0;
+        let mut code_values = 0;
+        let mut code_values_numeric = 0;
+        for evt in events.els.iter() {
+            *rubrics.entry(&evt.rubric).or_default() += 1;
+            if evt.date_known().is_none() {
+                missing_dates += 1;
+            }
+            if let Some(value) = &evt.code_value {
+                code_values += 1;
+                if value.trim().parse::<f64>().is_ok() {
+                    code_values_numeric += 1;
+                }
+            }
+        }
+        let mut top_rubrics: Vec<(ArcStr, usize)> = rubrics
+            .into_iter()
+            .map(|(rubric, count)| (rubric.clone(), count))
+            .collect();
+        top_rubrics.sort_by(|(r1, c1), (r2, c2)| c2.cmp(c1).then_with(|| r1.cmp(r2)));
+        top_rubrics.truncate(TOP_RUBRICS);
+
+        Ok(QualityReport {
+            fields,
+            top_rubrics,
+            events: events.len(),
+            missing_dates,
+            code_values,
+            code_values_numeric,
+        })
+    }
+
+    /// The proportion of events with a missing date, as a percentage.
+    pub fn missing_date_rate(&self) -> f64 {
+        if self.events == 0 {
+            return 0.;
+        }
+        self.missing_dates as f64 / self.events as f64 * 100.
+    }
+
+    /// The proportion of `code_value`s that are numbers, as a percentage.
+    pub fn code_value_parse_rate(&self) -> f64 {
+        if self.code_values == 0 {
+            return 0.;
+        }
+        self.code_values_numeric as f64 / self.code_values as f64 * 100.
+    }
+
+    pub fn fields_table(&self) -> tdt::Table<'_> {
+        use tdt::{Cell, Row, Table};
+        let mut table = Table::new().with_row(
+            Row::new()
+                .with_cell(Cell::from("Table"))
+                .with_cell(Cell::from("Field"))
+                .with_cell(Cell::from("Missing"))
+                .with_cell(Cell::from("Distinct"))
+                .with_cell(Cell::from("Min"))
+                .with_cell(Cell::from("Max")),
+        );
+        for field in self.fields.iter() {
+            table.add_row(
+                Row::new()
+                    .with_cell(Cell::from(field.table))
+                    .with_cell(Cell::from(field.field.as_str()))
+                    .with_cell(Cell::from(format!(
+                        "{} ({:.1}%)",
+                        field.missing,
+                        field.missing_percentage()
+                    )))
+                    .with_cell(Cell::from(field.distinct.to_string()))
+                    .with_cell(Cell::from(field.min.clone().unwrap_or_default()))
+                    .with_cell(Cell::from(field.max.clone().unwrap_or_default())),
+            );
+        }
+        table
+    }
+
+    pub fn top_rubrics_table(&self) -> tdt::Table<'_> {
+        use tdt::{Cell, Row, Table};
+        let mut table = Table::new().with_row(
+            Row::new()
+                .with_cell(Cell::from("Rubric"))
+                .with_cell(Cell::from("Count")),
+        );
+        for (rubric, count) in self.top_rubrics.iter() {
+            table.add_row(
+                Row::new()
+                    .with_cell(Cell::from(&**rubric))
+                    .with_cell(Cell::from(count.to_string())),
+            );
+        }
+        table
+    }
+
+    /// Print the whole report.
+    pub fn display(&self) {
+        println!("{}", self.fields_table());
+        println!(
+            "events with missing dates: {} of {} ({:.1}%)",
+            self.missing_dates,
+            self.events,
+            self.missing_date_rate()
+        );
+        println!(
+            "code values that are numbers: {} of {} ({:.1}%)",
+            self.code_values_numeric,
+            self.code_values,
+            self.code_value_parse_rate()
+        );
+        println!("\nMost common rubrics");
+        println!("{}", self.top_rubrics_table());
+    }
+}
+
+#[derive(Default)]
+struct FieldAcc {
+    missing: usize,
+    distinct: HashSet<String>,
+    numeric: Option<(f64, f64)>,
+    all_numeric: bool,
+    text: Option<(String, String)>,
+}
+
+impl FieldAcc {
+    fn new() -> Self {
+        Self {
+            all_numeric: true,
+            ..Default::default()
+        }
+    }
+
+    fn add(&mut self, value: &Value) {
+        let text = match value {
+            Value::Null => {
+                self.missing += 1;
+                return;
+            }
+            Value::String(s) if s.is_empty() => {
+                self.missing += 1;
+                return;
+            }
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        match value.as_f64() {
+            Some(v) => {
+                let (min, max) = self.numeric.get_or_insert((v, v));
+                *min = min.min(v);
+                *max = max.max(v);
+            }
+            None => self.all_numeric = false,
+        }
+        match &mut self.text {
+            Some((min, max)) => {
+                if text < *min {
+                    *min = text.clone();
+                }
+                if text > *max {
+                    *max = text.clone();
+                }
+            }
+            None => self.text = Some((text.clone(), text.clone())),
+        }
+        self.distinct.insert(text);
+    }
+
+    fn finish(self, table: &'static str, field: String, rows: usize) -> FieldProfile {
+        let (min, max) = if self.all_numeric {
+            match self.numeric {
+                Some((min, max)) => (Some(min.to_string()), Some(max.to_string())),
+                None => (None, None),
+            }
+        } else {
+            match self.text {
+                Some((min, max)) => (Some(min), Some(max)),
+                None => (None, None),
+            }
+        };
+        FieldProfile {
+            table,
+            field,
+            rows,
+            missing: self.missing,
+            distinct: self.distinct.len(),
+            min,
+            max,
+        }
+    }
+}
+
+/// Profile every field of some rows, sorted by field name.
+fn profile<'a, T: Serialize + 'a>(
+    table: &'static str,
+    rows: impl ExactSizeIterator<Item = &'a T>,
+) -> Result<Vec<FieldProfile>> {
+    let progress = Progress::new(format!("profiling {}", table), Some(rows.len() as u64));
+    let mut accs: BTreeMap<String, FieldAcc> = BTreeMap::new();
+    let mut count = 0;
+    for row in rows {
+        let Value::Object(map) = serde_json::to_value(row)? else {
+            bail!("rows in {} don't serialize to a map", table);
+        };
+        for (name, value) in map.iter() {
+            accs.entry(name.clone())
+                .or_insert_with(FieldAcc::new)
+                .add(value);
+        }
+        count += 1;
+        progress.inc();
+    }
+    progress.finish();
+    Ok(accs
+        .into_iter()
+        .map(|(name, acc)| acc.finish(table, name, count))
+        .collect())
+}
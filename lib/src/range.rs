@@ -1,9 +1,12 @@
+use crate::group::Counts;
 use itertools::{EitherOrBoth, Itertools};
-use serde::{Deserialize, Serialize};
+use noisy_float::types::N64;
+use qu::ick_use::*;
+use serde::{Deserialize, Serialize, Serializer};
 use std::{borrow::Borrow, fmt};
 
 /// Range where lower bound is inclusive, upper bound is exclusive or unbounded.
-#[derive(Copy, Clone, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Range<T>(T, Option<T>);
 
 impl<T> Range<T>
@@ -25,6 +28,12 @@ where
             val >= &self.0
         }
     }
+
+    /// Whether any value is in both ranges.
+    pub fn overlaps(&self, other: &Range<T>) -> bool {
+        let below = |value: &T, end: &Option<T>| end.as_ref().is_none_or(|end| value < end);
+        below(&self.0, &other.1) && below(&other.0, &self.1)
+    }
 }
 
 impl<T> Range<T> {
@@ -63,12 +72,118 @@ impl<T> RangeSet<T> {
     pub fn push(&mut self, range: Range<T>) {
         self.ranges.push(range);
     }
+
+    /// Change the type of the boundaries, e.g. from years to dates. `f` must keep the order of
+    /// values.
+    pub fn map<U>(self, mut f: impl FnMut(T) -> U) -> RangeSet<U> {
+        RangeSet {
+            ranges: self
+                .ranges
+                .into_iter()
+                .map(|Range(from, to)| Range(f(from), to.map(&mut f)))
+                .collect(),
+        }
+    }
+}
+
+impl<T> RangeSet<T>
+where
+    T: Interpolate,
+{
+    /// `n` ranges of equal width from `min` to `max`.
+    ///
+    /// The last range has no upper bound, so `max` (and anything above it) is counted in it. For
+    /// integers, boundaries are rounded down, and ranges that would be empty are left out.
+    pub fn equal_width(min: T, max: T, n: usize) -> Self {
+        assert!(n > 0, "there must be at least one range");
+        assert!(min < max, "ranges must go from low to high");
+        Self::from_boundaries(
+            (0..n)
+                .map(|idx| T::interpolate(min.clone(), max.clone(), idx, n))
+                .dedup()
+                .collect(),
+        )
+    }
+}
+
+impl<T> RangeSet<T>
+where
+    T: Ord + Clone,
+{
+    /// `n` ranges with (as near as possible) the same number of `values` in each, e.g. `n = 4`
+    /// for quartiles.
+    ///
+    /// The first range starts at the smallest value and the last has no upper bound. Where many
+    /// values are the same there may be fewer than `n` ranges.
+    pub fn quantiles(values: impl IntoIterator<Item = T>, n: usize) -> Self {
+        assert!(n > 0, "there must be at least one range");
+        let mut values = values.into_iter().collect::<Vec<_>>();
+        values.sort();
+        if values.is_empty() {
+            return Self::new(vec![]);
+        }
+        Self::from_boundaries(
+            (0..n)
+                .map(|idx| values[idx * values.len() / n].clone())
+                .dedup()
+                .collect(),
+        )
+    }
+
+    /// Ranges that overlap, and gaps between ranges, which would make counts misleading.
+    ///
+    /// Values below the lowest range (or above the highest, if it has an upper bound) aren't
+    /// counted as gaps.
+    pub fn validate(&self) -> Vec<RangeProblem<T>> {
+        let mut problems = vec![];
+        for (idx, first) in self.ranges.iter().enumerate() {
+            for second in &self.ranges[idx + 1..] {
+                if first.overlaps(second) {
+                    problems.push(RangeProblem::Overlap(first.clone(), second.clone()));
+                }
+            }
+        }
+
+        let mut sorted = self.ranges.iter().collect::<Vec<_>>();
+        sorted.sort_by(|a, b| a.0.cmp(&b.0));
+        // The end of the values covered so far (`None` once a range has no upper bound).
+        let mut covered_to = match sorted.first() {
+            Some(range) => range.1.clone(),
+            None => return problems,
+        };
+        for range in &sorted[1..] {
+            let Some(end) = covered_to else { break };
+            if end < range.0 {
+                problems.push(RangeProblem::Gap(Range(end.clone(), Some(range.0.clone()))));
+            }
+            covered_to = range.1.as_ref().map(|range_end| end.max(range_end.clone()));
+        }
+        problems
+    }
+
+    /// Ranges between consecutive `boundaries` (which must be increasing), and then from the last
+    /// boundary up.
+    fn from_boundaries(boundaries: Vec<T>) -> Self {
+        let mut ranges = boundaries
+            .iter()
+            .tuple_windows()
+            .map(|(from, to)| Range::new(from.clone(), Some(to.clone())))
+            .collect::<Vec<_>>();
+        if let Some(last) = boundaries.last() {
+            ranges.push(Range::new(last.clone(), None));
+        }
+        Self { ranges }
+    }
 }
 
 impl<T> RangeSet<T>
 where
     T: Ord,
 {
+    /// Count the values in each range.
+    ///
+    /// Values in more than one range are counted in each of them, and values in no range aren't
+    /// counted (see [`RangeSet::validate`] and [`RangeSet::bucket_values_as`]).
     pub fn bucket_values<I, B>(self, values: I) -> RangeSetCounts<T>
     where
         I: Iterator<Item = B>,
@@ -76,18 +191,54 @@ where
     {
         let mut buckets = vec![0usize; self.ranges.len()];
         for value in values {
-            for (idx, bucket) in self.ranges.iter().enumerate() {
-                if bucket.contains(value.borrow()) {
-                    buckets[idx] += 1;
-                }
-            }
+            self.count_value(value.borrow(), &mut buckets);
         }
         RangeSetCounts {
             set: self,
             counts: buckets,
+            other: None,
         }
     }
 
+    /// Count the values in each range, with `uncovered` saying what to do with values in no
+    /// range.
+    ///
+    /// With [`Uncovered::Error`], values in more than one range are also an error.
+    pub fn bucket_values_as<I, B>(
+        self,
+        values: I,
+        uncovered: Uncovered,
+    ) -> Result<RangeSetCounts<T>>
+    where
+        I: Iterator<Item = B>,
+        B: Borrow<T>,
+        T: fmt::Debug,
+    {
+        let mut buckets = vec![0usize; self.ranges.len()];
+        let mut other = 0;
+        for value in values {
+            let value = value.borrow();
+            match self.count_value(value, &mut buckets) {
+                0 => match uncovered {
+                    Uncovered::Drop => (),
+                    Uncovered::Error => bail!("{:?} is not in any range", value),
+                    Uncovered::Other => other += 1,
+                },
+                1 => (),
+                _ => ensure!(
+                    uncovered != Uncovered::Error,
+                    "{:?} is in more than one range",
+                    value
+                ),
+            }
+        }
+        Ok(RangeSetCounts {
+            set: self,
+            counts: buckets,
+            other: (uncovered == Uncovered::Other).then_some(other),
+        })
+    }
+
     pub fn bucket_values_with_missing<I, B>(self, values: I) -> RangeSetCountsWithMissing<T>
     where
         I: Iterator<Item = Option<B>>,
@@ -97,11 +248,7 @@ where
         let last = self.ranges.len();
         for value in values {
             if let Some(value) = value {
-                for (idx, bucket) in self.ranges.iter().enumerate() {
-                    if bucket.contains(value.borrow()) {
-                        buckets[idx] += 1;
-                    }
-                }
+                self.count_value(value.borrow(), &mut buckets);
             } else {
                 buckets[last] += 1;
             }
@@ -109,26 +256,141 @@ where
         RangeSetCountsWithMissing {
             set: self,
             counts: buckets,
+            missing_label: "missing data",
+        }
+    }
+
+    /// Add 1 to the bucket for every range containing `value`, returning how many there were.
+    fn count_value(&self, value: &T, buckets: &mut [usize]) -> usize {
+        let mut found = 0;
+        for (idx, bucket) in self.ranges.iter().enumerate() {
+            if bucket.contains(value) {
+                buckets[idx] += 1;
+                found += 1;
+            }
+        }
+        found
+    }
+}
+
+/// What to do with values that aren't in any range (see [`RangeSet::bucket_values_as`]).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Uncovered {
+    /// Leave them out of the counts.
+    Drop,
+    /// Stop with an error.
+    Error,
+    /// Count them in an extra "other" bucket.
+    Other,
+}
+
+/// Something wrong with the ranges in a [`RangeSet`], from [`RangeSet::validate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RangeProblem<T> {
+    /// Values in both ranges will be counted twice.
+    Overlap(Range<T>, Range<T>),
+    /// Values in this range won't be counted.
+    Gap(Range<T>),
+}
+
+impl<T> fmt::Display for RangeProblem<T>
+where
+    T: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RangeProblem::Overlap(first, second) => write!(f, "{} overlaps {}", first, second),
+            RangeProblem::Gap(gap) => write!(f, "{} is not in any range", gap),
         }
     }
 }
 
+/// Values that can be split into ranges of equal width (see [`RangeSet::equal_width`]).
+pub trait Interpolate: Ord + Clone {
+    /// The value `idx / n` of the way from `low` to `high`.
+    fn interpolate(low: Self, high: Self, idx: usize, n: usize) -> Self;
+}
+
+macro_rules! interpolate_int {
+    ($($ty:ty)*) => {
+        $(
+            impl Interpolate for $ty {
+                fn interpolate(low: Self, high: Self, idx: usize, n: usize) -> Self {
+                    // Exact for all the types here, and `idx < n` so the result is in range.
+                    let step = (high as i128 - low as i128) * idx as i128 / n as i128;
+                    (low as i128 + step) as $ty
+                }
+            }
+        )*
+    };
+}
+
+interpolate_int!(u8 u16 u32 u64 usize i16 i32 i64);
+
+impl Interpolate for N64 {
+    fn interpolate(low: Self, high: Self, idx: usize, n: usize) -> Self {
+        low + (high - low) * idx as f64 / n as f64
+    }
+}
+
 /// A range set with values bucketed, and bucket sizes recorded.
 pub struct RangeSetCounts<T> {
     set: RangeSet<T>,
     counts: Vec<usize>,
+    /// The number of values in no range, if they were counted ([`Uncovered::Other`]).
+    other: Option<usize>,
 }
 
 impl<T> RangeSetCounts<T> {
     pub fn iter(&self) -> impl Iterator<Item = (&Range<T>, usize)> {
         self.set.iter().zip_eq(self.counts.iter().copied())
     }
+
+    /// The number of values in no range, if they were counted ([`Uncovered::Other`]).
+    pub fn other(&self) -> Option<usize> {
+        self.other
+    }
+}
+
+/// The counts are for the ranges; the "other" bucket is only shown after them.
+impl<T> Counts for RangeSetCounts<T>
+where
+    T: fmt::Display,
+{
+    type Key<'a>
+        = &'a Range<T>
+    where
+        T: 'a;
+
+    fn counts(&self) -> impl Iterator<Item = (&Range<T>, usize)> {
+        self.iter()
+    }
+
+    fn for_display(&self) -> impl Iterator<Item = (&dyn fmt::Display, usize)> {
+        self.iter()
+            .map(|(range, count)| (range as &dyn fmt::Display, count))
+            .chain(
+                self.other
+                    .map(|count| (&"other" as &dyn fmt::Display, count)),
+            )
+    }
+}
+
+/// One row per range, then "other" if values in no range were counted.
+impl<T> Serialize for RangeSetCounts<T>
+where
+    T: fmt::Display,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.for_display().map(CountRow::new))
+    }
 }
 
 /// A range set with values bucketed, and bucket sizes recorded.
 pub struct RangeSetCountsWithMissing<T> {
     set: RangeSet<T>,
     counts: Vec<usize>,
+    missing_label: &'static str,
 }
 
 impl<T> RangeSetCountsWithMissing<T> {
@@ -142,19 +404,155 @@ impl<T> RangeSetCountsWithMissing<T> {
                 EitherOrBoth::Both(range, count) => (Some(range), count),
             })
     }
+
+    /// What to call the missing bucket when displaying the counts (default "missing data").
+    pub fn with_missing_label(mut self, label: &'static str) -> Self {
+        self.missing_label = label;
+        self
+    }
 }
 
-impl<T> RangeSetCountsWithMissing<T>
+/// The missing bucket has no range, and is labelled with the missing label when shown.
+impl<T> Counts for RangeSetCountsWithMissing<T>
 where
     T: fmt::Display,
 {
-    pub fn for_display(&self) -> impl Iterator<Item = (&dyn fmt::Display, usize)> {
+    type Key<'a>
+        = Option<&'a Range<T>>
+    where
+        T: 'a;
+
+    fn counts(&self) -> impl Iterator<Item = (Option<&Range<T>>, usize)> {
+        self.iter()
+    }
+
+    fn for_display(&self) -> impl Iterator<Item = (&dyn fmt::Display, usize)> {
         self.iter().map(|(range, count)| {
             let range = match range {
                 Some(range) => range,
-                None => &"missing data" as &dyn fmt::Display,
+                None => &self.missing_label as &dyn fmt::Display,
             };
             (range, count)
         })
     }
 }
+
+/// One row per range, then the missing bucket.
+impl<T> Serialize for RangeSetCountsWithMissing<T>
+where
+    T: fmt::Display,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.for_display().map(CountRow::new))
+    }
+}
+
+#[derive(Serialize)]
+struct CountRow {
+    range: String,
+    count: usize,
+}
+
+impl CountRow {
+    fn new((range, count): (&dyn fmt::Display, usize)) -> Self {
+        CountRow {
+            range: range.to_string(),
+            count,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::DisclosureControl;
+    use noisy_float::types::n64;
+
+    fn bounds<T: Clone>(set: &RangeSet<T>) -> Vec<(T, Option<T>)> {
+        set.iter()
+            .map(|range| (range.0.clone(), range.1.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn equal_width() {
+        let decades = RangeSet::equal_width(1900, 1930, 3);
+        assert_eq!(
+            bounds(&decades),
+            [(1900, Some(1910)), (1910, Some(1920)), (1920, None)]
+        );
+        // Rounded down, without empty ranges.
+        assert_eq!(
+            bounds(&RangeSet::equal_width(0u16, 3, 5)),
+            [(0, Some(1)), (1, Some(2)), (2, None)]
+        );
+        let halves = RangeSet::equal_width(n64(0.), n64(1.), 2);
+        assert_eq!(
+            bounds(&halves),
+            [(n64(0.), Some(n64(0.5))), (n64(0.5), None)]
+        );
+    }
+
+    #[test]
+    fn quantiles() {
+        let quartiles = RangeSet::quantiles(1..=8, 4);
+        assert_eq!(
+            bounds(&quartiles),
+            [(1, Some(3)), (3, Some(5)), (5, Some(7)), (7, None)]
+        );
+        let counts = quartiles.bucket_values(1..=8);
+        assert!(counts.iter().all(|(_, count)| count == 2));
+        // Ties give fewer ranges.
+        assert_eq!(
+            bounds(&RangeSet::quantiles([1, 1, 1, 2], 4)),
+            [(1, Some(2)), (2, None)]
+        );
+        assert!(bounds(&RangeSet::quantiles(Vec::<u16>::new(), 4)).is_empty());
+    }
+
+    #[test]
+    fn validate() {
+        let set = RangeSet::new(vec![
+            Range::new(0, Some(18)),
+            Range::new(10, Some(20)),
+            Range::new(25, Some(30)),
+            Range::new(30, None),
+        ]);
+        assert_eq!(
+            set.validate(),
+            [
+                RangeProblem::Overlap(Range::new(0, Some(18)), Range::new(10, Some(20))),
+                RangeProblem::Gap(Range::new(20, Some(25))),
+            ]
+        );
+        assert!(RangeSet::equal_width(0, 100, 5).validate().is_empty());
+
+        let values = || [5, 15, 22, 40].into_iter();
+        assert!(set
+            .clone()
+            .bucket_values_as(values(), Uncovered::Error)
+            .is_err());
+        let counts = set.bucket_values_as(values(), Uncovered::Other).unwrap();
+        assert_eq!(
+            counts.iter().map(|(_, count)| count).collect::<Vec<_>>(),
+            [2, 1, 0, 1]
+        );
+        assert_eq!(counts.other(), Some(1));
+        assert_eq!(counts.for_display().last().unwrap().0.to_string(), "other");
+    }
+
+    #[test]
+    fn percentages() {
+        let counts = RangeSet::equal_width(0u16, 20, 2).bucket_values([5, 12, 30, 40].into_iter());
+        assert_eq!(
+            counts.percentages(4).map(|(_, pc)| pc).collect::<Vec<_>>(),
+            [25., 75.]
+        );
+        assert_eq!(
+            serde_json::to_string(&counts).unwrap(),
+            r#"[{"range":"0 - 10","count":1},{"range":"10+","count":3}]"#
+        );
+        let table = counts.text_table("Age", 4, &DisclosureControl::NONE);
+        assert_eq!(table.rows()[1], ["10+", "3", "75.0%"]);
+    }
+}
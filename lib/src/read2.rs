@@ -1,18 +1,25 @@
 //! Get at the data in the Read browser, and use it to build a query utility for read v2.
 
+mod chapter;
+pub use chapter::{ChapterCount, ChapterGroup, ChapterSummary, ReadChapter};
 mod codeset;
-pub use codeset::{CodeSet, CodeSetMatcher};
+pub use codeset::{CodeCount, CodeSet, CodeSetMatcher, CodeUsage};
+mod medcodeset;
+pub use medcodeset::{MedCode, MedCodeSet};
+mod registry;
+pub use registry::{CodesetEntry, CodesetFormat, CodesetRegistry, Terminology};
 mod termset;
-pub use termset::{TermCodeSet, TermSet, User};
+pub use termset::{
+    CheckDiff, CheckReport, TermCodeSet, TermKind, TermSet, TermUsage, TermUsageReport, User,
+};
 mod thesaurus;
-pub use thesaurus::Thesaurus;
+pub use thesaurus::{CodeStatus, Thesaurus};
 
-use crate::ArcStr;
+use crate::{rubric::RubricNormalisation, ArcStr};
 use qu::ick_use::*;
 use serde::{Deserialize, Serialize};
 use std::{
     cmp::Ordering,
-    collections::BTreeSet,
     fmt::{self, Write},
     str::{self, FromStr},
 };
@@ -44,6 +51,9 @@ impl ReadCode {
         child.is_child_of(self)
     }
 
+    /// Parse a 5 character code, or a 7 character code with a term id.
+    ///
+    /// The term id is dropped: use [`ReadCodeTerm`] to keep it.
     pub fn from_bytes(v: &[u8]) -> Result<Self> {
         // validate
         if v.len() == 5 {
@@ -79,6 +89,185 @@ impl ReadCode {
     pub fn from_str(v: &str) -> Result<Self> {
         Self::from_bytes(v.as_bytes())
     }
+
+    /// Pack this code into the low 30 bits of a `u32`, 6 bits per character.
+    ///
+    /// Packed codes sort in the same order as the codes themselves.
+    pub fn to_packed(self) -> u32 {
+        self.0
+            .iter()
+            .fold(0, |packed, ch| packed << 6 | pack_read_ch(*ch))
+    }
+
+    /// Unpack a code packed using [`ReadCode::to_packed`].
+    pub fn from_packed(packed: u32) -> Result<Self> {
+        ensure!(packed >> 30 == 0, "packed Read codes only use 30 bits");
+        let mut out = [0; 5];
+        for (idx, ch) in out.iter_mut().enumerate() {
+            let bits = (packed >> (6 * (4 - idx))) & 0b11_1111;
+            *ch = unpack_read_ch(bits).context("invalid packed Read code character")?;
+        }
+        Ok(ReadCode(out))
+    }
+}
+
+/// Which of a code's descriptions was chosen (the last 2 characters of a 7 character code).
+///
+/// Term ids are letters or digits, e.g. `11` or `1M`. `00` is the preferred term, and the others
+/// are synonyms. The synonym can carry meaning the preferred term doesn't (e.g. laterality), so
+/// we keep it where the extract gives it.
+///
+/// Saved as a string in human-readable formats, and as (length-prefixed) bytes otherwise.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TermId([u8; 2]);
+
+impl TermId {
+    pub const PREFERRED: TermId = TermId(*b"00");
+
+    pub fn from_bytes(v: &[u8]) -> Result<Self> {
+        ensure!(
+            v.len() == 2 && v.iter().all(u8::is_ascii_alphanumeric),
+            "term ids are 2 letters or digits"
+        );
+        Ok(TermId([v[0], v[1]]))
+    }
+
+    pub fn is_preferred(self) -> bool {
+        self == TermId::PREFERRED
+    }
+}
+
+impl fmt::Debug for TermId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", char::from(self.0[0]), char::from(self.0[1]))
+    }
+}
+
+impl fmt::Display for TermId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl FromStr for TermId {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_bytes(s.as_bytes())
+    }
+}
+
+impl Serialize for TermId {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        if s.is_human_readable() {
+            s.serialize_str(str::from_utf8(&self.0).expect("we know we are an ascii string"))
+        } else {
+            s.serialize_bytes(&self.0)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for TermId {
+    fn deserialize<D>(deserializer: D) -> Result<TermId, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_str(TermIdVisitor)
+    }
+}
+
+struct TermIdVisitor;
+
+impl<'de> serde::de::Visitor<'de> for TermIdVisitor {
+    type Value = TermId;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a term id (either as a byte array or a string)")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        TermId::from_str(v).map_err(serde::de::Error::custom)
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        TermId::from_bytes(v).map_err(serde::de::Error::custom)
+    }
+}
+
+/// A Read code with the term id, if there was one.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ReadCodeTerm {
+    pub code: ReadCode,
+    pub term: Option<TermId>,
+}
+
+impl ReadCodeTerm {
+    /// Parse a 5 character code, or a 7 character code with a term id.
+    pub fn from_bytes(v: &[u8]) -> Result<Self> {
+        let code = ReadCode::from_bytes(v)?;
+        let term = match v.get(5..) {
+            Some(term) if !term.is_empty() => Some(TermId::from_bytes(term)?),
+            _ => None,
+        };
+        Ok(ReadCodeTerm { code, term })
+    }
+}
+
+impl fmt::Display for ReadCodeTerm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.code, f)?;
+        match self.term {
+            Some(term) => fmt::Display::fmt(&term, f),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FromStr for ReadCodeTerm {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_bytes(s.as_bytes())
+    }
+}
+
+/// A [`ReadCode`] packed into a `u32` (see [`ReadCode::to_packed`]), used as the key in our
+/// larger indexes.
+///
+/// Sorts in the same order as `ReadCode`, and serializes as one.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+#[serde(from = "ReadCode", into = "ReadCode")]
+pub struct PackedReadCode(u32);
+
+impl PackedReadCode {
+    pub fn code(self) -> ReadCode {
+        ReadCode::from_packed(self.0).expect("only valid Read codes are packed")
+    }
+}
+
+impl From<ReadCode> for PackedReadCode {
+    fn from(code: ReadCode) -> Self {
+        PackedReadCode(code.to_packed())
+    }
+}
+
+impl From<PackedReadCode> for ReadCode {
+    fn from(packed: PackedReadCode) -> Self {
+        packed.code()
+    }
+}
+
+impl fmt::Debug for PackedReadCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.code(), f)
+    }
 }
 
 impl fmt::Debug for ReadCode {
@@ -209,16 +398,44 @@ impl CodeRubric {
             rubric: rubric.into(),
         }
     }
+
+    /// A code/rubric pair with the rubric normalised.
+    pub fn normalised(code: ReadCode, rubric: &str, normalisation: &RubricNormalisation) -> Self {
+        Self::new(code, normalisation.apply(rubric))
+    }
 }
 
 fn is_read_ch(b: u8) -> bool {
     b.is_ascii_alphanumeric() || b == b'.'
 }
 
-/// Helper to render to string a set of descriptions from a thesaurus.
-fn show_descriptions(descs: &BTreeSet<ArcStr>) -> String {
+/// `.` is 0, then digits, then upper- and lower-case letters, so the packed value sorts like the
+/// character.
+fn pack_read_ch(b: u8) -> u32 {
+    u32::from(match b {
+        b'.' => 0,
+        b'0'..=b'9' => b - b'0' + 1,
+        b'A'..=b'Z' => b - b'A' + 11,
+        b'a'..=b'z' => b - b'a' + 37,
+        _ => unreachable!("Read codes contain characters [a-zA-Z0-9.]"),
+    })
+}
+
+fn unpack_read_ch(bits: u32) -> Option<u8> {
+    let bits = u8::try_from(bits).ok()?;
+    Some(match bits {
+        0 => b'.',
+        1..=10 => bits - 1 + b'0',
+        11..=36 => bits - 11 + b'A',
+        37..=62 => bits - 37 + b'a',
+        _ => return None,
+    })
+}
+
+/// Helper to render to string a list of descriptions from a thesaurus.
+fn show_descriptions<'a>(descs: impl IntoIterator<Item = &'a ArcStr>) -> String {
     let mut out = String::new();
-    let mut parts = descs.iter();
+    let mut parts = descs.into_iter();
     if let Some(desc) = parts.next() {
         write!(out, "{:?}", desc).unwrap();
     }
@@ -227,3 +444,41 @@ fn show_descriptions(descs: &BTreeSet<ArcStr>) -> String {
     }
     out
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn packed() {
+        let codes: Vec<ReadCode> = [
+            "B....", "B6...", "B62..", "B620.", "B62z.", "B6z..", "Ba...",
+        ]
+        .into_iter()
+        .map(|code| code.parse().unwrap())
+        .collect();
+        for code in &codes {
+            assert_eq!(ReadCode::from_packed(code.to_packed()).unwrap(), *code);
+        }
+        for pair in codes.windows(2) {
+            assert!(pair[0] < pair[1]);
+            assert!(pair[0].to_packed() < pair[1].to_packed());
+        }
+        assert!(ReadCode::from_packed(u32::MAX).is_err());
+        assert!(ReadCode::from_packed(63).is_err());
+    }
+
+    #[test]
+    fn code_term() {
+        let code_term: ReadCodeTerm = "B620.12".parse().unwrap();
+        assert_eq!(code_term.code, ReadCode::from_str("B620.").unwrap());
+        assert_eq!(code_term.term, Some(TermId::from_str("12").unwrap()));
+        assert_eq!(code_term.to_string(), "B620.12");
+        let code_term: ReadCodeTerm = "B620.".parse().unwrap();
+        assert_eq!(code_term.term, None);
+        assert_eq!(code_term.to_string(), "B620.");
+        assert_eq!(TermId::from_str("1M").unwrap().to_string(), "1M");
+        assert!(TermId::from_str("1-").is_err());
+        assert!(TermId::from_str("1").is_err());
+    }
+}
@@ -0,0 +1,295 @@
+//! The top-level chapters of Read v2.
+//!
+//! The first character of a code gives its chapter: digits are history, examination, procedures
+//! and administration, upper-case letters are diagnoses (roughly following ICD-9 chapters), and
+//! lower-case letters are drugs and appliances.
+use crate::{
+    read2::{ReadCode, Thesaurus},
+    render::TextTable,
+    DisclosureControl,
+};
+use std::{collections::BTreeMap, fmt};
+use term_data_table::Table;
+
+/// A top-level Read v2 chapter.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ReadChapter {
+    Occupations,
+    HistorySymptoms,
+    Examination,
+    DiagnosticProcedures,
+    Laboratory,
+    Radiology,
+    Preventive,
+    Operations,
+    OtherTherapeutic,
+    Administration,
+    Infectious,
+    Neoplasms,
+    Endocrine,
+    Blood,
+    Mental,
+    Nervous,
+    Circulatory,
+    Respiratory,
+    Digestive,
+    Genitourinary,
+    Pregnancy,
+    Skin,
+    Musculoskeletal,
+    Congenital,
+    Perinatal,
+    IllDefined,
+    Injury,
+    CausesOfInjury,
+    ExternalCauses,
+    UnspecifiedConditions,
+    /// All the lower-case chapters.
+    Drugs,
+    /// Codes that don't start with a character used by Read v2 (e.g. local codes).
+    Unknown,
+}
+
+impl ReadChapter {
+    pub fn of(code: ReadCode) -> Self {
+        let code: &[u8] = code.as_ref();
+        match code[0] {
+            b'0' => ReadChapter::Occupations,
+            b'1' => ReadChapter::HistorySymptoms,
+            b'2' => ReadChapter::Examination,
+            b'3' => ReadChapter::DiagnosticProcedures,
+            b'4' => ReadChapter::Laboratory,
+            b'5' => ReadChapter::Radiology,
+            b'6' => ReadChapter::Preventive,
+            b'7' => ReadChapter::Operations,
+            b'8' => ReadChapter::OtherTherapeutic,
+            b'9' => ReadChapter::Administration,
+            b'A' => ReadChapter::Infectious,
+            b'B' => ReadChapter::Neoplasms,
+            b'C' => ReadChapter::Endocrine,
+            b'D' => ReadChapter::Blood,
+            b'E' => ReadChapter::Mental,
+            b'F' => ReadChapter::Nervous,
+            b'G' => ReadChapter::Circulatory,
+            b'H' => ReadChapter::Respiratory,
+            b'J' => ReadChapter::Digestive,
+            b'K' => ReadChapter::Genitourinary,
+            b'L' => ReadChapter::Pregnancy,
+            b'M' => ReadChapter::Skin,
+            b'N' => ReadChapter::Musculoskeletal,
+            b'P' => ReadChapter::Congenital,
+            b'Q' => ReadChapter::Perinatal,
+            b'R' => ReadChapter::IllDefined,
+            b'S' => ReadChapter::Injury,
+            b'T' => ReadChapter::CausesOfInjury,
+            b'U' => ReadChapter::ExternalCauses,
+            b'Z' => ReadChapter::UnspecifiedConditions,
+            b'a'..=b'z' => ReadChapter::Drugs,
+            _ => ReadChapter::Unknown,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ReadChapter::Occupations => "Occupations",
+            ReadChapter::HistorySymptoms => "History / symptoms",
+            ReadChapter::Examination => "Examination / signs",
+            ReadChapter::DiagnosticProcedures => "Diagnostic procedures",
+            ReadChapter::Laboratory => "Laboratory procedures",
+            ReadChapter::Radiology => "Radiology / physics in medicine",
+            ReadChapter::Preventive => "Preventive procedures",
+            ReadChapter::Operations => "Operations, procedures, sites",
+            ReadChapter::OtherTherapeutic => "Other therapeutic procedures",
+            ReadChapter::Administration => "Administration",
+            ReadChapter::Infectious => "Infectious/parasitic diseases",
+            ReadChapter::Neoplasms => "Neoplasms",
+            ReadChapter::Endocrine => "Endocrine/nutritional/metabolic diseases",
+            ReadChapter::Blood => "Blood diseases",
+            ReadChapter::Mental => "Mental disorders",
+            ReadChapter::Nervous => "Nervous system/sense organ diseases",
+            ReadChapter::Circulatory => "Circulatory system diseases",
+            ReadChapter::Respiratory => "Respiratory system diseases",
+            ReadChapter::Digestive => "Digestive system diseases",
+            ReadChapter::Genitourinary => "Genitourinary system diseases",
+            ReadChapter::Pregnancy => "Pregnancy/childbirth/puerperium",
+            ReadChapter::Skin => "Skin/subcutaneous tissue diseases",
+            ReadChapter::Musculoskeletal => "Musculoskeletal/connective tissue diseases",
+            ReadChapter::Congenital => "Congenital anomalies",
+            ReadChapter::Perinatal => "Perinatal conditions",
+            ReadChapter::IllDefined => "Symptoms, signs and ill-defined conditions",
+            ReadChapter::Injury => "Injury and poisoning",
+            ReadChapter::CausesOfInjury => "Causes of injury and poisoning",
+            ReadChapter::ExternalCauses => "External causes of morbidity and mortality",
+            ReadChapter::UnspecifiedConditions => "Unspecified conditions",
+            ReadChapter::Drugs => "Drugs and appliances",
+            ReadChapter::Unknown => "Unknown chapter",
+        }
+    }
+
+    pub fn group(self) -> ChapterGroup {
+        match self {
+            ReadChapter::HistorySymptoms | ReadChapter::Examination => {
+                ChapterGroup::HistoryExamination
+            }
+            ReadChapter::DiagnosticProcedures
+            | ReadChapter::Laboratory
+            | ReadChapter::Radiology
+            | ReadChapter::Preventive
+            | ReadChapter::Operations
+            | ReadChapter::OtherTherapeutic => ChapterGroup::Procedures,
+            ReadChapter::Occupations | ReadChapter::Administration => ChapterGroup::Administration,
+            ReadChapter::Drugs => ChapterGroup::Medication,
+            ReadChapter::Unknown => ChapterGroup::Unknown,
+            _ => ChapterGroup::Diagnoses,
+        }
+    }
+}
+
+impl fmt::Display for ReadChapter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// Broad groups of Read chapters.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ChapterGroup {
+    Diagnoses,
+    HistoryExamination,
+    Procedures,
+    Medication,
+    Administration,
+    Unknown,
+}
+
+impl ChapterGroup {
+    pub const ALL: [ChapterGroup; 6] = [
+        ChapterGroup::Diagnoses,
+        ChapterGroup::HistoryExamination,
+        ChapterGroup::Procedures,
+        ChapterGroup::Medication,
+        ChapterGroup::Administration,
+        ChapterGroup::Unknown,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ChapterGroup::Diagnoses => "Diagnoses",
+            ChapterGroup::HistoryExamination => "History and examination",
+            ChapterGroup::Procedures => "Procedures and investigations",
+            ChapterGroup::Medication => "Medication",
+            ChapterGroup::Administration => "Administration",
+            ChapterGroup::Unknown => "Unknown",
+        }
+    }
+}
+
+impl fmt::Display for ChapterGroup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// The number of events in a chapter.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ChapterCount {
+    pub events: usize,
+    /// Events whose code isn't in the thesaurus.
+    pub not_in_thesaurus: usize,
+}
+
+/// How many events there are in each Read chapter.
+#[derive(Debug, Clone, Default)]
+pub struct ChapterSummary {
+    pub total: usize,
+    /// Only chapters with at least one event.
+    pub chapters: BTreeMap<ReadChapter, ChapterCount>,
+}
+
+impl ChapterSummary {
+    /// Summarise a list of codes, with the number of events for each.
+    pub fn new(code_counts: impl Iterator<Item = (ReadCode, usize)>, th: &Thesaurus) -> Self {
+        let mut summary = ChapterSummary::default();
+        for (code, events) in code_counts {
+            let count = summary.chapters.entry(ReadChapter::of(code)).or_default();
+            count.events += events;
+            if th.get(code).is_none() {
+                count.not_in_thesaurus += events;
+            }
+            summary.total += events;
+        }
+        summary
+    }
+
+    /// The number of events in each group of chapters.
+    pub fn groups(&self) -> BTreeMap<ChapterGroup, usize> {
+        let mut groups = BTreeMap::new();
+        for (chapter, count) in self.chapters.iter() {
+            *groups.entry(chapter.group()).or_default() += count.events;
+        }
+        groups
+    }
+
+    pub fn term_table(&self, dc: &DisclosureControl) -> Table<'_> {
+        self.text_table(dc).into_term_table()
+    }
+
+    pub fn text_table(&self, dc: &DisclosureControl) -> TextTable {
+        let mut table = TextTable::new([
+            "Chapter",
+            "Group",
+            "Events",
+            "Percentage",
+            "Not in thesaurus",
+        ]);
+        for (chapter, count) in self.chapters.iter() {
+            table.push_row([
+                chapter.label().to_string(),
+                chapter.group().label().to_string(),
+                dc.count(count.events).to_string(),
+                self.percentage(count.events, dc),
+                dc.count(count.not_in_thesaurus).to_string(),
+            ]);
+        }
+        table
+    }
+
+    pub fn group_table(&self, dc: &DisclosureControl) -> Table<'_> {
+        self.group_text_table(dc).into_term_table()
+    }
+
+    pub fn group_text_table(&self, dc: &DisclosureControl) -> TextTable {
+        let mut table = TextTable::new(["Group", "Events", "Percentage"]);
+        for (group, events) in self.groups() {
+            table.push_row([
+                group.label().to_string(),
+                dc.count(events).to_string(),
+                self.percentage(events, dc),
+            ]);
+        }
+        table
+    }
+
+    fn percentage(&self, events: usize, dc: &DisclosureControl) -> String {
+        match dc.percentage(events, self.total) {
+            Some(pc) => format!("{pc:.1}%"),
+            None => "-".into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn chapters() {
+        let chapter = |code: &str| ReadChapter::of(ReadCode::from_str(code).unwrap());
+        assert_eq!(chapter("9N1C."), ReadChapter::Administration);
+        assert_eq!(chapter("B22.."), ReadChapter::Neoplasms);
+        assert_eq!(chapter("bd3i."), ReadChapter::Drugs);
+        assert_eq!(chapter("ZV10."), ReadChapter::UnspecifiedConditions);
+        assert_eq!(chapter("B22..").group(), ChapterGroup::Diagnoses);
+        assert_eq!(chapter("44J3.").group(), ChapterGroup::Procedures);
+    }
+}
@@ -1,6 +1,7 @@
 use crate::{
-    read2::{show_descriptions, ReadCode, Thesaurus},
-    util, Events, PatientId,
+    provenance,
+    read2::{ReadCode, Thesaurus},
+    util, DisclosureControl, Events, PatientId,
 };
 
 use aho_corasick::AhoCorasick;
@@ -8,7 +9,7 @@ use chrono::NaiveDate;
 use qu::ick_use::*;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::{btree_set, BTreeSet, HashMap},
+    collections::{btree_set, BTreeSet, HashMap, HashSet},
     fmt, fs,
     io::prelude::*,
     iter, ops,
@@ -56,12 +57,16 @@ impl CodeSet {
 
     /// Load a codeset from a list of codes - 1 per line.
     ///
-    /// We use the csv deserializer to get nicer error messages.
+    /// We use the csv deserializer to get nicer error messages. There is no header line, so the
+    /// first line is a code.
     pub fn load(path: impl AsRef<Path>) -> Result<Self> {
         fn inner(path: &Path) -> Result<CodeSet> {
             let reader = fs::File::open(path)?;
+            provenance::record_input(path);
             Ok(CodeSet::new(
-                csv::Reader::from_reader(reader)
+                csv::ReaderBuilder::new()
+                    .has_headers(false)
+                    .from_reader(reader)
                     .into_deserialize()
                     .map(|v| v.map_err(Error::from))
                     .collect::<Result<BTreeSet<ReadCode>>>()?,
@@ -76,8 +81,14 @@ impl CodeSet {
     pub fn load_camb(path: impl AsRef<Path>) -> Result<Self> {
         fn inner(path: &Path) -> Result<CodeSet> {
             let reader = fs::File::open(path)?;
+            provenance::record_input(path);
+            let mut reader = csv::Reader::from_reader(reader);
+            ensure!(
+                !reader.headers()?.iter().any(|header| header == "prodcode"),
+                "this is a medication code list: load it with `MedCodeSet::load_camb`"
+            );
             Ok(CodeSet::new(
-                csv::Reader::from_reader(reader)
+                reader
                     .into_records()
                     .filter_map(|field| {
                         let field = match field {
@@ -106,6 +117,10 @@ impl CodeSet {
         self.codes.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.codes.is_empty()
+    }
+
     pub fn iter(&self) -> iter::Copied<btree_set::Iter<'_, ReadCode>> {
         self.codes.iter().copied()
     }
@@ -131,9 +146,7 @@ impl CodeSet {
                 table.add_row(
                     Row::new()
                         .with_cell(Cell::from(code.to_string()))
-                        .with_cell(Cell::from(show_descriptions(
-                            th.get(code).unwrap_or(&BTreeSet::new()),
-                        ))),
+                        .with_cell(Cell::from(th.describe(code))),
                 );
             }
             table
@@ -150,6 +163,32 @@ impl CodeSet {
     pub fn into_matcher(self) -> CodeSetMatcher {
         CodeSetMatcher::new(self)
     }
+
+    /// How many events, and how many different patients, there are for each code.
+    pub fn usage(&self, events: &Events) -> CodeUsage {
+        let mut all_patients = HashSet::new();
+        let codes = self
+            .iter()
+            .map(|code| {
+                let mut patients = HashSet::new();
+                let mut count = 0;
+                for evt in events.with_code(code) {
+                    patients.insert(evt.patient_id);
+                    count += 1;
+                }
+                all_patients.extend(patients.iter().copied());
+                CodeCount {
+                    code,
+                    events: count,
+                    patients: patients.len(),
+                }
+            })
+            .collect();
+        CodeUsage {
+            codes,
+            patients: all_patients.len(),
+        }
+    }
 }
 
 impl FromIterator<ReadCode> for CodeSet {
@@ -190,6 +229,93 @@ impl fmt::Display for CodeSet {
     }
 }
 
+/// The number of events and patients for one code.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CodeCount {
+    pub code: ReadCode,
+    pub events: usize,
+    pub patients: usize,
+}
+
+/// How much each code in a codeset is used, from [`CodeSet::usage`].
+#[derive(Debug, Clone)]
+pub struct CodeUsage {
+    /// In code order.
+    pub codes: Vec<CodeCount>,
+    /// The number of patients with any of the codes.
+    pub patients: usize,
+}
+
+#[derive(Serialize)]
+struct CodeUsageRow {
+    code: String,
+    description: String,
+    events: Option<usize>,
+    patients: Option<usize>,
+}
+
+impl CodeUsage {
+    /// Codes that no-one in the data has.
+    pub fn unused(&self) -> impl Iterator<Item = ReadCode> + '_ {
+        self.codes
+            .iter()
+            .filter(|count| count.events == 0)
+            .map(|count| count.code)
+    }
+
+    pub fn term_table(&self, th: &Thesaurus, dc: &DisclosureControl) -> term_data_table::Table<'_> {
+        use term_data_table::{Row, Table};
+        let mut table = Table::new().with_row(
+            Row::new()
+                .with_cell("Code")
+                .with_cell("Descriptions")
+                .with_cell("Events")
+                .with_cell("Patients"),
+        );
+        for count in self.codes.iter() {
+            table.add_row(
+                Row::new()
+                    .with_cell(count.code.to_string())
+                    .with_cell(th.describe(count.code))
+                    .with_cell(dc.count(count.events).to_string())
+                    .with_cell(dc.count(count.patients).to_string()),
+            );
+        }
+        table.with_row(
+            Row::new()
+                .with_cell("Any")
+                .with_cell("")
+                .with_cell(
+                    dc.count(self.codes.iter().map(|count| count.events).sum())
+                        .to_string(),
+                )
+                .with_cell(dc.count(self.patients).to_string()),
+        )
+    }
+
+    /// Write one row per code, with suppressed counts left empty.
+    pub fn save_csv(
+        &self,
+        path: impl AsRef<Path>,
+        th: &Thesaurus,
+        dc: &DisclosureControl,
+    ) -> Result<()> {
+        let path = path.as_ref();
+        let mut out = csv::Writer::from_path(path)
+            .with_context(|| format!("creating \"{}\"", path.display()))?;
+        for count in self.codes.iter() {
+            out.serialize(CodeUsageRow {
+                code: count.code.to_string(),
+                description: th.describe(count.code),
+                events: dc.count(count.events).value(),
+                patients: dc.count(count.patients).value(),
+            })?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
 // CodeSet with a matcher
 
 pub struct CodeSetMatcher {
@@ -207,15 +333,21 @@ impl CodeSetMatcher {
         self.matcher.is_match(code)
     }
 
+    /// The date of each patient's first event with a code in the set, ignoring missing dates.
+    ///
+    /// See [`Events::first_code_in`] for a single patient.
     pub fn earliest_code(&self, events: &Events) -> HashMap<PatientId, NaiveDate> {
-        let mut map = HashMap::new();
-        for evt in events.iter().filter(|evt| self.contains(evt.read_code)) {
-            let entry = map.entry(evt.patient_id).or_insert(evt.date);
-            if *entry < evt.date {
-                *entry = evt.date;
-            }
-        }
-        map
+        let patient_ids: HashSet<PatientId> = events
+            .with_codeset(&self.code_set)
+            .map(|evt| evt.patient_id)
+            .collect();
+        patient_ids
+            .into_iter()
+            .filter_map(|patient_id| {
+                let evt = events.first_code_in(&self.code_set, patient_id)?;
+                Some((patient_id, evt.date_known()?))
+            })
+            .collect()
     }
 
     pub fn into_inner(self) -> CodeSet {
@@ -230,3 +362,64 @@ impl ops::Deref for CodeSetMatcher {
         &self.code_set
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_event;
+
+    #[test]
+    fn usage() {
+        let date = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
+        let event = |patient_id, code| test_event(patient_id, date, code);
+        let events = Events::new(vec![
+            event(1, "B620."),
+            event(1, "B620."),
+            event(2, "B620."),
+            event(2, "B621."),
+            event(3, "H33.."),
+        ]);
+        let codes: CodeSet = ["B620.", "B621.", "B622."]
+            .into_iter()
+            .map(|code| code.parse().unwrap())
+            .collect();
+        let usage = codes.usage(&events);
+        let counts = usage
+            .codes
+            .iter()
+            .map(|count| (count.events, count.patients))
+            .collect::<Vec<_>>();
+        assert_eq!(counts, [(3, 2), (1, 1), (0, 0)]);
+        assert_eq!(usage.patients, 2);
+        assert_eq!(usage.unused().count(), 1);
+    }
+
+    #[test]
+    fn earliest_code() {
+        let date = |y| NaiveDate::from_ymd_opt(y, 1, 1).unwrap();
+        let event = |date, code| test_event(1, date, code);
+        let events = Events::new(vec![
+            event(date(2019), "B621."),
+            event(date(2015), "H33.."),
+            event(date(2017), "B620."),
+            event(crate::missing_date(), "B620."),
+        ]);
+        let codes: CodeSet = ["B620.", "B621."]
+            .into_iter()
+            .map(|code| code.parse().unwrap())
+            .collect();
+        let first = events.first_code_in(&codes, PatientId::new(1)).unwrap();
+        assert_eq!(
+            (first.date, first.read_code),
+            (date(2017), "B620.".parse().unwrap())
+        );
+        let last = events.last_code_in(&codes, PatientId::new(1)).unwrap();
+        assert_eq!(
+            (last.date, last.read_code),
+            (date(2019), "B621.".parse().unwrap())
+        );
+        assert!(events.first_code_in(&codes, PatientId::new(2)).is_none());
+        let earliest = codes.into_matcher().earliest_code(&events);
+        assert_eq!(earliest.get(&PatientId::new(1)), Some(&date(2017)));
+    }
+}
@@ -0,0 +1,143 @@
+//! Medication code lists from CPRD@Cambridge.
+//!
+//! The `_pc` lists give CPRD product codes (with the GEMSCRIPT code and product name) rather than
+//! Read codes, so they can't go in a [`CodeSet`](super::CodeSet). Our prescriptions are coded with
+//! Read drug codes, so nothing matches these yet: they are here for when we have prescriptions
+//! coded with product or GEMSCRIPT codes.
+use crate::{provenance, ArcStr};
+use qu::ick_use::*;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, io::Read, path::Path, sync::Arc};
+
+/// The coding systems in the `CodingSystem` column that we keep.
+const MED_CODING_SYSTEMS: &[&str] = &["prodcode", "gemscript"];
+
+/// One product in a medication code list.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct MedCode {
+    /// The CPRD product code.
+    pub prodcode: u64,
+    pub product_name: ArcStr,
+    /// The GEMSCRIPT code, if the list gives one.
+    pub gemscript: Option<ArcStr>,
+}
+
+/// A set of medication codes, keyed by CPRD product code.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct MedCodeSet {
+    codes: Arc<BTreeMap<u64, MedCode>>,
+}
+
+impl MedCodeSet {
+    /// Load a codeset from a file in the cprd@cambridge prodcodes format.
+    pub fn load_camb(path: impl AsRef<Path>) -> Result<Self> {
+        fn inner(path: &Path) -> Result<MedCodeSet> {
+            let reader = fs::File::open(path)?;
+            provenance::record_input(path);
+            MedCodeSet::from_camb_reader(reader)
+        }
+
+        let path = path.as_ref();
+        inner(path).with_context(|| {
+            format!(
+                "loading medication codeset from file \"{}\"",
+                path.display()
+            )
+        })
+    }
+
+    fn from_camb_reader(reader: impl Read) -> Result<Self> {
+        let mut reader = csv::Reader::from_reader(reader);
+        let headers = reader.headers()?.clone();
+        let column = |name: &str| {
+            headers
+                .iter()
+                .position(|header| header.eq_ignore_ascii_case(name))
+                .with_context(|| format!("no \"{}\" column", name))
+        };
+        let prodcode_col = column("prodcode")?;
+        let name_col = column("productname")?;
+        let system_col = column("CodingSystem")?;
+        // Not every list has GEMSCRIPT codes.
+        let gemscript_col = column("gemscriptcode").ok();
+
+        let mut codes = BTreeMap::new();
+        for (idx, record) in reader.into_records().enumerate() {
+            let record = record?;
+            // The header is line 1.
+            let line = idx + 2;
+            let get = |col: usize| record.get(col).unwrap_or("").trim();
+            if !MED_CODING_SYSTEMS.contains(&get(system_col)) {
+                continue;
+            }
+            let prodcode = get(prodcode_col)
+                .parse()
+                .with_context(|| format!("invalid prodcode on line {}", line))?;
+            let gemscript = gemscript_col
+                .map(get)
+                .filter(|code| !code.is_empty())
+                .map(ArcStr::from);
+            codes.insert(
+                prodcode,
+                MedCode {
+                    prodcode,
+                    product_name: get(name_col).into(),
+                    gemscript,
+                },
+            );
+        }
+        Ok(MedCodeSet {
+            codes: Arc::new(codes),
+        })
+    }
+
+    pub fn get(&self, prodcode: u64) -> Option<&MedCode> {
+        self.codes.get(&prodcode)
+    }
+
+    pub fn contains_prodcode(&self, prodcode: u64) -> bool {
+        self.codes.contains_key(&prodcode)
+    }
+
+    pub fn contains_gemscript(&self, gemscript: &str) -> bool {
+        self.iter()
+            .any(|code| code.gemscript.as_deref() == Some(gemscript))
+    }
+
+    pub fn len(&self) -> usize {
+        self.codes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.codes.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &MedCode> + '_ {
+        self.codes.values()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn load_camb() {
+        let input = "\"prodcode\",\"CodingSystem\",\"productname\",\"gemscriptcode\"\n\
+            \"20\",\"prodcode\",\"Temazepam 10mg tablets\",\"68342020\"\n\
+            \"35\",\"prodcode\",\"Nitrazepam 5mg tablets\",\"\"\n";
+        let codes = MedCodeSet::from_camb_reader(input.as_bytes()).unwrap();
+        assert_eq!(codes.len(), 2);
+        assert_eq!(
+            codes.get(20).unwrap().product_name.as_ref(),
+            "Temazepam 10mg tablets"
+        );
+        assert!(codes.contains_gemscript("68342020"));
+        assert_eq!(codes.get(35).unwrap().gemscript, None);
+        assert!(!codes.contains_prodcode(191));
+
+        let medcodes = "\"medcode\",\"readcode\",\"Description\",\"CodingSystem\"\n\
+            191,\"E278100\",\"Tension headache\",\"readcode\"\n";
+        assert!(MedCodeSet::from_camb_reader(medcodes.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,231 @@
+//! The codesets we use, by name.
+//!
+//! Rather than loading codesets from hard-coded file names, code asks the registry for a codeset by
+//! name, and the registry records where each codeset lives and where it came from. The manifest is
+//! a TOML file with a list of `[[codeset]]` tables. The built-in manifest is
+//! `read2/codesets.toml`, with entries like
+//!
+//! ```toml
+//! [[codeset]]
+//! name = "hef158_mc"
+//! format = "camb"
+//! path = "hef158_mc.csv"
+//! terminology = "read2"
+//! source = "CPRD@Cambridge"
+//! version = "v11"
+//! description = "Heart failure"
+//! ```
+use crate::{
+    read2::{CodeSet, MedCodeSet},
+    DataPaths,
+};
+use once_cell::sync::Lazy;
+use qu::ick_use::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeSet,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+const BUILTIN_MANIFEST: &str = include_str!("codesets.toml");
+
+static BUILTIN: Lazy<CodesetRegistry> = Lazy::new(|| {
+    CodesetRegistry::parse(BUILTIN_MANIFEST).expect("built-in codeset manifest is invalid")
+});
+
+/// Where a codeset's file is, and how to read it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CodesetFormat {
+    /// A CPRD@Cambridge code list, relative to the Cambridge codesets directory.
+    Camb,
+    /// One of our termsets, given by its directory relative to the termsets directory.
+    Termset,
+}
+
+impl CodesetFormat {
+    pub fn label(self) -> &'static str {
+        match self {
+            CodesetFormat::Camb => "Cambridge code list",
+            CodesetFormat::Termset => "Termset",
+        }
+    }
+}
+
+impl fmt::Display for CodesetFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// The kind of codes in a codeset.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Terminology {
+    Read2,
+    /// CPRD product codes (see [`MedCodeSet`]).
+    Prodcode,
+}
+
+impl Terminology {
+    pub fn label(self) -> &'static str {
+        match self {
+            Terminology::Read2 => "Read v2",
+            Terminology::Prodcode => "CPRD prodcode",
+        }
+    }
+}
+
+impl fmt::Display for Terminology {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// One codeset in the manifest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CodesetEntry {
+    pub name: String,
+    pub format: CodesetFormat,
+    /// Relative to the directory for the format.
+    pub path: PathBuf,
+    pub terminology: Terminology,
+    /// Who made the codeset.
+    pub source: String,
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+impl CodesetEntry {
+    /// The file the codes are in.
+    pub fn file_path(&self, paths: &DataPaths) -> PathBuf {
+        match self.format {
+            CodesetFormat::Camb => paths.camb_codeset_path(&self.path),
+            CodesetFormat::Termset => paths.termset_path(&self.path).join("codes.txt"),
+        }
+    }
+
+    /// Load a Read v2 codeset.
+    pub fn load(&self, paths: &DataPaths) -> Result<CodeSet> {
+        ensure!(
+            self.terminology == Terminology::Read2,
+            "codeset \"{}\" has {} codes, not Read v2",
+            self.name,
+            self.terminology
+        );
+        let path = self.file_path(paths);
+        match self.format {
+            CodesetFormat::Camb => CodeSet::load_camb(path),
+            CodesetFormat::Termset => CodeSet::load(path),
+        }
+    }
+
+    /// Load a medication codeset.
+    pub fn load_med(&self, paths: &DataPaths) -> Result<MedCodeSet> {
+        ensure!(
+            self.terminology == Terminology::Prodcode,
+            "codeset \"{}\" has {} codes, not product codes",
+            self.name,
+            self.terminology
+        );
+        ensure!(
+            self.format == CodesetFormat::Camb,
+            "product codes can only be loaded from Cambridge code lists"
+        );
+        MedCodeSet::load_camb(self.file_path(paths))
+    }
+
+    /// Load the codeset whatever its terminology, returning the number of codes.
+    pub fn check(&self, paths: &DataPaths) -> Result<usize> {
+        Ok(match self.terminology {
+            Terminology::Read2 => self.load(paths)?.len(),
+            Terminology::Prodcode => self.load_med(paths)?.len(),
+        })
+    }
+}
+
+/// All the codesets we know about.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CodesetRegistry {
+    #[serde(rename = "codeset")]
+    entries: Vec<CodesetEntry>,
+}
+
+impl CodesetRegistry {
+    /// The codesets in `read2/codesets.toml`.
+    pub fn builtin() -> &'static Self {
+        &BUILTIN
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        fn inner(path: &Path) -> Result<CodesetRegistry> {
+            CodesetRegistry::parse(&fs::read_to_string(path)?)
+        }
+        let path = path.as_ref();
+        inner(path).with_context(|| format!("while loading \"{}\"", path.display()))
+    }
+
+    fn parse(input: &str) -> Result<Self> {
+        let registry: Self = toml::from_str(input)?;
+        let mut names = BTreeSet::new();
+        for entry in registry.iter() {
+            ensure!(
+                names.insert(entry.name.as_str()),
+                "codeset \"{}\" is registered more than once",
+                entry.name
+            );
+        }
+        Ok(registry)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&CodesetEntry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    /// Load the Read v2 codeset called `name`.
+    pub fn load_codeset(&self, name: &str, paths: &DataPaths) -> Result<CodeSet> {
+        fn inner(this: &CodesetRegistry, name: &str, paths: &DataPaths) -> Result<CodeSet> {
+            this.get(name).context("not registered")?.load(paths)
+        }
+        inner(self, name, paths).with_context(|| format!("loading codeset \"{}\"", name))
+    }
+
+    /// Load the medication codeset called `name`.
+    pub fn load_med_codeset(&self, name: &str, paths: &DataPaths) -> Result<MedCodeSet> {
+        fn inner(this: &CodesetRegistry, name: &str, paths: &DataPaths) -> Result<MedCodeSet> {
+            this.get(name).context("not registered")?.load_med(paths)
+        }
+        inner(self, name, paths).with_context(|| format!("loading codeset \"{}\"", name))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &CodesetEntry> + '_ {
+        self.entries.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn builtin() {
+        let registry = CodesetRegistry::builtin();
+        let hef = registry.get("hef158_mc").unwrap();
+        assert_eq!(hef.format, CodesetFormat::Camb);
+        assert_eq!(hef.terminology, Terminology::Read2);
+        assert_eq!(
+            registry.get("anx141_pc").unwrap().terminology,
+            Terminology::Prodcode
+        );
+        assert!(registry.get("lymphoma_clean").is_some());
+
+        let twice = "[[codeset]]\nname = \"a\"\nformat = \"termset\"\npath = \"a\"\n\
+            terminology = \"read2\"\nsource = \"\"\n";
+        assert!(CodesetRegistry::parse(&twice.repeat(2)).is_err());
+    }
+}
@@ -14,12 +14,14 @@ use std::{
 };
 
 use crate::{
+    provenance,
     read2::{ReadCode, Thesaurus},
+    rubric::{ContextTriggers, RubricContext},
     util, ArcStr,
 };
 
 mod termcodeset;
-pub use termcodeset::TermCodeSet;
+pub use termcodeset::{CheckDiff, CheckReport, TermCodeSet, TermKind, TermUsage, TermUsageReport};
 
 lalrpop_mod!(parser, "/read2/termset/parser.rs");
 
@@ -378,6 +380,14 @@ impl TermSet {
         Ok(())
     }
 
+    pub fn include_terms(&self) -> &[ArcStr] {
+        &self.include_terms
+    }
+
+    pub fn exclude_terms(&self) -> &[ArcStr] {
+        &self.exclude_terms
+    }
+
     pub fn include_filter(&self) -> &FilterSet {
         &self.includes
     }
@@ -393,6 +403,15 @@ impl TermSet {
         self.includes.is_match(description) && !self.excludes.is_match(description)
     }
 
+    /// Does an event's rubric match this termset.
+    ///
+    /// Unlike thesaurus descriptions, rubrics are free text, so with `triggers` a rubric only
+    /// matches if it doesn't negate the condition, or say it is suspected or in the family.
+    pub fn is_rubric_match(&self, rubric: &str, triggers: Option<&ContextTriggers>) -> bool {
+        self.is_match(rubric)
+            && triggers.is_none_or(|triggers| triggers.context(rubric) == RubricContext::Affirmed)
+    }
+
     /// Does a code match this termset.
     ///
     /// This will match if
@@ -461,6 +480,7 @@ impl TermSet {
     pub fn load(path: impl Into<PathBuf>) -> Result<Self> {
         fn inner(path: &Path) -> Result<TermSet> {
             let text = fs::read_to_string(path)?;
+            provenance::record_input(path);
             serde_json::from_str(&text).map_err(Error::from)
         }
         let path = path.into().join("meta.json");
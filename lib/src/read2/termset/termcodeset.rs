@@ -2,14 +2,14 @@ use qu::ick_use::*;
 use serde::{Deserialize, Serialize};
 use std::{
     collections::{btree_set, BTreeSet},
-    iter,
+    fmt, iter,
     path::{Path, PathBuf},
 };
 
 use crate::{
     header,
-    read2::{show_descriptions, CodeSet, ReadCode, TermSet, Thesaurus},
-    termset_path, util, ArcStr, Table,
+    read2::{CodeSet, ReadCode, TermSet, Thesaurus},
+    termset_path, util, ArcStr, Events, Table,
 };
 
 /// A termset with corresponding codeset.
@@ -148,6 +148,48 @@ impl TermCodeSet {
         report
     }
 
+    /// How many thesaurus codes, and how many of the codes in `events`, each include and exclude
+    /// term matches.
+    ///
+    /// A term matches a code if it matches any of the code's descriptions, whether or not the
+    /// code ends up in the codeset. A term that matches nothing is usually a typo.
+    pub fn term_usage(&self, events: &Events) -> TermUsageReport {
+        let includes = self
+            .term_set
+            .include_terms()
+            .iter()
+            .zip(self.term_set.include_filter().filters())
+            .map(|(term, filter)| (TermKind::Include, term, filter));
+        let excludes = self
+            .term_set
+            .exclude_terms()
+            .iter()
+            .zip(self.term_set.exclude_filter().filters())
+            .map(|(term, filter)| (TermKind::Exclude, term, filter));
+        let filters = includes.chain(excludes).collect::<Vec<_>>();
+
+        let mut terms = filters
+            .iter()
+            .map(|(kind, term, _)| TermUsage {
+                term: (*term).clone(),
+                kind: *kind,
+                codes: 0,
+                events: 0,
+            })
+            .collect::<Vec<_>>();
+        for (code, descs) in self.th.iter() {
+            let mut code_events = None;
+            for ((_, _, filter), usage) in filters.iter().zip(terms.iter_mut()) {
+                if descs.iter().any(|desc| filter.is_match(desc)) {
+                    usage.codes += 1;
+                    usage.events +=
+                        *code_events.get_or_insert_with(|| events.with_code(code).count());
+                }
+            }
+        }
+        TermUsageReport { terms }
+    }
+
     pub fn term_table(&self) -> term_data_table::Table {
         use term_data_table::{Cell, Row, Table};
         let mut table = Table::new();
@@ -170,6 +212,97 @@ impl TermCodeSet {
     }
 }
 
+/// Whether a term includes or excludes codes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TermKind {
+    Include,
+    Exclude,
+}
+
+impl TermKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            TermKind::Include => "Include",
+            TermKind::Exclude => "Exclude",
+        }
+    }
+}
+
+impl fmt::Display for TermKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// How much one term of a termset is used.
+#[derive(Debug, Clone)]
+pub struct TermUsage {
+    pub term: ArcStr,
+    pub kind: TermKind,
+    /// The number of thesaurus codes with a description matching the term.
+    pub codes: usize,
+    /// The number of events with one of those codes.
+    pub events: usize,
+}
+
+/// The usage of every term in a termset, in the order they appear in the termset (includes first).
+#[derive(Debug, Clone)]
+pub struct TermUsageReport {
+    pub terms: Vec<TermUsage>,
+}
+
+impl TermUsageReport {
+    /// Terms that don't match any code in the thesaurus.
+    pub fn dead_terms(&self) -> impl Iterator<Item = &TermUsage> + '_ {
+        self.terms.iter().filter(|usage| usage.codes == 0)
+    }
+
+    pub fn term_table(&self) -> term_data_table::Table<'_> {
+        use term_data_table::{Row, Table};
+        let mut table = Table::new().with_row(
+            Row::new()
+                .with_cell("Term")
+                .with_cell("Kind")
+                .with_cell("Codes")
+                .with_cell("Events"),
+        );
+        for usage in self.terms.iter() {
+            table.add_row(
+                Row::new()
+                    .with_cell(usage.term.to_string())
+                    .with_cell(usage.kind.label())
+                    .with_cell(usage.codes.to_string())
+                    .with_cell(usage.events.to_string()),
+            );
+        }
+        table
+    }
+}
+
+/// The changes made by [`CheckReport::apply`].
+#[derive(Debug, Clone, Default)]
+pub struct CheckDiff {
+    pub added: CodeSet,
+    pub removed: CodeSet,
+}
+
+impl CheckDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl fmt::Display for CheckDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "added {} codes, removed {} codes",
+            self.added.len(),
+            self.removed.len()
+        )
+    }
+}
+
 #[derive(Debug)]
 pub struct CheckReport {
     /// Codes that we found but that don't match our query.
@@ -194,6 +327,35 @@ impl CheckReport {
         }
     }
 
+    /// Fix the codes of the termset this report is for, and rewrite its `codes.txt` in `dir`.
+    ///
+    /// Extra codes are removed and missing codes added. Codes that aren't in the thesaurus are
+    /// kept, because they may be in a newer release than ours.
+    pub fn apply(&self, termset: &mut TermCodeSet, dir: impl AsRef<Path>) -> Result<CheckDiff> {
+        let diff = CheckDiff {
+            added: self
+                .missing
+                .iter()
+                .filter(|code| !termset.code_set.contains(*code))
+                .collect(),
+            removed: self
+                .extra
+                .iter()
+                .filter(|code| termset.code_set.contains(*code))
+                .collect(),
+        };
+        for code in diff.added.iter() {
+            termset.code_set.insert(code);
+        }
+        for code in diff.removed.iter() {
+            termset.code_set.remove(code);
+        }
+        termset
+            .code_set
+            .save(dir.as_ref().join("codes.txt"), true)?;
+        Ok(diff)
+    }
+
     pub fn print_term_tables(&self) {
         use term_data_table::{Cell, Row, Table};
 
@@ -208,9 +370,7 @@ impl CheckReport {
             table.add_row(
                 Row::new()
                     .with_cell(Cell::from(code.to_string()))
-                    .with_cell(Cell::from(show_descriptions(
-                        self.th.get(code).expect("unreachable"),
-                    ))),
+                    .with_cell(Cell::from(self.th.describe(code))),
             );
         }
         println!("{}", table.for_terminal());
@@ -226,9 +386,7 @@ impl CheckReport {
             table.add_row(
                 Row::new()
                     .with_cell(Cell::from(code.to_string()))
-                    .with_cell(Cell::from(show_descriptions(
-                        self.th.get(code).expect("unreachable"),
-                    ))),
+                    .with_cell(Cell::from(self.th.describe(code))),
             );
         }
         println!("{}", table.for_terminal());
@@ -252,9 +410,7 @@ impl CheckReport {
             table.add_row(
                 Row::new()
                     .with_cell(Cell::from(code.to_string()))
-                    .with_cell(Cell::from(show_descriptions(
-                        self.th.get(code).expect("unreachable"),
-                    ))),
+                    .with_cell(Cell::from(self.th.describe(code))),
             );
         }
         println!("{}", table.for_terminal());
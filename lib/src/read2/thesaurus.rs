@@ -1,21 +1,77 @@
+use once_cell::sync::{Lazy, OnceCell};
+use parking_lot::Mutex;
 use qu::ick_use::*;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::{BTreeMap, BTreeSet},
-    fs, io,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt, fs, io,
+    path::{Path, PathBuf},
     sync::Arc,
 };
 
 use crate::{
-    read2::{CodeSet, ReadCode, TermCodeSet, TermSet},
-    ArcStr, Table,
+    progress::{Progress, ProgressReader},
+    provenance,
+    read2::{show_descriptions, CodeSet, PackedReadCode, ReadCode, TermCodeSet, TermId, TermSet},
+    ArcStr, DataPaths, Table,
 };
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Thesauruses loaded by [`Thesaurus::shared`], by path.
+///
+/// Each path has its own cell so that loading one thesaurus doesn't hold up loading another.
+static SHARED: Lazy<Mutex<HashMap<PathBuf, Arc<OnceCell<Thesaurus>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Whether a code is still in use in the Read release.
+#[derive(
+    Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub enum CodeStatus {
+    #[default]
+    Current,
+    /// Only used by some practices (e.g. more detail than most need).
+    Optional,
+    /// No longer used for new records, although old records will still have it.
+    Retired,
+}
+
+impl CodeStatus {
+    /// Parse the status flag in the last column of the readbrowser files.
+    ///
+    /// Returns `None` for flags we don't recognise.
+    pub fn from_flag(flag: &str) -> Option<Self> {
+        Some(match flag.trim() {
+            "" | "C" => CodeStatus::Current,
+            "O" => CodeStatus::Optional,
+            "R" | "E" => CodeStatus::Retired,
+            _ => return None,
+        })
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            CodeStatus::Current => "current",
+            CodeStatus::Optional => "optional",
+            CodeStatus::Retired => "retired",
+        }
+    }
+}
+
+impl fmt::Display for CodeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 /// All data from the Read v2 database loaded into memory.
 pub struct Thesaurus {
-    pub codes: Arc<BTreeMap<ReadCode, BTreeSet<ArcStr>>>,
+    pub codes: Arc<BTreeMap<PackedReadCode, BTreeSet<ArcStr>>>,
+    /// The (longest) description for each of a code's terms.
+    pub terms: Arc<BTreeMap<(PackedReadCode, TermId), ArcStr>>,
+    /// The status of codes that aren't [`CodeStatus::Current`].
+    pub status: Arc<BTreeMap<PackedReadCode, CodeStatus>>,
 }
 
 impl Thesaurus {
@@ -23,18 +79,93 @@ impl Thesaurus {
     ///
     /// Parameter is the root path of the readbrowser files.
     pub fn load() -> Result<Self> {
-        fn inner() -> Result<Thesaurus> {
-            let input = io::BufReader::new(fs::File::open("../data/read_db/all.bin")?);
-            bincode::deserialize_from(input).map_err(Into::into)
+        Self::load_from(&DataPaths::current())
+    }
+
+    /// Like [`Thesaurus::load`], but the thesaurus is only loaded once per process.
+    ///
+    /// Later calls get a (cheap) clone of the first one, so use this anywhere the thesaurus might
+    /// be needed more than once, e.g. in notebooks or in pipeline stages.
+    pub fn shared() -> Result<Self> {
+        Self::shared_from(&DataPaths::current())
+    }
+
+    /// Like [`Thesaurus::load_from`], but the thesaurus is only loaded once per process for each
+    /// path.
+    ///
+    /// Uses [`Thesaurus::load_mmap`] when the `mmap` feature is enabled.
+    pub fn shared_from(paths: &DataPaths) -> Result<Self> {
+        let path = Self::path(paths);
+        // Don't hold the lock while loading.
+        let cell = SHARED.lock().entry(path.clone()).or_default().clone();
+        let th = cell.get_or_try_init(|| {
+            #[cfg(feature = "mmap")]
+            return Self::load_mmap(paths);
+            #[cfg(not(feature = "mmap"))]
+            return Self::load_from(paths);
+        })?;
+        provenance::record_input(&path);
+        Ok(th.clone())
+    }
+
+    /// Forget the shared thesaurus for these data directories, so the next call to
+    /// [`Thesaurus::shared_from`] loads it again (e.g. after it has been re-imported).
+    pub fn forget_shared(paths: &DataPaths) {
+        SHARED.lock().remove(&Self::path(paths));
+    }
+
+    /// Load the thesaurus by memory-mapping the file rather than reading it through a buffer.
+    ///
+    /// This saves copying the file, which helps most when it is already in the OS page cache. The
+    /// maps still have to be built, so it isn't free: use [`Thesaurus::shared`] to only load once.
+    #[cfg(feature = "mmap")]
+    pub fn load_mmap(paths: &DataPaths) -> Result<Self> {
+        fn inner(path: &Path) -> Result<Thesaurus> {
+            let file = fs::File::open(path)?;
+            provenance::record_input(path);
+            // SAFETY: the thesaurus is only written by `eadapt thesaurus import`, which isn't
+            // run at the same time as anything that reads it, and we are finished with the map
+            // before returning.
+            let map = unsafe { memmap2::Mmap::map(&file)? };
+            let progress = Progress::new("loading thesaurus", None);
+            let th: Thesaurus = bincode::deserialize(&map)?;
+            progress.set_rows(th.codes.len() as u64);
+            progress.finish();
+            Ok(th)
         }
-        inner().context("loading thesaurus from \"../data/read_db/all.bin\"")
+        let path = Self::path(paths);
+        inner(&path).with_context(|| format!("loading thesaurus from \"{}\"", path.display()))
+    }
+
+    /// Load the thesaurus from the given data directories.
+    pub fn load_from(paths: &DataPaths) -> Result<Self> {
+        fn inner(path: &Path) -> Result<Thesaurus> {
+            let file = fs::File::open(path)?;
+            provenance::record_input(path);
+            let len = file.metadata()?.len();
+            let progress = Progress::new("loading thesaurus", None);
+            let mut input = io::BufReader::new(ProgressReader::new(file, len, progress));
+            let th: Thesaurus = bincode::deserialize_from(&mut input)?;
+            let input = input.into_inner();
+            input.set_rows(th.codes.len() as u64);
+            input.finish();
+            Ok(th)
+        }
+        let path = Self::path(paths);
+        inner(&path).with_context(|| format!("loading thesaurus from \"{}\"", path.display()))
+    }
+
+    /// Where the thesaurus is stored (written by `eadapt thesaurus import`).
+    pub fn path(paths: &DataPaths) -> PathBuf {
+        paths.read_db_path("all.bin")
     }
 
     /// Helper to show some records from the Read browser. Mostly there to check it's loaded
     /// correctly.
     pub fn evcxr_display(&self) {
-        Table::new(self.codes.iter(), |&(code, description), _| {
-            (code, format!("{:?}", description))
+        let th = self.clone();
+        Table::new(self.codes.keys(), move |&code, _| {
+            (code.code(), th.describe(code.code()))
         })
         .with_headers(["code", "description"])
         .evcxr_display();
@@ -43,11 +174,11 @@ impl Thesaurus {
     pub fn term_table(&self) -> term_data_table::Table {
         use term_data_table::{Cell, Row, Table};
         let mut table = Table::new();
-        for (code, desc) in self.iter() {
+        for (code, _) in self.iter() {
             table.add_row(
                 Row::new()
                     .with_cell(Cell::from(code.to_string()))
-                    .with_cell(Cell::from(format!("{:?}", desc))),
+                    .with_cell(Cell::from(self.describe(code))),
             );
         }
         table
@@ -55,7 +186,61 @@ impl Thesaurus {
 
     /// Get the description for a read code.
     pub fn get(&self, code: ReadCode) -> Option<&BTreeSet<ArcStr>> {
-        self.codes.get(&code)
+        self.codes.get(&code.into())
+    }
+
+    /// The preferred term for a read code (term `00`).
+    pub fn preferred_term(&self, code: ReadCode) -> Option<&ArcStr> {
+        self.term(code, TermId::PREFERRED)
+    }
+
+    /// The read code's terms other than the preferred term.
+    pub fn synonyms(&self, code: ReadCode) -> impl Iterator<Item = (TermId, &ArcStr)> + '_ {
+        self.terms_for(code)
+            .filter(|(term, _)| !term.is_preferred())
+    }
+
+    /// All the descriptions for a read code, with the preferred term first.
+    pub fn descriptions(&self, code: ReadCode) -> Vec<&ArcStr> {
+        let preferred = self.preferred_term(code);
+        preferred
+            .into_iter()
+            .chain(
+                self.get(code)
+                    .into_iter()
+                    .flatten()
+                    .filter(|desc| Some(*desc) != preferred),
+            )
+            .collect()
+    }
+
+    /// Whether the code is still in use.
+    pub fn status(&self, code: ReadCode) -> CodeStatus {
+        self.status.get(&code.into()).copied().unwrap_or_default()
+    }
+
+    /// The descriptions for a read code (preferred term first), and its status if it isn't
+    /// current, for showing in tables.
+    pub fn describe(&self, code: ReadCode) -> String {
+        let descs = show_descriptions(self.descriptions(code));
+        match self.status(code) {
+            CodeStatus::Current => descs,
+            status => format!("{descs} ({status})"),
+        }
+    }
+
+    /// Get the description for one of a read code's terms.
+    pub fn term(&self, code: ReadCode, term: TermId) -> Option<&ArcStr> {
+        self.terms.get(&(code.into(), term))
+    }
+
+    /// All of a read code's terms, in term id order.
+    pub fn terms_for(&self, code: ReadCode) -> impl Iterator<Item = (TermId, &ArcStr)> + '_ {
+        let code = PackedReadCode::from(code);
+        self.terms
+            .range((code, TermId::PREFERRED)..)
+            .take_while(move |((c, _), _)| *c == code)
+            .map(|((_, term), desc)| (*term, desc))
     }
 
     /// Filter the read codes
@@ -69,7 +254,7 @@ impl Thesaurus {
 
     /// An iterator over (code, description) pairs
     pub fn iter(&self) -> impl Iterator<Item = (ReadCode, &BTreeSet<ArcStr>)> + '_ {
-        self.codes.iter().map(|(code, set)| (*code, set))
+        self.codes.iter().map(|(code, set)| (code.code(), set))
     }
 
     /// An iterator over (code, description) pairs
@@ -78,26 +263,70 @@ impl Thesaurus {
     }
 
     /// Iterate over the descendants of a Read code
-    // this function relies on the `Ord` implementation on `ReadCode`, specifically the fact that
-    // `.` comes before alphanumeric, and the fact that we store read codes in an ordered
-    // collection (a b-tree).
+    // this function relies on the `Ord` implementation on `ReadCode` (which packed codes share),
+    // specifically the fact that `.` comes before alphanumeric, and the fact that we store read
+    // codes in an ordered collection (a b-tree).
     pub fn iter_descendants(
         &self,
         parent: ReadCode,
     ) -> impl Iterator<Item = (ReadCode, &BTreeSet<ArcStr>)> + '_ {
         self.codes
-            .range(parent..)
+            .range(PackedReadCode::from(parent)..)
             // skip the parent
             .skip(1)
-            .take_while(move |(code, _)| parent.is_parent_of(**code))
-            .map(|(code, set)| (*code, set))
+            .map(|(code, set)| (code.code(), set))
+            .take_while(move |(code, _)| parent.is_parent_of(*code))
     }
 }
 
+type ParIterItem<'a> = (ReadCode, &'a BTreeSet<ArcStr>);
+
 impl<'a> IntoParallelIterator for &'a Thesaurus {
-    type Item = (&'a ReadCode, &'a BTreeSet<ArcStr>);
-    type Iter = rayon::collections::btree_map::Iter<'a, ReadCode, BTreeSet<Arc<str>>>;
+    type Item = ParIterItem<'a>;
+    type Iter = rayon::iter::Map<
+        rayon::collections::btree_map::Iter<'a, PackedReadCode, BTreeSet<ArcStr>>,
+        fn((&'a PackedReadCode, &'a BTreeSet<ArcStr>)) -> ParIterItem<'a>,
+    >;
     fn into_par_iter(self) -> Self::Iter {
-        (&*self.codes).into_par_iter()
+        (&*self.codes)
+            .into_par_iter()
+            .map(|(code, set)| (code.code(), set))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn preferred_term_first() {
+        let code = ReadCode::from_str("B62x.").unwrap();
+        let term = |id: &str| id.parse::<TermId>().unwrap();
+        let th = Thesaurus {
+            codes: Arc::new(
+                [(
+                    code.into(),
+                    ["Burkitt lymphoma".into(), "Malignant lymphoma".into()].into(),
+                )]
+                .into(),
+            ),
+            terms: Arc::new(
+                [
+                    ((code.into(), term("00")), "Malignant lymphoma".into()),
+                    ((code.into(), term("11")), "Burkitt lymphoma".into()),
+                ]
+                .into(),
+            ),
+            status: Arc::new([(code.into(), CodeStatus::Optional)].into()),
+        };
+        assert_eq!(
+            th.preferred_term(code).unwrap().as_ref(),
+            "Malignant lymphoma"
+        );
+        assert_eq!(th.synonyms(code).count(), 1);
+        assert_eq!(
+            th.describe(code),
+            r#""Malignant lymphoma", "Burkitt lymphoma" (optional)"#
+        );
     }
 }
@@ -0,0 +1,227 @@
+//! Registration with the practice and date of death, from the follow-on extract.
+//!
+//! We only see a patient's record while they are registered, so follow-up must stop when they
+//! die or move practice rather than running to the date of extract. Use
+//! [`Registrations::follow_up_end`] for this.
+use crate::{
+    dataset::{Dataset, Record},
+    date_of_extract,
+    envelope::Schema,
+    file_exists, load_orig, output_path, PatientId, Result,
+};
+use chrono::NaiveDate;
+use qu::ick_use::*;
+use serde::{Deserialize, Serialize};
+use std::{ops::Deref, path::Path};
+
+#[derive(Debug, Deserialize)]
+struct RegistrationRaw {
+    #[serde(rename = "PatID")]
+    patient_id: PatientId,
+    #[serde(rename = "RegStartDate")]
+    start_date: NaiveDate,
+    #[serde(rename = "RegEndDate")]
+    end_date: Option<NaiveDate>,
+    #[serde(rename = "DeathDate")]
+    death_date: Option<NaiveDate>,
+}
+
+/// A row in the registrations dataset.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Registration {
+    pub patient_id: PatientId,
+    pub start_date: NaiveDate,
+    /// When the patient left the practice (transferred out or died), if they have.
+    pub end_date: Option<NaiveDate>,
+    pub death_date: Option<NaiveDate>,
+}
+
+impl Schema for Registration {
+    const SCHEMA: &'static str = "Registration { patient_id: u64, start_date: NaiveDate, \
+        end_date: Option<NaiveDate>, death_date: Option<NaiveDate> }";
+}
+
+impl From<RegistrationRaw> for Registration {
+    fn from(raw: RegistrationRaw) -> Self {
+        Self {
+            patient_id: raw.patient_id,
+            start_date: raw.start_date,
+            end_date: raw.end_date,
+            death_date: raw.death_date,
+        }
+    }
+}
+
+impl Registration {
+    /// The earliest of the end of registration, death, and the date of extract.
+    pub fn follow_up_end(&self) -> NaiveDate {
+        [self.end_date, self.death_date]
+            .into_iter()
+            .flatten()
+            .fold(date_of_extract(), NaiveDate::min)
+    }
+}
+
+impl Record for Registration {
+    type Key = PatientId;
+    const DATASET: &'static str = "registrations";
+    fn key(&self) -> PatientId {
+        self.patient_id
+    }
+    /// Each patient's registrations are indexed in order of when they started.
+    fn cmp_within_key(&self, other: &Self) -> std::cmp::Ordering {
+        self.start_date.cmp(&other.start_date)
+    }
+}
+
+/// The parsed list of registrations, with a pre-built index for the `id` field.
+///
+/// A patient who leaves the practice and comes back has one registration for each period.
+///
+/// Empty if we don't have registration data, in which case everyone is followed up to the date
+/// of extract.
+pub struct Registrations(Dataset<Registration>);
+
+impl Registrations {
+    pub fn load_orig(path: impl AsRef<Path>) -> Result<Self> {
+        let els: Vec<RegistrationRaw> = load_orig(path)?;
+        Ok(Self::new(els.into_iter().map(Into::into).collect()))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Registrations(Dataset::load(path)?))
+    }
+
+    /// Load registrations if we have imported them, otherwise use an empty set (so everyone is
+    /// followed up to the date of extract).
+    pub fn load_if_present(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if !file_exists(&output_path(path))? {
+            event!(
+                Level::WARN,
+                "no registration data at \"{}\", following everyone up to the date of extract",
+                path.display()
+            );
+            return Ok(Self::default());
+        }
+        Self::load(path)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result {
+        self.0.save(path)
+    }
+
+    /// A patient's registrations, earliest first.
+    pub fn registrations_for_patient(
+        &self,
+        patient_id: PatientId,
+    ) -> impl Iterator<Item = &Registration> + Clone + '_ {
+        self.0.get(patient_id)
+    }
+
+    /// The registration the patient was in on `date`: the latest one that started on or before
+    /// it, or their first registration if `date` is before all of them.
+    pub fn registration_at(&self, patient_id: PatientId, date: NaiveDate) -> Option<&Registration> {
+        self.registrations_for_patient(patient_id)
+            .take_while(|reg| reg.start_date <= date)
+            .last()
+            .or_else(|| self.0.find(patient_id))
+    }
+
+    /// The last date we can see the patient's record: the date of extract unless they died or left
+    /// the practice (for the last time) before then.
+    ///
+    /// Patients we have no registration for are assumed to have been registered throughout.
+    pub fn follow_up_end(&self, patient_id: PatientId) -> NaiveDate {
+        self.registrations_for_patient(patient_id)
+            .last()
+            .map(Registration::follow_up_end)
+            .unwrap_or_else(date_of_extract)
+    }
+
+    /// The first date we can see the patient's record, or `None` if we have no registration for
+    /// them.
+    pub fn follow_up_start(&self, patient_id: PatientId) -> Option<NaiveDate> {
+        Some(self.0.find(patient_id)?.start_date)
+    }
+
+    /// The date of death, if the patient has died.
+    pub fn death_date(&self, patient_id: PatientId) -> Option<NaiveDate> {
+        self.registrations_for_patient(patient_id)
+            .find_map(|reg| reg.death_date)
+    }
+
+    fn new(els: Vec<Registration>) -> Self {
+        Registrations(Dataset::new(els))
+    }
+}
+
+impl Default for Registrations {
+    fn default() -> Self {
+        Self::new(vec![])
+    }
+}
+
+impl Deref for Registrations {
+    type Target = Dataset<Registration>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn follow_up_end() {
+        let date = |y| NaiveDate::from_ymd_opt(y, 1, 1);
+        let regs = Registrations::new(vec![
+            Registration {
+                patient_id: PatientId::new(1),
+                start_date: date(2000).unwrap(),
+                end_date: date(2015),
+                death_date: date(2015),
+            },
+            Registration {
+                patient_id: PatientId::new(2),
+                start_date: date(2000).unwrap(),
+                end_date: date(2012),
+                death_date: None,
+            },
+            Registration {
+                patient_id: PatientId::new(3),
+                start_date: date(2000).unwrap(),
+                end_date: None,
+                death_date: None,
+            },
+            // Left and came back: followed up to the end of the later registration.
+            Registration {
+                patient_id: PatientId::new(5),
+                start_date: date(2010).unwrap(),
+                end_date: date(2018),
+                death_date: None,
+            },
+            Registration {
+                patient_id: PatientId::new(5),
+                start_date: date(2000).unwrap(),
+                end_date: date(2005),
+                death_date: None,
+            },
+        ]);
+        assert_eq!(regs.follow_up_end(PatientId::new(1)), date(2015).unwrap());
+        assert_eq!(regs.follow_up_end(PatientId::new(2)), date(2012).unwrap());
+        assert_eq!(regs.follow_up_end(PatientId::new(3)), date_of_extract());
+        assert_eq!(regs.follow_up_end(PatientId::new(4)), date_of_extract());
+        assert_eq!(regs.follow_up_end(PatientId::new(5)), date(2018).unwrap());
+        assert_eq!(regs.follow_up_start(PatientId::new(5)), date(2000));
+        let at = |y| {
+            regs.registration_at(PatientId::new(5), date(y).unwrap())
+                .map(|reg| reg.start_date)
+        };
+        assert_eq!(
+            (at(1990), at(2007), at(2012)),
+            (date(2000), date(2000), date(2010))
+        );
+    }
+}
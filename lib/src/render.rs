@@ -0,0 +1,281 @@
+//! Report tables as plain text, for pasting into write-ups.
+//!
+//! Report types build a [`TextTable`] of already formatted cells (with any disclosure control
+//! applied), which can then be drawn in the terminal, written as markdown or LaTeX, or saved as
+//! CSV for supplementary files.
+use crate::DisclosureControl;
+use qu::ick_use::*;
+use std::{
+    fmt::{self, Write},
+    io,
+    path::Path,
+    str::FromStr,
+};
+
+/// Which format to write report tables in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum TableFormat {
+    /// Drawn for the terminal.
+    #[default]
+    Terminal,
+    Markdown,
+    Latex,
+}
+
+impl TableFormat {
+    pub const ALL: [TableFormat; 3] = [
+        TableFormat::Terminal,
+        TableFormat::Markdown,
+        TableFormat::Latex,
+    ];
+
+    /// The name used on the command line.
+    pub fn code(self) -> &'static str {
+        match self {
+            TableFormat::Terminal => "term",
+            TableFormat::Markdown => "markdown",
+            TableFormat::Latex => "latex",
+        }
+    }
+}
+
+impl fmt::Display for TableFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl FromStr for TableFormat {
+    type Err = anyhow::Error;
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        TableFormat::ALL
+            .into_iter()
+            .find(|format| format.code() == input)
+            .ok_or_else(|| anyhow::format_err!("unrecognised table format \"{input}\""))
+    }
+}
+
+/// A table of formatted cells with a header row.
+///
+/// Tables without headers (e.g. tables of label/value pairs) have an empty header row, which is
+/// left out in the terminal and written as blank cells in markdown (which requires a header).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TextTable {
+    headers: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+impl TextTable {
+    pub fn new(headers: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        TextTable {
+            headers: headers.into_iter().map(Into::into).collect(),
+            rows: vec![],
+        }
+    }
+
+    /// A table of counts and their percentage of `total`, with `dc` applied to both.
+    pub fn counts(
+        heading: &str,
+        counts: impl IntoIterator<Item = (impl fmt::Display, usize)>,
+        total: usize,
+        dc: &DisclosureControl,
+    ) -> Self {
+        let mut table = TextTable::new([heading, "Count", "Percentage"]);
+        for (label, count) in counts {
+            let percentage = dc
+                .percentage(count, total)
+                .map(|pc| format!("{:.1}%", pc))
+                .unwrap_or_default();
+            table.push_row([label.to_string(), dc.count(count).to_string(), percentage]);
+        }
+        table
+    }
+
+    pub fn push_row(&mut self, row: impl IntoIterator<Item = impl fmt::Display>) {
+        self.rows
+            .push(row.into_iter().map(|cell| cell.to_string()).collect());
+    }
+
+    pub fn with_row(mut self, row: impl IntoIterator<Item = impl fmt::Display>) -> Self {
+        self.push_row(row);
+        self
+    }
+
+    pub fn headers(&self) -> &[String] {
+        &self.headers
+    }
+
+    pub fn rows(&self) -> &[Vec<String>] {
+        &self.rows
+    }
+
+    /// The number of columns in the widest row (including the header).
+    fn num_columns(&self) -> usize {
+        self.rows
+            .iter()
+            .map(Vec::len)
+            .chain([self.headers.len()])
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The table for drawing in the terminal.
+    pub fn into_term_table(self) -> term_data_table::Table<'static> {
+        use term_data_table::{Row, Table};
+        let mut table = Table::new();
+        for row in (!self.headers.is_empty())
+            .then_some(self.headers)
+            .into_iter()
+            .chain(self.rows)
+        {
+            table.add_row(row.into_iter().fold(Row::new(), Row::with_cell));
+        }
+        table
+    }
+
+    /// The table in the given format.
+    pub fn render(self, format: TableFormat) -> String {
+        match format {
+            TableFormat::Terminal => self.into_term_table().for_terminal().to_string(),
+            TableFormat::Markdown => self.to_markdown(),
+            TableFormat::Latex => self.to_latex(),
+        }
+    }
+
+    /// A GitHub-flavoured markdown table.
+    pub fn to_markdown(&self) -> String {
+        let columns = self.num_columns();
+        let mut out = String::new();
+        let write_row = |out: &mut String, row: &[String]| {
+            out.push('|');
+            for cell in padded(row, columns) {
+                let _ = write!(out, " {} |", escape_markdown(cell));
+            }
+            out.push('\n');
+        };
+        write_row(&mut out, &self.headers);
+        out.push('|');
+        out.push_str(&" --- |".repeat(columns));
+        out.push('\n');
+        for row in &self.rows {
+            write_row(&mut out, row);
+        }
+        out
+    }
+
+    /// A LaTeX `tabular`, using the rules from the `booktabs` package.
+    pub fn to_latex(&self) -> String {
+        let columns = self.num_columns();
+        let write_row = |out: &mut String, row: &[String]| {
+            let cells: Vec<_> = padded(row, columns).map(escape_latex).collect();
+            out.push_str(&cells.join(" & "));
+            out.push_str(" \\\\\n");
+        };
+        let mut out = format!("\\begin{{tabular}}{{{}}}\n\\toprule\n", "l".repeat(columns));
+        if !self.headers.is_empty() {
+            write_row(&mut out, &self.headers);
+            out.push_str("\\midrule\n");
+        }
+        for row in &self.rows {
+            write_row(&mut out, row);
+        }
+        out.push_str("\\bottomrule\n\\end{tabular}\n");
+        out
+    }
+
+    /// Save the table as CSV, with the header row if there is one.
+    pub fn write_csv(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let out = csv::Writer::from_path(path)
+            .with_context(|| format!("creating \"{}\"", path.display()))?;
+        self.write_csv_to(out)
+            .with_context(|| format!("writing \"{}\"", path.display()))
+    }
+
+    fn write_csv_to<W: io::Write>(&self, mut out: csv::Writer<W>) -> Result<()> {
+        // CSV rows must all be the same length.
+        let columns = self.num_columns();
+        if !self.headers.is_empty() {
+            out.write_record(padded(&self.headers, columns))?;
+        }
+        for row in &self.rows {
+            out.write_record(padded(row, columns))?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for TextTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.clone().into_term_table(), f)
+    }
+}
+
+/// The cells of `row`, with empty cells added to make it `columns` long.
+fn padded(row: &[String], columns: usize) -> impl Iterator<Item = &str> + '_ {
+    (0..columns).map(move |idx| row.get(idx).map(String::as_str).unwrap_or(""))
+}
+
+/// Stop `|` from ending the cell, and keep the cell on one line.
+fn escape_markdown(cell: &str) -> String {
+    cell.replace('|', "\\|").replace('\n', " ")
+}
+
+fn escape_latex(cell: &str) -> String {
+    let mut out = String::with_capacity(cell.len());
+    for ch in cell.chars() {
+        match ch {
+            '\\' => out.push_str("\\textbackslash{}"),
+            '~' => out.push_str("\\textasciitilde{}"),
+            '^' => out.push_str("\\textasciicircum{}"),
+            '&' | '%' | '$' | '#' | '_' | '{' | '}' => {
+                out.push('\\');
+                out.push(ch);
+            }
+            '\n' => out.push(' '),
+            _ => out.push(ch),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let table = TextTable::new(["Condition", "0 years"])
+            .with_row(["Totals", "100"])
+            .with_row(["A|B & C", "12 (12.0%)"])
+            .with_row(["Short"]);
+        assert_eq!(
+            table.to_markdown(),
+            "| Condition | 0 years |\n| --- | --- |\n| Totals | 100 |\n\
+             | A\\|B & C | 12 (12.0%) |\n| Short |  |\n"
+        );
+        assert_eq!(
+            table.to_latex(),
+            "\\begin{tabular}{ll}\n\\toprule\nCondition & 0 years \\\\\n\\midrule\n\
+             Totals & 100 \\\\\nA|B \\& C & 12 (12.0\\%) \\\\\nShort &  \\\\\n\
+             \\bottomrule\n\\end{tabular}\n"
+        );
+
+        let no_headers = TextTable::default().with_row(["Mean", "1.0"]);
+        assert_eq!(
+            no_headers.to_markdown(),
+            "|  |  |\n| --- | --- |\n| Mean | 1.0 |\n"
+        );
+        assert!(!no_headers.to_latex().contains("\\midrule"));
+
+        let mut csv = vec![];
+        table
+            .write_csv_to(csv::Writer::from_writer(&mut csv))
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "Condition,0 years\nTotals,100\nA|B & C,12 (12.0%)\nShort,\n"
+        );
+    }
+}
@@ -0,0 +1,268 @@
+//! Tidying up rubrics (the free text on events).
+//!
+//! The same rubric is often typed in slightly different ways ("Hodgkin's lymphoma",
+//! "hodgkin's lymphoma.", "Hodgkin's  lymphoma"), which makes the list of code/rubric pairs we
+//! review by hand much longer than it needs to be. [`RubricNormalisation`] removes the differences
+//! that never matter, and [`edit_distance`] is used to group the ones that are nearly the same
+//! (see [`CodeRubricCounts::group_similar`](crate::CodeRubricCounts::group_similar)).
+//!
+//! Rubrics are also free text, so matching a termset against them can find mentions that aren't
+//! diagnoses: "No evidence of lymphoma", "FH: Hodgkin's disease". [`ContextTriggers`] looks for
+//! trigger words (in the style of NegEx) to tell these apart.
+use std::{borrow::Cow, fmt};
+
+/// Which differences between rubrics to ignore.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RubricNormalisation {
+    pub lowercase: bool,
+    /// Trim leading and trailing whitespace, and replace runs of whitespace with one space.
+    pub whitespace: bool,
+    /// Remove punctuation at the end of the rubric (e.g. a trailing full stop).
+    pub trailing_punctuation: bool,
+}
+
+impl RubricNormalisation {
+    /// Leave rubrics as they are.
+    pub const NONE: RubricNormalisation = RubricNormalisation {
+        lowercase: false,
+        whitespace: false,
+        trailing_punctuation: false,
+    };
+
+    /// Ignore case, whitespace and trailing punctuation.
+    pub const ALL: RubricNormalisation = RubricNormalisation {
+        lowercase: true,
+        whitespace: true,
+        trailing_punctuation: true,
+    };
+
+    /// Normalise a rubric, only allocating if it changes.
+    pub fn apply<'a>(&self, rubric: &'a str) -> Cow<'a, str> {
+        let mut out = Cow::Borrowed(rubric);
+        if self.whitespace && needs_whitespace_fix(&out) {
+            out = Cow::Owned(out.split_whitespace().collect::<Vec<_>>().join(" "));
+        }
+        if self.trailing_punctuation {
+            let trimmed = out.trim_end_matches(|ch: char| ch.is_ascii_punctuation());
+            if trimmed.len() != out.len() {
+                // Punctuation may have been followed by whitespace.
+                let trimmed = if self.whitespace {
+                    trimmed.trim_end()
+                } else {
+                    trimmed
+                };
+                out = Cow::Owned(trimmed.to_owned());
+            }
+        }
+        if self.lowercase && out.chars().any(char::is_uppercase) {
+            out = Cow::Owned(out.to_lowercase());
+        }
+        out
+    }
+}
+
+impl Default for RubricNormalisation {
+    fn default() -> Self {
+        RubricNormalisation::ALL
+    }
+}
+
+fn needs_whitespace_fix(s: &str) -> bool {
+    s.trim() != s || s.contains(|ch: char| ch.is_whitespace() && ch != ' ') || s.contains("  ")
+}
+
+/// What a rubric says about the condition it mentions.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RubricContext {
+    /// The patient has the condition (no trigger words).
+    Affirmed,
+    /// The condition was ruled out.
+    Negated,
+    /// Someone in the patient's family has the condition.
+    FamilyHistory,
+    /// The condition is suspected but not confirmed.
+    Suspected,
+}
+
+impl RubricContext {
+    pub const ALL: [RubricContext; 4] = [
+        RubricContext::Affirmed,
+        RubricContext::Negated,
+        RubricContext::FamilyHistory,
+        RubricContext::Suspected,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            RubricContext::Affirmed => "Affirmed",
+            RubricContext::Negated => "Negated",
+            RubricContext::FamilyHistory => "Family history",
+            RubricContext::Suspected => "Suspected",
+        }
+    }
+}
+
+impl fmt::Display for RubricContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// Words and phrases that change the meaning of a rubric.
+///
+/// Triggers are matched as whole words, ignoring case and punctuation. A rubric with a family
+/// history trigger is [`RubricContext::FamilyHistory`] whatever else it says, then negation
+/// takes priority over suspicion ("suspected lymphoma - ruled out" is negated).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContextTriggers {
+    pub negation: Vec<String>,
+    pub family_history: Vec<String>,
+    pub suspected: Vec<String>,
+}
+
+const NEGATION: &[&str] = &[
+    "no",
+    "not",
+    "no evidence of",
+    "no sign of",
+    "no signs of",
+    "negative for",
+    "ruled out",
+    "excluded",
+    "denies",
+    "without",
+    "absence of",
+];
+const FAMILY_HISTORY: &[&str] = &[
+    "family history",
+    "fh",
+    "f/h",
+    "mother",
+    "father",
+    "brother",
+    "sister",
+    "sibling",
+];
+const SUSPECTED: &[&str] = &[
+    "suspected",
+    "suspicion of",
+    "query",
+    "possible",
+    "probable",
+    "likely",
+    "rule out",
+    "r/o",
+    "to exclude",
+];
+
+impl Default for ContextTriggers {
+    fn default() -> Self {
+        let owned = |words: &[&str]| words.iter().map(|word| word.to_string()).collect();
+        ContextTriggers {
+            negation: owned(NEGATION),
+            family_history: owned(FAMILY_HISTORY),
+            suspected: owned(SUSPECTED),
+        }
+    }
+}
+
+impl ContextTriggers {
+    /// What the rubric says about the condition it mentions.
+    pub fn context(&self, rubric: &str) -> RubricContext {
+        let words = padded_words(rubric);
+        let any = |triggers: &[String]| {
+            triggers
+                .iter()
+                .any(|trigger| words.contains(&padded_words(trigger)))
+        };
+        if any(&self.family_history) {
+            RubricContext::FamilyHistory
+        } else if any(&self.negation) {
+            RubricContext::Negated
+        } else if rubric.contains('?') || any(&self.suspected) {
+            RubricContext::Suspected
+        } else {
+            RubricContext::Affirmed
+        }
+    }
+}
+
+/// The lower-case words of `s`, separated and surrounded by single spaces, so that
+/// `padded_words(s).contains(&padded_words(phrase))` only matches whole words.
+fn padded_words(s: &str) -> String {
+    let mut out = String::from(" ");
+    for word in s
+        .split(|ch: char| !(ch.is_alphanumeric() || ch == '/' || ch == '\''))
+        .filter(|word| !word.is_empty())
+    {
+        out.push_str(&word.to_lowercase());
+        out.push(' ');
+    }
+    out
+}
+
+/// The Levenshtein distance between `a` and `b` in characters, or `None` if it is more than `max`.
+pub fn edit_distance(a: &str, b: &str, max: usize) -> Option<usize> {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    let mut current = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = prev[j] + usize::from(ca != cb);
+            current[j + 1] = substitute.min(prev[j + 1] + 1).min(current[j] + 1);
+        }
+        if current.iter().all(|dist| *dist > max) {
+            return None;
+        }
+        std::mem::swap(&mut prev, &mut current);
+    }
+    Some(prev[b.len()]).filter(|dist| *dist <= max)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn normalise() {
+        let all = RubricNormalisation::ALL;
+        assert_eq!(all.apply("  Hodgkin's\tLymphoma. "), "hodgkin's lymphoma");
+        assert_eq!(all.apply("hodgkin's lymphoma"), "hodgkin's lymphoma");
+        assert!(matches!(all.apply("already fine"), Cow::Borrowed(_)));
+        assert_eq!(RubricNormalisation::NONE.apply(" As Is. "), " As Is. ");
+    }
+
+    #[test]
+    fn context() {
+        let triggers = ContextTriggers::default();
+        let context = |rubric| triggers.context(rubric);
+        assert_eq!(context("Hodgkin's lymphoma"), RubricContext::Affirmed);
+        assert_eq!(context("No evidence of lymphoma"), RubricContext::Negated);
+        assert_eq!(context("Lymphoma excluded."), RubricContext::Negated);
+        assert_eq!(
+            context("Family history of Hodgkin's disease"),
+            RubricContext::FamilyHistory
+        );
+        assert_eq!(context("FH: lymphoma"), RubricContext::FamilyHistory);
+        assert_eq!(context("?lymphoma"), RubricContext::Suspected);
+        assert_eq!(context("Suspected NHL"), RubricContext::Suspected);
+        // whole words only
+        assert_eq!(context("Non-Hodgkin lymphoma"), RubricContext::Affirmed);
+        assert_eq!(context("Lymphoma, nodular"), RubricContext::Affirmed);
+    }
+
+    #[test]
+    fn distance() {
+        assert_eq!(edit_distance("lymphoma", "lymphoma", 0), Some(0));
+        assert_eq!(edit_distance("lymphoma", "lymphomas", 2), Some(1));
+        assert_eq!(edit_distance("lymphoma", "lympoma", 2), Some(1));
+        assert_eq!(edit_distance("kitten", "sitting", 3), Some(3));
+        assert_eq!(edit_distance("kitten", "sitting", 2), None);
+        assert_eq!(edit_distance("", "abc", 5), Some(3));
+    }
+}
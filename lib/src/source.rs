@@ -0,0 +1,146 @@
+use crate::{ArcStr, Event, Events, GroupCounts};
+use std::fmt;
+
+/// Where an event was recorded, from the free-text `source` field of the extract.
+///
+/// Practices fill the field in inconsistently ("Surgery", "GP surgery consultation", "OOH", ...),
+/// so we match on whole words in it. Anything we don't recognise is kept as [`Source::Other`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Source {
+    GpSurgery,
+    HomeVisit,
+    OutOfHours,
+    HospitalLetter,
+    Laboratory,
+    /// The source field was empty.
+    Unrecorded,
+    Other(ArcStr),
+}
+
+/// Words (or phrases) in the source field for each kind of source, checked in this order (so "GP
+/// out of hours" is out of hours, not the GP surgery).
+const KEYWORDS: &[(&[&str], Source)] = &[
+    (&["out of hours", "ooh"], Source::OutOfHours),
+    (
+        &[
+            "hospital",
+            "letter",
+            "letters",
+            "discharge",
+            "outpatient",
+            "outpatients",
+        ],
+        Source::HospitalLetter,
+    ),
+    (
+        &["lab", "labs", "laboratory", "path", "pathology"],
+        Source::Laboratory,
+    ),
+    (&["home visit"], Source::HomeVisit),
+    (&["surgery", "practice", "gp", "gps"], Source::GpSurgery),
+];
+
+impl Source {
+    /// Classify the raw text of an event's source.
+    pub fn of(raw: &ArcStr) -> Source {
+        let lower = raw.to_lowercase();
+        let words: Vec<&str> = lower
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .collect();
+        if words.is_empty() {
+            return Source::Unrecorded;
+        }
+        // Whether the words of `phrase` appear next to each other.
+        let has_phrase = |phrase: &str| {
+            let phrase: Vec<&str> = phrase.split(' ').collect();
+            words.windows(phrase.len()).any(|window| window == phrase)
+        };
+        KEYWORDS
+            .iter()
+            .find(|(phrases, _)| phrases.iter().any(|phrase| has_phrase(phrase)))
+            .map(|(_, source)| source.clone())
+            .unwrap_or_else(|| Source::Other(raw.clone()))
+    }
+
+    pub fn label(&self) -> &str {
+        match self {
+            Source::GpSurgery => "GP surgery",
+            Source::HomeVisit => "Home visit",
+            Source::OutOfHours => "Out of hours",
+            Source::HospitalLetter => "Hospital letter",
+            Source::Laboratory => "Laboratory",
+            Source::Unrecorded => "Not recorded",
+            Source::Other(raw) => raw,
+        }
+    }
+
+    /// Whether the event was recorded by primary care (the practice or an out-of-hours service).
+    pub fn is_primary_care(&self) -> bool {
+        matches!(
+            self,
+            Source::GpSurgery | Source::HomeVisit | Source::OutOfHours
+        )
+    }
+
+    /// Whether the event came from outside primary care, e.g. a hospital letter or lab result.
+    pub fn is_external(&self) -> bool {
+        matches!(self, Source::HospitalLetter | Source::Laboratory)
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+impl Event {
+    /// Where this event was recorded (see [`Source`]).
+    pub fn source_kind(&self) -> Source {
+        Source::of(&self.source)
+    }
+}
+
+impl Events {
+    /// The number of events from each [`Source`].
+    pub fn source_counts(&self) -> GroupCounts<Source> {
+        GroupCounts::from_keys(self.iter_ref().map(Event::source_kind))
+    }
+
+    /// Only the events recorded by primary care (see [`Source::is_primary_care`]).
+    ///
+    /// The filter is recorded in the [`audit`](crate::audit) log.
+    pub fn primary_care(&self) -> Self {
+        self.filter_described("recorded in primary care", |evt| {
+            evt.source_kind().is_primary_care()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn classify() {
+        let of = |raw: &str| Source::of(&raw.into());
+        assert_eq!(of("GP Surgery"), Source::GpSurgery);
+        assert_eq!(of("GP out of hours"), Source::OutOfHours);
+        assert_eq!(of("Hospital letter"), Source::HospitalLetter);
+        assert_eq!(of("Pathology lab"), Source::Laboratory);
+        assert_eq!(of("  "), Source::Unrecorded);
+        assert_eq!(of("Out-of-hours"), Source::OutOfHours);
+        assert_eq!(of("Pharmacy"), Source::Other("Pharmacy".into()));
+        // Only whole words count.
+        assert_eq!(
+            of("Collaborative care"),
+            Source::Other("Collaborative care".into())
+        );
+        assert_eq!(of("Care pathway"), Source::Other("Care pathway".into()));
+        assert_eq!(of("Osteopath"), Source::Other("Osteopath".into()));
+        assert_eq!(of("Flagpole House"), Source::Other("Flagpole House".into()));
+        assert!(of("OOH").is_primary_care());
+        assert!(!of("Pharmacy").is_primary_care());
+    }
+}
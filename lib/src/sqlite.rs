@@ -0,0 +1,130 @@
+//! An indexed on-disk store for events.
+//!
+//! Loading the whole events file to answer a question about a handful of patients or codes is
+//! slow, so this lets us write the events to sqlite once, then pull out just the rows we need.
+use crate::{intern, output_path, provenance, util, Event, Events, PatientId, ReadCode};
+use qu::ick_use::*;
+use rusqlite::{params, Connection};
+use std::{fs, path::Path};
+
+const CREATE_TABLE: &str = "
+    DROP TABLE IF EXISTS events;
+    CREATE TABLE events (
+        patient_id INTEGER NOT NULL,
+        date TEXT NOT NULL,
+        read_code TEXT NOT NULL,
+        term_id TEXT,
+        rubric TEXT NOT NULL,
+        code_value TEXT,
+        code_units TEXT,
+        source TEXT NOT NULL
+    );
+";
+
+// Created after the data is inserted, which is much quicker than maintaining them as we go.
+const CREATE_INDEXES: &str = "
+    CREATE INDEX events_patient_id_date ON events (patient_id, date);
+    CREATE INDEX events_date ON events (date);
+    CREATE INDEX events_read_code ON events (read_code);
+";
+
+impl Events {
+    /// Write the events to an sqlite database in the output directory.
+    ///
+    /// Any existing `events` table in the database is replaced.
+    pub fn to_sqlite(&self, path: impl AsRef<Path>) -> Result {
+        fn inner(events: &Events, path: &Path) -> Result {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).context("could not create parent")?;
+            }
+            if util::path_exists(path)? {
+                event!(
+                    Level::WARN,
+                    "overwriting events in existing database at \"{}\"",
+                    path.display()
+                );
+            }
+            let mut conn = Connection::open(path)?;
+            conn.execute_batch(CREATE_TABLE)?;
+            let tx = conn.transaction()?;
+            {
+                let mut stmt = tx.prepare(
+                    "INSERT INTO events (patient_id, date, read_code, term_id, rubric, \
+                    code_value, code_units, source) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                )?;
+                for evt in events.els.iter() {
+                    stmt.execute(params![
+                        evt.patient_id.get(),
+                        evt.date,
+                        evt.read_code.to_string(),
+                        evt.term_id.map(|term| term.to_string()),
+                        &*evt.rubric,
+                        evt.code_value.as_deref(),
+                        evt.code_units.as_deref(),
+                        &*evt.source,
+                    ])?;
+                }
+            }
+            tx.execute_batch(CREATE_INDEXES)?;
+            tx.commit()?;
+            drop(conn);
+            provenance::write_sidecar(path)
+        }
+        let path = output_path(path.as_ref());
+        crate::check_extension(&path, "sqlite")?;
+
+        inner(self, &path)
+            .with_context(|| format!("unable to save events to \"{}\"", path.display()))
+    }
+
+    /// Load events from an sqlite database written by [`Events::to_sqlite`].
+    ///
+    /// `where_clause` is inserted as-is after `WHERE`, so it can use any of the column names,
+    /// e.g. `"patient_id = 42 AND date >= '2010-01-01'"`. Dates are stored as `YYYY-MM-DD` text
+    /// so compare correctly as strings. Use `None` to load everything.
+    pub fn from_sqlite(path: impl AsRef<Path>, where_clause: Option<&str>) -> Result<Self> {
+        fn inner(path: &Path, where_clause: Option<&str>) -> Result<Events> {
+            provenance::record_input(path);
+            let conn = Connection::open(path)?;
+            let mut sql = "SELECT patient_id, date, read_code, term_id, rubric, code_value, \
+                code_units, source FROM events"
+                .to_string();
+            if let Some(where_clause) = where_clause {
+                sql.push_str(" WHERE ");
+                sql.push_str(where_clause);
+            }
+            let mut stmt = conn
+                .prepare(&sql)
+                .with_context(|| format!("invalid query: {}", sql))?;
+            let mut rows = stmt.query([])?;
+            let mut els = vec![];
+            while let Some(row) = rows.next()? {
+                let read_code: String = row.get(2)?;
+                els.push(Event {
+                    patient_id: PatientId::try_from(row.get::<_, i64>(0)?)?,
+                    date: row.get(1)?,
+                    read_code: ReadCode::from_str(&read_code)?,
+                    term_id: row
+                        .get::<_, Option<String>>(3)?
+                        .map(|term| term.parse())
+                        .transpose()?,
+                    rubric: intern::RUBRICS.intern(&row.get::<_, String>(4)?),
+                    code_value: row.get::<_, Option<String>>(5)?.map(Into::into),
+                    code_units: row.get::<_, Option<String>>(6)?.map(Into::into),
+                    source: intern::SOURCES.intern(&row.get::<_, String>(7)?),
+                });
+            }
+            Ok(Events::new(els))
+        }
+        let path = output_path(path.as_ref());
+        crate::check_extension(&path, "sqlite")?;
+        ensure!(
+            util::path_exists(&path)?,
+            "no database at \"{}\"",
+            path.display()
+        );
+
+        inner(&path, where_clause)
+            .with_context(|| format!("unable to load events from \"{}\"", path.display()))
+    }
+}
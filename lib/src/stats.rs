@@ -0,0 +1,309 @@
+//! Summary statistics shared by the reports.
+//!
+//! Quantiles follow Hyndman & Fan (1996), "Sample quantiles in statistical packages", so our
+//! numbers can be checked against R (`quantile(x, type = 7)` is the default there and here).
+//!
+//! The weighted functions take `(value, weight)` pairs, for survey-style or standardisation
+//! weights. Weights must be finite and non-negative, and are treated as relative importance, so
+//! multiplying every weight by the same number doesn't change the result.
+pub mod glm;
+
+use statrs::distribution::{ContinuousCDF, Normal};
+use std::fmt;
+
+/// How to estimate a quantile that falls between two data points.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum QuantileMethod {
+    /// Type 1: the smallest value with at least a proportion `p` of the data at or below it (no
+    /// interpolation).
+    InverseCdf,
+    /// Type 6: linear interpolation, with the `p` quantile at position `p(n + 1)`. Used by SPSS
+    /// and Minitab.
+    Weibull,
+    /// Type 7: linear interpolation, with the `p` quantile at position `1 + p(n - 1)`. The
+    /// default in R and numpy.
+    #[default]
+    Linear,
+}
+
+impl QuantileMethod {
+    pub fn label(self) -> &'static str {
+        match self {
+            QuantileMethod::InverseCdf => "Inverse of the empirical CDF (type 1)",
+            QuantileMethod::Weibull => "Weibull (type 6)",
+            QuantileMethod::Linear => "Linear (type 7)",
+        }
+    }
+}
+
+impl fmt::Display for QuantileMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// The `p` quantile of `sorted`, which must be in increasing order.
+///
+/// Returns `None` if `sorted` is empty or `p` is not between 0 and 1.
+pub fn quantile(sorted: &[f64], p: f64, method: QuantileMethod) -> Option<f64> {
+    debug_assert!(sorted.windows(2).all(|pair| pair[0] <= pair[1]));
+    if sorted.is_empty() || !(0. ..=1.).contains(&p) {
+        return None;
+    }
+    let n = sorted.len();
+    // The 0-based position of the quantile in `sorted`.
+    let position = match method {
+        QuantileMethod::InverseCdf => {
+            let rank = (p * n as f64).ceil() as usize;
+            return Some(sorted[rank.clamp(1, n) - 1]);
+        }
+        QuantileMethod::Weibull => p * (n + 1) as f64 - 1.,
+        QuantileMethod::Linear => p * (n - 1) as f64,
+    };
+    let position = position.clamp(0., (n - 1) as f64);
+    let lower = position.floor() as usize;
+    let upper = (lower + 1).min(n - 1);
+    let fraction = position - lower as f64;
+    Some(sorted[lower] + fraction * (sorted[upper] - sorted[lower]))
+}
+
+/// The median of `sorted` (which must be in increasing order), or `None` if it is empty.
+pub fn median(sorted: &[f64]) -> Option<f64> {
+    quantile(sorted, 0.5, QuantileMethod::Linear)
+}
+
+/// The quartiles of some data.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Quartiles {
+    pub lower: f64,
+    pub median: f64,
+    pub upper: f64,
+}
+
+impl Quartiles {
+    /// The quartiles of `sorted` (which must be in increasing order), or `None` if it is empty.
+    pub fn new(sorted: &[f64], method: QuantileMethod) -> Option<Self> {
+        Some(Quartiles {
+            lower: quantile(sorted, 0.25, method)?,
+            median: quantile(sorted, 0.5, method)?,
+            upper: quantile(sorted, 0.75, method)?,
+        })
+    }
+
+    /// The interquartile range.
+    pub fn iqr(&self) -> f64 {
+        self.upper - self.lower
+    }
+}
+
+/// Sort `values` into increasing order, for the functions in this module. NaNs go last.
+pub fn sorted(values: impl IntoIterator<Item = f64>) -> Vec<f64> {
+    let mut values = values.into_iter().collect::<Vec<_>>();
+    values.sort_by(f64::total_cmp);
+    values
+}
+
+/// The total weight of `data`, if the weights are valid and it is more than 0.
+fn total_weight(data: &[(f64, f64)]) -> Option<f64> {
+    if data
+        .iter()
+        .any(|(_, weight)| !weight.is_finite() || *weight < 0.)
+    {
+        return None;
+    }
+    let total = data.iter().map(|(_, weight)| weight).sum::<f64>();
+    (total > 0.).then_some(total)
+}
+
+/// The weighted mean of `data`, or `None` if there is no (valid) weight.
+pub fn weighted_mean(data: &[(f64, f64)]) -> Option<f64> {
+    let total = total_weight(data)?;
+    Some(
+        data.iter()
+            .map(|(value, weight)| value * weight)
+            .sum::<f64>()
+            / total,
+    )
+}
+
+/// The weighted standard deviation of `data` around its weighted mean, or `None` if there is no
+/// (valid) weight.
+///
+/// This divides by the total weight, so with equal weights it is the population (not sample)
+/// standard deviation.
+pub fn weighted_sd(data: &[(f64, f64)]) -> Option<f64> {
+    let total = total_weight(data)?;
+    let mean = weighted_mean(data)?;
+    let variance = data
+        .iter()
+        .map(|(value, weight)| weight * (value - mean).powi(2))
+        .sum::<f64>()
+        / total;
+    Some(variance.sqrt())
+}
+
+/// The weighted `p` quantile of `data`: the smallest value with at least a proportion `p` of the
+/// total weight at or below it.
+///
+/// With equal weights this is [`QuantileMethod::InverseCdf`]. Returns `None` if there is no
+/// (valid) weight or `p` is not between 0 and 1.
+pub fn weighted_quantile(data: &[(f64, f64)], p: f64) -> Option<f64> {
+    let total = total_weight(data)?;
+    if !(0. ..=1.).contains(&p) {
+        return None;
+    }
+    let mut sorted = data
+        .iter()
+        .filter(|(_, weight)| *weight > 0.)
+        .collect::<Vec<_>>();
+    sorted.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+    let target = p * total;
+    let mut cumulative = 0.;
+    for (value, weight) in sorted.iter() {
+        cumulative += weight;
+        // Allow for rounding in the running total.
+        if cumulative >= target * (1. - f64::EPSILON * 4.) {
+            return Some(*value);
+        }
+    }
+    sorted.last().map(|(value, _)| *value)
+}
+
+/// The weighted median of `data` (see [`weighted_quantile`]).
+pub fn weighted_median(data: &[(f64, f64)]) -> Option<f64> {
+    weighted_quantile(data, 0.5)
+}
+
+/// The number of cases (e.g. adherent patients) out of the total in one of a set of ordered
+/// groups.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct TrendGroup {
+    pub cases: usize,
+    pub total: usize,
+}
+
+/// The result of a [`cochran_armitage`] trend test.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TrendTest {
+    /// Positive if the proportion of cases goes up with the score.
+    pub z: f64,
+    /// Two-sided.
+    pub p_value: f64,
+}
+
+impl TrendTest {
+    /// The chi-squared statistic (on 1 degree of freedom), as reported by R's `prop.trend.test`.
+    pub fn chi_squared(&self) -> f64 {
+        self.z * self.z
+    }
+}
+
+/// The Cochran-Armitage test for a linear trend in the proportion of cases across ordered groups,
+/// each with a score (e.g. the IMD decile).
+///
+/// Returns `None` if there are no cases, or nothing but cases, or the groups with anyone in them
+/// all have the same score.
+pub fn cochran_armitage(groups: &[(f64, TrendGroup)]) -> Option<TrendTest> {
+    let total = groups.iter().map(|(_, g)| g.total).sum::<usize>() as f64;
+    let cases = groups.iter().map(|(_, g)| g.cases).sum::<usize>() as f64;
+    debug_assert!(groups.iter().all(|(_, g)| g.cases <= g.total));
+    let proportion = cases / total;
+    let statistic = groups
+        .iter()
+        .map(|(score, g)| score * (g.cases as f64 - g.total as f64 * proportion))
+        .sum::<f64>();
+    let sum_score = groups
+        .iter()
+        .map(|(score, g)| score * g.total as f64)
+        .sum::<f64>();
+    let sum_score_sq = groups
+        .iter()
+        .map(|(score, g)| score * score * g.total as f64)
+        .sum::<f64>();
+    let variance = proportion * (1. - proportion) * (sum_score_sq - sum_score * sum_score / total);
+    if total == 0. || variance <= 0. {
+        return None;
+    }
+    let z = statistic / variance.sqrt();
+    let normal = Normal::new(0., 1.).unwrap();
+    Some(TrendTest {
+        z,
+        p_value: 2. * (1. - normal.cdf(z.abs())),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn quantiles() {
+        // Checked against R's `quantile(1:10, 0.25, type = t)`.
+        let data = sorted((1..=10).rev().map(f64::from));
+        let q = |p, method| quantile(&data, p, method).unwrap();
+        assert_eq!(q(0.25, QuantileMethod::Linear), 3.25);
+        assert_eq!(q(0.25, QuantileMethod::Weibull), 2.75);
+        assert_eq!(q(0.25, QuantileMethod::InverseCdf), 3.);
+        for method in [
+            QuantileMethod::InverseCdf,
+            QuantileMethod::Weibull,
+            QuantileMethod::Linear,
+        ] {
+            assert_eq!(q(0., method), 1.);
+            assert_eq!(q(1., method), 10.);
+        }
+
+        assert_eq!(median(&[1., 2., 3., 4.]), Some(2.5));
+        assert_eq!(median(&[7.]), Some(7.));
+        assert_eq!(median(&[]), None);
+        assert_eq!(quantile(&data, 1.5, QuantileMethod::Linear), None);
+        let quartiles = Quartiles::new(&data, QuantileMethod::Linear).unwrap();
+        assert_eq!(quartiles.iqr(), 4.5);
+    }
+
+    #[test]
+    fn weighted() {
+        let equal = [(1., 2.), (2., 2.), (3., 2.), (4., 2.)];
+        assert_eq!(weighted_mean(&equal), Some(2.5));
+        assert!((weighted_sd(&equal).unwrap() - 1.25f64.sqrt()).abs() < 1e-12);
+        let data = sorted([4., 2., 1., 3.]);
+        for p in [0., 0.25, 0.3, 0.5, 0.9, 1.] {
+            assert_eq!(
+                weighted_quantile(&equal, p),
+                quantile(&data, p, QuantileMethod::InverseCdf)
+            );
+        }
+
+        // 3 counts three times.
+        let data = [(1., 1.), (3., 3.)];
+        assert_eq!(weighted_mean(&data), Some(2.5));
+        assert_eq!(weighted_median(&data), Some(3.));
+        assert_eq!(weighted_quantile(&data, 0.25), Some(1.));
+
+        assert_eq!(weighted_mean(&[]), None);
+        assert_eq!(weighted_mean(&[(1., 0.)]), None);
+        assert_eq!(weighted_mean(&[(1., -1.), (2., 2.)]), None);
+    }
+
+    #[test]
+    fn trend() {
+        // The example for R's `prop.trend.test`.
+        let group = |cases, total| TrendGroup { cases, total };
+        let groups = [
+            (1., group(83, 86)),
+            (2., group(90, 93)),
+            (3., group(129, 136)),
+            (4., group(70, 82)),
+        ];
+        let test = cochran_armitage(&groups).unwrap();
+        assert!((test.chi_squared() - 8.2249).abs() < 1e-4);
+        assert!((test.p_value - 0.004132).abs() < 1e-6);
+        assert!(test.z < 0.);
+
+        assert_eq!(cochran_armitage(&[]), None);
+        assert_eq!(
+            cochran_armitage(&[(1., group(3, 3)), (2., group(4, 4))]),
+            None
+        );
+    }
+}
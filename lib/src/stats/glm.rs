@@ -0,0 +1,480 @@
+//! Generalised linear models, for adjusted estimates (e.g. odds of adherence by age, sex, IMD and
+//! subtype).
+//!
+//! Models are fitted by iteratively reweighted least squares, as R's `glm` does, and should give
+//! the same estimates and standard errors. Confidence intervals and p-values use the normal
+//! approximation (Wald), as `summary.glm` and `confint.default` do.
+use crate::render::TextTable;
+use qu::ick_use::*;
+use statrs::distribution::{ContinuousCDF, Normal};
+use std::{collections::BTreeSet, fmt};
+
+/// Stop when the deviance changes by less than this (relative) amount. R's default.
+const TOLERANCE: f64 = 1e-8;
+const MAX_ITERATIONS: usize = 25;
+
+/// The error distribution and link function of a model.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Family {
+    /// Logistic regression, for yes/no outcomes. Coefficients are log odds ratios.
+    Binomial,
+    /// Poisson regression (log link), for counts. Coefficients are log rate ratios.
+    Poisson,
+}
+
+impl Family {
+    pub fn label(self) -> &'static str {
+        match self {
+            Family::Binomial => "Logistic",
+            Family::Poisson => "Poisson",
+        }
+    }
+
+    /// What the exponentiated coefficients are.
+    pub fn ratio_label(self) -> &'static str {
+        match self {
+            Family::Binomial => "Odds ratio",
+            Family::Poisson => "Rate ratio",
+        }
+    }
+
+    fn check_response(self, y: f64) -> bool {
+        match self {
+            Family::Binomial => (0. ..=1.).contains(&y),
+            Family::Poisson => y >= 0. && y.is_finite(),
+        }
+    }
+
+    /// The fitted mean for linear predictor `eta`.
+    fn mean(self, eta: f64) -> f64 {
+        match self {
+            Family::Binomial => 1. / (1. + (-eta).exp()),
+            Family::Poisson => eta.exp(),
+        }
+    }
+
+    /// The linear predictor for mean `mu`.
+    fn link(self, mu: f64) -> f64 {
+        match self {
+            Family::Binomial => (mu / (1. - mu)).ln(),
+            Family::Poisson => mu.ln(),
+        }
+    }
+
+    /// The variance of an observation with mean `mu`, which (for canonical links) is also the
+    /// derivative of the mean with respect to the linear predictor.
+    fn variance(self, mu: f64) -> f64 {
+        match self {
+            Family::Binomial => mu * (1. - mu),
+            Family::Poisson => mu,
+        }
+    }
+
+    /// A starting mean that is valid for the link function (as R's `mustart`).
+    fn start(self, y: f64) -> f64 {
+        match self {
+            Family::Binomial => (y + 0.5) / 2.,
+            Family::Poisson => y + 0.1,
+        }
+    }
+
+    fn deviance(self, y: &[f64], mu: &[f64]) -> f64 {
+        // `y ln(y / mu)`, which is 0 when `y` is.
+        let ylogy = |y: f64, mu: f64| if y > 0. { y * (y / mu).ln() } else { 0. };
+        let total: f64 = y
+            .iter()
+            .zip(mu)
+            .map(|(&y, &mu)| match self {
+                Family::Binomial => ylogy(y, mu) + ylogy(1. - y, 1. - mu),
+                Family::Poisson => ylogy(y, mu) - (y - mu),
+            })
+            .sum();
+        2. * total
+    }
+}
+
+impl fmt::Display for Family {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// The covariates for a model, one column per coefficient.
+///
+/// Starts with just an intercept. Categorical covariates get a 0/1 column for each level other
+/// than the reference level.
+#[derive(Debug, Clone)]
+pub struct Design {
+    names: Vec<String>,
+    columns: Vec<Vec<f64>>,
+}
+
+impl Design {
+    /// A design with an intercept, for `rows` observations.
+    pub fn new(rows: usize) -> Self {
+        Design {
+            names: vec!["Intercept".into()],
+            columns: vec![vec![1.; rows]],
+        }
+    }
+
+    /// The number of observations.
+    pub fn rows(&self) -> usize {
+        self.columns[0].len()
+    }
+
+    /// The name of each coefficient.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Add a numeric covariate.
+    ///
+    /// # Panics
+    ///
+    /// If there isn't one value for each observation.
+    pub fn with_numeric(mut self, name: &str, values: impl IntoIterator<Item = f64>) -> Self {
+        let values = values.into_iter().collect::<Vec<_>>();
+        assert_eq!(
+            values.len(),
+            self.rows(),
+            "wrong number of values for {name}"
+        );
+        self.names.push(name.into());
+        self.columns.push(values);
+        self
+    }
+
+    /// Add a categorical covariate, compared to the `reference` level.
+    ///
+    /// Only levels that appear in `values` get a column.
+    ///
+    /// # Panics
+    ///
+    /// If there isn't one value for each observation.
+    pub fn with_categorical<K: Ord + fmt::Display>(
+        mut self,
+        name: &str,
+        values: &[K],
+        reference: &K,
+    ) -> Self {
+        assert_eq!(
+            values.len(),
+            self.rows(),
+            "wrong number of values for {name}"
+        );
+        let levels = values
+            .iter()
+            .filter(|value| *value != reference)
+            .collect::<BTreeSet<_>>();
+        for level in levels {
+            self.names.push(format!("{name}: {level}"));
+            self.columns.push(
+                values
+                    .iter()
+                    .map(|value| if value == level { 1. } else { 0. })
+                    .collect(),
+            );
+        }
+        self
+    }
+
+    /// `X^T W X`, where `X` is the design matrix and `W` has `weights` on the diagonal.
+    fn weighted_cross_product(&self, weights: &[f64]) -> Vec<Vec<f64>> {
+        self.columns
+            .iter()
+            .map(|a| {
+                self.columns
+                    .iter()
+                    .map(|b| {
+                        (0..weights.len())
+                            .map(|idx| a[idx] * weights[idx] * b[idx])
+                            .sum()
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// The linear predictor for `coefficients`, without any offset.
+    fn predict(&self, coefficients: &[f64]) -> Vec<f64> {
+        let mut out = vec![0.; self.rows()];
+        for (column, coefficient) in self.columns.iter().zip(coefficients) {
+            for (out, x) in out.iter_mut().zip(column) {
+                *out += x * coefficient;
+            }
+        }
+        out
+    }
+}
+
+/// Fit a model of `response` on `design`.
+///
+/// For rates, `offset` should be the log of each observation's exposure (e.g. person-years), so
+/// that the coefficients are log rate ratios.
+pub fn fit(
+    family: Family,
+    design: &Design,
+    response: &[f64],
+    offset: Option<&[f64]>,
+) -> Result<GlmFit> {
+    let n = design.rows();
+    ensure!(
+        response.len() == n,
+        "{} responses for {} observations",
+        response.len(),
+        n
+    );
+    if let Some(offset) = offset {
+        ensure!(
+            offset.len() == n,
+            "{} offsets for {} observations",
+            offset.len(),
+            n
+        );
+        ensure!(
+            offset.iter().all(|v| v.is_finite()),
+            "offsets must be finite"
+        );
+    }
+    ensure!(
+        response.iter().all(|&y| family.check_response(y)),
+        "invalid response for a {} model",
+        family.label().to_lowercase()
+    );
+    ensure!(
+        n > design.columns.len(),
+        "{} observations is too few to fit {} coefficients",
+        n,
+        design.columns.len()
+    );
+    let offset_at = |idx: usize| offset.map(|offset| offset[idx]).unwrap_or(0.);
+
+    let mut mu = response
+        .iter()
+        .map(|&y| family.start(y))
+        .collect::<Vec<_>>();
+    let mut eta = mu.iter().map(|&mu| family.link(mu)).collect::<Vec<_>>();
+    let mut deviance = family.deviance(response, &mu);
+    let mut coefficients = vec![];
+    let mut covariance = vec![];
+    let mut converged = false;
+    let mut iterations = 0;
+    while iterations < MAX_ITERATIONS {
+        iterations += 1;
+        // Weighted least squares of the working response on the design.
+        let weights = mu.iter().map(|&mu| family.variance(mu)).collect::<Vec<_>>();
+        let working = (0..n)
+            .map(|idx| eta[idx] - offset_at(idx) + (response[idx] - mu[idx]) / weights[idx])
+            .collect::<Vec<_>>();
+        let xtwx = design.weighted_cross_product(&weights);
+        let xtwz = design
+            .columns
+            .iter()
+            .map(|a| (0..n).map(|idx| a[idx] * weights[idx] * working[idx]).sum())
+            .collect::<Vec<f64>>();
+        covariance = invert_spd(&xtwx).ok_or_else(|| {
+            format_err!(
+                "the design is singular (a covariate is constant, or can be worked out from the others)"
+            )
+        })?;
+        coefficients = covariance
+            .iter()
+            .map(|row| row.iter().zip(&xtwz).map(|(a, b)| a * b).sum())
+            .collect();
+
+        eta = design.predict(&coefficients);
+        for (idx, eta) in eta.iter_mut().enumerate() {
+            *eta += offset_at(idx);
+        }
+        mu = eta.iter().map(|&eta| family.mean(eta)).collect();
+        let previous = deviance;
+        deviance = family.deviance(response, &mu);
+        if (deviance - previous).abs() / (deviance.abs() + 0.1) < TOLERANCE {
+            converged = true;
+            break;
+        }
+    }
+    // The covariance at the final estimates.
+    let weights = mu.iter().map(|&mu| family.variance(mu)).collect::<Vec<_>>();
+    if let Some(final_covariance) = invert_spd(&design.weighted_cross_product(&weights)) {
+        covariance = final_covariance;
+    }
+
+    let normal = Normal::new(0., 1.).unwrap();
+    let coefficients = design
+        .names
+        .iter()
+        .zip(coefficients)
+        .enumerate()
+        .map(|(idx, (name, estimate))| {
+            let std_error = covariance[idx][idx].sqrt();
+            let z = estimate / std_error;
+            Coefficient {
+                name: name.clone(),
+                estimate,
+                std_error,
+                z,
+                p_value: 2. * (1. - normal.cdf(z.abs())),
+            }
+        })
+        .collect();
+    Ok(GlmFit {
+        family,
+        coefficients,
+        deviance,
+        observations: n,
+        iterations,
+        converged,
+    })
+}
+
+/// One estimated coefficient of a [`GlmFit`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Coefficient {
+    pub name: String,
+    /// On the link scale (e.g. a log odds ratio).
+    pub estimate: f64,
+    pub std_error: f64,
+    pub z: f64,
+    /// Two-sided.
+    pub p_value: f64,
+}
+
+impl Coefficient {
+    /// The odds or rate ratio.
+    pub fn ratio(&self) -> f64 {
+        self.estimate.exp()
+    }
+
+    /// The `level` (e.g. 0.95) confidence interval for the odds or rate ratio.
+    pub fn ratio_ci(&self, level: f64) -> (f64, f64) {
+        let normal = Normal::new(0., 1.).unwrap();
+        let z = normal.inverse_cdf(0.5 + level / 2.);
+        (
+            (self.estimate - z * self.std_error).exp(),
+            (self.estimate + z * self.std_error).exp(),
+        )
+    }
+}
+
+/// A fitted model.
+#[derive(Debug, Clone)]
+pub struct GlmFit {
+    pub family: Family,
+    /// Starting with the intercept, in the order of the [`Design`].
+    pub coefficients: Vec<Coefficient>,
+    pub deviance: f64,
+    pub observations: usize,
+    pub iterations: usize,
+    /// Whether the fit converged. If not (e.g. because an outcome is perfectly predicted by a
+    /// covariate) the estimates shouldn't be used.
+    pub converged: bool,
+}
+
+impl GlmFit {
+    pub fn coefficient(&self, name: &str) -> Option<&Coefficient> {
+        self.coefficients.iter().find(|coef| coef.name == name)
+    }
+
+    /// The odds or rate ratio for each covariate, with its 95% confidence interval and p-value.
+    ///
+    /// There are no counts in the table, but it shouldn't be released if any category of a
+    /// covariate is small.
+    pub fn text_table(&self) -> TextTable {
+        let mut table = TextTable::new(["Term", self.family.ratio_label(), "95% CI", "p"]);
+        for coef in &self.coefficients {
+            let (lower, upper) = coef.ratio_ci(0.95);
+            table.push_row([
+                coef.name.clone(),
+                format!("{:.2}", coef.ratio()),
+                format!("{:.2} - {:.2}", lower, upper),
+                format_p(coef.p_value),
+            ]);
+        }
+        table.push_row(["Observations".to_string(), self.observations.to_string()]);
+        if !self.converged {
+            table.push_row(["Did not converge".to_string()]);
+        }
+        table
+    }
+}
+
+fn format_p(p: f64) -> String {
+    if p < 0.001 {
+        "<0.001".into()
+    } else {
+        format!("{:.3}", p)
+    }
+}
+
+/// The inverse of a symmetric positive definite matrix, or `None` if it is singular.
+fn invert_spd(matrix: &[Vec<f64>]) -> Option<Vec<Vec<f64>>> {
+    let n = matrix.len();
+    // Cholesky decomposition, `matrix = L L^T`.
+    let mut lower = vec![vec![0.; n]; n];
+    for i in 0..n {
+        for j in 0..=i {
+            let sum = matrix[i][j] - (0..j).map(|k| lower[i][k] * lower[j][k]).sum::<f64>();
+            if i == j {
+                // Relative to the diagonal, so the scale of a covariate doesn't matter.
+                if sum <= matrix[i][i].abs() * 1e-10 {
+                    return None;
+                }
+                lower[i][i] = sum.sqrt();
+            } else {
+                lower[i][j] = sum / lower[j][j];
+            }
+        }
+    }
+    // Solve `L L^T x = e_col` for each column of the identity. The inverse is symmetric, so each
+    // solution is also a row.
+    let mut out = vec![];
+    for col in 0..n {
+        let mut y = vec![0.; n];
+        for i in 0..n {
+            let rhs = if i == col { 1. } else { 0. };
+            y[i] = (rhs - (0..i).map(|k| lower[i][k] * y[k]).sum::<f64>()) / lower[i][i];
+        }
+        let mut x = vec![0.; n];
+        for i in (0..n).rev() {
+            x[i] = (y[i] - (i + 1..n).map(|k| lower[k][i] * x[k]).sum::<f64>()) / lower[i][i];
+        }
+        out.push(x);
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fit_models() {
+        // Checked against a separate Newton-Raphson fit.
+        let x = [1., 2., 3., 4., 5., 6., 7., 8.];
+        let y = [0., 0., 1., 0., 1., 0., 1., 1.];
+        let design = Design::new(x.len()).with_numeric("x", x);
+        let model = fit(Family::Binomial, &design, &y, None).unwrap();
+        assert!(model.converged);
+        let slope = model.coefficient("x").unwrap();
+        assert!((slope.estimate - 0.5941).abs() < 1e-4);
+        assert!((slope.std_error - 0.4322).abs() < 1e-4);
+        assert!((model.coefficients[0].estimate + 2.6734).abs() < 1e-4);
+
+        // Rates by group with person-time offsets: the rate ratio is the ratio of crude rates.
+        let group = ["a", "a", "b", "b"];
+        let counts = [2., 4., 9., 3.];
+        let years = [2., 4., 3., 1.];
+        let design = Design::new(group.len()).with_categorical("group", &group, &"a");
+        assert_eq!(design.names(), ["Intercept", "group: b"]);
+        let offset = years.map(f64::ln);
+        let model = fit(Family::Poisson, &design, &counts, Some(&offset)).unwrap();
+        let ratio = model.coefficient("group: b").unwrap().ratio();
+        assert!((ratio - 3.).abs() < 1e-8);
+        assert!((model.coefficients[0].ratio() - 1.).abs() < 1e-8);
+
+        let singular = Design::new(4).with_numeric("constant", [1.; 4]);
+        assert!(fit(Family::Poisson, &singular, &counts, None).is_err());
+    }
+}
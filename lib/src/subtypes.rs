@@ -33,13 +33,20 @@
 //! 1. Between Hodgkin and non-Hodgkin (including subtypes)
 //! 2. Between different non-Hodgkin subtypes
 //!
-use crate::{load, read2::CodeRubric, save, Events, PatientId};
+use crate::{
+    envelope::Schema,
+    file_exists, load,
+    read2::{CodeRubric, ReadCode},
+    render::TextTable,
+    save, ArcStr, CodeRubricCounts, DisclosureControl, Events, PatientId, Patients, RangeSet,
+};
+use chrono::NaiveDate;
 use itertools::Itertools;
 use qu::ick_use::*;
 use serde::{Deserialize, Serialize};
 use std::{
     collections::{BTreeMap, BTreeSet},
-    fmt,
+    fmt, fs, iter,
     path::Path,
 };
 use term_data_table as tdt;
@@ -72,6 +79,13 @@ impl fmt::Display for LymphomaSubtype {
 }
 
 impl LymphomaSubtype {
+    /// Every subtype, from least to most specific.
+    pub fn all() -> impl Iterator<Item = LymphomaSubtype> {
+        [LymphomaSubtype::Unspecified, LymphomaSubtype::Hodgkin]
+            .into_iter()
+            .chain(NonHodgkinSubtype::ALL.map(LymphomaSubtype::NonHodgkin))
+    }
+
     /// A human-readable label for the subtype.
     pub fn label(self) -> &'static str {
         use LymphomaSubtype::*;
@@ -91,6 +105,15 @@ impl LymphomaSubtype {
         }
     }
 
+    /// Whether this is a named subtype, rather than lymphoma or non-Hodgkin lymphoma unspecified.
+    pub fn is_specific(self) -> bool {
+        !matches!(
+            self,
+            LymphomaSubtype::Unspecified
+                | LymphomaSubtype::NonHodgkin(NonHodgkinSubtype::Unspecified)
+        )
+    }
+
     /// Is `other` a subtype of `self`
     pub fn is_subtype_of(&self, other: &Self) -> bool {
         use LymphomaSubtype::*;
@@ -134,7 +157,8 @@ impl std::str::FromStr for NonHodgkinSubtype {
     fn from_str(input: &str) -> Result<Self, Self::Err> {
         use NonHodgkinSubtype::*;
         Ok(match input {
-            "nonhodgkin" => Unspecified,
+            // `code` gives "unspecified", older files use "nonhodgkin".
+            "nonhodgkin" | "unspecified" => Unspecified,
             "small" => Small,
             "splenic" => Splenic,
             "lymphoplasmacytic" => Lymphoplasmacytic,
@@ -156,6 +180,25 @@ impl std::str::FromStr for NonHodgkinSubtype {
 }
 
 impl NonHodgkinSubtype {
+    pub const ALL: [NonHodgkinSubtype; 16] = [
+        NonHodgkinSubtype::Unspecified,
+        NonHodgkinSubtype::Small,
+        NonHodgkinSubtype::Splenic,
+        NonHodgkinSubtype::Lymphoplasmacytic,
+        NonHodgkinSubtype::ExtraMarginal,
+        NonHodgkinSubtype::Follicular,
+        NonHodgkinSubtype::Mantle,
+        NonHodgkinSubtype::DLBCL,
+        NonHodgkinSubtype::Mediastinal,
+        NonHodgkinSubtype::Burkitt,
+        NonHodgkinSubtype::Nasal,
+        NonHodgkinSubtype::SubcutaneousT,
+        NonHodgkinSubtype::Peripheral,
+        NonHodgkinSubtype::Angioimmunoblastic,
+        NonHodgkinSubtype::AlkPos,
+        NonHodgkinSubtype::AlkNeg,
+    ];
+
     /// A human-readable label for the subtype.
     ///
     /// Text for non-Hodgkin lymphoma subtypes comes from 'WHO classification of non-Hodgkin
@@ -208,6 +251,17 @@ impl NonHodgkinSubtype {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CodeSubtypeMap(BTreeMap<CodeRubric, LymphomaSubtype>);
 
+// The map is saved as a list of pairs.
+const CODE_SUBTYPE_SCHEMA: &str = "(CodeRubric { code: ReadCode, rubric: str }, LymphomaSubtype)";
+
+impl Schema for (CodeRubric, LymphomaSubtype) {
+    const SCHEMA: &'static str = CODE_SUBTYPE_SCHEMA;
+}
+
+impl Schema for (&CodeRubric, &LymphomaSubtype) {
+    const SCHEMA: &'static str = CODE_SUBTYPE_SCHEMA;
+}
+
 impl CodeSubtypeMap {
     pub fn save(&self, path: impl AsRef<Path>) -> Result {
         Ok(save(&self.0.iter().collect::<Vec<_>>(), path)?)
@@ -222,6 +276,45 @@ impl CodeSubtypeMap {
         self.0.get(code_rubric).map(|x| *x)
     }
 
+    /// Map `code_rubric` to `subtype`, returning the subtype it was mapped to before (if any).
+    pub fn insert(
+        &mut self,
+        code_rubric: CodeRubric,
+        subtype: LymphomaSubtype,
+    ) -> Option<LymphomaSubtype> {
+        self.0.insert(code_rubric, subtype)
+    }
+
+    /// The code/rubric pairs in `counts` that aren't in the map.
+    ///
+    /// `counts` should already be filtered to the lymphoma codeset (see
+    /// [`CodeRubricCounts::filter_by_codeset`]), otherwise this will include every non-lymphoma
+    /// code.
+    pub fn unmapped(&self, counts: &CodeRubricCounts) -> CodeRubricCounts {
+        counts.filter(|cr| !self.0.contains_key(&cr.code_rubric))
+    }
+
+    /// Add the decisions from a review file (see [`append_review_decision`]) to the map.
+    ///
+    /// Decisions replace any mapping already in the map for the same code/rubric. Returns the
+    /// number of decisions applied.
+    pub fn apply_review(&mut self, path: impl AsRef<Path>) -> Result<usize> {
+        let path = path.as_ref();
+        let mut reader = csv::Reader::from_path(path)
+            .with_context(|| format!("opening \"{}\"", path.display()))?;
+        let mut applied = 0;
+        for decision in reader.deserialize() {
+            let decision: ReviewDecision =
+                decision.with_context(|| format!("reading \"{}\"", path.display()))?;
+            self.insert(
+                CodeRubric::new(decision.read_code, decision.rubric),
+                decision.subtype.parse()?,
+            );
+            applied += 1;
+        }
+        Ok(applied)
+    }
+
     /// Takes a collection of record events and classifies the patient IDs.
     ///
     /// See the module documentation for details of how this is accomplished.
@@ -270,6 +363,37 @@ impl CodeSubtypeMap {
         subtype_map
     }
 
+    /// Classify patients as [`classify`](Self::classify) does, and keep the events that put each
+    /// patient in each of their subtypes.
+    pub fn classify_with_evidence(&self, events: &Events) -> BTreeMap<PatientId, PatientSubtypes> {
+        let classified = self.classify(events);
+        let mut patients: BTreeMap<PatientId, PatientSubtypes> = BTreeMap::new();
+        for event in events.into_iter() {
+            let Some(&subtype) = self.0.get(&event.code_rubric()) else {
+                continue;
+            };
+            if !classified
+                .get(&subtype)
+                .map(|ids| ids.contains(&event.patient_id))
+                .unwrap_or(false)
+            {
+                continue;
+            }
+            patients
+                .entry(event.patient_id)
+                .or_default()
+                .subtypes
+                .entry(subtype)
+                .or_default()
+                .push(SubtypeEvidence {
+                    date: event.date,
+                    read_code: event.read_code,
+                    rubric: event.rubric.clone(),
+                });
+        }
+        patients
+    }
+
     /// To display in the console/terminal.
     pub fn term_table(&self) -> tdt::Table<'static> {
         self.0.iter().fold(tdt::Table::new(), |tbl, (cr, subtype)| {
@@ -304,8 +428,215 @@ impl CodeSubtypeMap {
     }
 }
 
+/// A subtype chosen for a code/rubric pair while reviewing unmapped pairs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ReviewDecision {
+    read_code: ReadCode,
+    rubric: String,
+    /// The subtype's [`code`](LymphomaSubtype::code).
+    subtype: String,
+}
+
+/// Append a review decision to a CSV file, creating it (with headers) if it doesn't exist.
+///
+/// Decisions are kept in their own file so they survive the map being imported again from Excel.
+pub fn append_review_decision(
+    path: impl AsRef<Path>,
+    code_rubric: &CodeRubric,
+    subtype: LymphomaSubtype,
+) -> Result {
+    let path = path.as_ref();
+    let is_new = !file_exists(path)?;
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("opening \"{}\"", path.display()))?;
+    let mut out = csv::WriterBuilder::new()
+        .has_headers(is_new)
+        .from_writer(file);
+    out.serialize(ReviewDecision {
+        read_code: code_rubric.code,
+        rubric: code_rubric.rubric.to_string(),
+        subtype: subtype.code().to_string(),
+    })?;
+    out.flush()?;
+    Ok(())
+}
+
+/// An event that puts a patient in a subtype.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SubtypeEvidence {
+    pub date: NaiveDate,
+    pub read_code: ReadCode,
+    pub rubric: ArcStr,
+}
+
+/// A patient's subtypes, with the events behind each one (in the order they were recorded).
+#[derive(Debug, Clone, Default)]
+pub struct PatientSubtypes {
+    pub subtypes: BTreeMap<LymphomaSubtype, Vec<SubtypeEvidence>>,
+}
+
+impl PatientSubtypes {
+    pub fn term_table(&self) -> tdt::Table<'_> {
+        use tdt::{Row, Table};
+        let mut table = Table::new().with_row(
+            Row::new()
+                .with_cell("Subtype")
+                .with_cell("Date")
+                .with_cell("Read code")
+                .with_cell("Rubric"),
+        );
+        for (subtype, evidence) in self.subtypes.iter() {
+            for evt in evidence {
+                table.add_row(
+                    Row::new()
+                        .with_cell(subtype.label())
+                        .with_cell(evt.date.to_string())
+                        .with_cell(evt.read_code.to_string())
+                        .with_cell(&*evt.rubric),
+                );
+            }
+        }
+        table
+    }
+}
+
+/// Write the evidence for every patient's subtypes to a CSV file, one row per event.
+///
+/// This is patient-level data, so it must stay in the secure environment.
+pub fn save_evidence(
+    patients: &BTreeMap<PatientId, PatientSubtypes>,
+    path: impl AsRef<Path>,
+) -> Result {
+    #[derive(Serialize)]
+    struct Row<'a> {
+        patient_id: PatientId,
+        subtype: &'static str,
+        date: NaiveDate,
+        read_code: ReadCode,
+        rubric: &'a str,
+    }
+
+    let path = path.as_ref();
+    let mut out =
+        csv::Writer::from_path(path).with_context(|| format!("creating \"{}\"", path.display()))?;
+    for (patient_id, subtypes) in patients.iter() {
+        for (subtype, evidence) in subtypes.subtypes.iter() {
+            for evidence in evidence {
+                out.serialize(Row {
+                    patient_id: *patient_id,
+                    subtype: subtype.code(),
+                    date: evidence.date,
+                    read_code: evidence.read_code,
+                    rubric: &evidence.rubric,
+                })?;
+            }
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
 impl From<BTreeMap<CodeRubric, LymphomaSubtype>> for CodeSubtypeMap {
     fn from(from: BTreeMap<CodeRubric, LymphomaSubtype>) -> Self {
         Self(from)
     }
 }
+
+/// Each patient's lymphoma subtype, split by when they were diagnosed.
+///
+/// This shows whether the mix of subtypes, and how specifically they are coded, has changed over
+/// the years the extract covers.
+pub struct SubtypesByPeriod {
+    periods: RangeSet<NaiveDate>,
+    /// For each subtype, the number of patients diagnosed in each period.
+    counts: BTreeMap<LymphomaSubtype, Vec<usize>>,
+    /// The number of patients with a subtype diagnosed in each period.
+    totals: Vec<usize>,
+}
+
+impl SubtypesByPeriod {
+    /// Patients without a diagnosis date or subtype are left out, as are those diagnosed outside
+    /// all of `periods`.
+    pub fn new(patients: &Patients, periods: &RangeSet<NaiveDate>) -> Self {
+        let mut dates: BTreeMap<LymphomaSubtype, Vec<NaiveDate>> = BTreeMap::new();
+        for pat in patients.iter_ref() {
+            if let (Some(date), Some(subtype)) = (
+                pat.lymphoma_diagnosis_date,
+                pat.lymphoma_diagnosis_subtype(),
+            ) {
+                dates.entry(subtype).or_default().push(date);
+            }
+        }
+        let counts: BTreeMap<_, Vec<_>> = dates
+            .into_iter()
+            .map(|(subtype, dates)| {
+                let buckets = periods.clone().bucket_values(dates.into_iter());
+                (subtype, buckets.iter().map(|(_, count)| count).collect())
+            })
+            .collect();
+        let mut totals = vec![0; periods.iter().count()];
+        for period_counts in counts.values() {
+            for (total, count) in totals.iter_mut().zip(period_counts) {
+                *total += count;
+            }
+        }
+        SubtypesByPeriod {
+            periods: periods.clone(),
+            counts,
+            totals,
+        }
+    }
+
+    /// The number of patients in each period with a specific subtype (see
+    /// [`LymphomaSubtype::is_specific`]).
+    pub fn specific(&self) -> Vec<usize> {
+        let mut specific = vec![0; self.totals.len()];
+        for (subtype, period_counts) in self.counts.iter() {
+            if subtype.is_specific() {
+                for (total, count) in specific.iter_mut().zip(period_counts) {
+                    *total += count;
+                }
+            }
+        }
+        specific
+    }
+
+    /// One column per period, with counts and percentages of the patients diagnosed in that
+    /// period protected by `dc`. Periods without any patients are left out.
+    pub fn term_table(&self, dc: &DisclosureControl) -> tdt::Table<'_> {
+        self.text_table(dc).into_term_table()
+    }
+
+    pub fn text_table(&self, dc: &DisclosureControl) -> TextTable {
+        let columns: Vec<_> = (0..self.totals.len())
+            .filter(|idx| self.totals[*idx] > 0)
+            .collect();
+        let periods: Vec<_> = self.periods.iter().collect();
+        let headers = iter::once("Subtype".to_string())
+            .chain(columns.iter().map(|idx| periods[*idx].to_string()));
+        let count_row = |label: &str, counts: &[usize]| {
+            iter::once(label.to_string())
+                .chain(
+                    columns
+                        .iter()
+                        .map(|idx| dc.count_with_percentage(counts[*idx], self.totals[*idx])),
+                )
+                .collect::<Vec<_>>()
+        };
+        let mut table = TextTable::new(headers);
+        for (subtype, counts) in self.counts.iter() {
+            table.push_row(count_row(subtype.label(), counts));
+        }
+        table.push_row(count_row("Specific subtype", &self.specific()));
+        table.with_row(
+            iter::once("Total".to_string()).chain(
+                columns
+                    .iter()
+                    .map(|idx| dc.count(self.totals[*idx]).to_string()),
+            ),
+        )
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{ArcStr, Imd, ReadCode};
+use crate::{read2::ReadCodeTerm, render::TextTable, ArcStr, Imd, ReadCode};
 use chrono::{NaiveDate, NaiveDateTime, Timelike};
 use serde::{de, Deserialize, Deserializer};
 use std::{collections::BTreeSet, fs, io, path::Path};
@@ -11,8 +11,10 @@ use std::{
     fmt::Write,
 };
 
-/// The default maximum number of rows displayed.
+/// The default number of rows on each page of a table in evcxr.
 pub const DEFAULT_MAX_ROWS: usize = 100;
+/// Pages after this many are left out, so huge tables don't swamp the notebook.
+const MAX_PAGES: usize = 50;
 
 /// Converts a not found error to Ok(false)
 pub fn path_exists(path: &Path) -> io::Result<bool> {
@@ -37,19 +39,7 @@ where
         if v != v.floor() || v < 0. || v > 10. || !v.is_finite() {
             Err(de::Error::custom("invalid value"))
         } else {
-            Ok(match v.floor() as u8 {
-                1 => Imd::_1,
-                2 => Imd::_2,
-                3 => Imd::_3,
-                4 => Imd::_4,
-                5 => Imd::_5,
-                6 => Imd::_6,
-                7 => Imd::_7,
-                8 => Imd::_8,
-                9 => Imd::_9,
-                10 => Imd::_10,
-                _ => return Err(de::Error::custom("invalid value")),
-            })
+            Imd::from_decile(v as u8).ok_or_else(|| de::Error::custom("invalid value"))
         }
     } else {
         Err(de::Error::custom("invalid value"))
@@ -68,6 +58,15 @@ where
     }
 }
 
+/// Like [`maybe_read`], but keeps the term id if there is one.
+pub fn maybe_read_term<'de, D>(d: D) -> Result<Option<ReadCodeTerm>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: &[u8] = Deserialize::deserialize(d)?;
+    Ok(ReadCodeTerm::from_bytes(s).ok())
+}
+
 /// Parse a string, but map "null" to `None` (in addition to the default "" -> None mapping)
 pub fn optional_string<'de, D>(d: D) -> Result<Option<ArcStr>, D::Error>
 where
@@ -151,17 +150,12 @@ pub fn set_default_table_max_rows(new_max_rows: usize) {
 */
 
 pub struct RowDrawer<'a> {
-    output: &'a mut String,
-    scratch: &'a mut String,
+    cells: &'a mut Vec<String>,
 }
 
 impl<'a> RowDrawer<'a> {
     fn cell(&mut self, content: impl fmt::Display) {
-        self.output.push_str("<td>");
-        self.scratch.clear();
-        let _ = write!(self.scratch, "{}", content);
-        html_escape::encode_text_to_string(&mut self.scratch, self.output);
-        self.output.push_str("</td>");
+        self.cells.push(content.to_string());
     }
 }
 
@@ -214,7 +208,7 @@ pub struct Table<Row, I, DR> {
     title: Option<Cow<'static, str>>,
     row_fn: Box<dyn Fn(&Row, usize) -> DR>,
     data: RefCell<I>,
-    /// must be even - enforced by setter and `new`.
+    /// Rows per page.
     max_rows: Option<usize>,
     col_count: Cell<Option<usize>>,
     completed: Cell<bool>,
@@ -255,21 +249,17 @@ where
         self
     }
 
-    /// Set the maximum number of rows to show
+    /// Set the number of rows on each page (0 shows every row on one page).
+    ///
+    /// The first page is shown as a table, and the rest as collapsed sections underneath it.
     pub fn set_max_rows(mut self, max_rows: usize) -> Self {
-        self.max_rows = Some(constrain_max_rows(max_rows));
+        self.max_rows = Some(max_rows);
         self
     }
 
     /// Display this table as HTML in the evcxr window.
     pub fn evcxr_display(&self) {
-        let iter = self.data.borrow_mut();
-        if self.completed.replace(true) {
-            panic!(
-                "Tables are used once. Please recreate the table for each display \
-                   (they are cheap to create)"
-            );
-        }
+        let iter = self.take_data();
 
         // buffer our output so we only draw something when there's no error
         let mut output = if let Some(title) = &self.title {
@@ -282,23 +272,7 @@ where
             String::from("")
         };
 
-        output.push_str("<table>");
-        if let Some(headers) = &self.headers {
-            self.col_count.set(Some(headers.len()));
-            output.push_str("<thead><tr><th></th>");
-            for header in headers {
-                output.push_str("<th>");
-                html_escape::encode_text_to_string(header, &mut output);
-                output.push_str("</th>");
-            }
-            output.push_str("</tr></thead>");
-        } else {
-            self.col_count.set(None);
-        }
-
-        output.push_str("<tbody>");
         self.write_body(iter, &mut output);
-        output.push_str("</tbody></table>");
 
         println!(
             "EVCXR_BEGIN_CONTENT text/html\n{}\nEVCXR_END_CONTENT",
@@ -306,37 +280,91 @@ where
         );
     }
 
-    fn write_body(&self, iter: RefMut<'_, I>, output: &mut String) {
-        if iter.len() == 0 {
-            return;
+    /// All the rows of this table (the maximum number of rows is only for evcxr).
+    pub fn text_table(&self) -> TextTable {
+        let mut iter = self.take_data();
+        let mut table = TextTable::new(self.headers.iter().flatten().map(|h| h.to_string()));
+        let mut cells = vec![];
+        for (idx, row) in (&mut *iter).enumerate() {
+            cells.clear();
+            (self.row_fn)(&row, idx).draw(RowDrawer { cells: &mut cells });
+            table.push_row(&cells);
         }
-        let max_rows = self.max_rows.unwrap_or_else(|| DEFAULT_MAX_ROWS);
-        self.write_some_rows(iter, max_rows, output);
+        table
     }
 
-    fn write_some_rows(&self, mut iter: RefMut<'_, I>, max_rows: usize, output: &mut String) {
-        let len = iter.len();
-        if max_rows == 0 || max_rows >= len {
-            return self.write_rows(&mut *iter, 0, len, output);
+    pub fn to_markdown(&self) -> String {
+        self.text_table().to_markdown()
+    }
+
+    pub fn to_latex(&self) -> String {
+        self.text_table().to_latex()
+    }
+
+    /// Save all the rows as CSV.
+    pub fn write_csv(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        self.text_table().write_csv(path)
+    }
+
+    fn take_data(&self) -> RefMut<'_, I> {
+        if self.completed.replace(true) {
+            panic!(
+                "Tables are used once. Please recreate the table for each display \
+                   (they are cheap to create)"
+            );
         }
+        self.data.borrow_mut()
+    }
 
-        let window_len = max_rows / 2;
-        self.write_rows(&mut *iter, 0, window_len, output);
-        output.push_str("<tr><th>...</th>");
-        if let Some(headers) = &self.headers {
-            for _ in 0..headers.len() {
-                output.push_str("<td>...</td>");
+    fn write_body(&self, mut iter: RefMut<'_, I>, output: &mut String) {
+        let len = iter.len();
+        let page_size = match self.max_rows.unwrap_or(DEFAULT_MAX_ROWS) {
+            0 => len.max(1),
+            page_size => page_size,
+        };
+        self.write_page(&mut *iter, 0, len.min(page_size), output);
+        for (page, start) in (page_size..len).step_by(page_size).enumerate() {
+            if page + 1 == MAX_PAGES {
+                let _ = write!(output, "<p>{} more rows not shown</p>", len - start);
+                break;
             }
+            let end = len.min(start + page_size);
+            let _ = write!(
+                output,
+                "<details><summary>Rows {} to {} of {}</summary>",
+                start,
+                end - 1,
+                len
+            );
+            self.write_page(&mut *iter, start, end, output);
+            output.push_str("</details>");
         }
-        output.push_str("</tr>");
+    }
 
-        // skip middle records
-        let skip_len = len - 2 * window_len;
-        // TODO use advance_by when stable.
-        for _ in 0..skip_len {
-            let _ = iter.next();
+    /// Write rows `start..end` as a table with the headers.
+    fn write_page(
+        &self,
+        rows: impl Iterator<Item = Row>,
+        start: usize,
+        end: usize,
+        output: &mut String,
+    ) {
+        output.push_str("<table>");
+        if let Some(headers) = &self.headers {
+            self.col_count.set(Some(headers.len()));
+            output.push_str("<thead><tr><th></th>");
+            for header in headers {
+                output.push_str("<th>");
+                html_escape::encode_text_to_string(header, output);
+                output.push_str("</th>");
+            }
+            output.push_str("</tr></thead>");
+        } else {
+            self.col_count.set(None);
         }
-        self.write_rows(&mut *iter, skip_len + window_len, len, output);
+        output.push_str("<tbody>");
+        self.write_rows(rows, start, end, output);
+        output.push_str("</tbody></table>");
     }
 
     fn write_rows(
@@ -346,16 +374,18 @@ where
         count: usize,
         output: &mut String,
     ) {
-        let mut scratch = String::new();
+        let mut cells = vec![];
         for idx in start..count {
             let row = rows.next().expect("internal inconsistency in Table");
             let _ = write!(output, "<tr><th>{}</th>", idx);
-            let drawer = RowDrawer {
-                output,
-                scratch: &mut scratch,
-            };
+            cells.clear();
             let to_draw = (self.row_fn)(&row, idx);
-            to_draw.draw(drawer);
+            to_draw.draw(RowDrawer { cells: &mut cells });
+            for cell in &cells {
+                output.push_str("<td>");
+                html_escape::encode_text_to_string(cell, output);
+                output.push_str("</td>");
+            }
             output.push_str("</tr>");
         }
     }
@@ -396,18 +426,6 @@ impl<T> ResultExt for Result<T, anyhow::Error> {
     }
 }
 
-fn constrain_max_rows(mut max_rows: usize) -> usize {
-    // make sure 0 -> 0, true since we only touch odd numbers
-    if max_rows % 2 == 1 {
-        if max_rows == 1 {
-            max_rows = 2;
-        } else {
-            max_rows -= 1;
-        }
-    }
-    max_rows
-}
-
 pub fn header(header: &str) {
     let len = header.len();
     print!("\n{}\n", header);
@@ -418,3 +436,19 @@ pub fn header(header: &str) {
 }
 
 pub(crate) static EMPTY_DESC: Lazy<BTreeSet<ArcStr>> = Lazy::new(|| BTreeSet::new());
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn table_pages() {
+        let table = Table::new(0..250, |n: &i32, _| [n * 2]).set_max_rows(100);
+        let mut output = String::new();
+        table.write_body(table.take_data(), &mut output);
+        assert_eq!(output.matches("<tr>").count(), 250);
+        assert_eq!(output.matches("<details>").count(), 2);
+        assert!(output.contains("<summary>Rows 200 to 249 of 250</summary>"));
+        assert!(output.contains("<td>498</td>"));
+    }
+}
@@ -0,0 +1,335 @@
+//! Checks for data that is inconsistent or obviously wrong.
+//!
+//! Each [`Rule`] looks at one thing that can go wrong in the extract. Running [`validate`] gives a
+//! [`ValidationReport`] listing every row that broke a rule, so we can decide whether to clean it
+//! or ask the data provider about it.
+//!
+//! [`adapt_flags`] checks the ADAPT form against the GP record.
+pub mod adapt_flags;
+pub mod linkage;
+
+use crate::{date_of_extract, orig_path, Adapts, Events, Patient, PatientId, Patients};
+use chrono::Datelike;
+use qu::ick_use::*;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    path::Path,
+};
+use term_data_table as tdt;
+
+/// The column holding IMD deciles in the original patients extract.
+const IMD_COLUMN: &str = "imdDecile-1-is-most-deprived-10percent";
+/// The highest possible Charlson comorbidity index.
+const MAX_CHARLSON: f32 = 37.;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rule {
+    /// An event for a patient not in the patients table.
+    UnknownPatient,
+    /// An event dated after the data was extracted.
+    FutureDate,
+    /// An event dated before the year the patient was born.
+    DateBeforeBirth,
+    /// An event with a missing date (see [`missing_date`](crate::missing_date)).
+    SentinelDate,
+    /// An ADAPT record where treatment ended before diagnosis.
+    TreatmentEndBeforeDiagnosis,
+    /// An IMD decile in the original extract that isn't between 1 and 10.
+    ImdOutOfRange,
+    /// A patient who would be older than [`MAX_PLAUSIBLE_AGE`](crate::MAX_PLAUSIBLE_AGE), or not
+    /// born yet, when the data was extracted.
+    ImplausibleBirthYear,
+    /// A patient ID that is in the patients table more than once.
+    DuplicatePatient,
+    /// A Charlson comorbidity index that is negative or more than the highest possible score.
+    CharlsonOutOfRange,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 9] = [
+        Rule::UnknownPatient,
+        Rule::FutureDate,
+        Rule::DateBeforeBirth,
+        Rule::SentinelDate,
+        Rule::TreatmentEndBeforeDiagnosis,
+        Rule::ImdOutOfRange,
+        Rule::ImplausibleBirthYear,
+        Rule::DuplicatePatient,
+        Rule::CharlsonOutOfRange,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Rule::UnknownPatient => "unknown-patient",
+            Rule::FutureDate => "future-date",
+            Rule::DateBeforeBirth => "date-before-birth",
+            Rule::SentinelDate => "sentinel-date",
+            Rule::TreatmentEndBeforeDiagnosis => "treatment-end-before-diagnosis",
+            Rule::ImdOutOfRange => "imd-out-of-range",
+            Rule::ImplausibleBirthYear => "implausible-birth-year",
+            Rule::DuplicatePatient => "duplicate-patient",
+            Rule::CharlsonOutOfRange => "charlson-out-of-range",
+        }
+    }
+
+    /// The table the rule checks.
+    pub fn table(self) -> &'static str {
+        match self {
+            Rule::UnknownPatient
+            | Rule::FutureDate
+            | Rule::DateBeforeBirth
+            | Rule::SentinelDate => "events",
+            Rule::TreatmentEndBeforeDiagnosis => "adapt",
+            Rule::ImdOutOfRange
+            | Rule::ImplausibleBirthYear
+            | Rule::DuplicatePatient
+            | Rule::CharlsonOutOfRange => "patients",
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A row that broke a rule.
+#[derive(Debug, Clone, Serialize)]
+pub struct Violation {
+    pub rule: Rule,
+    /// The index of the row in its table.
+    pub row: usize,
+    pub patient_id: Option<PatientId>,
+    /// What was wrong with the row.
+    pub detail: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    violations: Vec<Violation>,
+}
+
+impl ValidationReport {
+    pub fn violations(&self) -> &[Violation] {
+        &self.violations
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// Add violations found elsewhere, e.g. by [`check_raw_imd`].
+    pub fn extend(&mut self, violations: impl IntoIterator<Item = Violation>) {
+        self.violations.extend(violations)
+    }
+
+    /// The number of violations of each rule, including rules with none.
+    pub fn counts(&self) -> BTreeMap<Rule, usize> {
+        let mut counts: BTreeMap<Rule, usize> = Rule::ALL.iter().map(|rule| (*rule, 0)).collect();
+        for violation in self.violations.iter() {
+            *counts.entry(violation.rule).or_default() += 1;
+        }
+        counts
+    }
+
+    pub fn term_table(&self) -> tdt::Table<'_> {
+        use tdt::{Cell, Row, Table};
+        let mut table = Table::new().with_row(
+            Row::new()
+                .with_cell(Cell::from("Rule"))
+                .with_cell(Cell::from("Table"))
+                .with_cell(Cell::from("Violations")),
+        );
+        for (rule, count) in self.counts() {
+            table.add_row(
+                Row::new()
+                    .with_cell(Cell::from(rule.name()))
+                    .with_cell(Cell::from(rule.table()))
+                    .with_cell(Cell::from(count.to_string())),
+            );
+        }
+        table
+    }
+
+    /// Write all violations to a CSV file.
+    pub fn save_violations(&self, path: impl AsRef<Path>) -> Result {
+        let path = path.as_ref();
+        let mut out = csv::Writer::from_path(path)
+            .with_context(|| format!("creating \"{}\"", path.display()))?;
+        for violation in self.violations.iter() {
+            out.serialize(violation)?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// Run all the rules that can be checked on imported data.
+pub fn validate(patients: &Patients, events: &Events, adapts: &Adapts) -> ValidationReport {
+    let extract_date = date_of_extract();
+    let birth_years: HashMap<PatientId, u16> = patients
+        .iter_ref()
+        .map(|pat| (pat.patient_id, pat.year_of_birth))
+        .collect();
+
+    let mut report = ValidationReport::default();
+    report.extend(check_patients(&patients.els));
+    let mut push = |rule, row, patient_id, detail: String| {
+        report.violations.push(Violation {
+            rule,
+            row,
+            patient_id: Some(patient_id),
+            detail,
+        })
+    };
+
+    for (row, evt) in events.els.iter().enumerate() {
+        let birth_year = birth_years.get(&evt.patient_id);
+        if birth_year.is_none() {
+            push(
+                Rule::UnknownPatient,
+                row,
+                evt.patient_id,
+                format!("{} on {}", evt.read_code, evt.date),
+            );
+        }
+        if evt.date_known().is_none() {
+            push(
+                Rule::SentinelDate,
+                row,
+                evt.patient_id,
+                format!("{} on {}", evt.read_code, evt.date),
+            );
+            // The date is missing, so the other date rules don't mean anything.
+            continue;
+        }
+        if evt.date > extract_date {
+            push(
+                Rule::FutureDate,
+                row,
+                evt.patient_id,
+                format!("{} on {}", evt.read_code, evt.date),
+            );
+        }
+        if let Some(&birth_year) = birth_year {
+            if evt.date.year() < i32::from(birth_year) {
+                push(
+                    Rule::DateBeforeBirth,
+                    row,
+                    evt.patient_id,
+                    format!("{} on {}, born {}", evt.read_code, evt.date, birth_year),
+                );
+            }
+        }
+    }
+
+    for (row, adapt) in adapts.iter().enumerate() {
+        if let Some(diagnosis_date) = adapt.diagnosis_date {
+            if adapt.treatment_end_date < diagnosis_date {
+                push(
+                    Rule::TreatmentEndBeforeDiagnosis,
+                    row,
+                    adapt.id,
+                    format!(
+                        "diagnosed {}, treatment ended {}",
+                        diagnosis_date, adapt.treatment_end_date
+                    ),
+                );
+            }
+        }
+    }
+
+    report
+}
+
+/// Check the patients table for implausible years of birth, duplicate IDs and Charlson scores
+/// out of range.
+///
+/// [`Patients::load_orig`] runs this before removing the duplicates, so it can also be used on
+/// rows that aren't in a [`Patients`] yet.
+pub fn check_patients(patients: &[Patient]) -> Vec<Violation> {
+    let extract_date = date_of_extract();
+    let mut seen = HashSet::new();
+    let mut out = vec![];
+    let mut push = |rule, row, pat: &Patient, detail: String| {
+        out.push(Violation {
+            rule,
+            row,
+            patient_id: Some(pat.patient_id),
+            detail,
+        })
+    };
+    for (row, pat) in patients.iter().enumerate() {
+        if pat.plausible_age_at(extract_date).is_none() {
+            push(
+                Rule::ImplausibleBirthYear,
+                row,
+                pat,
+                format!("born {}", pat.year_of_birth),
+            );
+        }
+        if !seen.insert(pat.patient_id) {
+            push(
+                Rule::DuplicatePatient,
+                row,
+                pat,
+                format!("patient {} is already in the table", pat.patient_id),
+            );
+        }
+        if !(0. ..=MAX_CHARLSON).contains(&pat.charlson) {
+            push(
+                Rule::CharlsonOutOfRange,
+                row,
+                pat,
+                format!("Charlson index {}", pat.charlson),
+            );
+        }
+    }
+    out
+}
+
+/// Check the IMD deciles in the original patients extract.
+///
+/// Importing fails on the first bad decile, so we look at the raw text to find all of them.
+pub fn check_raw_imd(path: impl AsRef<Path>) -> Result<Vec<Violation>> {
+    fn inner(path: &Path) -> Result<Vec<Violation>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .trim(csv::Trim::All)
+            .from_path(path)?;
+        let headers = reader.headers()?;
+        let Some(imd_idx) = headers.iter().position(|h| h == IMD_COLUMN) else {
+            bail!("no \"{}\" column", IMD_COLUMN);
+        };
+        let id_idx = headers.iter().position(|h| h == "PatID");
+
+        let mut out = vec![];
+        for (row, record) in reader.into_records().enumerate() {
+            let record = record?;
+            let value = record.get(imd_idx).unwrap_or("");
+            if value.is_empty() || value.eq_ignore_ascii_case("null") {
+                continue;
+            }
+            // Match the importer, which accepts e.g. "3.0".
+            let in_range =
+                matches!(value.parse::<f32>(), Ok(v) if v == v.floor() && (1. ..=10.).contains(&v));
+            if !in_range {
+                out.push(Violation {
+                    rule: Rule::ImdOutOfRange,
+                    row,
+                    patient_id: id_idx
+                        .and_then(|idx| record.get(idx))
+                        .and_then(|id| id.parse().ok()),
+                    detail: format!("IMD decile \"{}\"", value),
+                });
+            }
+        }
+        Ok(out)
+    }
+    let path = orig_path(path.as_ref());
+    inner(&path).with_context(|| format!("checking IMD in \"{}\"", path.display()))
+}
@@ -0,0 +1,238 @@
+//! How well the treatment flags on the ADAPT form agree with the GP record.
+//!
+//! The ADAPT form is filled in by hand, so we check each flag (e.g. `any_radiotherapy`) against
+//! whether the patient has any code for that treatment. The codesets to use for each flag are
+//! listed in a TOML file like
+//!
+//! ```toml
+//! [[flag]]
+//! flag = "any_radiotherapy"
+//! codeset = "radiotherapy/codes.txt"
+//! ```
+//!
+//! where `codeset` is relative to the termsets directory.
+use crate::{
+    lemp::AdaptFlag, read2::CodeSet, termset_path, Adapts, DisclosureControl, Events, PatientId,
+    Result,
+};
+use qu::ick_use::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+use term_data_table as tdt;
+
+/// The codes that show a patient had the treatment in an ADAPT flag.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FlagCodeset {
+    pub flag: AdaptFlag,
+    /// Relative to the termsets directory.
+    pub codeset: PathBuf,
+}
+
+/// A list of [`FlagCodeset`]s.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FlagCodesets {
+    #[serde(rename = "flag")]
+    pub flags: Vec<FlagCodeset>,
+}
+
+impl FlagCodesets {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        fn inner(path: &Path) -> Result<FlagCodesets> {
+            Ok(toml::from_str(&fs::read_to_string(path)?)?)
+        }
+        let path = path.as_ref();
+        inner(path).with_context(|| format!("while loading \"{}\"", path.display()))
+    }
+
+    /// Load the codeset for each flag.
+    pub fn load_codesets(&self) -> Result<Vec<(AdaptFlag, CodeSet)>> {
+        self.flags
+            .iter()
+            .map(|fc| {
+                let codeset = CodeSet::load(termset_path(&fc.codeset))
+                    .with_context(|| format!("loading the codeset for flag \"{}\"", fc.flag))?;
+                Ok((fc.flag, codeset))
+            })
+            .collect()
+    }
+}
+
+/// How often one flag agrees with the GP record.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize)]
+pub struct FlagAgreement {
+    /// Flag set and at least one code.
+    pub both: usize,
+    /// Flag set but no codes.
+    pub adapt_only: usize,
+    /// Codes but flag not set.
+    pub ehr_only: usize,
+    /// Flag not set and no codes.
+    pub neither: usize,
+}
+
+impl FlagAgreement {
+    pub fn total(&self) -> usize {
+        self.both + self.adapt_only + self.ehr_only + self.neither
+    }
+
+    /// The proportion of patients where the flag and the GP record agree.
+    pub fn observed_agreement(&self) -> Option<f64> {
+        let total = self.total();
+        (total > 0).then(|| (self.both + self.neither) as f64 / total as f64)
+    }
+
+    /// Cohen's kappa, or `None` if it is undefined (no patients, or everyone in one category
+    /// for both sources).
+    pub fn kappa(&self) -> Option<f64> {
+        let total = self.total() as f64;
+        let observed = self.observed_agreement()?;
+        let adapt_yes = (self.both + self.adapt_only) as f64 / total;
+        let ehr_yes = (self.both + self.ehr_only) as f64 / total;
+        let expected = adapt_yes * ehr_yes + (1. - adapt_yes) * (1. - ehr_yes);
+        (expected < 1.).then(|| (observed - expected) / (1. - expected))
+    }
+
+    /// The counts after disclosure control, or `None` if any are suppressed.
+    fn controlled(&self, dc: &DisclosureControl) -> Option<FlagAgreement> {
+        Some(FlagAgreement {
+            both: dc.count(self.both).value()?,
+            adapt_only: dc.count(self.adapt_only).value()?,
+            ehr_only: dc.count(self.ehr_only).value()?,
+            neither: dc.count(self.neither).value()?,
+        })
+    }
+}
+
+/// A patient whose flag disagrees with their GP record.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Discordance {
+    pub patient_id: PatientId,
+    pub flag: AdaptFlag,
+    /// Whether the flag is set on the ADAPT form.
+    pub in_adapt: bool,
+    /// Whether there is a code for the treatment in the GP record.
+    pub in_ehr: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct FlagReport {
+    pub agreement: Vec<(AdaptFlag, FlagAgreement)>,
+    pub discordant: Vec<Discordance>,
+}
+
+impl FlagReport {
+    pub fn term_table(&self, dc: &DisclosureControl) -> tdt::Table<'_> {
+        use tdt::{Row, Table};
+        let mut table = Table::new().with_row(
+            Row::new()
+                .with_cell("Flag")
+                .with_cell("Both")
+                .with_cell("ADAPT only")
+                .with_cell("EHR only")
+                .with_cell("Neither")
+                .with_cell("Agreement")
+                .with_cell("Kappa"),
+        );
+        for (flag, agreement) in self.agreement.iter() {
+            // Like percentages, agreement must come from the counts we show.
+            let controlled = agreement.controlled(dc);
+            let agreement_pc = match controlled.and_then(|a| a.observed_agreement()) {
+                Some(v) => format!("{:.1}%", v * 100.),
+                None => "-".into(),
+            };
+            let kappa = match controlled.and_then(|a| a.kappa()) {
+                Some(v) => format!("{v:.2}"),
+                None => "-".into(),
+            };
+            table.add_row(
+                Row::new()
+                    .with_cell(flag.label())
+                    .with_cell(dc.count(agreement.both).to_string())
+                    .with_cell(dc.count(agreement.adapt_only).to_string())
+                    .with_cell(dc.count(agreement.ehr_only).to_string())
+                    .with_cell(dc.count(agreement.neither).to_string())
+                    .with_cell(agreement_pc)
+                    .with_cell(kappa),
+            );
+        }
+        table
+    }
+
+    /// Write the discordant patients to a CSV file.
+    ///
+    /// This is patient-level data, so it must stay in the secure environment.
+    pub fn save_discordant(&self, path: impl AsRef<Path>) -> Result {
+        let path = path.as_ref();
+        let mut out = csv::Writer::from_path(path)
+            .with_context(|| format!("creating \"{}\"", path.display()))?;
+        for row in self.discordant.iter() {
+            out.serialize(row)?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// Compare each flag with whether the patient has any code in its codeset (at any date).
+pub fn check_adapt_flags(
+    adapts: &Adapts,
+    events: &Events,
+    codesets: &[(AdaptFlag, CodeSet)],
+) -> FlagReport {
+    let mut report = FlagReport::default();
+    for (flag, codeset) in codesets {
+        let coded: HashSet<PatientId> = events
+            .with_codeset(codeset)
+            .map(|evt| evt.patient_id)
+            .collect();
+        let mut agreement = FlagAgreement::default();
+        for adapt in adapts.iter_ref() {
+            let in_adapt = flag.is_set(adapt);
+            let in_ehr = coded.contains(&adapt.id);
+            match (in_adapt, in_ehr) {
+                (true, true) => agreement.both += 1,
+                (true, false) => agreement.adapt_only += 1,
+                (false, true) => agreement.ehr_only += 1,
+                (false, false) => agreement.neither += 1,
+            }
+            if in_adapt != in_ehr {
+                report.discordant.push(Discordance {
+                    patient_id: adapt.id,
+                    flag: *flag,
+                    in_adapt,
+                    in_ehr,
+                });
+            }
+        }
+        report.agreement.push((*flag, agreement));
+    }
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn kappa() {
+        // Expected agreement 0.49 * 0.46 + 0.51 * 0.54 = 0.5008.
+        let agreement = FlagAgreement {
+            both: 40,
+            adapt_only: 9,
+            ehr_only: 6,
+            neither: 45,
+        };
+        assert_eq!(agreement.observed_agreement(), Some(0.85));
+        assert!((agreement.kappa().unwrap() - 0.6995).abs() < 1e-4);
+        let all_yes = FlagAgreement {
+            both: 10,
+            ..Default::default()
+        };
+        assert_eq!(all_yes.kappa(), None);
+        assert_eq!(FlagAgreement::default().kappa(), None);
+    }
+}
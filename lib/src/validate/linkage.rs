@@ -0,0 +1,147 @@
+//! How well the patients, events and ADAPT tables link up on patient ID.
+//!
+//! These are the numbers for the data-flow diagram: everyone should have a row in the patients
+//! table, but ADAPT forms and events can refer to patients that weren't extracted, and some
+//! patients have no events at all.
+use crate::{Adapts, DisclosureControl, Events, PatientId, Patients};
+use std::collections::{BTreeMap, BTreeSet};
+use term_data_table as tdt;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LinkageReport {
+    /// The number of (distinct) patients in the patients table.
+    pub patients: usize,
+    /// The number of (distinct) patients with an ADAPT form.
+    pub adapts: usize,
+    /// The number of events.
+    pub events: usize,
+    /// ADAPT forms for patients that aren't in the patients table.
+    pub adapt_without_patient: BTreeSet<PatientId>,
+    /// Patients in the patients table with no events.
+    pub patients_without_events: BTreeSet<PatientId>,
+    /// Patient IDs in the events table that aren't in the patients table, with how many events
+    /// each has.
+    pub events_without_patient: BTreeMap<PatientId, usize>,
+    /// Patients in the patients table with an ADAPT form.
+    pub patients_with_adapt: usize,
+    /// Patients in the patients table with at least one event.
+    pub patients_with_events: usize,
+    /// Patients in the patients table with an ADAPT form and at least one event.
+    pub linked: usize,
+}
+
+impl LinkageReport {
+    /// Build the report from the patient IDs in each table. `event_ids` has one ID per event.
+    pub fn from_ids(
+        patient_ids: impl IntoIterator<Item = PatientId>,
+        adapt_ids: impl IntoIterator<Item = PatientId>,
+        event_ids: impl IntoIterator<Item = PatientId>,
+    ) -> Self {
+        let patient_ids: BTreeSet<_> = patient_ids.into_iter().collect();
+        let adapt_ids: BTreeSet<_> = adapt_ids.into_iter().collect();
+        let mut event_counts = BTreeMap::new();
+        let mut events = 0;
+        for id in event_ids {
+            *event_counts.entry(id).or_insert(0) += 1;
+            events += 1;
+        }
+
+        let with_adapt = |id: &&PatientId| adapt_ids.contains(*id);
+        let with_events = |id: &&PatientId| event_counts.contains_key(*id);
+        LinkageReport {
+            patients: patient_ids.len(),
+            adapts: adapt_ids.len(),
+            events,
+            adapt_without_patient: adapt_ids.difference(&patient_ids).copied().collect(),
+            patients_without_events: patient_ids
+                .iter()
+                .filter(|id| !with_events(id))
+                .copied()
+                .collect(),
+            events_without_patient: event_counts
+                .iter()
+                .filter(|(id, _)| !patient_ids.contains(*id))
+                .map(|(id, count)| (*id, *count))
+                .collect(),
+            patients_with_adapt: patient_ids.iter().filter(with_adapt).count(),
+            patients_with_events: patient_ids.iter().filter(with_events).count(),
+            linked: patient_ids
+                .iter()
+                .filter(|id| with_adapt(id) && with_events(id))
+                .count(),
+        }
+    }
+
+    /// The number of events whose patient isn't in the patients table.
+    pub fn orphan_events(&self) -> usize {
+        self.events_without_patient.values().sum()
+    }
+
+    pub fn term_table(&self, dc: &DisclosureControl) -> tdt::Table<'_> {
+        use tdt::{Row, Table};
+        let rows = [
+            ("Patients", self.patients),
+            ("ADAPT forms", self.adapts),
+            ("Events", self.events),
+            (
+                "ADAPT forms not in the patients table",
+                self.adapt_without_patient.len(),
+            ),
+            (
+                "Patients with no events",
+                self.patients_without_events.len(),
+            ),
+            (
+                "Patient IDs in events not in the patients table",
+                self.events_without_patient.len(),
+            ),
+            ("Events not in the patients table", self.orphan_events()),
+            ("Patients with an ADAPT form", self.patients_with_adapt),
+            ("Patients with events", self.patients_with_events),
+            ("Patients with an ADAPT form and events", self.linked),
+        ];
+        let mut table = Table::new().with_row(Row::new().with_cell("").with_cell("Count"));
+        for (label, count) in rows {
+            table.add_row(
+                Row::new()
+                    .with_cell(label)
+                    .with_cell(dc.count(count).to_string()),
+            );
+        }
+        table
+    }
+}
+
+/// Check which patient IDs link between the patients, events and ADAPT tables.
+pub fn linkage_report(patients: &Patients, events: &Events, adapts: &Adapts) -> LinkageReport {
+    LinkageReport::from_ids(
+        patients.keys(),
+        adapts.keys(),
+        events.iter_ref().map(|evt| evt.patient_id),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn from_ids() {
+        let ids = |ids: &[u64]| ids.iter().copied().map(PatientId::new).collect::<Vec<_>>();
+        let report = LinkageReport::from_ids(ids(&[1, 2, 3]), ids(&[2, 3, 4]), ids(&[1, 1, 3, 5]));
+        assert_eq!((report.patients, report.adapts, report.events), (3, 3, 4));
+        assert_eq!(
+            report.adapt_without_patient,
+            ids(&[4]).into_iter().collect()
+        );
+        assert_eq!(
+            report.patients_without_events,
+            ids(&[2]).into_iter().collect()
+        );
+        assert_eq!(report.orphan_events(), 1);
+        assert_eq!(report.events_without_patient.len(), 1);
+        assert_eq!(report.patients_with_adapt, 2);
+        assert_eq!(report.patients_with_events, 2);
+        assert_eq!(report.linked, 1);
+    }
+}